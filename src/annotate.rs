@@ -0,0 +1,187 @@
+use mupdf::pdf::{PdfDocument, PdfObject};
+
+use crate::stamp::inherited;
+
+/* Text markup annotations made on the selected text */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnnotationKind {
+    Highlight,
+    Underline,
+}
+
+impl AnnotationKind {
+    pub fn subtype(&self) -> &'static str {
+        match self {
+            AnnotationKind::Highlight => "Highlight",
+            AnnotationKind::Underline => "Underline",
+        }
+    }
+
+    /* The color written into the document and drawn until it has been written */
+    pub fn color(&self) -> [u8; 3] {
+        match self {
+            AnnotationKind::Highlight => [255, 235, 59],
+            AnnotationKind::Underline => [229, 57, 53],
+        }
+    }
+}
+
+/* An annotation that has not been written into the document yet, given by the bounds
+ * of the lines it covers in page units */
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingAnnotation {
+    pub page: usize,
+    pub kind: AnnotationKind,
+    pub rects: Vec<(f32, f32, f32, f32)>,
+}
+
+/* Maps a point in page units, whose origin is the top left corner of the displayed
+ * page, into the space of the PDF page, whose origin is at the bottom of the box
+ * and which is turned by `/Rotate` degrees clockwise when displayed */
+pub fn pdf_point(
+    (x, y): (f32, f32),
+    (x0, y0, x1, y1): (f32, f32, f32, f32),
+    rotation: i32,
+) -> (f32, f32) {
+    let (width, height) = (x1 - x0, y1 - y0);
+    let (u, v) = match rotation.rem_euclid(360) {
+        90 => (y, height - x),
+        180 => (width - x, height - y),
+        270 => (width - y, x),
+        _ => (x, y),
+    };
+    (x0 + u, y1 - v)
+}
+
+/* The `/QuadPoints` of the rectangles, each as its upper left, upper right, lower left
+ * and lower right corner as the text is read */
+pub fn quad_points(
+    rects: &[(f32, f32, f32, f32)],
+    bounds: (f32, f32, f32, f32),
+    rotation: i32,
+) -> Vec<f32> {
+    rects
+        .iter()
+        .flat_map(|&(x0, y0, x1, y1)| [(x0, y0), (x1, y0), (x0, y1), (x1, y1)])
+        .flat_map(|x| {
+            let (x, y) = pdf_point(x, bounds, rotation);
+            [x, y]
+        })
+        .collect()
+}
+
+fn real_array(pdf: &PdfDocument, values: &[f32]) -> Result<PdfObject, mupdf::Error> {
+    let mut array = pdf.new_array()?;
+    for x in values {
+        array.array_push(pdf.new_real(*x)?)?;
+    }
+    Ok(array)
+}
+
+fn add_annotation(
+    pdf: &mut PdfDocument,
+    annotation: &PendingAnnotation,
+) -> Result<(), mupdf::Error> {
+    let mut object = pdf.find_page(annotation.page as i32)?;
+
+    /* Displayed pages are the crop box, which defaults to the media box */
+    let mut bounds = [0.0f32, 0.0, 612.0, 792.0];
+    let page_box = match inherited(&object, "CropBox")? {
+        Some(x) => Some(x),
+        None => inherited(&object, "MediaBox")?,
+    };
+    if let Some(page_box) = page_box {
+        for (i, x) in bounds.iter_mut().enumerate() {
+            if let Some(value) = page_box.get_array(i as i32)? {
+                *x = value.as_float()?;
+            }
+        }
+    }
+    let bounds = (
+        f32::min(bounds[0], bounds[2]),
+        f32::min(bounds[1], bounds[3]),
+        f32::max(bounds[0], bounds[2]),
+        f32::max(bounds[1], bounds[3]),
+    );
+    let rotation = match inherited(&object, "Rotate")? {
+        Some(x) => x.as_int()?,
+        None => 0,
+    };
+
+    let points = quad_points(&annotation.rects, bounds, rotation);
+    let xs = points.iter().step_by(2);
+    let ys = points.iter().skip(1).step_by(2);
+    let rect = [
+        xs.clone().copied().fold(f32::INFINITY, f32::min),
+        ys.clone().copied().fold(f32::INFINITY, f32::min),
+        xs.copied().fold(-f32::INFINITY, f32::max),
+        ys.copied().fold(-f32::INFINITY, f32::max),
+    ];
+    let color = annotation.kind.color().map(|x| x as f32 / 255.0);
+
+    /* Readers draw text markup without an appearance stream from its quads */
+    let mut annot = pdf.add_object(&pdf.new_dict()?)?;
+    annot.dict_put("Type", pdf.new_name("Annot")?)?;
+    annot.dict_put("Subtype", pdf.new_name(annotation.kind.subtype())?)?;
+    annot.dict_put("Rect", real_array(pdf, &rect)?)?;
+    annot.dict_put("QuadPoints", real_array(pdf, &points)?)?;
+    annot.dict_put("C", real_array(pdf, &color)?)?;
+    /* Printed along with the page */
+    annot.dict_put("F", pdf.new_int(4)?)?;
+
+    match object.get_dict("Annots")? {
+        Some(mut annots) if annots.is_array()? => annots.array_push(annot),
+        _ => {
+            let mut annots = pdf.new_array()?;
+            annots.array_push(annot)?;
+            object.dict_put("Annots", annots)
+        }
+    }
+}
+
+/* Writes the annotations into a PDF. It is written next to it first so that the
+ * reloads never see it half written. Returns the number of written annotations */
+pub fn save_annotations(
+    file: &str,
+    annotations: &[PendingAnnotation],
+) -> Result<usize, String> {
+    let mut pdf = PdfDocument::open(file)
+        .map_err(|x| format!("Only PDF documents can be annotated: {}", x))?;
+    for annotation in annotations {
+        add_annotation(&mut pdf, annotation).map_err(|x| {
+            format!("Could not annotate page {}: {}", annotation.page + 1, x)
+        })?;
+    }
+
+    let written = format!("{}.meowpdf-annotate", file);
+    pdf.save(&written)
+        .map_err(|x| format!("Could not save {}: {}", written, x))?;
+    std::fs::rename(&written, file)
+        .map_err(|x| format!("Could not replace {}: {}", file, x))?;
+    Ok(annotations.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quad_points() {
+        let bounds = (0.0, 0.0, 600.0, 800.0);
+        assert!(pdf_point((10.0, 20.0), bounds, 0) == (10.0, 780.0));
+        assert!(pdf_point((10.0, 20.0), (50.0, 100.0, 650.0, 900.0), 0) == (60.0, 880.0));
+
+        /* The top left corner of a page turned clockwise is its bottom left one */
+        assert!(pdf_point((0.0, 0.0), bounds, 90) == (0.0, 0.0));
+        assert!(pdf_point((800.0, 0.0), bounds, 90) == (0.0, 800.0));
+        assert!(pdf_point((0.0, 0.0), bounds, 180) == (600.0, 0.0));
+        assert!(pdf_point((0.0, 0.0), bounds, 270) == (600.0, 800.0));
+        assert!(pdf_point((0.0, 0.0), bounds, -90) == (600.0, 800.0));
+
+        assert!(
+            quad_points(&[(10.0, 20.0, 110.0, 32.0)], bounds, 0)
+                == [10.0, 780.0, 110.0, 780.0, 10.0, 768.0, 110.0, 768.0]
+        );
+        assert!(quad_points(&[], bounds, 0).is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+/* What the viewer has been started to do */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    Help,
+    Version,
+    /* Displays the first document and opens the others in tabs. Without documents
+     * one is picked from the working directory */
+    View(Vec<String>),
+    Compare(String, String),
+    Remote(String),
+    Query(String, String),
+    Check,
+    Prerender(String),
+    /* The arguments of `--export`, which has options of its own */
+    Export(Vec<String>),
+    ExportHtml(String, String),
+    Dest(String, String),
+    Section(String, String),
+    /* Renders the document for the check of `--sandbox` */
+    Preflight(String),
+    /* Renders the pages of the document that are requested on stdin */
+    RenderWorker(String),
+}
+
+/* The parsed command line. `page` and the fields after it override the config for
+ * this session only */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Args {
+    pub mode: Mode,
+    pub serve: bool,
+    pub safe_mode: bool,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub capture: Option<String>,
+    pub sandbox: bool,
+    /* Zero-indexed page to start at */
+    pub page: Option<usize>,
+    pub scale: Option<f32>,
+    pub inverse: bool,
+    pub alpha: bool,
+    pub config: Option<String>,
+    pub no_watch: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            mode: Mode::View(Vec::new()),
+            serve: false,
+            safe_mode: false,
+            record: None,
+            replay: None,
+            capture: None,
+            sandbox: false,
+            page: None,
+            scale: None,
+            inverse: false,
+            alpha: false,
+            config: None,
+            no_watch: false,
+        }
+    }
+}
+
+/* Options that select the mode, the arguments of the mode are the documents */
+const MODES: [&str; 11] = [
+    "--compare",
+    "--remote",
+    "--query",
+    "--check",
+    "--prerender",
+    "--export",
+    "--export-html",
+    "--dest",
+    "--section",
+    "--preflight",
+    "--render-worker",
+];
+
+/* Parses the arguments without the name of the program. Options can be given before
+ * and after the documents, except for the ones after `--export`, and everything
+ * after `--` is taken as a document */
+pub fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut mode: Option<&str> = None;
+    let mut rest = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |expected: &str| {
+            args.next()
+                .cloned()
+                .ok_or(format!("`{}` expects {}", arg, expected))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => {
+                parsed.mode = Mode::Help;
+                return Ok(parsed);
+            }
+            "-v" | "--version" => {
+                parsed.mode = Mode::Version;
+                return Ok(parsed);
+            }
+            "--" => {
+                rest.extend(args.cloned());
+                break;
+            }
+            "--serve" => parsed.serve = true,
+            "--safe-mode" => parsed.safe_mode = true,
+            "--sandbox" => parsed.sandbox = true,
+            "--record" => parsed.record = Some(value("a file")?),
+            "--replay" => parsed.replay = Some(value("a file")?),
+            "--capture" => parsed.capture = Some(value("a directory")?),
+            "--page" => {
+                let page = value("a page number")?;
+                parsed.page = match page.parse::<usize>() {
+                    Ok(x) if x > 0 => Some(x - 1),
+                    _ => {
+                        return Err(format!(
+                            "`--page` expects a page number counted from 1, got `{}`",
+                            page
+                        ))
+                    }
+                };
+            }
+            "--scale" => {
+                let scale = value("a scale")?;
+                parsed.scale = match scale.parse::<f32>() {
+                    Ok(x) if x.is_finite() && x > 0.0 => Some(x),
+                    _ => {
+                        return Err(format!(
+                            "`--scale` expects a positive number such as 1.5, got `{}`",
+                            scale
+                        ))
+                    }
+                };
+            }
+            "--inverse" => parsed.inverse = true,
+            "--alpha" => parsed.alpha = true,
+            "--config" => parsed.config = Some(value("a config file")?),
+            "--no-watch" => parsed.no_watch = true,
+            x if MODES.contains(&x) => {
+                if let Some(mode) = mode {
+                    return Err(format!("`{}` can not be combined with `{}`", x, mode));
+                }
+                mode = Some(x);
+                /* The rest belongs to `--export`, whose `--page` takes a range */
+                if x == "--export" {
+                    rest.extend(args.cloned());
+                    break;
+                }
+            }
+            x if x.starts_with("--") => {
+                return Err(format!("Unknown option `{}`, see `--help`", x))
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    let pair = |expected: &str| match <[String; 2]>::try_from(rest.clone()) {
+        Ok([a, b]) => Ok((a, b)),
+        Err(_) => Err(format!(
+            "`{}` expects {}",
+            mode.unwrap_or_default(),
+            expected
+        )),
+    };
+    parsed.mode = match mode {
+        None => Mode::View(rest),
+        Some("--compare") => {
+            let (a, b) = pair("exactly two files")?;
+            Mode::Compare(a, b)
+        }
+        Some("--remote") => match <[String; 1]>::try_from(rest) {
+            Ok([x]) => Mode::Remote(x),
+            Err(_) => return Err("`--remote` expects a file or a deep link".to_string()),
+        },
+        Some("--query") => {
+            let (socket, query) = pair("a socket and a query")?;
+            Mode::Query(socket, query)
+        }
+        Some("--check") if rest.is_empty() => Mode::Check,
+        Some("--check") => return Err("`--check` does not take a file".to_string()),
+        Some("--prerender") => match rest.as_slice() {
+            [file] => Mode::Prerender(file.clone()),
+            _ => return Err("`--prerender` expects a file".to_string()),
+        },
+        Some("--export") => Mode::Export(rest),
+        Some("--export-html") => {
+            let (file, directory) = pair("a file and a directory")?;
+            Mode::ExportHtml(file, directory)
+        }
+        Some("--dest") => {
+            let (name, file) = pair("a name and a file")?;
+            Mode::Dest(name, file)
+        }
+        Some("--section") => {
+            let (title, file) = pair("a name and a file")?;
+            Mode::Section(title, file)
+        }
+        Some(x) => match (x, <[String; 1]>::try_from(rest)) {
+            ("--preflight", Ok([file])) => Mode::Preflight(file),
+            (_, Ok([file])) => Mode::RenderWorker(file),
+            (x, Err(_)) => return Err(format!("`{}` expects a file", x)),
+        },
+    };
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Args, String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert!(parse("").unwrap() == Args::default());
+        assert!(parse("a.pdf --help b.pdf").unwrap().mode == Mode::Help);
+
+        let args = parse("--serve a.pdf b.pdf --page 3 --scale 1.5 --no-watch").unwrap();
+        assert!(args.mode == Mode::View(vec!["a.pdf".to_string(), "b.pdf".to_string()]));
+        assert!(args.serve && args.no_watch && !args.inverse);
+        assert!(args.page == Some(2) && args.scale == Some(1.5));
+
+        let args = parse("--inverse --compare a.pdf b.pdf --config c.toml").unwrap();
+        assert!(args.mode == Mode::Compare("a.pdf".to_string(), "b.pdf".to_string()));
+        assert!(args.inverse && args.config == Some("c.toml".to_string()));
+        assert!(parse("--sandbox a.pdf").unwrap().sandbox);
+        assert!(
+            parse("--render-worker a.pdf").unwrap().mode
+                == Mode::RenderWorker("a.pdf".to_string())
+        );
+        assert!(
+            parse("-- --a.pdf").unwrap().mode == Mode::View(vec!["--a.pdf".to_string()])
+        );
+
+        /* The options of `--export` are left to it */
+        let args = parse("--alpha --export a.png --page 2-3 a.pdf").unwrap();
+        assert!(args.alpha && args.page.is_none());
+        assert!(
+            args.mode
+                == Mode::Export(vec![
+                    "a.png".to_string(),
+                    "--page".to_string(),
+                    "2-3".to_string(),
+                    "a.pdf".to_string()
+                ])
+        );
+
+        assert!(
+            parse("--prerender a.pdf").unwrap().mode
+                == Mode::Prerender("a.pdf".to_string())
+        );
+        assert!(parse("--prerender a.pdf 2").is_err());
+        assert!(parse("a.pdf --page 0").unwrap_err().contains("got `0`"));
+        assert!(parse("a.pdf --scale").unwrap_err() == "`--scale` expects a scale");
+        assert!(parse("--scale -1 a.pdf").is_err());
+        assert!(parse("--zoom 2 a.pdf")
+            .unwrap_err()
+            .starts_with("Unknown option"));
+        assert!(parse("--compare a.pdf").is_err());
+        assert!(parse("--check --dest a b").is_err());
+        assert!(parse("--check a.pdf").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{io, path::Path};
+
+use crossterm::{
+    cursor::{MoveTo, RestorePosition, SavePosition},
+    execute,
+    style::{Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+};
+
+use crate::{config::ConfigBarPosition, CONFIG, TERMINAL_SIZE};
+
+/* Settings that change how the document looks, shown by `{toggles}` while they are
+ * active */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BarToggle {
+    Alpha,
+    Inverse,
+    Diff,
+    Paper,
+    TextMode,
+    ColorFilter,
+    Horizontal,
+}
+
+impl BarToggle {
+    fn letter(self) -> char {
+        match self {
+            BarToggle::Alpha => 'A',
+            BarToggle::Inverse => 'I',
+            BarToggle::Diff => 'D',
+            BarToggle::Paper => 'P',
+            BarToggle::TextMode => 'T',
+            BarToggle::ColorFilter => 'C',
+            BarToggle::Horizontal => 'H',
+        }
+    }
+}
+
+/* What the placeholders of the bar templates are replaced with */
+pub struct BarState<'a> {
+    /* The page in the middle of the view counted from one */
+    pub page: usize,
+    pub pages: usize,
+    /* Zoom of the viewer in percent */
+    pub scale: f32,
+    pub file: &'a str,
+    /* Formatted size of the rendered pages in memory */
+    pub memory: &'a str,
+    /* The status segments of the scripts */
+    pub script: &'a str,
+    /* The displayed tab counted from one and the number of tabs */
+    pub tab: usize,
+    pub tabs: usize,
+    /* The active toggles */
+    pub toggles: Vec<BarToggle>,
+}
+
+/* Replaces `{page}`, `{pages}`, `{scale}`, `{file}`, `{memory}`, `{script}`, `{tab}`,
+ * `{tabs}` and `{toggles}` in a template. Unknown placeholders are kept as they are */
+pub fn format_bar(template: &str, state: &BarState) -> String {
+    format_bar_with(template, state, |_, x| x.letter())
+}
+
+/* Formats the template with the toggles written as the given characters, such as the
+ * markers by which `toggle_at` finds them */
+fn format_bar_with(
+    template: &str,
+    state: &BarState,
+    letter: impl Fn(usize, BarToggle) -> char,
+) -> String {
+    let toggles = match state.toggles.is_empty() {
+        true => String::new(),
+        false => {
+            let letters: String = state
+                .toggles
+                .iter()
+                .enumerate()
+                .map(|(i, x)| letter(i, *x))
+                .collect();
+            format!("[{}]", letters)
+        }
+    };
+
+    let file = Path::new(state.file)
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_else(|| state.file.to_string());
+
+    template
+        .replace("{pages}", &state.pages.to_string())
+        .replace("{page}", &state.page.to_string())
+        .replace("{scale}", &format!("{:.0}", state.scale))
+        .replace("{file}", &file)
+        .replace("{memory}", state.memory)
+        .replace("{script}", state.script)
+        .replace("{tabs}", &state.tabs.to_string())
+        .replace("{tab}", &state.tab.to_string())
+        .replace("{toggles}", &toggles)
+}
+
+/* Lays out the left and right segments on a line of the given width. The left one is
+ * cut off first when both do not fit */
+pub fn layout_bar(left: &str, right: &str, width: usize) -> String {
+    let right: String = right.chars().take(width).collect();
+    let space = width - right.chars().count();
+    let left: String = left.chars().take(space).collect();
+    let padding = space - left.chars().count();
+
+    format!("{}{}{}", left, " ".repeat(padding), right)
+}
+
+/* The toggle drawn at the column of a bar laid out on a line of the given width */
+pub fn toggle_at(
+    (left, right): (&str, &str),
+    state: &BarState,
+    width: usize,
+    column: usize,
+) -> Option<BarToggle> {
+    /* Characters of the private use area stand in for the letters of the toggles */
+    let marker = |i, _| char::from_u32(0xE000 + i as u32).unwrap();
+    let line = layout_bar(
+        &format_bar_with(left, state, marker),
+        &format_bar_with(right, state, marker),
+        width,
+    );
+
+    let i = line.chars().nth(column)? as u32;
+    let i = i.checked_sub(0xE000)? as usize;
+    state.toggles.get(i).copied()
+}
+
+/* The line of the terminal the bar is drawn over, the first or the last one */
+pub fn bar_row() -> u16 {
+    match CONFIG.get().unwrap().bar.position {
+        ConfigBarPosition::Top => 0,
+        ConfigBarPosition::Bottom => TERMINAL_SIZE.get().unwrap().read().unwrap().rows,
+    }
+}
+
+/* The toggle under the cell of the terminal, for turning toggles off by clicking them */
+pub fn bar_toggle(state: &BarState, (column, row): (u16, u16)) -> Option<BarToggle> {
+    let config = &CONFIG.get().unwrap().bar;
+    let bar = bar_row();
+    let size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+    /* The bar at the bottom is drawn below the last line and ends up on it */
+    if !config.enabled || row != bar.min(size.rows.saturating_sub(1)) {
+        return None;
+    }
+
+    toggle_at(
+        (&config.left, &config.right),
+        state,
+        size.columns as usize,
+        column as usize,
+    )
+}
+
+/* Draws the status bar over the first or the last line of the terminal */
+pub fn display_bar(state: &BarState) {
+    let config = &CONFIG.get().unwrap().bar;
+    if !config.enabled {
+        return;
+    }
+
+    let row = bar_row();
+    let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+    let line = layout_bar(
+        &format_bar(&config.left, state),
+        &format_bar(&config.right, state),
+        terminal_size.columns as usize,
+    );
+
+    execute!(
+        io::stdout(),
+        SavePosition,
+        MoveTo(0, row),
+        SetBackgroundColor(config.background),
+        SetForegroundColor(config.foreground),
+        Print(line),
+        ResetColor,
+        RestorePosition
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bar() {
+        let state = BarState {
+            page: 3,
+            pages: 12,
+            scale: 150.0,
+            file: "/home/user/paper.pdf",
+            memory: "12.5 MiB",
+            script: "",
+            tab: 2,
+            tabs: 3,
+            toggles: vec![BarToggle::Inverse, BarToggle::TextMode],
+        };
+        assert!(
+            format_bar("{file} {page}/{pages} {scale}%", &state) == "paper.pdf 3/12 150%"
+        );
+        assert!(format_bar("[{memory}]", &state) == "[12.5 MiB]");
+        assert!(format_bar("{tab}/{tabs}", &state) == "2/3");
+        assert!(format_bar("{toggles} {page}", &state) == "[IT] 3");
+
+        /* `left    [IT] 3` */
+        let templates = ("left", "{toggles} {page}");
+        assert!(toggle_at(templates, &state, 14, 9) == Some(BarToggle::Inverse));
+        assert!(toggle_at(templates, &state, 14, 10) == Some(BarToggle::TextMode));
+        assert!(toggle_at(templates, &state, 14, 8).is_none());
+        assert!(toggle_at(templates, &state, 14, 20).is_none());
+        assert!(format_bar("{unknown}", &state) == "{unknown}");
+
+        assert!(layout_bar("left", "right", 12) == "left   right");
+        assert!(layout_bar("left", "right", 7) == "leright");
+        assert!(layout_bar("left", "right", 3) == "rig");
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
+    path::PathBuf,
+    time::UNIX_EPOCH,
+};
+
+use mupdf::{Colorspace, ImageFormat, Pixmap};
+
+use crate::transform::PageTransform;
+
+/* Rasterized pages stored on disk as PNG files. The pages of a document are kept in
+ * `<cache>/meowpdf/<path hash>/<modification time>-<size>/` so that a rewritten
+ * document never hits the renders of its old revision */
+pub struct DiskCache {
+    document: PathBuf,
+    revision: PathBuf,
+}
+
+impl DiskCache {
+    pub fn open(file: &str) -> Result<Self, String> {
+        let path = std::fs::canonicalize(file)
+            .map_err(|x| format!("Could not resolve {}: {}", file, x))?;
+        let metadata = std::fs::metadata(&path)
+            .map_err(|x| format!("Could not read metadata of {}: {}", file, x))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+
+        let mut document =
+            dirs::cache_dir().ok_or("No cache directory has been found")?;
+        document.push("meowpdf");
+        document.push(format!("{:016X}", hasher.finish()));
+        let revision = document.join(format!("{}-{}", modified, metadata.len()));
+
+        Ok(Self { document, revision })
+    }
+
+    fn page_path(
+        &self,
+        page: usize,
+        precision: f64,
+        alpha: bool,
+        transform: &PageTransform,
+    ) -> PathBuf {
+        self.revision.join(format!(
+            "{}-{}-{}-{}.png",
+            page,
+            (precision * 100.0).round() as u32,
+            if alpha { "a" } else { "o" },
+            transform.key()
+        ))
+    }
+
+    pub fn load(
+        &self,
+        page: usize,
+        precision: f64,
+        alpha: bool,
+        transform: &PageTransform,
+    ) -> Option<Pixmap> {
+        let path = self.page_path(page, precision, alpha, transform);
+        if !path.exists() {
+            return None;
+        }
+
+        mupdf::Image::from_file(path.to_str()?)
+            .ok()?
+            .to_pixmap()
+            .ok()
+    }
+
+    /* The page is written to a temporary file first so that a running renderer never
+     * reads a half written page */
+    pub fn store(
+        &self,
+        page: usize,
+        precision: f64,
+        alpha: bool,
+        transform: &PageTransform,
+        pixmap: &Pixmap,
+    ) -> Result<(), String> {
+        std::fs::create_dir_all(&self.revision)
+            .map_err(|x| format!("Could not create cache directory: {}", x))?;
+
+        let path = self.page_path(page, precision, alpha, transform);
+        let tmp = path.with_extension("tmp");
+        pixmap
+            .save_as(tmp.to_str().unwrap(), ImageFormat::PNG)
+            .map_err(|x| format!("Could not write page {} to cache: {}", page, x))?;
+        std::fs::rename(&tmp, &path)
+            .map_err(|x| format!("Could not move page {} into cache: {}", page, x))?;
+
+        Ok(())
+    }
+
+    pub fn contains(
+        &self,
+        page: usize,
+        precision: f64,
+        alpha: bool,
+        transform: &PageTransform,
+    ) -> bool {
+        self.page_path(page, precision, alpha, transform).exists()
+    }
+
+    /* Removes the renders of older revisions of the document */
+    pub fn prune(&self) {
+        let entries = match std::fs::read_dir(&self.document) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            if entry.path() != self.revision {
+                let _ = std::fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+}
+
+/* The checksum of everything that a page is rendered with */
+pub fn render_key(inputs: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    inputs.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn copy_pixmap(pixmap: &Pixmap) -> Result<Pixmap, String> {
+    let mut copy = Pixmap::new_with_w_h(
+        &Colorspace::device_rgb(),
+        pixmap.width() as i32,
+        pixmap.height() as i32,
+        pixmap.n() == 4,
+    )
+    .map_err(|x| format!("Could not allocate page pixmap: {}", x))?;
+    copy.samples_mut().copy_from_slice(pixmap.samples());
+    Ok(copy)
+}
+
+/* Rendered pages kept in memory by the checksum of their render inputs, so that
+ * flipping a toggle back reuses the pages rendered before it instead of rendering
+ * them again. The oldest pages are dropped once the limit in bytes is reached */
+pub struct RenderCache {
+    limit: usize,
+    used: usize,
+    pages: HashMap<u64, Pixmap>,
+    order: VecDeque<u64>,
+}
+
+impl RenderCache {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: 0,
+            pages: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&self, key: u64) -> Option<&Pixmap> {
+        self.pages.get(&key)
+    }
+
+    /* Pages larger than the limit are not kept */
+    pub fn insert(&mut self, key: u64, pixmap: Pixmap) {
+        let size = pixmap.samples().len();
+        if size > self.limit || self.pages.contains_key(&key) {
+            return;
+        }
+
+        while self.used + size > self.limit {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(page) = self.pages.remove(&oldest) {
+                self.used -= page.samples().len();
+            }
+        }
+        self.used += size;
+        self.pages.insert(key, pixmap);
+        self.order.push_back(key);
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.order.clear();
+        self.used = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixmap(width: i32) -> Pixmap {
+        Pixmap::new_with_w_h(&Colorspace::device_rgb(), width, 10, false).unwrap()
+    }
+
+    #[test]
+    fn test_render_cache() {
+        assert!(render_key(&(1, true, "a")) == render_key(&(1, true, "a")));
+        assert!(render_key(&(1, true, "a")) != render_key(&(1, false, "a")));
+
+        /* Room for two pages of 300 bytes */
+        let mut cache = RenderCache::new(600);
+        cache.insert(1, pixmap(10));
+        cache.insert(2, pixmap(10));
+        assert!(cache.get(1).is_some() && cache.get(2).is_some());
+        cache.insert(3, pixmap(10));
+        assert!(cache.get(1).is_none() && cache.get(3).is_some());
+        assert!(cache.used == 600);
+
+        cache.insert(4, pixmap(100));
+        assert!(cache.get(4).is_none() && cache.get(2).is_some());
+        let copy = copy_pixmap(cache.get(2).unwrap()).unwrap();
+        assert!(copy.samples() == cache.get(2).unwrap().samples());
+
+        cache.clear();
+        assert!(cache.get(2).is_none() && cache.used == 0);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    io::{stdout, IsTerminal},
+    sync::Mutex,
+};
+
+use crossterm::{
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode},
+};
+
+use crate::{
+    drivers::{
+        commands::{DisableMouseCapturePixels, EnableMouseCapturePixels},
+        graphics::{
+            in_tmux, terminal_graphics_probe_medium, terminal_graphics_test_support,
+            terminal_identity, terminal_query, TerminalQuirks, TransferMedium,
+            TERMINAL_QUIRKS,
+        },
+    },
+    threads, RECEIVER_GR, SOFTWARE_ID,
+};
+
+/* DECRQM for the pixel mouse reports, the kitty query for the pointer shapes that
+ * are used and XTGETTCAP for `Ms`, the capability of terminals that handle OSC 52 */
+const QUERIES: &[u8] = b"\x1B[?1016$p\x1B]22;?pointer,text\x1B\\\x1BP+q4d73\x1B\\";
+
+struct Probe {
+    name: String,
+    passed: bool,
+    details: String,
+}
+
+impl Probe {
+    fn new(name: &str, passed: bool, details: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            passed,
+            details: details.to_string(),
+        }
+    }
+}
+
+/* Whether the terminal knows the private mode, from its answer `ESC [ ? <mode> ; <n>
+ * $ y` to DECRQM. A value of 0 stands for an unknown mode and `None` for no answer */
+fn mode_supported(response: &[u8], mode: u32) -> Option<bool> {
+    let prefix = format!("\x1B[?{};", mode);
+    let start = response
+        .windows(prefix.len())
+        .position(|x| x == prefix.as_bytes())?
+        + prefix.len();
+    let answer = &response[start..];
+    let end = answer.windows(2).position(|x| x == b"$y")?;
+
+    let value: u32 = std::str::from_utf8(&answer[..end]).ok()?.parse().ok()?;
+    Some(value != 0)
+}
+
+/* Whether the answer `ESC ] 22 ; <1|0>,.. ESC \` to the pointer shape query marks
+ * every queried shape as supported */
+fn pointer_shapes_supported(response: &[u8]) -> bool {
+    const PREFIX: &[u8] = b"\x1B]22;";
+    let Some(start) = response.windows(PREFIX.len()).position(|x| x == PREFIX) else {
+        return false;
+    };
+    let answer = &response[start + PREFIX.len()..];
+    let end = answer
+        .iter()
+        .position(|x| *x == 0x1B || *x == 0x07)
+        .unwrap_or(answer.len());
+
+    let answer = String::from_utf8_lossy(&answer[..end]);
+    !answer.is_empty() && answer.split(',').all(|x| x == "1")
+}
+
+/* Whether XTGETTCAP has been answered with `ESC P 1 + r`, a known capability */
+fn capability_advertised(response: &[u8]) -> bool {
+    const PREFIX: &[u8] = b"\x1BP1+r";
+    response.windows(PREFIX.len()).any(|x| x == PREFIX)
+}
+
+fn table(probes: &[Probe]) -> String {
+    let width = probes.iter().map(|x| x.name.len()).max().unwrap_or(0);
+
+    let mut lines = Vec::new();
+    for probe in probes {
+        let result = if probe.passed { "PASS" } else { "FAIL" };
+        let line = format!("{:<width$}  {}  {}", probe.name, result, probe.details);
+        lines.push(line.trim_end().to_string());
+    }
+    lines.join("\n")
+}
+
+fn run_probes(quirks: impl Fn(&str) -> TerminalQuirks, probes: &mut Vec<Probe>) {
+    /* The probes read the answers of the terminal without the event thread first */
+    let identity = terminal_identity();
+    TERMINAL_QUIRKS.get_or_init(|| quirks(&identity));
+    probes.push(Probe::new("Terminal", true, &identity));
+
+    execute!(stdout(), EnableMouseCapturePixels).ok();
+    let response = terminal_query(QUERIES).unwrap_or_default();
+    execute!(stdout(), DisableMouseCapturePixels).ok();
+
+    probes.push(match mode_supported(&response, 1016) {
+        Some(true) => Probe::new("Mouse pixel reporting", true, "SGR-Pixels (1016)"),
+        Some(false) => Probe::new("Mouse pixel reporting", false, "Mode 1016 is unknown"),
+        None => Probe::new("Mouse pixel reporting", false, "No answer to DECRQM"),
+    });
+    probes.push(match pointer_shapes_supported(&response) {
+        true => Probe::new("Pointer shape (OSC 22)", true, "pointer, text"),
+        false => Probe::new("Pointer shape (OSC 22)", false, "No answer to the query"),
+    });
+    probes.push(match capability_advertised(&response) {
+        true => Probe::new("Clipboard (OSC 52)", true, "Advertised through XTGETTCAP"),
+        false => Probe::new(
+            "Clipboard (OSC 52)",
+            false,
+            "Not advertised, `wl-copy` or `xclip` are used when installed",
+        ),
+    });
+
+    /* The graphics responses come through the event thread */
+    let event_inputs = threads::event::spawn(None, Vec::new(), true);
+    RECEIVER_GR.get_or_init(|| Mutex::new(event_inputs.graphics));
+
+    if let Err(x) = terminal_graphics_test_support() {
+        probes.push(Probe::new("Kitty graphics protocol", false, &x));
+        return;
+    }
+    probes.push(Probe::new("Kitty graphics protocol", true, ""));
+
+    for medium in TransferMedium::available() {
+        let name = format!("Transfer medium {:?}", medium);
+        /* The answers to the transfers do not come back through tmux */
+        if in_tmux() {
+            probes.push(Probe::new(&name, false, "Can not be probed inside of tmux"));
+            continue;
+        }
+        probes.push(match terminal_graphics_probe_medium(medium) {
+            Ok(true) => Probe::new(&name, true, ""),
+            Ok(false) => Probe::new(&name, false, "Rejected by the terminal"),
+            Err(x) => Probe::new(&name, false, &x),
+        });
+    }
+}
+
+/* Runs every probe of the startup outside of the alternate screen and prints a table
+ * of the results. Returns whether every probe has passed */
+pub fn check(quirks: impl Fn(&str) -> TerminalQuirks) -> Result<bool, String> {
+    if !stdout().is_terminal() {
+        return Err("`--check` has to be run in a terminal".to_string());
+    }
+    SOFTWARE_ID.get_or_init(|| format!("check{:X}", std::process::id()));
+
+    let mut probes = Vec::new();
+    match enable_raw_mode() {
+        Ok(_) => {
+            probes.push(Probe::new("Raw mode", true, ""));
+            run_probes(quirks, &mut probes);
+            disable_raw_mode()
+                .map_err(|x| format!("Could not cook the terminal: {}", x))?;
+        }
+        Err(x) => probes.push(Probe::new("Raw mode", false, &x.to_string())),
+    }
+
+    println!("{}", table(&probes));
+    Ok(probes.iter().all(|x| x.passed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answers() {
+        let response = b"\x1B[?1016;2$y\x1B]22;1,1\x1B\\\x1BP1+r4d73=1B\x1B\\\x1B[?62;4c";
+        assert!(mode_supported(response, 1016) == Some(true));
+        assert!(mode_supported(b"\x1B[?1016;0$y", 1016) == Some(false));
+        assert!(mode_supported(b"\x1B[?62;4c", 1016).is_none());
+
+        assert!(pointer_shapes_supported(response));
+        assert!(!pointer_shapes_supported(b"\x1B]22;1,0\x1B\\"));
+        assert!(capability_advertised(response));
+        assert!(!capability_advertised(b"\x1BP0+r4d73\x1B\\"));
+
+        let probes = [
+            Probe::new("Raw mode", true, ""),
+            Probe::new("Clipboard", false, "No"),
+        ];
+        assert!(table(&probes) == "Raw mode   PASS\nClipboard  FAIL  No");
+    }
+}
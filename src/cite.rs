@@ -0,0 +1,215 @@
+use mupdf::MetadataName;
+
+use crate::{
+    config::ConfigCitationStyle,
+    document::open_document,
+    format::parse_pdf_date,
+    text::{find_identifiers, page_paragraphs},
+    CONFIG,
+};
+
+/* How many of the first pages are searched for a DOI when the metadata has none */
+const DOI_PAGES: i32 = 2;
+
+/* What is known about a document to cite it */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Citation {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub year: Option<i64>,
+    pub doi: Option<String>,
+}
+
+/* The first DOI in the text without its resolver */
+fn find_doi(text: &str) -> Option<String> {
+    find_identifiers(text).into_iter().find_map(|x| {
+        x.uri
+            .strip_prefix("https://doi.org/")
+            .map(|x| x.to_string())
+    })
+}
+
+/* Splits the author field of the metadata into its authors. Commas only separate
+ * authors when every part has a first and a last name, since `Doe, Jane` is a
+ * single author */
+fn split_authors(field: &str) -> Vec<String> {
+    let field = field.replace(" and ", ";").replace('&', ";");
+    let parts: Vec<&str> = match field.contains(';') {
+        true => field.split(';').collect(),
+        false => {
+            let parts: Vec<&str> = field.split(',').collect();
+            match parts.iter().all(|x| x.trim().contains(' ')) {
+                true => parts,
+                false => vec![field.as_str()],
+            }
+        }
+    };
+
+    parts
+        .iter()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+/* Reads the citation out of the metadata of the document. The DOI is looked for in
+ * the subject and then in the text of the first pages, where papers print it */
+pub fn find_citation(file: &str) -> Result<Citation, String> {
+    let document = open_document(file)?;
+    let metadata = |name| {
+        document
+            .metadata(name)
+            .map(|x| x.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    let mut doi = find_doi(&metadata(MetadataName::Subject));
+    let pages = document
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?;
+    for i in 0..i32::min(pages, DOI_PAGES) {
+        if doi.is_some() {
+            break;
+        }
+        let Ok(page) = document.load_page(i) else {
+            continue;
+        };
+        doi = page_paragraphs(&page)
+            .unwrap_or_default()
+            .iter()
+            .find_map(|x| find_doi(x));
+    }
+
+    /* Documents without a title are cited by the name of their file */
+    let title = match metadata(MetadataName::Title) {
+        x if x.is_empty() => std::path::Path::new(file)
+            .file_stem()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        x => x,
+    };
+
+    Ok(Citation {
+        title,
+        authors: split_authors(&metadata(MetadataName::Author)),
+        year: parse_pdf_date(&metadata(MetadataName::CreationDate)).map(|x| x.year),
+        doi,
+    })
+}
+
+/* The key of the BibTeX entry made of the last name of the first author, the year
+ * and the first longer word of the title, such as `doe2020attention` */
+fn citation_key(citation: &Citation) -> String {
+    let word = |x: &str| -> String {
+        x.chars()
+            .filter(|x| x.is_alphanumeric())
+            .flat_map(|x| x.to_lowercase())
+            .collect()
+    };
+
+    let name =
+        citation
+            .authors
+            .first()
+            .map_or(String::new(), |x| match x.split_once(',') {
+                Some((last, _)) => word(last),
+                None => word(x.split_whitespace().last().unwrap_or_default()),
+            });
+    let year = citation.year.map(|x| x.to_string()).unwrap_or_default();
+    let title = citation
+        .title
+        .split_whitespace()
+        .map(word)
+        .find(|x| x.chars().count() > 3)
+        .unwrap_or_default();
+
+    match format!("{}{}{}", name, year, title) {
+        x if x.is_empty() => "document".to_string(),
+        x => x,
+    }
+}
+
+pub fn format_bibtex(citation: &Citation) -> String {
+    let fields = [
+        ("title", citation.title.clone()),
+        ("author", citation.authors.join(" and ")),
+        (
+            "year",
+            citation.year.map(|x| x.to_string()).unwrap_or_default(),
+        ),
+        ("doi", citation.doi.clone().unwrap_or_default()),
+    ];
+
+    let mut entry = format!("@misc{{{},\n", citation_key(citation));
+    for (name, value) in fields.iter().filter(|x| !x.1.is_empty()) {
+        entry.push_str(&format!("  {} = {{{}}},\n", name, value));
+    }
+    entry.push('}');
+    entry
+}
+
+/* Replaces `%title`, `%author`, `%year` and `%doi` in the template. Fields that are
+ * not known are left empty */
+pub fn format_plain(template: &str, citation: &Citation) -> String {
+    template
+        .replace("%title", &citation.title)
+        .replace("%author", &citation.authors.join(", "))
+        .replace(
+            "%year",
+            &citation.year.map(|x| x.to_string()).unwrap_or_default(),
+        )
+        .replace("%doi", citation.doi.as_deref().unwrap_or_default())
+        .trim()
+        .to_string()
+}
+
+/* The citation of the document in the configured style */
+pub fn cite(file: &str) -> Result<String, String> {
+    let config = &CONFIG.get().unwrap().viewer.citation;
+    let citation = find_citation(file)?;
+
+    Ok(match config.style {
+        ConfigCitationStyle::BibTeX => format_bibtex(&citation),
+        ConfigCitationStyle::Plain => format_plain(&config.template, &citation),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_citation() {
+        assert!(split_authors("Jane Doe, John Smith") == ["Jane Doe", "John Smith"]);
+        assert!(split_authors("Doe, Jane") == ["Doe, Jane"]);
+        assert!(split_authors("Doe, J. and Smith, J.") == ["Doe, J.", "Smith, J."]);
+        assert!(split_authors(" ").is_empty());
+        assert!(
+            find_doi("DOI 10.1145/3290605.3300233.")
+                == Some("10.1145/3290605.3300233".to_string())
+        );
+
+        let citation = Citation {
+            title: "On the Attention of Cats".to_string(),
+            authors: vec!["Jane Doe".to_string(), "John Smith".to_string()],
+            year: Some(2020),
+            doi: Some("10.1000/182".to_string()),
+        };
+        assert!(citation_key(&citation) == "doe2020attention");
+        assert!(
+            format_bibtex(&citation)
+                == "@misc{doe2020attention,\n  title = {On the Attention of Cats},\n  \
+                    author = {Jane Doe and John Smith},\n  year = {2020},\n  \
+                    doi = {10.1000/182},\n}"
+        );
+        assert!(
+            format_plain("%author (%year). %title. %doi", &citation)
+                == "Jane Doe, John Smith (2020). On the Attention of Cats. 10.1000/182"
+        );
+
+        let citation = Citation::default();
+        assert!(citation_key(&citation) == "document");
+        assert!(format_plain("%title %doi", &citation).is_empty());
+    }
+}
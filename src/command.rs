@@ -0,0 +1,662 @@
+use std::io::{self, Write};
+
+use crossterm::{
+    cursor::{MoveTo, RestorePosition, SavePosition},
+    event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+};
+
+use keybinds::KeySeq;
+use serde::{
+    de::{value::StrDeserializer, IntoDeserializer},
+    Deserialize,
+};
+
+use crate::{
+    annotate::AnnotationKind,
+    config::ConfigAction,
+    export::parse_permissions,
+    filters::{ColorDeficiency, Daltonize},
+    CONFIG, TERMINAL_SIZE,
+};
+
+/* Commands entered in the command line opened with `:` */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /* Jumps to the page with the given number counted from one */
+    GoTo(usize),
+    /* Stores every page in the disk cache at the precision of the current zoom */
+    Prerender,
+    /* Corrects or simulates a color deficiency, `None` turns the filter off */
+    ColorBlind(Option<Daltonize>),
+    /* Lists the embedded images of a page, the current one if no page is given, or
+     * saves the selected ones. Pages and images are counted from one */
+    Images {
+        page: Option<usize>,
+        selection: Vec<usize>,
+    },
+    /* Shows the reading statistics of the document */
+    Stats,
+    /* Renders the page counted from one again after it has failed, every failed
+     * page if no page is given */
+    Retry(Option<usize>),
+    /* Shows the metadata of the document */
+    Info,
+    /* Binds the key sequence to the action in place of its current binding. `save`
+     * writes the binding into the config file as well */
+    Bind {
+        keys: String,
+        action: ConfigAction,
+        save: bool,
+    },
+    /* Writes the document with its annotations drawn into the pages to a PDF */
+    ExportFlat(String),
+    /* Removes the binding of the key sequence */
+    Unbind {
+        keys: String,
+        save: bool,
+    },
+    /* Saves a copy of the document with the configured watermark on the pages, all
+     * of them if no range is given. Pages are counted from one */
+    Stamp {
+        out: String,
+        pages: Option<(usize, usize)>,
+    },
+    Organize(OrganizeEdit),
+    /* Inserts the pages of another PDF into the document, behind the page counted
+     * from one or at the end if no page is given. 0 inserts them in front */
+    Append {
+        file: String,
+        after: Option<usize>,
+    },
+    Outline(OutlineEdit),
+    /* Saves a copy of the document, encrypted with the given permissions after the
+     * passwords have been entered */
+    Write {
+        out: String,
+        encrypt: Option<u32>,
+    },
+    Note(NoteEdit),
+    /* Lists the notes of the document */
+    Notes,
+    /* Annotates the selected text */
+    Annotate(AnnotationKind),
+    /* Drops the last annotation that has not been written */
+    UndoAnnotation,
+    /* Writes the annotations into the document once it has been confirmed */
+    WriteAnnotations,
+}
+
+/* Edits of the note of the current page */
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteEdit {
+    /* Opens the note in `$VISUAL` or `$EDITOR` */
+    Edit,
+    Set(String),
+    Delete,
+}
+
+/* Edits of the outline, whose entries are counted from one */
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutlineEdit {
+    /* Shows the numbered entries */
+    Show,
+    /* Adds an entry with the title pointing at the current position */
+    Add(String),
+    Rename(usize, String),
+    Remove(usize),
+    /* Writes the entries back into the document */
+    Save,
+}
+
+/* Edits of the page organizer, applied to the marked pages or to the current page */
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrganizeEdit {
+    /* Shows the organized pages */
+    Show,
+    Delete,
+    /* Degrees that are added to the rotation */
+    Rotate(i32),
+    /* Moves behind the page counted from one, in front of the first page for 0 */
+    Move(usize),
+    /* Restores the pages of the document */
+    Reset,
+    Save(String),
+}
+
+/* Splits a command into its words, words in double quotes are kept together */
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+
+    for x in line.chars() {
+        match x {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            x if x.is_whitespace() && !quoted => words.extend(word.take()),
+            x => word.get_or_insert_with(String::new).push(x),
+        }
+    }
+    if quoted {
+        return Err("Unterminated quote".to_string());
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+fn parse_keys(keys: &str) -> Result<String, String> {
+    keys.parse::<KeySeq>()
+        .map_err(|x| format!("Invalid keys `{}`: {}", keys, x))?;
+    Ok(keys.to_string())
+}
+
+pub fn parse_action(action: &str) -> Result<ConfigAction, String> {
+    let deserializer: StrDeserializer<serde::de::value::Error> =
+        action.into_deserializer();
+    ConfigAction::deserialize(deserializer)
+        .map_err(|_| format!("Unknown action `{}`", action))
+}
+
+/* Parses `<page>` or `<first>-<last>` */
+pub fn parse_range(range: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("Invalid range `{}`", range);
+    let (first, last) = range.split_once('-').unwrap_or((range, range));
+    let first: usize = first.parse().map_err(|_| invalid())?;
+    let last: usize = last.parse().map_err(|_| invalid())?;
+    if first == 0 || first > last {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+fn parse_entry(number: &str) -> Result<usize, String> {
+    match number.parse::<usize>() {
+        Ok(0) | Err(_) => Err(format!("Invalid entry `{}`", number)),
+        Ok(x) => Ok(x),
+    }
+}
+
+/* Printing and the accessibility tools are allowed unless other permissions are given */
+const DEFAULT_PERMISSIONS: u32 = (1 << 2) | (1 << 9) | (1 << 11);
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let words = split_words(line)?;
+    let mut words = words.iter().map(String::as_str);
+    let name = words.next().ok_or("No command given")?;
+    let args: Vec<&str> = words.collect();
+
+    match (name, args.as_slice()) {
+        (number, []) if number.chars().all(|x| x.is_ascii_digit()) => {
+            match number.parse::<usize>() {
+                Ok(0) | Err(_) => Err(format!("Invalid page `{}`", number)),
+                Ok(x) => Ok(Command::GoTo(x)),
+            }
+        }
+        ("prerender", []) => Ok(Command::Prerender),
+        ("prerender", _) => Err("Usage: prerender".to_string()),
+        ("colorblind", ["off"]) => Ok(Command::ColorBlind(None)),
+        ("colorblind", [deficiency]) => Ok(Command::ColorBlind(Some(Daltonize {
+            deficiency: ColorDeficiency::parse(deficiency)?,
+            simulate: false,
+        }))),
+        ("colorblind", [deficiency, "simulate"]) => {
+            Ok(Command::ColorBlind(Some(Daltonize {
+                deficiency: ColorDeficiency::parse(deficiency)?,
+                simulate: true,
+            })))
+        }
+        ("images", args) => {
+            let numbers = args
+                .iter()
+                .map(|x| match x.parse::<usize>() {
+                    Ok(0) | Err(_) => Err(format!("Invalid number `{}`", x)),
+                    Ok(x) => Ok(x),
+                })
+                .collect::<Result<Vec<usize>, String>>()?;
+
+            Ok(Command::Images {
+                page: numbers.first().copied(),
+                selection: numbers.iter().skip(1).copied().collect(),
+            })
+        }
+        ("stats", []) => Ok(Command::Stats),
+        ("retry", []) => Ok(Command::Retry(None)),
+        ("retry", [page]) => match page.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("Invalid page `{}`", page)),
+            Ok(x) => Ok(Command::Retry(Some(x))),
+        },
+        ("retry", _) => Err("Usage: retry [page]".to_string()),
+        ("info", []) => Ok(Command::Info),
+        ("note", []) => Ok(Command::Note(NoteEdit::Edit)),
+        ("note", ["--delete"]) => Ok(Command::Note(NoteEdit::Delete)),
+        ("note", text) => Ok(Command::Note(NoteEdit::Set(text.join(" ")))),
+        ("notes", []) => Ok(Command::Notes),
+        ("bind" | "bind!", [keys, action]) => Ok(Command::Bind {
+            keys: parse_keys(keys)?,
+            action: parse_action(action)?,
+            save: name.ends_with('!'),
+        }),
+        ("bind" | "bind!", _) => Err("Usage: bind[!] <keys> <action>".to_string()),
+        ("unbind" | "unbind!", [keys]) => Ok(Command::Unbind {
+            keys: parse_keys(keys)?,
+            save: name.ends_with('!'),
+        }),
+        ("unbind" | "unbind!", _) => Err("Usage: unbind[!] <keys>".to_string()),
+        ("export-flat", [path]) => Ok(Command::ExportFlat(path.to_string())),
+        ("export-flat", _) => Err("Usage: export-flat <file>".to_string()),
+        ("stamp", [out]) => Ok(Command::Stamp {
+            out: out.to_string(),
+            pages: None,
+        }),
+        ("stamp", [out, range]) => Ok(Command::Stamp {
+            out: out.to_string(),
+            pages: Some(parse_range(range)?),
+        }),
+        ("stamp", _) => Err("Usage: stamp <file> [first[-last]]".to_string()),
+        ("organize", []) => Ok(Command::Organize(OrganizeEdit::Show)),
+        ("organize", ["delete"]) => Ok(Command::Organize(OrganizeEdit::Delete)),
+        ("organize", ["rotate", degrees]) => match degrees.parse::<i32>() {
+            Ok(x) if x % 90 == 0 => Ok(Command::Organize(OrganizeEdit::Rotate(x))),
+            _ => Err(format!("Invalid rotation `{}`", degrees)),
+        },
+        ("organize", ["move", page]) => match page.parse::<usize>() {
+            Ok(x) => Ok(Command::Organize(OrganizeEdit::Move(x))),
+            Err(_) => Err(format!("Invalid page `{}`", page)),
+        },
+        ("organize", ["reset"]) => Ok(Command::Organize(OrganizeEdit::Reset)),
+        ("organize", ["save", out]) => {
+            Ok(Command::Organize(OrganizeEdit::Save(out.to_string())))
+        }
+        ("append", [file]) => Ok(Command::Append {
+            file: file.to_string(),
+            after: None,
+        }),
+        ("append", [file, page]) => match page.parse::<usize>() {
+            Ok(x) => Ok(Command::Append {
+                file: file.to_string(),
+                after: Some(x),
+            }),
+            Err(_) => Err(format!("Invalid page `{}`", page)),
+        },
+        ("append", _) => Err("Usage: append <file> [after-page]".to_string()),
+        ("highlight", []) => Ok(Command::Annotate(AnnotationKind::Highlight)),
+        ("underline", []) => Ok(Command::Annotate(AnnotationKind::Underline)),
+        ("undo", []) => Ok(Command::UndoAnnotation),
+        ("w", []) => Ok(Command::WriteAnnotations),
+        ("w", [out]) if *out != "--encrypt" => Ok(Command::Write {
+            out: out.to_string(),
+            encrypt: None,
+        }),
+        ("w", ["--encrypt", out]) => Ok(Command::Write {
+            out: out.to_string(),
+            encrypt: Some(DEFAULT_PERMISSIONS),
+        }),
+        ("w", ["--encrypt", out, permissions]) => Ok(Command::Write {
+            out: out.to_string(),
+            encrypt: Some(parse_permissions(permissions)?),
+        }),
+        ("w", _) => Err("Usage: w [[--encrypt] <file> [permissions]]".to_string()),
+        ("outline", []) => Ok(Command::Outline(OutlineEdit::Show)),
+        ("outline", ["add", title @ ..]) if !title.is_empty() => {
+            Ok(Command::Outline(OutlineEdit::Add(title.join(" "))))
+        }
+        ("outline", ["rename", number, title @ ..]) if !title.is_empty() => Ok(
+            Command::Outline(OutlineEdit::Rename(parse_entry(number)?, title.join(" "))),
+        ),
+        ("outline", ["remove", number]) => {
+            Ok(Command::Outline(OutlineEdit::Remove(parse_entry(number)?)))
+        }
+        ("outline", ["save"]) => Ok(Command::Outline(OutlineEdit::Save)),
+        ("outline", _) => Err(
+            "Usage: outline [add <title>|rename <entry> <title>|remove <entry>|save]"
+                .to_string(),
+        ),
+        ("organize", _) => Err(
+            "Usage: organize [delete|rotate <degrees>|move <page>|reset|save <file>]"
+                .to_string(),
+        ),
+        ("colorblind", _) => {
+            Err("Usage: colorblind off|<deficiency> [simulate]".to_string())
+        }
+        _ => Err(format!("Unknown command `{}`", name)),
+    }
+}
+
+/* What a key does to the page number typed outside of the command line */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CountInput {
+    /* The number typed so far */
+    Typing(usize),
+    /* Enter has been pressed after the number */
+    Submit(usize),
+    /* Esc has dropped the number */
+    Cancel,
+    /* The key is not a part of the number, which is dropped, and is handled as usual */
+    Pass,
+}
+
+/* Feeds a key into the typed number, `None` before the first digit. Numbers do not
+ * start with 0 so that it stays free for bindings */
+pub fn count_input(count: Option<usize>, key: &KeyEvent) -> CountInput {
+    if !(key.modifiers - KeyModifiers::SHIFT).is_empty() {
+        return CountInput::Pass;
+    }
+
+    match (count, key.code) {
+        (None, KeyCode::Char('0')) => CountInput::Pass,
+        (_, KeyCode::Char(x @ '0'..='9')) => {
+            let digit = x.to_digit(10).unwrap() as usize;
+            let count = count.unwrap_or(0);
+            CountInput::Typing(count.saturating_mul(10).saturating_add(digit))
+        }
+        (Some(count), KeyCode::Backspace) if count >= 10 => {
+            CountInput::Typing(count / 10)
+        }
+        (Some(_), KeyCode::Backspace | KeyCode::Esc) => CountInput::Cancel,
+        (Some(count), KeyCode::Enter) => CountInput::Submit(count),
+        _ => CountInput::Pass,
+    }
+}
+
+/* The line at the bottom of the terminal where commands and search queries are
+ * typed. It also shows the errors of executed commands until the next key is
+ * pressed */
+#[derive(Default)]
+pub struct CommandLine {
+    /* `:` for commands, `/` for search queries, `*` for passwords and `?` for
+     * confirmations */
+    prompt: char,
+    /* Shown in place of the prompt for passwords, whose input is hidden, and for
+     * confirmations */
+    label: Option<String>,
+    input: Option<String>,
+    message: Option<String>,
+    /* Lines shown in a box in the middle of the terminal, closed like messages */
+    overlay: Option<Vec<String>>,
+}
+
+impl CommandLine {
+    pub fn open(&mut self, prompt: char) {
+        self.prompt = prompt;
+        self.label = None;
+        self.input = Some(String::new());
+        self.message = None;
+    }
+
+    /* Asks for a password behind the label */
+    pub fn open_secret(&mut self, label: &str) {
+        self.open('*');
+        self.label = Some(label.to_string());
+    }
+
+    /* Asks for a confirmation behind the label */
+    pub fn open_question(&mut self, label: &str) {
+        self.open('?');
+        self.label = Some(label.to_string());
+    }
+
+    pub fn prompt(&self) -> char {
+        self.prompt
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    pub fn set_overlay(&mut self, lines: Vec<String>) {
+        self.overlay = Some(lines);
+    }
+
+    /* Edits the typed command. Returns the command once it has been submitted */
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<String> {
+        /* The message of the action a key triggered stays when the key is released */
+        if key.kind == KeyEventKind::Release {
+            return None;
+        }
+        self.message = None;
+        self.overlay = None;
+
+        let input = self.input.as_mut()?;
+        match key.code {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                if input.pop().is_none() {
+                    self.input = None;
+                }
+            }
+            KeyCode::Enter => return self.input.take(),
+            KeyCode::Esc => self.input = None,
+            _ => (),
+        }
+
+        None
+    }
+
+    pub fn display(&self) {
+        self.display_overlay();
+
+        let text = match (&self.input, &self.message) {
+            (Some(input), _) if self.label.is_some() => {
+                let label = self.label.as_deref().unwrap_or_default();
+                match self.prompt {
+                    '*' => format!("{}{}", label, "*".repeat(input.chars().count())),
+                    _ => format!("{}{}", label, input),
+                }
+            }
+            (Some(input), _) => format!("{}{}", self.prompt, input),
+            (None, Some(message)) => message.clone(),
+            (None, None) => return,
+        };
+
+        let config = CONFIG.get().unwrap();
+        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+
+        execute!(
+            io::stdout(),
+            SavePosition,
+            MoveTo(0, terminal_size.rows),
+            SetBackgroundColor(config.viewer.uri_hint.background),
+            SetForegroundColor(config.viewer.uri_hint.foreground),
+            Print(text),
+            ResetColor,
+            RestorePosition
+        )
+        .unwrap();
+    }
+
+    fn display_overlay(&self) {
+        let Some(lines) = &self.overlay else {
+            return;
+        };
+
+        let config = CONFIG.get().unwrap();
+        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+
+        /* Every line is padded to the longest one with a margin of one cell */
+        let width = lines.iter().map(|x| x.chars().count()).max().unwrap_or(0) + 2;
+        let column = (terminal_size.columns as usize).saturating_sub(width) / 2;
+        let row = (terminal_size.rows as usize).saturating_sub(lines.len()) / 2;
+
+        let mut handle = io::stdout().lock();
+        queue!(
+            handle,
+            SavePosition,
+            SetBackgroundColor(config.viewer.uri_hint.background),
+            SetForegroundColor(config.viewer.uri_hint.foreground)
+        )
+        .unwrap();
+        for (i, line) in lines.iter().enumerate() {
+            queue!(
+                handle,
+                MoveTo(column as u16, (row + i) as u16),
+                Print(format!(" {:<1$} ", line, width - 2))
+            )
+            .unwrap();
+        }
+        queue!(handle, ResetColor, RestorePosition).unwrap();
+        handle.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_input() {
+        let key = |x| KeyEvent::new(x, KeyModifiers::NONE);
+        let digit = |x| key(KeyCode::Char(x));
+
+        assert!(count_input(None, &digit('1')) == CountInput::Typing(1));
+        assert!(count_input(Some(12), &digit('3')) == CountInput::Typing(123));
+        assert!(count_input(None, &digit('0')) == CountInput::Pass);
+        assert!(count_input(Some(1), &digit('0')) == CountInput::Typing(10));
+        assert!(count_input(Some(123), &key(KeyCode::Enter)) == CountInput::Submit(123));
+        assert!(count_input(None, &key(KeyCode::Enter)) == CountInput::Pass);
+        assert!(
+            count_input(Some(123), &key(KeyCode::Backspace)) == CountInput::Typing(12)
+        );
+        assert!(count_input(Some(1), &key(KeyCode::Backspace)) == CountInput::Cancel);
+        assert!(count_input(Some(1), &key(KeyCode::Esc)) == CountInput::Cancel);
+        assert!(count_input(Some(1), &digit('j')) == CountInput::Pass);
+        assert!(
+            count_input(
+                None,
+                &KeyEvent::new(KeyCode::Char('1'), KeyModifiers::CONTROL)
+            ) == CountInput::Pass
+        );
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert!(parse_command(" prerender ") == Ok(Command::Prerender));
+        assert!(parse_command("prerender 2.5").is_err());
+        assert!(parse_command("colorblind off") == Ok(Command::ColorBlind(None)));
+        assert!(
+            parse_command("colorblind tritanopia simulate")
+                == Ok(Command::ColorBlind(Some(Daltonize {
+                    deficiency: ColorDeficiency::Tritanopia,
+                    simulate: true,
+                })))
+        );
+        assert!(parse_command("colorblind achromatopsia").is_err());
+        assert!(
+            parse_command("images")
+                == Ok(Command::Images {
+                    page: None,
+                    selection: Vec::new()
+                })
+        );
+        assert!(
+            parse_command("images 3 1 2")
+                == Ok(Command::Images {
+                    page: Some(3),
+                    selection: vec![1, 2]
+                })
+        );
+        assert!(parse_command("images 0").is_err());
+        assert!(parse_command("42") == Ok(Command::GoTo(42)));
+        assert!(parse_command("0").is_err());
+        assert!(parse_command("42 1").is_err());
+        assert!(parse_command("stats") == Ok(Command::Stats));
+        assert!(parse_command("info") == Ok(Command::Info));
+        assert!(parse_command("stats 1").is_err());
+        assert!(parse_command("retry") == Ok(Command::Retry(None)));
+        assert!(parse_command("retry 3") == Ok(Command::Retry(Some(3))));
+        assert!(parse_command("retry 0").is_err());
+        assert!(parse_command("note") == Ok(Command::Note(NoteEdit::Edit)));
+        assert!(
+            parse_command("note  see  \"page 9\"")
+                == Ok(Command::Note(NoteEdit::Set("see page 9".to_string())))
+        );
+        assert!(parse_command("note --delete") == Ok(Command::Note(NoteEdit::Delete)));
+        assert!(parse_command("notes") == Ok(Command::Notes));
+        assert!(
+            parse_command("bind! \"Ctrl+x\" ToggleAlpha")
+                == Ok(Command::Bind {
+                    keys: "Ctrl+x".to_string(),
+                    action: ConfigAction::ToggleAlpha,
+                    save: true,
+                })
+        );
+        assert!(
+            parse_command("unbind \"g g\"")
+                == Ok(Command::Unbind {
+                    keys: "g g".to_string(),
+                    save: false,
+                })
+        );
+        assert!(parse_command("bind x Fly").is_err());
+        assert!(
+            parse_command("export-flat \"a b.pdf\"")
+                == Ok(Command::ExportFlat("a b.pdf".to_string()))
+        );
+        assert!(
+            parse_command("stamp out.pdf 2-5")
+                == Ok(Command::Stamp {
+                    out: "out.pdf".to_string(),
+                    pages: Some((2, 5)),
+                })
+        );
+        assert!(
+            parse_command("stamp out.pdf 3")
+                == Ok(Command::Stamp {
+                    out: "out.pdf".to_string(),
+                    pages: Some((3, 3)),
+                })
+        );
+        assert!(parse_command("stamp out.pdf 5-2").is_err());
+        assert!(
+            parse_command("organize rotate -90")
+                == Ok(Command::Organize(OrganizeEdit::Rotate(-90)))
+        );
+        assert!(parse_command("organize rotate 45").is_err());
+        assert!(
+            parse_command("append scan.pdf 0")
+                == Ok(Command::Append {
+                    file: "scan.pdf".to_string(),
+                    after: Some(0),
+                })
+        );
+        assert!(parse_command("append scan.pdf last").is_err());
+        assert!(
+            parse_command("outline rename 2 Part  Two")
+                == Ok(Command::Outline(OutlineEdit::Rename(
+                    2,
+                    "Part Two".to_string()
+                )))
+        );
+        assert!(parse_command("outline add").is_err());
+        assert!(
+            parse_command("w --encrypt out.pdf print,form")
+                == Ok(Command::Write {
+                    out: "out.pdf".to_string(),
+                    encrypt: Some(4 | 256),
+                })
+        );
+        assert!(parse_command("w --encrypt").is_err());
+        assert!(parse_command("w") == Ok(Command::WriteAnnotations));
+        assert!(
+            parse_command("underline")
+                == Ok(Command::Annotate(AnnotationKind::Underline))
+        );
+        assert!(parse_command("highlight all").is_err());
+        assert!(parse_command("outline remove 0").is_err());
+        assert!(
+            parse_command("organize move 0")
+                == Ok(Command::Organize(OrganizeEdit::Move(0)))
+        );
+        assert!(parse_command("bind \"x").is_err());
+        assert!(parse_command("unknown").is_err());
+        assert!(parse_command("").is_err());
+    }
+}
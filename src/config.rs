@@ -1,17 +1,26 @@
-use std::{collections::HashSet, mem::discriminant};
+use std::{
+    collections::HashSet, mem::discriminant, path::PathBuf, sync::atomic::Ordering,
+};
 
 use toml::{Table, Value};
 
 use crossterm::style::Color;
 use dirs::config_dir;
-use keybinds::Keybinds;
+use keybinds::{KeySeq, Keybinds};
 use serde::Deserialize;
 
-use crate::{CONFIG_FILENAME, DEFAULT_CONFIG};
+use crate::{
+    filters::PixelFilter,
+    globals::{CONFIG_FILE, CONFIG_FILENAME, DEFAULT_CONFIG, SAFE_MODE},
+    language::Language,
+};
 
+/** The config file of the user, or the built-in one, as read by
+ * `config_load_or_create` and `config_default` */
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub viewer: ConfigViewer,
+    pub bar: ConfigBar,
     pub bindings: Option<Keybinds<ConfigAction>>,
 }
 
@@ -19,28 +28,298 @@ pub struct Config {
 pub struct ConfigViewer {
     pub scroll_speed: f32,
     pub render_precision: f64,
+    pub zoom_rendering: bool,
+    pub zoom_precision_max: f64,
+    pub zoom_debounce: u64,
+    pub preview_precision: f64,
     pub memory_limit: usize,
+    pub eviction_policy: ConfigEvictionPolicy,
     pub scale_min: f32,
     pub scale_amount: f32,
     pub margin_bottom: f32,
     pub pages_preloaded: usize,
     pub inverse_scroll: bool,
+    pub reuse_instance: bool,
+    pub background_priority: bool,
+    pub isolate_renderer: bool,
+    pub sandbox_preflight: bool,
+    /* Bytes of displayed pages that the renderer keeps to reuse, 0 disables */
+    pub render_cache: usize,
+    pub queue_hint_threshold: usize,
+    /* Frames drawn per second at most, 0 for no limit */
+    pub max_fps: f32,
+    pub detect_identifiers: bool,
+    pub search_hyphenation: bool,
+    /* Tag of the language of the documents, detected per document when empty */
+    pub language: String,
+    pub plain_quotes: bool,
+    /* Resolution of the pages exported as PNG files */
+    pub export_dpi: f32,
+    /* Directory of the executables started as plugins, empty for none */
+    pub plugin_directory: String,
+    pub fallback: ConfigFallback,
+    pub end_of_document: ConfigEndOfDocument,
+    pub page_layout: ConfigPageLayout,
+    pub transfer_medium: ConfigTransferMedium,
+    pub precision_tiers: Vec<ConfigPrecisionTier>,
+    pub filters: Vec<PixelFilter>,
+    pub page_background: Vec<f32>,
+    pub page_tint: Vec<f32>,
 
     pub uri_hint: ConfigViewerUriHint,
+    pub quirks: ConfigViewerQuirks,
+    pub layout: ConfigViewerLayout,
+    pub format: ConfigViewerFormat,
+    pub stamp: ConfigViewerStamp,
+    pub citation: ConfigViewerCitation,
+    pub smooth_scroll: ConfigViewerSmoothScroll,
+    pub kinetic_scroll: ConfigViewerKineticScroll,
+    pub context: ConfigViewerContext,
 }
 
+/* Render precision used for scales up to and including `zoom` */
 #[derive(Debug, Deserialize)]
-pub struct ConfigViewerUriHint {
+pub struct ConfigPrecisionTier {
+    pub zoom: f32,
+    pub precision: f64,
+}
+
+impl ConfigViewer {
+    /* Picks the precision matching the scale with zoom rendering, or else the one of
+     * the smallest tier containing the scale. Scales above every tier are rendered at
+     * `render_precision` */
+    pub fn precision_at(&self, scale: f32) -> f64 {
+        if self.zoom_rendering {
+            return f64::min(scale as f64, self.zoom_precision_max);
+        }
+
+        self.precision_tiers
+            .iter()
+            .filter(|x| scale <= x.zoom)
+            .min_by(|a, b| a.zoom.total_cmp(&b.zoom))
+            .map(|x| x.precision)
+            .unwrap_or(self.render_precision)
+    }
+
+    pub fn precision_max(&self) -> f64 {
+        if self.zoom_rendering {
+            return self.zoom_precision_max;
+        }
+
+        self.precision_tiers
+            .iter()
+            .map(|x| x.precision)
+            .fold(self.render_precision, f64::max)
+    }
+}
+
+/* How pages are drawn out of characters when the terminal can not display images */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigFallback {
+    /* Colored half blocks with two pixels per cell */
+    Blocks,
+    /* Dithered Braille patterns with 2x4 dots per cell */
+    Braille,
+}
+
+/* What scrolling forward past the last page does */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigEndOfDocument {
+    Stop,
+    /* Jumps back to the first page */
+    Wrap,
+    /* Displays the tab of the next document given on the command line */
+    NextDocument,
+    Quit,
+}
+
+/* Which rendered page is dropped first once `memory_limit` has been reached. Pages on
+ * the screen are never dropped */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigEvictionPolicy {
+    /* The page that has not been displayed for the longest time */
+    Lru,
+    /* The page farthest from the one in the middle of the view */
+    Distance,
+    /* The page that has been rendered first */
+    Fifo,
+}
+
+/* The direction the pages follow each other in */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigPageLayout {
+    Vertical,
+    /* Pages are placed left to right and scrolled through sideways */
+    Horizontal,
+}
+
+impl ConfigPageLayout {
+    /* Splits a horizontal and vertical pair into the component along the pages and
+     * the one across them */
+    pub fn split<T>(self, pair: (T, T)) -> (T, T) {
+        match self {
+            ConfigPageLayout::Vertical => (pair.1, pair.0),
+            ConfigPageLayout::Horizontal => pair,
+        }
+    }
+
+    /* Inverse of `split` */
+    pub fn join<T>(self, along: T, across: T) -> (T, T) {
+        self.split((along, across))
+    }
+}
+
+/* How bitmaps get to the terminal, `Auto` picks the fastest one measured */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigTransferMedium {
+    Auto,
+    TempFile,
+    SharedMemory,
+    Direct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigBarPosition {
+    Top,
+    Bottom,
+}
+
+/* Status bar whose segments are templates with placeholders for the viewer state */
+#[derive(Debug, Deserialize)]
+pub struct ConfigBar {
     pub enabled: bool,
+    pub position: ConfigBarPosition,
     pub background: Color,
     pub foreground: Color,
+    pub left: String,
+    pub right: String,
+}
+
+/* Overrides of the quirks looked up by the identity of the terminal. Empty and zero
+ * values keep the built-in ones */
+#[derive(Debug, Deserialize)]
+pub struct ConfigViewerQuirks {
+    pub placement_offset: Vec<u32>,
+    pub chunk_size: usize,
+}
+
+/* Pages that reflowable documents like EPUB files are laid out on, in points */
+#[derive(Debug, Deserialize)]
+pub struct ConfigViewerLayout {
     pub width: f32,
+    pub height: f32,
+    pub font_size: f32,
+}
+
+/* How dates and large numbers are shown in the overlays and the bar */
+#[derive(Debug, Deserialize)]
+pub struct ConfigViewerFormat {
+    pub date: String,
+    /* Empty separators are taken from the locale */
+    pub thousands_separator: String,
+    pub decimal_separator: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigStampPosition {
+    Center,
+    Top,
+    Bottom,
 }
 
+/* Watermark of `:stamp`, an image when one is given and the text otherwise */
 #[derive(Debug, Deserialize)]
+pub struct ConfigViewerStamp {
+    pub text: String,
+    pub image: String,
+    pub font_size: f32,
+    pub opacity: f32,
+    pub color: Vec<f32>,
+    pub position: ConfigStampPosition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigCitationStyle {
+    BibTeX,
+    Plain,
+}
+
+/* Citation copied by `CopyCitation` */
+#[derive(Debug, Deserialize)]
+pub struct ConfigViewerCitation {
+    pub style: ConfigCitationStyle,
+    /* Pattern of the `Plain` style */
+    pub template: String,
+}
+
+/* How the distance of a smooth scroll is spread over its duration */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigScrollEasing {
+    Linear,
+    /* Starts fast and slows down towards the end */
+    EaseOut,
+    /* Speeds up and then slows down */
+    EaseInOut,
+}
+
+/* Animation of the scrolling with the keys and the mouse wheel */
+#[derive(Debug, Deserialize)]
+pub struct ConfigViewerSmoothScroll {
+    pub enabled: bool,
+    /* Seconds that every scroll is spread over */
+    pub duration: f32,
+    pub easing: ConfigScrollEasing,
+}
+
+/* Momentum of the scrolling with the mouse wheel and touchpads */
+#[derive(Debug, Deserialize)]
+pub struct ConfigViewerKineticScroll {
+    pub enabled: bool,
+    /* Rate per second at which the velocity decays */
+    pub friction: f32,
+    /* Units per second */
+    pub max_velocity: f32,
+}
+
+/* Where the page, the section, the hovered link and the selection are mirrored to */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ConfigContextOutput {
+    Off,
+    /* A file that is rewritten with every change */
+    File,
+    /* An existing FIFO that gets a line for every change */
+    Fifo,
+    /* OSC 777 notifications written to the terminal */
+    Osc,
+}
+
+/* Side channel for screen readers and status bars */
+#[derive(Debug, Deserialize)]
+pub struct ConfigViewerContext {
+    pub output: ConfigContextOutput,
+    /* The file or the FIFO */
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfigViewerUriHint {
+    pub enabled: bool,
+    pub background: Color,
+    pub foreground: Color,
+    pub width: f32,
+    /* Seconds without mouse movement after which the hint is hidden, 0 keeps it */
+    pub hide_after: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub enum ConfigAction {
     ToggleAlpha,
     ToggleInverse,
+    ToggleDiff,
+    TogglePaper,
+    /* Shows the extracted text of the pages in place of their images */
+    ToggleTextMode,
+    CycleColorBlind,
     CenterViewer,
     MoveUp,
     MoveDown,
@@ -50,11 +329,56 @@ pub enum ConfigAction {
     ZoomOut,
     JumpFirstPage,
     JumpLastPage,
+    JumpBookmark,
+    SetMark,
+    JumpMark,
+    JumpBack,
+    /* Marks the current page for the edits of `:organize` */
+    MarkPage,
+    NextTab,
+    PrevTab,
     PrevPage,
     NextPage,
+    PrevFigure,
+    NextFigure,
+    ZoomBlock,
+    ClosePopup,
+    CopyImage,
+    InvertRegion,
+    ClearRegions,
+    CopyDeepLink,
+    /* Shows the URL of the link under the mouse as a QR code in the popup */
+    LinkQrCode,
+    /* Copies a citation of the document made from its metadata */
+    CopyCitation,
+    /* Shows the metadata of the document and the render settings like `:info` */
+    ShowInfo,
+    /* Labels the links on the screen, typing a label follows its link */
+    HintLinks,
+    /* Writes the current page as a PNG file into the working directory */
+    ExportPage,
+    CommandLine,
+    Search,
+    NextMatch,
+    PrevMatch,
     Quit,
+    /* Does nothing, disables a default binding */
+    Unbound,
+    /* Runs a shell command in which `%file`, `%page` and `%selection` are replaced */
+    RunCommand(String),
+    /* Calls the function that a script has defined with `meowpdf.define` */
+    RunScript(String),
 }
 
+/* Default bindings that have changed, as the keys with their old action and their new
+ * one, `None` when the keys are no longer bound. Configs that still bind the keys to
+ * the old action get the new one, so that no action is left unbound and no old
+ * binding clashes with the new ones */
+const CHANGED_BINDINGS: [(&str, &str, Option<&str>); 2] = [
+    ("Ctrl+o", "ToggleInverse", Some("JumpBack")),
+    ("' '", "JumpBookmark", None),
+];
+
 /* Remove old config variables, add new defaults if not existant */
 fn fix_config_toml(current: &mut toml::Table, default: &toml::Table) -> bool {
     let mut config_has_changed = false;
@@ -64,7 +388,43 @@ fn fix_config_toml(current: &mut toml::Table, default: &toml::Table) -> bool {
         let key_in_current = current.contains_key(&key);
         let key_in_default = default.contains_key(&key);
 
-        if key_in_current && !key_in_default {
+        if key == "bindings"
+            && key_in_default
+            && current.get(&key).is_some_and(|x| x.is_table())
+        {
+            /* The keys of the bindings are chosen by the user, so only the ones that
+             * do not name an action, or an action with its argument in a table, are
+             * removed and the new defaults are added */
+            if let (Value::Table(current_rec), Value::Table(default_rec)) =
+                (&mut current[&key], &default[&key])
+            {
+                let invalid: Vec<String> = current_rec
+                    .iter()
+                    .filter(|(_, x)| !x.is_str() && !x.is_table())
+                    .map(|(x, _)| x.clone())
+                    .collect();
+                for keys in invalid {
+                    current_rec.remove(&keys);
+                    config_has_changed = true;
+                }
+                for (keys, old, new) in CHANGED_BINDINGS {
+                    if current_rec.get(keys).and_then(Value::as_str) != Some(old) {
+                        continue;
+                    }
+                    match new {
+                        Some(x) => current_rec.insert(keys.to_string(), x.into()),
+                        None => current_rec.remove(keys),
+                    };
+                    config_has_changed = true;
+                }
+                for (keys, action) in default_rec.iter() {
+                    if !current_rec.contains_key(keys) {
+                        current_rec.insert(keys.clone(), action.clone());
+                        config_has_changed = true;
+                    }
+                }
+            }
+        } else if key_in_current && !key_in_default {
             /* Remove old config variable that has been removed in new version */
             current.remove(&key);
             config_has_changed = true;
@@ -87,12 +447,104 @@ fn fix_config_toml(current: &mut toml::Table, default: &toml::Table) -> bool {
     config_has_changed
 }
 
-pub fn config_load_or_create() -> Result<Config, String> {
+/* Whether every condition of a `[[when]]` section holds. `terminal` is the start of
+ * `$TERM_PROGRAM`, `$LC_TERMINAL` or `$TERM` ignoring case, `ssh` is whether
+ * `$SSH_TTY` or `$SSH_CONNECTION` is set and `tmux` whether `$TMUX` is set */
+fn condition_holds(
+    section: &Table,
+    var: &impl Fn(&str) -> Option<String>,
+) -> Result<bool, String> {
+    let mut holds = true;
+    for (key, value) in section {
+        holds &= match (key.as_str(), value) {
+            (_, Value::Table(_)) => true,
+            ("terminal", Value::String(name)) => {
+                let name = name.to_lowercase();
+                ["TERM_PROGRAM", "LC_TERMINAL", "TERM"]
+                    .iter()
+                    .filter_map(|x| var(x))
+                    .any(|x| x.to_lowercase().starts_with(&name))
+            }
+            ("ssh", Value::Boolean(ssh)) => {
+                (var("SSH_TTY").is_some() || var("SSH_CONNECTION").is_some()) == *ssh
+            }
+            ("tmux", Value::Boolean(tmux)) => var("TMUX").is_some() == *tmux,
+            _ => {
+                return Err(format!("`config.when` has an invalid condition `{}`!", key))
+            }
+        };
+    }
+
+    Ok(holds)
+}
+
+/* Overrides the values of the table with the ones of the other, tables are merged */
+fn merge_toml(table: &mut Table, overrides: &Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(key), value) {
+            (Some(Value::Table(current)), Value::Table(value)) => {
+                merge_toml(current, value)
+            }
+            _ => {
+                table.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/* Merges the tables of the `[[when]]` sections whose conditions hold into the config,
+ * later sections override the earlier ones */
+fn apply_conditions(
+    table: &mut Table,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    let Some(sections) = table.remove("when") else {
+        return Ok(());
+    };
+    let invalid = || "`config.when` has to be a list of `[[when]]` tables!".to_string();
+
+    for section in sections.as_array().ok_or_else(invalid)? {
+        let section = section.as_table().ok_or_else(invalid)?;
+        if !condition_holds(section, &var)? {
+            continue;
+        }
+
+        let overrides: Table = section
+            .iter()
+            .filter(|(_, x)| x.is_table())
+            .map(|(x, y)| (x.clone(), y.clone()))
+            .collect();
+        merge_toml(table, &overrides);
+    }
+
+    Ok(())
+}
+
+/* The config file given with `--config` or else the one in the config directory */
+fn config_path() -> Result<PathBuf, String> {
+    if let Some(path) = CONFIG_FILE.get() {
+        return Ok(path.clone());
+    }
+
     let mut config =
         config_dir().ok_or("Incompatible OS: No config directory has been found")?;
     config.push(CONFIG_FILENAME);
+    Ok(config)
+}
+
+/** Reads the config file of the user, or `--config`, and writes the default one when
+ * there is none. Settings added in a newer version are written into it */
+pub fn config_load_or_create() -> Result<Config, String> {
+    let config = config_path()?;
 
     let mut config_content = String::new();
+    /* A given config file is not created, since a mistyped path would be */
+    if !config.as_path().exists() && CONFIG_FILE.get().is_some() {
+        return Err(format!(
+            "The config file {} does not exist",
+            config.display()
+        ));
+    }
     if !config.as_path().exists() {
         std::fs::write(config.as_path(), DEFAULT_CONFIG.as_bytes())
             .map_err(|x| format!("Could not create and write config file: {}", x))?;
@@ -109,8 +561,15 @@ pub fn config_load_or_create() -> Result<Config, String> {
             format!("Could not parse default config contents as TOML: {}", x)
         })?;
 
+        /* The conditional sections are not a part of the defaults and are kept */
+        let conditions = current_config_toml.remove("when");
+        let fixed = fix_config_toml(&mut current_config_toml, &default_config_toml);
+        if let Some(conditions) = conditions {
+            current_config_toml.insert("when".to_string(), conditions);
+        }
+
         /* Check if the current config has been fixed, and if so just rewrite the old one */
-        if fix_config_toml(&mut current_config_toml, &default_config_toml) {
+        if fixed {
             let fixed_config_content = toml::to_string_pretty(&current_config_toml)
                 .map_err(|x| format!("Could not serialize toml to string: {}", x))?;
 
@@ -122,12 +581,65 @@ pub fn config_load_or_create() -> Result<Config, String> {
                     )
                 },
             )?;
-
-            config_content = fixed_config_content;
         }
+
+        apply_conditions(&mut current_config_toml, |x| std::env::var(x).ok())?;
+        config_content = toml::to_string(&current_config_toml)
+            .map_err(|x| format!("Could not serialize toml to string: {}", x))?;
     }
 
-    let config_parsed: Config = toml::from_str(config_content.as_str())
+    config_parse(&config_content)
+}
+
+/* Writes the binding of a key sequence into the config file, `None` binds it to
+ * `Unbound` so that a default binding is not added again. The bindings that are
+ * written differently for the same keys are replaced as well */
+pub fn config_save_binding(
+    keys: &str,
+    action: Option<&ConfigAction>,
+) -> Result<(), String> {
+    if SAFE_MODE.load(Ordering::Acquire) {
+        return Err("The config is not written in safe mode".to_string());
+    }
+
+    let config = config_path()?;
+
+    let content = std::fs::read_to_string(config.as_path())
+        .map_err(|x| format!("Could not open and read config file: {}", x))?;
+    let mut table = content
+        .parse::<Table>()
+        .map_err(|x| format!("Could not parse config contents as TOML: {}", x))?;
+    let bindings = table
+        .entry("bindings")
+        .or_insert(Value::Table(Table::new()))
+        .as_table_mut()
+        .ok_or("`config.bindings` is not a table")?;
+
+    let seq = keys.parse::<KeySeq>().ok();
+    let same: Vec<String> = bindings
+        .keys()
+        .filter(|x| *x == keys || (seq.is_some() && x.parse::<KeySeq>().ok() == seq))
+        .cloned()
+        .collect();
+    for key in same {
+        bindings.remove(&key);
+    }
+    let action = action.unwrap_or(&ConfigAction::Unbound);
+    bindings.insert(keys.to_string(), Value::String(format!("{:?}", action)));
+
+    let content = toml::to_string_pretty(&table)
+        .map_err(|x| format!("Could not serialize toml to string: {}", x))?;
+    std::fs::write(config.as_path(), content.as_bytes())
+        .map_err(|x| format!("Could not write config file: {}", x))
+}
+
+/** The built-in config, used instead of the one of the user in safe mode */
+pub fn config_default() -> Result<Config, String> {
+    config_parse(DEFAULT_CONFIG)
+}
+
+fn config_parse(content: &str) -> Result<Config, String> {
+    let config_parsed: Config = toml::from_str(content)
         .map_err(|x| format!("Could not parse config file: {}", x))?;
 
     /* ========================== Check constant constraints ========================= */
@@ -138,6 +650,30 @@ pub fn config_load_or_create() -> Result<Config, String> {
         );
     }
 
+    if config_parsed.viewer.preview_precision < 0.0f64 {
+        return Err("`config.viewer.preview_precision` can not be negative!".to_string());
+    }
+
+    if config_parsed.viewer.zoom_precision_max <= 0.0f64 {
+        return Err(
+            "`config.viewer.zoom_precision_max` can not be negative or equal to 0!"
+                .to_string(),
+        );
+    }
+
+    if config_parsed
+        .viewer
+        .precision_tiers
+        .iter()
+        .any(|x| x.precision <= 0.0f64)
+    {
+        return Err(
+            "`config.viewer.precision_tiers` can not contain precisions that are \
+             negative or equal to 0!"
+                .to_string(),
+        );
+    }
+
     if config_parsed.viewer.scale_min <= 0.0f32 {
         return Err(
             "`config.viewer.scale_min` can not be negative or equal to 0!".to_string(),
@@ -148,6 +684,100 @@ pub fn config_load_or_create() -> Result<Config, String> {
         return Err("`config.viewer.margin_bottom` can not be negative!".to_string());
     }
 
+    if !matches!(config_parsed.viewer.quirks.placement_offset.len(), 0 | 2) {
+        return Err(
+            "`config.viewer.quirks.placement_offset` has to be empty or [x, y]!"
+                .to_string(),
+        );
+    }
+
+    if config_parsed.viewer.export_dpi <= 0.0f32 {
+        return Err(
+            "`config.viewer.export_dpi` can not be negative or equal to 0!".to_string(),
+        );
+    }
+
+    let language = &config_parsed.viewer.language;
+    if !language.is_empty() && Language::from_tag(language).is_none() {
+        return Err(format!(
+            "`config.viewer.language` names the unknown language `{}`!",
+            language
+        ));
+    }
+
+    if config_parsed.viewer.uri_hint.hide_after < 0.0f32 {
+        return Err(
+            "`config.viewer.uri_hint.hide_after` can not be negative!".to_string()
+        );
+    }
+
+    let unit = |x: &f32| (0.0f32..=1.0f32).contains(x);
+    let background = &config_parsed.viewer.page_background;
+    if !matches!(background.len(), 0 | 3) || !background.iter().all(unit) {
+        return Err(
+            "`config.viewer.page_background` has to be empty or [r, g, b] between 0 and 1!"
+                .to_string(),
+        );
+    }
+
+    let tint = &config_parsed.viewer.page_tint;
+    if !matches!(tint.len(), 0 | 4) || !tint.iter().all(unit) {
+        return Err(
+            "`config.viewer.page_tint` has to be empty or [r, g, b, strength] between 0 \
+             and 1!"
+                .to_string(),
+        );
+    }
+
+    let layout = &config_parsed.viewer.layout;
+    if layout.width <= 0.0f32 || layout.height <= 0.0f32 || layout.font_size <= 0.0f32 {
+        return Err("`config.viewer.layout` can only contain positive sizes!".to_string());
+    }
+
+    let stamp = &config_parsed.viewer.stamp;
+    if stamp.font_size <= 0.0f32 {
+        return Err(
+            "`config.viewer.stamp.font_size` can not be negative or equal to 0!"
+                .to_string(),
+        );
+    }
+
+    if !(0.0f32..=1.0f32).contains(&stamp.opacity) {
+        return Err(
+            "`config.viewer.stamp.opacity` has to be between 0 and 1!".to_string()
+        );
+    }
+
+    if stamp.color.len() != 3
+        || stamp.color.iter().any(|x| !(0.0f32..=1.0f32).contains(x))
+    {
+        return Err(
+            "`config.viewer.stamp.color` has to be [r, g, b] between 0 and 1!"
+                .to_string(),
+        );
+    }
+
+    let max_fps = config_parsed.viewer.max_fps;
+    if max_fps.is_nan() || max_fps < 0.0f32 {
+        return Err("`config.viewer.max_fps` can not be negative!".to_string());
+    }
+
+    let smooth_scroll = &config_parsed.viewer.smooth_scroll;
+    if !(0.0f32..=1.0f32).contains(&smooth_scroll.duration) {
+        return Err(
+            "`config.viewer.smooth_scroll.duration` has to be between 0 and 1 seconds!"
+                .to_string(),
+        );
+    }
+
+    let kinetic_scroll = &config_parsed.viewer.kinetic_scroll;
+    if kinetic_scroll.friction <= 0.0f32 || kinetic_scroll.max_velocity <= 0.0f32 {
+        return Err(
+            "`config.viewer.kinetic_scroll` can only contain positive values!"
+                .to_string(),
+        );
+    }
+
     if config_parsed.bindings.is_none() {
         return Err("`config.bindings` can not be empty!".to_string());
     }
@@ -1053,4 +1683,110 @@ mod tests {
                     .collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn test_fix_config_changed_bindings() {
+        /* The bindings of a config from before `Ctrl+o` went back through the jump
+         * list and `'` started jumping to marks */
+        const OLD_CONFIG: &str = r#"
+		[bindings]
+		"Ctrl+o" = "ToggleInverse"
+		"' '" = "JumpBookmark"
+		"g g" = "JumpFirstPage"
+		"q" = "Quit"
+		"#;
+        const CUSTOM_CONFIG: &str = r#"
+		[bindings]
+		"Ctrl+o" = "Quit"
+		"Ctrl+n" = "NextPage"
+		"#;
+
+        let default_config_toml = DEFAULT_CONFIG.parse::<Table>().unwrap();
+
+        let mut test_config_toml = OLD_CONFIG.parse::<Table>().unwrap();
+        assert!(fix_config_toml(&mut test_config_toml, &default_config_toml));
+        let bindings = test_config_toml["bindings"].as_table().unwrap();
+        assert!(bindings["Ctrl+o"].as_str() == Some("JumpBack"));
+        assert!(bindings["Ctrl+n"].as_str() == Some("ToggleInverse"));
+        assert!(bindings["'"].as_str() == Some("JumpMark"));
+        assert!(!bindings.contains_key("' '"));
+        assert!(bindings["q"].as_str() == Some("Quit"));
+        let fixed = toml::to_string(&test_config_toml).unwrap();
+        assert!(toml::from_str::<Config>(&fixed).is_ok());
+
+        /* Bindings that the user has changed are left alone */
+        let mut test_config_toml = CUSTOM_CONFIG.parse::<Table>().unwrap();
+        assert!(fix_config_toml(&mut test_config_toml, &default_config_toml));
+        let bindings = test_config_toml["bindings"].as_table().unwrap();
+        assert!(bindings["Ctrl+o"].as_str() == Some("Quit"));
+        assert!(bindings["Ctrl+n"].as_str() == Some("NextPage"));
+    }
+
+    #[test]
+    fn test_precision_tiers() {
+        let mut config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        config.viewer.zoom_rendering = false;
+        config.viewer.render_precision = 3.0;
+        config.viewer.precision_tiers = vec![
+            ConfigPrecisionTier {
+                zoom: 1.5,
+                precision: 1.5,
+            },
+            ConfigPrecisionTier {
+                zoom: 0.75,
+                precision: 1.0,
+            },
+        ];
+
+        assert!(config.viewer.precision_at(0.5) == 1.0);
+        assert!(config.viewer.precision_at(0.75) == 1.0);
+        assert!(config.viewer.precision_at(1.0) == 1.5);
+        assert!(config.viewer.precision_at(2.0) == 3.0);
+        assert!(config.viewer.precision_max() == 3.0);
+
+        config.viewer.zoom_rendering = true;
+        config.viewer.zoom_precision_max = 4.0;
+        assert!(config.viewer.precision_at(0.5) == 0.5);
+        assert!(config.viewer.precision_at(5.0) == 4.0);
+        assert!(config.viewer.precision_max() == 4.0);
+    }
+
+    #[test]
+    fn test_conditional_config() {
+        let mut table: Table = r#"
+            [viewer]
+            render_precision = 1.5
+            scroll_speed = 20.0
+
+            [[when]]
+            ssh = true
+            [when.viewer]
+            render_precision = 0.75
+
+            [[when]]
+            terminal = "WezTerm"
+            tmux = false
+            [when.viewer]
+            scroll_speed = 40.0
+            [when.bindings]
+            "Ctrl+w" = "Quit"
+        "#
+        .parse()
+        .unwrap();
+        let var = |x: &str| match x {
+            "SSH_TTY" => Some("/dev/pts/1".to_string()),
+            "TERM_PROGRAM" => Some("wezterm".to_string()),
+            "TMUX" => Some("/tmp/tmux-1000/default".to_string()),
+            _ => None,
+        };
+
+        apply_conditions(&mut table, var).unwrap();
+        assert!(!table.contains_key("when"));
+        assert!(table["viewer"]["render_precision"] == Value::Float(0.75));
+        assert!(table["viewer"]["scroll_speed"] == Value::Float(20.0));
+        assert!(!table.contains_key("bindings"));
+
+        let mut table: Table = "[[when]]\ncolor = true".parse().unwrap();
+        assert!(apply_conditions(&mut table, var).is_err());
+    }
 }
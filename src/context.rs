@@ -0,0 +1,211 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    os::unix::fs::FileTypeExt,
+};
+
+use crossbeam_channel::{unbounded, Sender};
+use crossterm::{execute, style::Print};
+
+use crate::{
+    config::ConfigContextOutput,
+    outline::{outline_sections, section_at},
+};
+
+/* Longer selections are cut off, screen readers would read them out in full */
+const SELECTION_CHARS: usize = 200;
+
+/* What the viewer shows and what is pointed at */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Context<'a> {
+    /* The page in the middle of the view counted from one */
+    pub page: usize,
+    pub pages: usize,
+    pub section: Option<&'a str>,
+    pub link: Option<&'a str>,
+    pub selection: Option<&'a str>,
+}
+
+/* Joins the words with single spaces and drops control characters, which would end
+ * the line or the escape sequence early */
+fn single_line(text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    words
+        .join(" ")
+        .chars()
+        .filter(|x| !x.is_control())
+        .collect()
+}
+
+/* The context as a line such as `Page 3 of 12 | 2 Methods | Link: https://a.org` */
+pub fn format_context(context: &Context) -> String {
+    let mut parts = vec![format!("Page {} of {}", context.page, context.pages)];
+    if let Some(section) = context.section.map(single_line).filter(|x| !x.is_empty()) {
+        parts.push(section);
+    }
+    if let Some(link) = context.link.map(single_line).filter(|x| !x.is_empty()) {
+        parts.push(format!("Link: {}", link));
+    }
+    if let Some(selection) = context.selection.map(single_line).filter(|x| !x.is_empty())
+    {
+        let mut cut: String = selection.chars().take(SELECTION_CHARS).collect();
+        if cut.len() < selection.len() {
+            cut.push_str(" ..");
+        }
+        parts.push(format!("Selected: {}", cut));
+    }
+    parts.join(" | ")
+}
+
+/* Mirrors the context of the viewer to `viewer.context` whenever it changes, for
+ * screen readers and status bars such as the one of tmux */
+pub struct ContextMirror {
+    output: ConfigContextOutput,
+    /* Lines are written by another thread so that a FIFO that nobody reads never
+     * blocks the viewer */
+    lines: Option<Sender<String>>,
+    last: String,
+    /* The document whose outline the sections are looked up in */
+    outline: Option<(String, Vec<(String, Option<usize>)>)>,
+}
+
+impl Default for ContextMirror {
+    fn default() -> Self {
+        Self {
+            output: ConfigContextOutput::Off,
+            lines: None,
+            last: String::new(),
+            outline: None,
+        }
+    }
+}
+
+impl ContextMirror {
+    /* Starts mirroring to the output. A FIFO has to exist already */
+    pub fn open(
+        &mut self,
+        output: ConfigContextOutput,
+        path: &str,
+    ) -> Result<(), String> {
+        self.output = output;
+        let (sender, lines) = unbounded::<String>();
+        match output {
+            ConfigContextOutput::Off | ConfigContextOutput::Osc => return Ok(()),
+            ConfigContextOutput::File => {
+                let path = path.to_string();
+                /* Written next to the file first so that its readers never see half
+                 * of a line */
+                std::thread::spawn(move || {
+                    let written = format!("{}.meowpdf-context", path);
+                    for line in lines.iter() {
+                        if std::fs::write(&written, format!("{}\n", line))
+                            .and_then(|_| std::fs::rename(&written, &path))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+            ConfigContextOutput::Fifo => {
+                let metadata = std::fs::metadata(path).map_err(|x| {
+                    format!("Could not find the context FIFO {}: {}", path, x)
+                })?;
+                if !metadata.file_type().is_fifo() {
+                    return Err(format!(
+                        "{} is not a FIFO, create it with `mkfifo`",
+                        path
+                    ));
+                }
+
+                /* Opened for reading too, since opening it only for writing waits
+                 * until a reader opens it */
+                let mut fifo = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path)
+                    .map_err(|x| format!("Could not open {}: {}", path, x))?;
+                std::thread::spawn(move || {
+                    for line in lines.iter() {
+                        if writeln!(fifo, "{}", line)
+                            .and_then(|_| fifo.flush())
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        }
+        self.lines = Some(sender);
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.output != ConfigContextOutput::Off
+    }
+
+    /* The title of the section of the zero-indexed page. The outline is read once for
+     * every document */
+    pub fn section(&mut self, file: &str, page: usize) -> Option<&str> {
+        if self.outline.as_ref().is_none_or(|x| x.0 != file) {
+            let sections = outline_sections(file).unwrap_or_default();
+            self.outline = Some((file.to_string(), sections));
+        }
+        section_at(&self.outline.as_ref()?.1, page)
+    }
+
+    /* Writes the context when it differs from the last one */
+    pub fn update(&mut self, context: &Context) {
+        let line = format_context(context);
+        if line == self.last {
+            return;
+        }
+
+        match self.output {
+            ConfigContextOutput::Off => (),
+            ConfigContextOutput::Osc => {
+                let _ = execute!(
+                    io::stdout(),
+                    Print(format!("\x1b]777;notify;MeowPDF;{}\x07", line))
+                );
+            }
+            ConfigContextOutput::File | ConfigContextOutput::Fifo => {
+                if let Some(lines) = &self.lines {
+                    let _ = lines.send(line.clone());
+                }
+            }
+        }
+        self.last = line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_context() {
+        let mut context = Context {
+            page: 3,
+            pages: 12,
+            ..Default::default()
+        };
+        assert!(format_context(&context) == "Page 3 of 12");
+
+        context.section = Some(" 2  Related\tWork ");
+        context.link = Some("https://example.com");
+        context.selection = Some("first line\nsecond\x1b line");
+        assert!(
+            format_context(&context)
+                == "Page 3 of 12 | 2 Related Work | Link: https://example.com | \
+                    Selected: first line second line"
+        );
+
+        let long = "a".repeat(SELECTION_CHARS + 1);
+        context.selection = Some(&long);
+        assert!(format_context(&context).ends_with(&format!("{} ..", &long[1..])));
+        context.selection = Some(" ");
+        assert!(!format_context(&context).contains("Selected"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::path::Path;
+
+pub const DEEPLINK_SCHEME: &str = "meowpdf://";
+
+/* A position in a document that can be shared as a `meowpdf://` URI such as
+ * `meowpdf:///home/user/paper.pdf?page=42&zoom=150` */
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeepLink {
+    pub file: String,
+    /* One-indexed page number */
+    pub page: usize,
+    /* Zoom given in percent */
+    pub zoom: Option<f32>,
+}
+
+fn percent_encode(input: &str) -> String {
+    let mut encoded = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn percent_decode(input: &str) -> Result<String, String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input
+                .get(i + 1..i + 3)
+                .ok_or(format!("Truncated percent encoding in `{}`", input))?;
+            let byte = u8::from_str_radix(hex, 16)
+                .map_err(|x| format!("Invalid percent encoding `%{}`: {}", hex, x))?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|x| format!("Deep link is not valid UTF-8: {}", x))
+}
+
+impl DeepLink {
+    pub fn parse(uri: &str) -> Result<Self, String> {
+        let rest = uri
+            .strip_prefix(DEEPLINK_SCHEME)
+            .ok_or(format!("`{}` is not a {} URI", uri, DEEPLINK_SCHEME))?;
+
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let file = percent_decode(path)?;
+        if file.is_empty() {
+            return Err(format!("Deep link `{}` does not contain a file", uri));
+        }
+
+        let mut link = Self {
+            file,
+            page: 1,
+            zoom: None,
+        };
+
+        for pair in query.split('&').filter(|x| !x.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "page" => {
+                    link.page = value
+                        .parse::<usize>()
+                        .map_err(|x| {
+                            format!("Invalid page `{}` in deep link: {}", value, x)
+                        })?
+                        .max(1);
+                }
+                "zoom" => {
+                    let zoom = value.parse::<f32>().map_err(|x| {
+                        format!("Invalid zoom `{}` in deep link: {}", value, x)
+                    })?;
+                    if zoom <= 0.0f32 {
+                        return Err(format!(
+                            "Zoom in deep link must be positive: {}",
+                            zoom
+                        ));
+                    }
+                    link.zoom = Some(zoom);
+                }
+                /* Unknown keys are ignored for links created by newer versions */
+                _ => (),
+            }
+        }
+
+        Ok(link)
+    }
+
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!(
+            "{}{}?page={}",
+            DEEPLINK_SCHEME,
+            percent_encode(&self.file),
+            self.page
+        );
+        if let Some(zoom) = self.zoom {
+            uri.push_str(&format!("&zoom={}", zoom.round()));
+        }
+
+        uri
+    }
+}
+
+/* The open parameters of PDF files that browsers and xdg handlers append to the path
+ * as a fragment, such as `paper.pdf#page=12&zoom=150` or `paper.pdf#nameddest=intro` */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OpenParameters {
+    /* One-indexed page number */
+    pub page: Option<usize>,
+    pub nameddest: Option<String>,
+    /* Zoom given in percent */
+    pub zoom: Option<f32>,
+}
+
+impl OpenParameters {
+    /* A fragment without any `=` such as `#intro` is the name of a destination, like
+     * in browsers. The position of `zoom=150,72,400` is ignored */
+    pub fn parse(fragment: &str) -> Result<Self, String> {
+        let mut parameters = Self::default();
+        if !fragment.contains('=') {
+            parameters.nameddest = Some(percent_decode(fragment)?);
+            return Ok(parameters);
+        }
+
+        for pair in fragment.split('&').filter(|x| !x.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "page" => {
+                    let page = value
+                        .parse::<usize>()
+                        .map_err(|x| format!("Invalid page `{}`: {}", value, x))?;
+                    parameters.page = Some(page.max(1));
+                }
+                "nameddest" => parameters.nameddest = Some(percent_decode(value)?),
+                "zoom" => {
+                    let zoom = value.split(',').next().unwrap_or("");
+                    let zoom = zoom
+                        .parse::<f32>()
+                        .map_err(|x| format!("Invalid zoom `{}`: {}", zoom, x))?;
+                    parameters.zoom = Some(zoom);
+                }
+                /* Parameters such as `view` and `search` are not supported */
+                _ => (),
+            }
+        }
+
+        Ok(parameters)
+    }
+}
+
+/* Splits the open parameters off a path, which may also be given as a `file://` URI.
+ * Paths that exist with the `#` in them are kept as they are */
+pub fn split_fragment(arg: &str) -> Result<(String, Option<OpenParameters>), String> {
+    let (file, fragment) = match arg.rsplit_once('#') {
+        Some((file, fragment)) if !Path::new(arg).exists() => (file, Some(fragment)),
+        _ => (arg, None),
+    };
+    let file = match file.strip_prefix("file://") {
+        Some(x) => percent_decode(x)?,
+        None => file.to_owned(),
+    };
+
+    Ok((file, fragment.map(OpenParameters::parse).transpose()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deeplink_roundtrip() {
+        let link = DeepLink {
+            file: "/home/user/my paper.pdf".to_string(),
+            page: 42,
+            zoom: Some(150.0),
+        };
+
+        let uri = link.to_uri();
+        assert!(uri == "meowpdf:///home/user/my%20paper.pdf?page=42&zoom=150");
+        assert!(DeepLink::parse(&uri).unwrap() == link);
+    }
+
+    #[test]
+    fn test_deeplink_parse() {
+        let link = DeepLink::parse("meowpdf://paper.pdf").unwrap();
+        assert!(link.file == "paper.pdf" && link.page == 1 && link.zoom.is_none());
+
+        let link = DeepLink::parse("meowpdf://paper.pdf?zoom=80&unknown=1").unwrap();
+        assert!(link.zoom == Some(80.0));
+
+        assert!(DeepLink::parse("https://paper.pdf").is_err());
+        assert!(DeepLink::parse("meowpdf://?page=2").is_err());
+        assert!(DeepLink::parse("meowpdf://paper.pdf?page=x").is_err());
+        assert!(DeepLink::parse("meowpdf://paper%2.pdf").is_err());
+    }
+
+    #[test]
+    fn test_open_parameters() {
+        let (file, parameters) =
+            split_fragment("paper.pdf#page=12&zoom=150,72,400").unwrap();
+        let parameters = parameters.unwrap();
+        assert!(file == "paper.pdf");
+        assert!(parameters.page == Some(12) && parameters.zoom == Some(150.0));
+
+        let (file, parameters) =
+            split_fragment("file:///home/user/my%20paper.pdf#nameddest=sec:intro")
+                .unwrap();
+        assert!(file == "/home/user/my paper.pdf");
+        assert!(parameters.unwrap().nameddest.as_deref() == Some("sec:intro"));
+
+        let parameters = OpenParameters::parse("sec%3Aintro").unwrap();
+        assert!(parameters.nameddest.as_deref() == Some("sec:intro"));
+        assert!(OpenParameters::parse("view=FitH&page=0").unwrap().page == Some(1));
+        assert!(OpenParameters::parse("page=x").is_err());
+        assert!(split_fragment("paper.pdf").unwrap().1.is_none());
+    }
+}
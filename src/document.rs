@@ -0,0 +1,82 @@
+use std::io::Read;
+
+use mupdf::Document;
+
+use crate::CONFIG;
+
+/* Opens any document that mupdf reads, such as PDF, XPS, EPUB, FB2 and CBZ files.
+ * Reflowable documents are laid out on pages of the size configured in
+ * `[viewer.layout]` */
+pub fn open_document(file: &str) -> Result<Document, String> {
+    let mut document = Document::open(file)
+        .map_err(|x| format!("Could not open the given document: {}", x))?;
+
+    let reflowable = document
+        .is_reflowable()
+        .map_err(|x| format!("Could not check if the document is reflowable: {}", x))?;
+    if reflowable {
+        let layout = &CONFIG.get().unwrap().viewer.layout;
+        document
+            .layout(layout.width, layout.height, layout.font_size)
+            .map_err(|x| format!("Could not lay out the document: {}", x))?;
+    }
+
+    Ok(document)
+}
+
+/* The extension mupdf needs to tell the format of a document apart, guessed from its
+ * first bytes. ZIP archives are taken for CBZ files unless they carry the media type
+ * of EPUB files, since CBZ files do not contain anything that marks them */
+fn sniff_extension(content: &[u8]) -> &'static str {
+    match content {
+        [b'%', b'P', b'D', b'F', ..] => "pdf",
+        [b'P', b'K', 3, 4, ..]
+            if content.windows(20).any(|x| x == b"application/epub+zip") =>
+        {
+            "epub"
+        }
+        [b'P', b'K', 3, 4, ..] => "cbz",
+        [b'<', ..] if content.windows(11).any(|x| x == b"FictionBook") => "fb2",
+        _ if content.get(60..68) == Some(b"BOOKMOBI") => "mobi",
+        _ => "pdf",
+    }
+}
+
+/* Reads a document piped into the viewer and stores it in a temporary file, since
+ * mupdf only opens documents by their path. Returns the path of the file */
+pub fn buffer_stdin() -> Result<String, String> {
+    let mut content = Vec::new();
+    std::io::stdin()
+        .lock()
+        .read_to_end(&mut content)
+        .map_err(|x| format!("Could not read the document from stdin: {}", x))?;
+    if content.is_empty() {
+        return Err("No document has been given on stdin".to_string());
+    }
+
+    let file = std::env::temp_dir().join(format!(
+        "meowpdf-stdin-{}.{}",
+        std::process::id(),
+        sniff_extension(&content)
+    ));
+    std::fs::write(&file, &content)
+        .map_err(|x| format!("Could not write {}: {}", file.display(), x))?;
+    Ok(file.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_extension() {
+        assert!(sniff_extension(b"%PDF-1.7\n") == "pdf");
+        assert!(sniff_extension(b"PK\x03\x04\0\0mimetypeapplication/epub+zip") == "epub");
+        assert!(sniff_extension(b"PK\x03\x04\0\0page001.png") == "cbz");
+        assert!(sniff_extension(b"<?xml?><FictionBook>") == "fb2");
+        assert!(
+            sniff_extension(&[b"x".repeat(60), b"BOOKMOBI".to_vec()].concat()) == "mobi"
+        );
+        assert!(sniff_extension(b"") == "pdf");
+    }
+}
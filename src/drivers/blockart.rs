@@ -0,0 +1,212 @@
+use std::io::{stdout, Write};
+
+use crossterm::{
+    cursor::{MoveTo, RestorePosition, SavePosition},
+    queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+};
+
+/* Size in pixels that every cell is assumed to have when the terminal can not display
+ * images. Each cell shows two vertically stacked pixels of this size */
+pub const BLOCKART_CELL: (u16, u16) = (8, 16);
+
+/* Levels of the 6x6x6 color cube of the 256 color palette */
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn nearest_level(value: u8) -> usize {
+    CUBE_LEVELS
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, x)| (**x as i32 - value as i32).abs())
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> i32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| (*x as i32 - *y as i32).pow(2))
+        .sum()
+}
+
+/* Maps a color to the nearest color of the 256 color palette, either from the color
+ * cube or from the grayscale ramp */
+pub fn rgb_to_ansi256(rgb: [u8; 3]) -> u8 {
+    let [r, g, b] = rgb.map(nearest_level);
+    let cube = [CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]];
+
+    /* The grayscale ramp goes from 8 to 238 in steps of 10 */
+    let average = (rgb.iter().map(|x| *x as u32).sum::<u32>() / 3) as u8;
+    let step = (average.saturating_sub(3) / 10).min(23);
+    let gray_level = 8 + step * 10;
+    let gray = [gray_level; 3];
+
+    if distance(rgb, gray) < distance(rgb, cube) {
+        232 + step
+    } else {
+        (16 + 36 * r + 6 * g + b) as u8
+    }
+}
+
+fn cell_color(pixel: Option<[u8; 3]>) -> Color {
+    match pixel {
+        Some(x) => Color::AnsiValue(rgb_to_ansi256(x)),
+        None => Color::Reset,
+    }
+}
+
+/* Draws `rows` x `columns` cells starting at the zero-indexed `col` and `row` out of
+ * upper half blocks. `sample` returns the color at a point of the drawn area given in
+ * fractions of its width and height, or `None` for transparent points */
+pub fn terminal_blockart_display<F>(
+    col: u16,
+    row: u16,
+    columns: u16,
+    rows: u16,
+    sample: F,
+) -> Result<(), String>
+where
+    F: Fn(f64, f64) -> Option<[u8; 3]>,
+{
+    let mut handle = stdout().lock();
+    queue!(handle, SavePosition).map_err(|x| format!("Could not draw cells: {}", x))?;
+
+    for r in 0..rows {
+        queue!(handle, MoveTo(col, row + r))
+            .map_err(|x| format!("Could not draw cells: {}", x))?;
+
+        for c in 0..columns {
+            let x = (c as f64 + 0.5) / columns as f64;
+            let top = (2.0 * r as f64 + 0.5) / (2.0 * rows as f64);
+            let bottom = (2.0 * r as f64 + 1.5) / (2.0 * rows as f64);
+
+            queue!(
+                handle,
+                SetForegroundColor(cell_color(sample(x, top))),
+                SetBackgroundColor(cell_color(sample(x, bottom))),
+                Print('▀')
+            )
+            .map_err(|x| format!("Could not draw cells: {}", x))?;
+        }
+    }
+
+    queue!(handle, ResetColor, RestorePosition)
+        .map_err(|x| format!("Could not draw cells: {}", x))?;
+    handle
+        .flush()
+        .map_err(|x: std::io::Error| format!("Could not flush stdout: {}", x))?;
+
+    Ok(())
+}
+
+/* Thresholds of a 4x4 ordered dithering matrix scaled to luminance values */
+const BAYER: [[u8; 4]; 4] = [
+    [8, 136, 40, 168],
+    [200, 72, 232, 104],
+    [56, 184, 24, 152],
+    [248, 120, 216, 88],
+];
+
+/* Braille patterns number their dots column by column, with the bottom row of dots
+ * added afterwards */
+pub fn braille_char(dots: [[bool; 4]; 2]) -> char {
+    const BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+    let code = dots
+        .iter()
+        .flatten()
+        .zip(BITS.iter().flatten())
+        .filter(|(dot, _)| **dot)
+        .fold(0x2800, |code, (_, bit)| code | bit);
+    char::from_u32(code).unwrap()
+}
+
+/* A dot is set where the page is darker than the dithering threshold at its
+ * position, so that gray areas are drawn as patterns of dots */
+pub fn braille_dot(pixel: Option<[u8; 3]>, x: usize, y: usize) -> bool {
+    let [r, g, b] = match pixel {
+        Some(x) => x.map(|x| x as u32),
+        None => return false,
+    };
+    let luminance = (299 * r + 587 * g + 114 * b) / 1000;
+    luminance < BAYER[y % 4][x % 4] as u32
+}
+
+/* Draws the same area as `terminal_blockart_display` out of Braille patterns with
+ * 2x4 dots per cell in the default foreground color */
+pub fn terminal_braille_display<F>(
+    col: u16,
+    row: u16,
+    columns: u16,
+    rows: u16,
+    sample: F,
+) -> Result<(), String>
+where
+    F: Fn(f64, f64) -> Option<[u8; 3]>,
+{
+    let mut handle = stdout().lock();
+    queue!(handle, SavePosition).map_err(|x| format!("Could not draw cells: {}", x))?;
+
+    let (width, height) = (2 * columns as usize, 4 * rows as usize);
+    for r in 0..rows as usize {
+        queue!(handle, MoveTo(col, row + r as u16))
+            .map_err(|x| format!("Could not draw cells: {}", x))?;
+
+        let mut line = String::new();
+        for c in 0..columns as usize {
+            let mut dots = [[false; 4]; 2];
+            for (dx, column) in dots.iter_mut().enumerate() {
+                for (dy, dot) in column.iter_mut().enumerate() {
+                    let (x, y) = (2 * c + dx, 4 * r + dy);
+                    let pixel = sample(
+                        (x as f64 + 0.5) / width as f64,
+                        (y as f64 + 0.5) / height as f64,
+                    );
+                    /* Dither in terminal coordinates so that the pattern does not
+                     * move relative to the cells while scrolling */
+                    *dot = braille_dot(pixel, x + 2 * col as usize, y + 4 * row as usize);
+                }
+            }
+            line.push(braille_char(dots));
+        }
+
+        queue!(handle, Print(line))
+            .map_err(|x| format!("Could not draw cells: {}", x))?;
+    }
+
+    queue!(handle, RestorePosition)
+        .map_err(|x| format!("Could not draw cells: {}", x))?;
+    handle
+        .flush()
+        .map_err(|x: std::io::Error| format!("Could not flush stdout: {}", x))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgb_to_ansi256() {
+        assert!(rgb_to_ansi256([0, 0, 0]) == 16);
+        assert!(rgb_to_ansi256([255, 255, 255]) == 231);
+        assert!(rgb_to_ansi256([255, 0, 0]) == 196);
+        assert!(rgb_to_ansi256([128, 128, 128]) == 244);
+        assert!(rgb_to_ansi256([0, 95, 215]) == 26);
+    }
+
+    #[test]
+    fn test_braille() {
+        assert!(braille_char([[false; 4]; 2]) == '\u{2800}');
+        assert!(braille_char([[true; 4]; 2]) == '\u{28FF}');
+        assert!(braille_char([[true, false, false, true], [false; 4]]) == '\u{2841}');
+
+        /* Black is always drawn, white and transparent never */
+        for (x, y) in [(0, 0), (1, 3), (3, 2)] {
+            assert!(braille_dot(Some([0, 0, 0]), x, y));
+            assert!(!braille_dot(Some([255, 255, 255]), x, y));
+            assert!(!braille_dot(None, x, y));
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::{path::PathBuf, sync::Mutex};
+
+use super::graphics::{Bitmap, GraphicsBackend};
+use super::iterm2::png_encode;
+use crate::TERMINAL_SIZE;
+
+/* Size of the frames in pixels and in cells, which stays the same on every machine
+ * so that captured frames can be compared against the ones of earlier runs */
+pub const CAPTURE_SIZE: (u16, u16, u16, u16) = (1280, 800, 160, 50);
+
+/* Scales the `crop` rectangle of a RGBA bitmap with rows of `stride` pixels to
+ * `size` pixels with the nearest pixel */
+fn scale_bitmap(
+    data: &[u8],
+    stride: usize,
+    crop: (usize, usize, usize, usize),
+    size: (usize, usize),
+) -> Vec<u8> {
+    let rows = data.len() / (4 * stride);
+    let mut scaled = Vec::with_capacity(size.0 * size.1 * 4);
+
+    for y in 0..size.1 {
+        let py = usize::min(crop.1 + y * crop.3 / size.1, rows - 1);
+        for x in 0..size.0 {
+            let px = usize::min(crop.0 + x * crop.2 / size.0, stride - 1);
+            let i = (py * stride + px) * 4;
+            scaled.extend_from_slice(&data[i..i + 4]);
+        }
+    }
+
+    scaled
+}
+
+/* Draws a placement over an opaque frame that is `width` pixels wide, clipping it
+ * against the frame */
+fn blend(frame: &mut [u8], width: usize, placement: &Placement) {
+    let height = frame.len() / (4 * width);
+
+    for y in 0..placement.size.1 {
+        let fy = placement.position.1 + y as i64;
+        if fy < 0 || fy >= height as i64 {
+            continue;
+        }
+        for x in 0..placement.size.0 {
+            let fx = placement.position.0 + x as i64;
+            if fx < 0 || fx >= width as i64 {
+                continue;
+            }
+
+            let src = &placement.pixels[(y * placement.size.0 + x) * 4..][..4];
+            let dst = &mut frame[(fy as usize * width + fx as usize) * 4..][..4];
+            let alpha = src[3] as u32;
+            for (d, s) in dst.iter_mut().zip(src).take(3) {
+                *d = ((*s as u32 * alpha + *d as u32 * (255 - alpha)) / 255) as u8;
+            }
+        }
+    }
+}
+
+struct Placement {
+    z: i32,
+    position: (i64, i64),
+    size: (usize, usize),
+    pixels: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Frame {
+    placements: Vec<Placement>,
+    count: usize,
+}
+
+/* Composites the placements of every frame instead of sending them to a terminal and
+ * writes the frames to numbered PNG files. Text drawn into the cells, such as the bar
+ * and the command line, is not part of the frames */
+pub struct CaptureBackend {
+    directory: PathBuf,
+    frame: Mutex<Frame>,
+}
+
+impl CaptureBackend {
+    pub fn new(directory: &str) -> Result<Self, String> {
+        std::fs::create_dir_all(directory)
+            .map_err(|x| format!("Could not create {}: {}", directory, x))?;
+
+        Ok(Self {
+            directory: PathBuf::from(directory),
+            frame: Mutex::new(Frame::default()),
+        })
+    }
+}
+
+impl GraphicsBackend for CaptureBackend {
+    fn transfer(&self, _bitmap: &Bitmap) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn display(
+        &self,
+        bitmap: &Bitmap,
+        cell: (usize, usize),
+        crop: (usize, usize, usize, usize),
+        cells: (usize, usize),
+        z: i32,
+    ) -> Result<bool, String> {
+        let (pxpercol, pxperrow) = {
+            let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+            (
+                terminal_size.width as usize / terminal_size.columns as usize,
+                terminal_size.height as usize / terminal_size.rows as usize,
+            )
+        };
+        let size = (cells.0 * pxpercol, cells.1 * pxperrow);
+        if size.0 == 0 || size.1 == 0 || crop.2 == 0 || crop.3 == 0 {
+            return Ok(false);
+        }
+
+        self.frame.lock().unwrap().placements.push(Placement {
+            z,
+            position: (
+                ((cell.0 - 1) * pxpercol) as i64,
+                ((cell.1 - 1) * pxperrow) as i64,
+            ),
+            size,
+            pixels: scale_bitmap(bitmap.data, bitmap.width, crop, size),
+        });
+
+        /* Nothing answers the placements */
+        Ok(false)
+    }
+
+    fn check(&self, _bitmap: &Bitmap) -> Result<bool, String> {
+        Ok(false)
+    }
+
+    fn present(&self) -> Result<(), String> {
+        let mut frame = self.frame.lock().unwrap();
+        let (width, height) = (CAPTURE_SIZE.0 as usize, CAPTURE_SIZE.1 as usize);
+
+        /* Placements with a higher z-index cover the ones below them, and ones with
+         * the same z-index are drawn in order */
+        let mut placements = std::mem::take(&mut frame.placements);
+        placements.sort_by_key(|x| x.z);
+
+        let mut pixels = [0, 0, 0, 255].repeat(width * height);
+        for placement in placements.iter() {
+            blend(&mut pixels, width, placement);
+        }
+
+        frame.count += 1;
+        let path = self.directory.join(format!("frame-{:05}.png", frame.count));
+        std::fs::write(&path, png_encode(&pixels, width, height))
+            .map_err(|x| format!("Could not write {}: {}", path.display(), x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_composite() {
+        /* A 2x1 bitmap with a red and a half transparent white pixel */
+        let data = [255, 0, 0, 255, 255, 255, 255, 128];
+        let scaled = scale_bitmap(&data, 2, (0, 0, 2, 1), (4, 2));
+        assert!(scaled.len() == 32);
+        assert!(
+            scaled[4..8] == [255, 0, 0, 255] && scaled[24..28] == [255, 255, 255, 128]
+        );
+
+        let mut frame = [0, 0, 0, 255].repeat(3 * 2);
+        let placement = Placement {
+            z: 0,
+            position: (1, -1),
+            size: (4, 2),
+            pixels: scaled,
+        };
+        blend(&mut frame, 3, &placement);
+        assert!(frame[0..12] == [0, 0, 0, 255, 255, 0, 0, 255, 255, 0, 0, 255]);
+        assert!(frame[12..24] == [0, 0, 0, 255].repeat(3));
+
+        let placement = Placement {
+            z: 0,
+            position: (0, 0),
+            size: (1, 1),
+            pixels: vec![255, 255, 255, 128],
+        };
+        blend(&mut frame, 3, &placement);
+        assert!(frame[0..4] == [128, 128, 128, 255]);
+    }
+}
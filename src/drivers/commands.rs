@@ -1,11 +1,14 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
 use core::fmt;
 use crossterm::Command;
 
+use super::graphics::passthrough;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ClearImages;
 impl Command for ClearImages {
     fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
-        f.write_str("\x1B_Ga=d,d=a\x1B\\")
+        f.write_str(&passthrough("\x1B_Ga=d,d=a\x1B\\"))
     }
 }
 
@@ -56,3 +59,21 @@ impl Command for SetPointerShape {
         write!(f, "\x1B]22;{}\x1B\\", LOOKUP[self.0 as usize])
     }
 }
+
+/* Copies text to the system clipboard through the terminal using OSC 52 */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyToClipboard(pub String);
+impl Command for CopyToClipboard {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        write!(f, "\x1B]52;c;{}\x1B\\", STANDARD.encode(self.0.as_bytes()))
+    }
+}
+
+/* Rings the bell which makes the terminal mark its window as urgent */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestAttention;
+impl Command for RequestAttention {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        f.write_str("\x07")
+    }
+}
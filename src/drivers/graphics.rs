@@ -1,19 +1,458 @@
 use base64::{engine::general_purpose::STANDARD, Engine};
+use crossbeam_channel::Receiver;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     fs::File,
-    io::{stdout, Write},
-    time::Duration,
+    hash::{BuildHasher, Hasher, RandomState},
+    io::{stdin, stdout, IsTerminal, Read, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
-use crate::{RECEIVER_GR, SOFTWARE_ID};
+use super::iterm2::{crop_bitmap, png_encode, terminal_iterm2_display};
+use super::sixel::{sixel_encode, sixel_resample, terminal_sixel_display};
+use crate::{image::Z_INDEX_PAGE, RECEIVER_GR, SOFTWARE_ID, TERMINAL_SIZE};
+
+/* How bitmaps get to the terminal */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TransferMedium {
+    /* A temporary file that the terminal deletes after reading it */
+    TempFile,
+    /* A POSIX shared memory object, only available on Linux */
+    SharedMemory,
+    /* Base64 encoded chunks inside of the escape sequences */
+    Direct,
+}
+
+impl TransferMedium {
+    pub fn available() -> Vec<Self> {
+        let mut media = vec![Self::TempFile, Self::Direct];
+        /* Shared memory objects are files in `/dev/shm` on Linux */
+        if cfg!(target_os = "linux")
+            && terminal_quirks().shared_memory
+            && PathBuf::from("/dev/shm").is_dir()
+        {
+            media.push(Self::SharedMemory);
+        }
+        media
+    }
+}
+
+pub static TRANSFER_MEDIUM: OnceLock<TransferMedium> = OnceLock::new();
+
+/* A RGBA bitmap as the backends get it from the images */
+pub struct Bitmap<'a> {
+    pub id: usize,
+    pub width: usize,
+    pub height: usize,
+    pub data: &'a [u8],
+}
+
+/* A protocol for displaying bitmaps in the terminal */
+pub trait GraphicsBackend: Send + Sync {
+    /* Hands the bitmap over to the terminal if it keeps the bitmaps by their IDs */
+    fn transfer(&self, bitmap: &Bitmap) -> Result<(), String>;
+
+    /* Displays the `crop` rectangle of the bitmap on `cells` columns and rows starting
+     * at the one-indexed `cell`. Returns `true` if a graphics response should be
+     * expected */
+    fn display(
+        &self,
+        bitmap: &Bitmap,
+        cell: (usize, usize),
+        crop: (usize, usize, usize, usize),
+        cells: (usize, usize),
+        z: i32,
+    ) -> Result<bool, String>;
+
+    /* Asks if the terminal still has the bitmap. Returns `true` if a graphics
+     * response should be expected */
+    fn check(&self, bitmap: &Bitmap) -> Result<bool, String>;
+
+    /* Called once everything of a frame has been displayed */
+    fn present(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub struct KittyBackend;
+
+impl GraphicsBackend for KittyBackend {
+    fn transfer(&self, bitmap: &Bitmap) -> Result<(), String> {
+        terminal_graphics_transfer_bitmap(
+            bitmap.id,
+            bitmap.width,
+            bitmap.height,
+            bitmap.data,
+            true,
+        )
+    }
+
+    fn display(
+        &self,
+        bitmap: &Bitmap,
+        cell: (usize, usize),
+        crop: (usize, usize, usize, usize),
+        cells: (usize, usize),
+        z: i32,
+    ) -> Result<bool, String> {
+        terminal_graphics_display_image(
+            bitmap.id, cell.0, cell.1, crop, cells.0, cells.1, z,
+        )?;
+        Ok(true)
+    }
+
+    fn check(&self, bitmap: &Bitmap) -> Result<bool, String> {
+        /* The first pixels should be invisible and therefore we have an easy if
+         * the image still exists */
+        self.display(bitmap, (1, 1), (1, 1, 1, 1), (2, 2), Z_INDEX_PAGE)
+    }
+}
+
+/* Sixel terminals do not keep images, so the pixels are sent again every time that an
+ * image is displayed. There is no z-index either, images drawn later cover the ones
+ * drawn before */
+pub struct SixelBackend;
+
+impl GraphicsBackend for SixelBackend {
+    fn transfer(&self, _bitmap: &Bitmap) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn display(
+        &self,
+        bitmap: &Bitmap,
+        cell: (usize, usize),
+        crop: (usize, usize, usize, usize),
+        cells: (usize, usize),
+        _z: i32,
+    ) -> Result<bool, String> {
+        let size = {
+            let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+            (
+                cells.0 * terminal_size.width as usize / terminal_size.columns as usize,
+                cells.1 * terminal_size.height as usize / terminal_size.rows as usize,
+            )
+        };
+        if size.0 == 0 || size.1 == 0 {
+            return Ok(false);
+        }
+
+        let pixels = sixel_resample(bitmap.data, bitmap.width, crop, size);
+        terminal_sixel_display(cell.0, cell.1, &sixel_encode(&pixels, size.0, size.1))?;
+
+        /* Sixel images are not answered */
+        Ok(false)
+    }
+
+    fn check(&self, _bitmap: &Bitmap) -> Result<bool, String> {
+        Ok(false)
+    }
+}
+
+/* The inline images of iTerm2 are not kept either, but unlike sixel images they are
+ * scaled to the cells by the terminal */
+pub struct Iterm2Backend;
+
+impl GraphicsBackend for Iterm2Backend {
+    fn transfer(&self, _bitmap: &Bitmap) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn display(
+        &self,
+        bitmap: &Bitmap,
+        cell: (usize, usize),
+        crop: (usize, usize, usize, usize),
+        cells: (usize, usize),
+        _z: i32,
+    ) -> Result<bool, String> {
+        let (data, width, height) = crop_bitmap(bitmap.data, bitmap.width, crop);
+        if width == 0 || height == 0 {
+            return Ok(false);
+        }
+
+        let png = png_encode(&data, width, height);
+        terminal_iterm2_display(cell.0, cell.1, cells.0, cells.1, &png)?;
+
+        /* Inline images are not answered */
+        Ok(false)
+    }
+
+    fn check(&self, _bitmap: &Bitmap) -> Result<bool, String> {
+        Ok(false)
+    }
+}
+
+pub static GRAPHICS_BACKEND: OnceLock<Box<dyn GraphicsBackend>> = OnceLock::new();
+
+/* The Kitty graphics protocol is used until another backend has been selected */
+pub fn graphics_backend() -> &'static dyn GraphicsBackend {
+    GRAPHICS_BACKEND
+        .get_or_init(|| Box::new(KittyBackend))
+        .as_ref()
+}
+
+/* Set when the terminal reports sixel graphics in its device attributes */
+static SIXEL: AtomicBool = AtomicBool::new(false);
+
+pub fn terminal_supports_sixel() -> bool {
+    SIXEL.load(Ordering::Acquire)
+}
+
+/* Set when the terminal answers the cell size query of iTerm2 */
+static ITERM2: AtomicBool = AtomicBool::new(false);
+
+/* Terminals known to display iTerm2 inline images by their terminfo entry or the
+ * program name they export */
+const ITERM2_TERMINALS: [&str; 2] = ["iterm", "wezterm"];
+
+/* Old versions of iTerm2 do not answer the query, the environment is trusted for them */
+pub fn terminal_supports_iterm2() -> bool {
+    let known = ["TERM_PROGRAM", "LC_TERMINAL", "TERM"].iter().any(|x| {
+        let value = std::env::var(x).unwrap_or_default().to_lowercase();
+        ITERM2_TERMINALS.iter().any(|y| value.starts_with(y))
+    });
+    known || ITERM2.load(Ordering::Acquire)
+}
+
+/* IDs given to the images of the viewer. The ones above are used by the benchmark */
+pub const IMAGE_IDS: RangeInclusive<usize> = 1..=0x7FFEFFFF;
+/* Number of IDs of one instance. Every instance takes a block of its own out of
+ * `IMAGE_IDS`, so that the viewers sharing a terminal, such as the ones in the panes
+ * of tmux, neither place nor delete the images of each other */
+const INSTANCE_IDS: usize = 1 << 20;
+
+/* The block of IDs of this instance, picked at random on the first use */
+pub fn instance_ids() -> RangeInclusive<usize> {
+    static BLOCK: OnceLock<usize> = OnceLock::new();
+
+    let blocks = (IMAGE_IDS.end() - IMAGE_IDS.start() + 1) / INSTANCE_IDS;
+    let block = *BLOCK
+        .get_or_init(|| RandomState::new().build_hasher().finish() as usize % blocks);
+    let start = IMAGE_IDS.start() + block * INSTANCE_IDS;
+    start..=start + INSTANCE_IDS - 1
+}
+
+/* The ID of the `n`th image of this instance, starting over at the beginning of the
+ * block once all of its IDs have been given out */
+pub fn instance_id(n: usize) -> usize {
+    instance_ids().start() + n % INSTANCE_IDS
+}
+
+/* Deviations of a terminal from the graphics protocol that the driver works around */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerminalQuirks {
+    /* Pixels within the first cell that every placement is shifted by */
+    pub placement_offset: (u32, u32),
+    /* Largest base64 payload of a single escape sequence during direct transmission */
+    pub chunk_size: usize,
+    /* Whether bitmaps can be read from shared memory objects */
+    pub shared_memory: bool,
+}
+
+impl Default for TerminalQuirks {
+    fn default() -> Self {
+        Self {
+            placement_offset: (0, 0),
+            /* Largest payload of a single escape sequence allowed by the protocol */
+            chunk_size: 4096,
+            shared_memory: true,
+        }
+    }
+}
+
+/* Terminals known to support the graphics protocol */
+const GRAPHICS_TERMINALS: [&str; 4] = ["kitty", "ghostty", "wezterm", "konsole"];
+
+/* Known quirks looked up by a case insensitive part of the terminal identity */
+const QUIRKS: [(&str, TerminalQuirks); 3] = [
+    (
+        "konsole",
+        TerminalQuirks {
+            placement_offset: (0, 1),
+            chunk_size: 4096,
+            shared_memory: false,
+        },
+    ),
+    (
+        "wezterm",
+        TerminalQuirks {
+            placement_offset: (0, 0),
+            chunk_size: 1024,
+            shared_memory: true,
+        },
+    ),
+    (
+        "ghostty",
+        TerminalQuirks {
+            placement_offset: (0, 0),
+            chunk_size: 4096,
+            shared_memory: false,
+        },
+    ),
+];
+
+impl TerminalQuirks {
+    pub fn lookup(identity: &str) -> Self {
+        let identity = identity.to_lowercase();
+        QUIRKS
+            .iter()
+            .find(|(name, _)| identity.contains(name))
+            .map(|(_, quirks)| *quirks)
+            .unwrap_or_default()
+    }
+}
+
+pub static TERMINAL_QUIRKS: OnceLock<TerminalQuirks> = OnceLock::new();
+
+/* Chunk size of the direct transmission once it has been reduced, 0 before that */
+static CHUNK_SIZE: AtomicUsize = AtomicUsize::new(0);
+/* Smallest chunk size that a rejected transfer is retried with */
+const CHUNK_SIZE_MIN: usize = 256;
+/* IDs of the bitmaps transmitted directly that have not been placed since, with the
+ * chunk size they have been transmitted with */
+static UNCONFIRMED: Mutex<Option<HashMap<usize, usize>>> = Mutex::new(None);
+/* The last placement ID, every placement gets its own so that the responses to the
+ * placements tell apart from the ones to the transfers, which carry none */
+static PLACEMENT_ID: AtomicU32 = AtomicU32::new(0);
+
+fn chunk_size() -> usize {
+    match CHUNK_SIZE.load(Ordering::Acquire) {
+        0 => terminal_quirks().chunk_size,
+        x => x,
+    }
+}
+
+/* Whether the response is the one to a transfer. The transfers are sent with `q=1`,
+ * so the terminal only answers the ones that it has rejected */
+pub fn terminal_graphics_transfer_response(response: &mut GraphicsResponse) -> bool {
+    !response.control().contains_key("p")
+}
+
+/* Lowers the chunk size after the terminal has rejected a bitmap transmitted
+ * directly. The placement of the bitmap fails in turn, after which the viewer
+ * transfers it again with half the chunk size. Fails once a transfer in chunks of
+ * `CHUNK_SIZE_MIN` has been rejected as well */
+pub fn terminal_graphics_transfer_failed(
+    response: &mut GraphicsResponse,
+) -> Result<(), String> {
+    let id = response
+        .control()
+        .get("i")
+        .and_then(|x| x.parse::<usize>().ok());
+    let sent = id.and_then(|x| UNCONFIRMED.lock().unwrap().as_mut()?.remove(&x));
+
+    /* The rejected transfers of the other media and the ones sent before the chunk
+     * size has been lowered already say nothing about the current chunk size */
+    let current = chunk_size();
+    if sent != Some(current) {
+        return Ok(());
+    }
+    if current <= CHUNK_SIZE_MIN {
+        return Err(format!(
+            "The terminal rejects image data even in chunks of {} bytes: {}",
+            current,
+            response.payload()
+        ));
+    }
+    CHUNK_SIZE.store((current / 2).max(CHUNK_SIZE_MIN), Ordering::Release);
+    Ok(())
+}
+
+/* Checks the response to a placement, confirming that the bitmap has been accepted
+ * by the terminal when the placement succeeded */
+pub fn terminal_graphics_placed(response: &mut GraphicsResponse) -> bool {
+    if !response.payload().contains("OK") {
+        return false;
+    }
+
+    let id = response
+        .control()
+        .get("i")
+        .and_then(|x| x.parse::<usize>().ok());
+    if let (Some(id), Some(unconfirmed)) = (id, UNCONFIRMED.lock().unwrap().as_mut()) {
+        unconfirmed.remove(&id);
+    }
+    true
+}
+
+fn terminal_quirks() -> TerminalQuirks {
+    TERMINAL_QUIRKS.get().copied().unwrap_or_default()
+}
+
+/* Whether the viewer runs inside of tmux, which only hands the graphics escape
+ * sequences over to the terminal when they are wrapped in passthrough sequences */
+pub fn in_tmux() -> bool {
+    static TMUX: OnceLock<bool> = OnceLock::new();
+    *TMUX.get_or_init(|| std::env::var_os("TMUX").is_some())
+}
+
+/* Wraps an escape sequence into a tmux passthrough sequence in which every ESC of
+ * the wrapped sequence is doubled */
+fn tmux_wrap(sequence: &str) -> String {
+    format!("\x1BPtmux;{}\x1B\\", sequence.replace('\x1B', "\x1B\x1B"))
+}
+
+/* Graphics escape sequences as they have to be written to the terminal */
+pub fn passthrough(sequence: &str) -> String {
+    if in_tmux() {
+        tmux_wrap(sequence)
+    } else {
+        sequence.to_string()
+    }
+}
+
+fn tmux(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("tmux")
+        .args(args)
+        .output()
+        .map_err(|x| format!("Could not run tmux: {}", x))?;
+    if !output.status.success() {
+        return Err(format!(
+            "tmux failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/* tmux drops the passthrough sequences unless `allow-passthrough` is enabled, which
+ * is done for the pane of the viewer when it is not */
+fn tmux_allow_passthrough() -> Result<(), String> {
+    let value = tmux(&["show-options", "-Apqv", "allow-passthrough"])?;
+    if matches!(value.as_str(), "on" | "all") {
+        return Ok(());
+    }
+    tmux(&["set-option", "-p", "allow-passthrough", "on"]).map(|_| ())
+}
+
+/* The terminal tmux is attached to, as it identified itself to tmux */
+fn tmux_client_terminal() -> Option<String> {
+    let terminal = tmux(&["display-message", "-p", "#{client_termtype}"])
+        .ok()
+        .filter(|x| !x.is_empty())
+        .or_else(|| tmux(&["display-message", "-p", "#{client_termname}"]).ok())?;
+    (!terminal.is_empty()).then_some(terminal)
+}
 
 /* Should be executed only after uncooking the terminal. This method expects the
  * terminal that a non-blocking and unbuffered read from stdin is possible */
 pub fn terminal_graphics_test_support() -> Result<(), String> {
+    if in_tmux() {
+        tmux_allow_passthrough()?;
+    }
+
     let mut handle1 = stdout().lock();
     handle1
-        .write_all(b"\x1B_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1B\\")
+        .write_all(passthrough("\x1B_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1B\\").as_bytes())
         .unwrap();
     handle1.flush().unwrap();
 
@@ -24,10 +463,25 @@ pub fn terminal_graphics_test_support() -> Result<(), String> {
         .unwrap()
         .lock()
         .unwrap()
-        .recv_timeout(Duration::from_millis(1000))
-        .map_err(|x| {
+        .recv_timeout(Duration::from_millis(1000));
+
+    /* tmux does not hand the responses of the terminal back, so the terminal it is
+     * attached to is trusted to support the protocol if it is known to */
+    let response = match response {
+        Err(_) if in_tmux() => {
+            let terminal = tmux_client_terminal().unwrap_or_default().to_lowercase();
+            if GRAPHICS_TERMINALS.iter().any(|x| terminal.contains(x)) {
+                return Ok(());
+            }
+            return Err(format!(
+                "{} inside of tmux is not known to support images",
+                terminal
+            ));
+        }
+        x => x.map_err(|x| {
             format!("Could not receive from Graphics Response channel: {}", x)
-        })?;
+        })?,
+    };
 
     if !response.payload().contains("OK") {
         Err(format!(
@@ -38,57 +492,309 @@ pub fn terminal_graphics_test_support() -> Result<(), String> {
     Ok(())
 }
 
-#[allow(dead_code)]
 pub fn terminal_graphics_deallocate_id(id: usize) -> Result<(), String> {
     let mut handle = stdout().lock();
-    write!(handle, "\x1B_Ga=d,d=I,i={};\x1B\\", id).unwrap();
+    let sequence = format!("\x1B_Ga=d,d=I,i={};\x1B\\", id);
+    handle.write_all(passthrough(&sequence).as_bytes()).unwrap();
 
     handle.flush().unwrap();
 
     Ok(())
 }
 
-pub fn terminal_graphics_transfer_bitmap(
-    id: usize,
-    width: usize,
-    height: usize,
-    data: &[u8],
-    alpha: bool,
+/* Frees the images with IDs in the given range together with their data. Removes the
+ * images that a viewer which has not exited cleanly has left behind in the block of
+ * this instance, whose pages would otherwise be placed in place of the ones with the
+ * same IDs that are yet to be transferred */
+pub fn terminal_graphics_deallocate_range(
+    ids: RangeInclusive<usize>,
 ) -> Result<(), String> {
     let mut handle = stdout().lock();
-    let mut tmp_file_path = std::env::temp_dir();
+    let sequence = format!("\x1B_Gq=2,a=d,d=R,x={},y={};\x1B\\", ids.start(), ids.end());
 
-    tmp_file_path.push(format!(
+    handle
+        .write_all(passthrough(&sequence).as_bytes())
+        .and_then(|_| handle.flush())
+        .map_err(|x: std::io::Error| format!("Could not delete stale images: {}", x))
+}
+
+fn object_name(id: usize) -> String {
+    format!(
         "tty-graphics-protocol-{}-{}",
         SOFTWARE_ID.get().unwrap(),
         id
-    ));
+    )
+}
 
+/* Writes the bitmap into a file that the terminal reads and deletes */
+fn write_object(path: PathBuf, data: &[u8]) -> Result<(), String> {
     /* Wait for the file to get automatically get deleted by Kitty from a previous
      * render instance of this exact image with the same ID. If this is not done
      * this will lead to extreme bugs where the Kitty terminal can crash */
-    while tmp_file_path.as_path().exists() {}
+    while path.as_path().exists() {}
 
-    {
-        let mut tmp_file = File::create(tmp_file_path.as_path()).unwrap();
-        tmp_file.write_all(data).unwrap();
-    }
+    let mut file = File::create(path.as_path())
+        .map_err(|x| format!("Could not create {}: {}", path.display(), x))?;
+    file.write_all(data)
+        .map_err(|x| format!("Could not write {}: {}", path.display(), x))
+}
 
-    /* First chunk with bitmap metadata */
-    write!(
-        handle,
-        "\x1B_Gq=2,f={},i={},s={},v={},t=t;{}\x1B\\",
+/* Transfers a bitmap through the given medium. `quiet` suppresses the response of
+ * the terminal unless the transfer has failed */
+fn transfer_bitmap(
+    medium: TransferMedium,
+    id: usize,
+    size: (usize, usize),
+    data: &[u8],
+    alpha: bool,
+    quiet: bool,
+) -> Result<(), String> {
+    let mut handle = stdout().lock();
+    let control = format!(
+        "q={},f={},i={},s={},v={}",
+        quiet as u8,
         if alpha { 32 } else { 24 },
         id,
-        width,
-        height,
-        STANDARD.encode(tmp_file_path.to_str().unwrap())
-    )
-    .unwrap();
+        size.0,
+        size.1
+    );
 
-    handle.flush().unwrap();
+    match medium {
+        TransferMedium::TempFile => {
+            let path = std::env::temp_dir().join(object_name(id));
+            write_object(path.clone(), data)?;
+            let sequence = format!(
+                "\x1B_G{},t=t;{}\x1B\\",
+                control,
+                STANDARD.encode(path.to_str().unwrap())
+            );
+            handle.write_all(passthrough(&sequence).as_bytes()).unwrap();
+        }
+        TransferMedium::SharedMemory => {
+            let name = object_name(id);
+            write_object(PathBuf::from("/dev/shm").join(&name), data)?;
+            let sequence =
+                format!("\x1B_G{},t=s;{}\x1B\\", control, STANDARD.encode(name));
+            handle.write_all(passthrough(&sequence).as_bytes()).unwrap();
+        }
+        TransferMedium::Direct => {
+            let chunk_size = chunk_size();
+            if quiet {
+                let mut unconfirmed = UNCONFIRMED.lock().unwrap();
+                unconfirmed
+                    .get_or_insert_with(HashMap::new)
+                    .insert(id, chunk_size);
+            }
 
-    Ok(())
+            let encoded = STANDARD.encode(data);
+            let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(chunk_size).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let more = (i + 1 < chunks.len()) as u8;
+                let chunk = std::str::from_utf8(chunk).unwrap();
+                let sequence = if i == 0 {
+                    format!("\x1B_G{},t=d,m={};{}\x1B\\", control, more, chunk)
+                } else {
+                    format!("\x1B_Gm={};{}\x1B\\", more, chunk)
+                };
+                handle.write_all(passthrough(&sequence).as_bytes()).unwrap();
+            }
+        }
+    }
+
+    handle
+        .flush()
+        .map_err(|x: std::io::Error| format!("Could not flush stdout: {}", x))
+}
+
+pub fn terminal_graphics_transfer_bitmap(
+    id: usize,
+    width: usize,
+    height: usize,
+    data: &[u8],
+    alpha: bool,
+) -> Result<(), String> {
+    let medium = *TRANSFER_MEDIUM.get().unwrap_or(&TransferMedium::TempFile);
+    transfer_bitmap(medium, id, (width, height), data, alpha, true)
+}
+
+/* IDs far above the ones given to the pages, used by the benchmark and the probes */
+const BENCHMARK_ID: usize = *IMAGE_IDS.end() + 1;
+
+/* Transfers a bitmap and waits for the terminal to accept it */
+fn transfer_accepted(
+    receiver: &Receiver<GraphicsResponse>,
+    medium: TransferMedium,
+    id: usize,
+    size: (usize, usize),
+    data: &[u8],
+) -> Result<bool, String> {
+    let sent = transfer_bitmap(medium, id, size, data, true, false);
+    let response = receiver.recv_timeout(Duration::from_millis(2000));
+    terminal_graphics_deallocate_id(id)?;
+
+    Ok(sent.is_ok() && response.is_ok_and(|x| x.payload().contains("OK")))
+}
+
+/* Whether the terminal accepts a small bitmap through the given medium */
+pub fn terminal_graphics_probe_medium(medium: TransferMedium) -> Result<bool, String> {
+    let data = [255u8; 16 * 16 * 4];
+    let receiver = RECEIVER_GR.get().unwrap().lock().unwrap();
+    transfer_accepted(&receiver, medium, BENCHMARK_ID, (16, 16), &data)
+}
+
+/* Measures how long each available medium takes to transfer a synthetic bitmap and
+ * returns the fastest one. Expects the terminal to support the graphics protocol */
+pub fn terminal_graphics_benchmark() -> Result<TransferMedium, String> {
+    const BENCHMARK_SIZE: usize = 1024;
+    const BENCHMARK_RUNS: usize = 3;
+
+    /* The responses the measurement waits for do not come back through tmux */
+    if in_tmux() {
+        return Err("Transfer media can not be measured inside of tmux".to_string());
+    }
+
+    let data: Vec<u8> = (0..BENCHMARK_SIZE * BENCHMARK_SIZE * 4)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let receiver = RECEIVER_GR.get().unwrap().lock().unwrap();
+
+    let mut fastest = None;
+    for (i, medium) in TransferMedium::available().into_iter().enumerate() {
+        let start = Instant::now();
+        let mut failed = false;
+
+        for run in 0..BENCHMARK_RUNS {
+            let id = BENCHMARK_ID + i * BENCHMARK_RUNS + run;
+            let size = (BENCHMARK_SIZE, BENCHMARK_SIZE);
+            if !transfer_accepted(&receiver, medium, id, size, &data)? {
+                failed = true;
+                break;
+            }
+        }
+
+        let elapsed = start.elapsed();
+        if !failed && fastest.is_none_or(|(_, x)| elapsed < x) {
+            fastest = Some((medium, elapsed));
+        }
+    }
+
+    fastest
+        .map(|(x, _)| x)
+        .ok_or("No transfer medium is supported by the terminal".to_string())
+}
+
+/* Extracts the name and version from a XTVERSION response `ESC P >| <name> ESC \` */
+fn parse_xtversion(response: &[u8]) -> Option<String> {
+    const PREFIX: &[u8] = b"\x1BP>|";
+
+    let start = response.windows(PREFIX.len()).position(|x| x == PREFIX)? + PREFIX.len();
+    let length = response[start..].iter().position(|x| *x == 0x1B)?;
+    let name = String::from_utf8_lossy(&response[start..start + length]);
+    (!name.trim().is_empty()).then(|| name.trim().to_string())
+}
+
+/* Whether the response ends with the primary device attributes `ESC [ ? ... c` */
+fn ends_with_device_attributes(response: &[u8]) -> bool {
+    let Some(start) = response.windows(3).rposition(|x| x == b"\x1B[?") else {
+        return false;
+    };
+    let attributes = &response[start + 3..];
+    attributes.last() == Some(&b'c')
+        && attributes[..attributes.len() - 1]
+            .iter()
+            .all(|x| x.is_ascii_digit() || *x == b';')
+}
+
+/* The attributes of the last primary device attributes response in `response` */
+fn device_attributes(response: &[u8]) -> Vec<u32> {
+    let Some(start) = response.windows(3).rposition(|x| x == b"\x1B[?") else {
+        return Vec::new();
+    };
+    let attributes = &response[start + 3..];
+    let end = attributes
+        .iter()
+        .position(|x| *x == b'c')
+        .unwrap_or(attributes.len());
+
+    String::from_utf8_lossy(&attributes[..end])
+        .split(';')
+        .filter_map(|x| x.parse().ok())
+        .collect()
+}
+
+/* Whether the response contains the answer `ESC ] 1337 ; ReportCellSize=` to the cell
+ * size query of iTerm2 */
+fn reports_cell_size(response: &[u8]) -> bool {
+    const PREFIX: &[u8] = b"\x1B]1337;ReportCellSize=";
+    response.windows(PREFIX.len()).any(|x| x == PREFIX)
+}
+
+/* Sends the queries followed by one for the primary device attributes, which every
+ * terminal answers, so that terminals that ignore the others do not have to be
+ * waited for, and returns the answers. The wait ends with that answer instead of a
+ * timeout since a blocking read from stdin can not be cancelled without losing input
+ * of the event thread. Has to be executed after uncooking the terminal and before
+ * the event thread starts reading from stdin */
+pub fn terminal_query(queries: &[u8]) -> Option<Vec<u8>> {
+    /* Responses longer than this are not going to contain the answers */
+    const RESPONSE_LIMIT: usize = 512;
+
+    /* Documents read from stdin leave the terminal to `/dev/tty`, like crossterm */
+    let mut input: Box<dyn Read> = match stdin().is_terminal() {
+        true => Box::new(stdin().lock()),
+        false => Box::new(File::open("/dev/tty").ok()?),
+    };
+
+    let mut handle = stdout().lock();
+    handle.write_all(queries).ok()?;
+    handle.write_all(b"\x1B[c").ok()?;
+    handle.flush().ok()?;
+
+    let mut response = Vec::new();
+    let mut byte = [0u8];
+    while response.len() < RESPONSE_LIMIT && input.read_exact(&mut byte).is_ok() {
+        response.push(byte[0]);
+        if ends_with_device_attributes(&response) {
+            break;
+        }
+    }
+    Some(response)
+}
+
+/* Asks the terminal for its name with XTVERSION and for its cell size the way iTerm2
+ * answers it. The device attributes also tell if sixel graphics are supported */
+fn terminal_query_identity() -> Option<String> {
+    let response = terminal_query(b"\x1B[>0q\x1B]1337;ReportCellSize\x07")?;
+
+    /* Attribute 4 stands for sixel graphics */
+    SIXEL.store(device_attributes(&response).contains(&4), Ordering::Release);
+    ITERM2.store(reports_cell_size(&response), Ordering::Release);
+    parse_xtversion(&response)
+}
+
+/* Identifies the terminal by its XTVERSION response, or by the environment variables
+ * terminals commonly set when it does not answer */
+pub fn terminal_identity() -> String {
+    let queried = terminal_query_identity();
+
+    /* tmux answers XTVERSION itself, the quirks are the ones of the outer terminal */
+    if in_tmux() {
+        if let Some(identity) = tmux_client_terminal() {
+            return identity;
+        }
+    }
+
+    if let Some(identity) = queried {
+        return identity;
+    }
+
+    let program = std::env::var("TERM_PROGRAM")
+        .or_else(|_| std::env::var("TERM"))
+        .unwrap_or("unknown".to_string());
+    match std::env::var("TERM_PROGRAM_VERSION") {
+        Ok(version) => format!("{} {}", program, version),
+        Err(_) => program,
+    }
 }
 
 pub fn terminal_graphics_display_image(
@@ -100,19 +806,24 @@ pub fn terminal_graphics_display_image(
 
     c: usize,
     r: usize,
+
+    z: i32,
 ) -> Result<(), String> {
     let mut handle = stdout().lock();
+    let offset = terminal_quirks().placement_offset;
 
     write!(handle, "\x1B[s\x1B[{};{}H", row, col).unwrap();
 
-    /* Z-index < -1,073,741,824 will make the images to be drawn behind
-     * cells with colored background */
-    write!(
-        handle,
-        "\x1B_Gz=-1073741825,a=p,C=1,i={},x={},y={},w={},h={},c={},r={};\x1B\\",
-        id, rect.0, rect.1, rect.2, rect.3, c, r
-    )
-    .unwrap();
+    let placement = PLACEMENT_ID
+        .fetch_add(1, Ordering::Relaxed)
+        .wrapping_add(1)
+        .max(1);
+
+    let sequence = format!(
+        "\x1B_Gz={},a=p,C=1,i={},p={},x={},y={},w={},h={},c={},r={},X={},Y={};\x1B\\",
+        z, id, placement, rect.0, rect.1, rect.2, rect.3, c, r, offset.0, offset.1
+    );
+    handle.write_all(passthrough(&sequence).as_bytes()).unwrap();
 
     handle.write_all(b"\x1B[u").unwrap();
 
@@ -145,9 +856,9 @@ impl GraphicsResponse {
         }
     }
 
-    #[allow(dead_code)]
     fn load(&mut self) {
-        let spl1 = self.source.split(',');
+        /* The control data follows the `G` that starts every graphics response */
+        let spl1 = self.source.trim_start_matches('G').split(',');
         for kv in spl1 {
             let spl2: Vec<&str> = kv.split('=').collect();
             if spl2.len() != 2 {
@@ -162,7 +873,6 @@ impl GraphicsResponse {
         self.loaded = true;
     }
 
-    #[allow(dead_code)]
     pub fn control(&mut self) -> &HashMap<String, String> {
         if !self.loaded {
             self.load();
@@ -174,3 +884,75 @@ impl GraphicsResponse {
         self.payload.as_str()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminal_identity() {
+        let response = b"\x1BP>|WezTerm 20240203-110809\x1B\\\x1B[?65;4;6;18;22c";
+        assert!(parse_xtversion(response).as_deref() == Some("WezTerm 20240203-110809"));
+        assert!(ends_with_device_attributes(response));
+
+        /* Terminals without XTVERSION only answer the device attributes */
+        assert!(parse_xtversion(b"\x1B[?62;22c").is_none());
+        assert!(!ends_with_device_attributes(b"\x1B[?62;2"));
+        assert!(device_attributes(response) == vec![65, 4, 6, 18, 22]);
+        assert!(device_attributes(b"\x1B[?62;22c").iter().all(|x| *x != 4));
+        assert!(reports_cell_size(
+            b"\x1B]1337;ReportCellSize=17.0;8.0;2.0\x07\x1B[?62c"
+        ));
+        assert!(!reports_cell_size(response));
+
+        assert!(TerminalQuirks::lookup("WezTerm 20240203-110809").chunk_size == 1024);
+        assert!(TerminalQuirks::lookup("kitty(0.35.2)") == TerminalQuirks::default());
+    }
+
+    #[test]
+    fn test_instance_ids() {
+        let ids = instance_ids();
+        assert!(IMAGE_IDS.contains(ids.start()) && IMAGE_IDS.contains(ids.end()));
+        assert!(ids.end() - ids.start() + 1 == INSTANCE_IDS);
+        assert!(instance_ids() == ids);
+        assert!(
+            instance_id(0) == *ids.start() && instance_id(INSTANCE_IDS) == *ids.start()
+        );
+        assert!(instance_id(INSTANCE_IDS - 1) == *ids.end());
+    }
+
+    #[test]
+    fn test_transfer_failed() {
+        let mut placed = GraphicsResponse::new(b"Gi=7,p=12;ENOENT:No such image");
+        assert!(!terminal_graphics_transfer_response(&mut placed));
+
+        /* Only the rejection of a transfer with the current chunk size lowers it */
+        let rejected = || GraphicsResponse::new(b"Gi=7;EINVAL:Bad chunk");
+        assert!(terminal_graphics_transfer_response(&mut rejected()));
+        assert!(terminal_graphics_transfer_failed(&mut rejected()).is_ok());
+        assert!(chunk_size() == 4096);
+
+        let mut unconfirmed = UNCONFIRMED.lock().unwrap();
+        unconfirmed.get_or_insert_with(HashMap::new).insert(7, 4096);
+        drop(unconfirmed);
+        assert!(terminal_graphics_transfer_failed(&mut rejected()).is_ok());
+        assert!(chunk_size() == 2048);
+
+        CHUNK_SIZE.store(CHUNK_SIZE_MIN, Ordering::Release);
+        let mut unconfirmed = UNCONFIRMED.lock().unwrap();
+        unconfirmed
+            .get_or_insert_with(HashMap::new)
+            .insert(7, CHUNK_SIZE_MIN);
+        drop(unconfirmed);
+        assert!(terminal_graphics_transfer_failed(&mut rejected()).is_err());
+        CHUNK_SIZE.store(0, Ordering::Release);
+    }
+
+    #[test]
+    fn test_tmux_wrap() {
+        assert!(
+            tmux_wrap("\x1B_Ga=d,d=a\x1B\\")
+                == "\x1BPtmux;\x1B\x1B_Ga=d,d=a\x1B\x1B\\\x1B\\"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::io::{stdout, Write};
+
+use super::graphics::passthrough;
+
+/* CRC-32 of the PNG chunks as specified in ISO 3309 */
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/* Checksum of the zlib stream inside of the image data */
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/* Encodes a RGBA bitmap as a PNG file. There is no deflate implementation at hand, so
+ * the image data is stored uncompressed, which is still cheaper to produce than to
+ * compress for images that are sent once */
+pub fn png_encode(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    /* Every row starts with the filter type, 0 meaning no filter */
+    let mut raw = Vec::with_capacity(height * (4 * width + 1));
+    for row in data.chunks(4 * width).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    /* A zlib stream with a fastest compression header made of stored blocks */
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = raw.chunks(u16::MAX as usize).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        let length = block.len() as u16;
+        zlib.push((i + 1 == blocks.len()) as u8);
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    /* 8 bits per channel of a RGBA image, no interlacing */
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1A\n".to_vec();
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"IDAT", &zlib);
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/* Copies the `crop` rectangle out of a RGBA bitmap with rows of `stride` pixels. The
+ * rectangle is clamped to the bitmap */
+pub fn crop_bitmap(
+    data: &[u8],
+    stride: usize,
+    crop: (usize, usize, usize, usize),
+) -> (Vec<u8>, usize, usize) {
+    let rows = data.len() / (4 * stride);
+    let x0 = usize::min(crop.0, stride);
+    let x1 = usize::min(crop.0 + crop.2, stride);
+    let y0 = usize::min(crop.1, rows);
+    let y1 = usize::min(crop.1 + crop.3, rows);
+
+    let mut cropped = Vec::with_capacity((x1 - x0) * (y1 - y0) * 4);
+    for y in y0..y1 {
+        cropped.extend_from_slice(&data[(y * stride + x0) * 4..(y * stride + x1) * 4]);
+    }
+    (cropped, x1 - x0, y1 - y0)
+}
+
+/* Draws a PNG file stretched over `columns` x `rows` cells with its top left corner at
+ * the one-indexed `col` and `row` through the OSC 1337 inline images of iTerm2 */
+pub fn terminal_iterm2_display(
+    col: usize,
+    row: usize,
+    columns: usize,
+    rows: usize,
+    png: &[u8],
+) -> Result<(), String> {
+    let mut handle = stdout().lock();
+
+    write!(handle, "\x1B[s\x1B[{};{}H", row, col).unwrap();
+    let sequence = format!(
+        "\x1B]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=0;\
+         doNotMoveCursor=1:{}\x07",
+        png.len(),
+        columns,
+        rows,
+        STANDARD.encode(png)
+    );
+    handle.write_all(passthrough(&sequence).as_bytes()).unwrap();
+    handle.write_all(b"\x1B[u").unwrap();
+
+    handle
+        .flush()
+        .map_err(|x: std::io::Error| format!("Could not flush stdout: {}", x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png() {
+        assert!(crc32(b"IEND") == 0xAE426082);
+        assert!(adler32(b"Wikipedia") == 0x11E60398);
+
+        /* A transparent pixel next to a red one */
+        let png = png_encode(&[0, 0, 0, 0, 255, 0, 0, 255], 2, 1);
+        assert!(png.starts_with(b"\x89PNG\r\n\x1A\n\0\0\0\x0DIHDR\0\0\0\x02\0\0\0\x01"));
+        assert!(png.ends_with(b"\0\0\0\0IEND\xAE\x42\x60\x82"));
+
+        let data: Vec<u8> = (0..24).collect();
+        let (cropped, width, height) = crop_bitmap(&data, 3, (1, 1, 5, 1));
+        assert!(cropped == (16..24).collect::<Vec<u8>>());
+        assert!((width, height) == (2, 1));
+    }
+}
@@ -1,3 +1,7 @@
+pub mod blockart;
+pub mod capture;
 pub mod commands;
 pub mod graphics;
+pub(crate) mod iterm2;
 pub mod priority_channel;
+pub(crate) mod sixel;
@@ -51,6 +51,14 @@ impl<T, const U: usize> PriorityReceiver<T, U> {
         sel
     }
 
+    /* Adds the receivers in priority order to an existing select */
+    #[allow(dead_code)]
+    pub fn register<'a>(&'a self, sel: &mut Select<'a>) {
+        for i in 0..U {
+            sel.recv(&self.receivers[i]);
+        }
+    }
+
     #[allow(dead_code)]
     pub fn recv_priority(&self, priority: usize) -> Result<T, RecvError> {
         self.receivers[priority].recv()
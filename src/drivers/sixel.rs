@@ -0,0 +1,162 @@
+use std::io::{stdout, Write};
+
+/* Levels of every channel of the 6x6x6 color cube the bitmaps are quantized to. Sixel
+ * colors are given in percent */
+const SIXEL_LEVELS: usize = 6;
+
+/* The color register of a pixel, or `None` for transparent pixels */
+fn sixel_register(pixel: &[u8]) -> Option<u8> {
+    if pixel[3] < 128 {
+        return None;
+    }
+
+    let level = |x: u8| (x as usize * (SIXEL_LEVELS - 1) + 127) / 255;
+    Some((36 * level(pixel[0]) + 6 * level(pixel[1]) + level(pixel[2])) as u8)
+}
+
+/* Scales the `crop` rectangle of a RGBA bitmap with rows of `stride` pixels to
+ * `size` pixels and quantizes it to color registers */
+pub fn sixel_resample(
+    data: &[u8],
+    stride: usize,
+    crop: (usize, usize, usize, usize),
+    size: (usize, usize),
+) -> Vec<Option<u8>> {
+    let rows = data.len() / (4 * stride);
+    let mut pixels = Vec::with_capacity(size.0 * size.1);
+
+    for y in 0..size.1 {
+        let py = usize::min(crop.1 + y * crop.3 / size.1, rows - 1);
+        for x in 0..size.0 {
+            let px = usize::min(crop.0 + x * crop.2 / size.0, stride - 1);
+            let i = (py * stride + px) * 4;
+            pixels.push(sixel_register(&data[i..i + 4]));
+        }
+    }
+
+    pixels
+}
+
+/* Appends `count` repetitions of a sixel, run-length encoded when that is shorter */
+fn push_run(out: &mut String, sixel: char, count: usize) {
+    if count > 3 {
+        out.push_str(&format!("!{}{}", count, sixel));
+    } else {
+        out.extend(std::iter::repeat_n(sixel, count));
+    }
+}
+
+/* Encodes quantized pixels as a sixel image. Transparent pixels keep what is behind
+ * the image */
+pub fn sixel_encode(pixels: &[Option<u8>], width: usize, height: usize) -> String {
+    let mut used = [false; SIXEL_LEVELS * SIXEL_LEVELS * SIXEL_LEVELS];
+    for register in pixels.iter().flatten() {
+        used[*register as usize] = true;
+    }
+
+    /* Background pixels stay transparent and the aspect ratio is 1:1 */
+    let mut out = format!("\x1BP0;1;0q\"1;1;{};{}", width, height);
+    for (register, _) in used.iter().enumerate().filter(|(_, x)| **x) {
+        let percent = |x: usize| x * 100 / (SIXEL_LEVELS - 1);
+        out.push_str(&format!(
+            "#{};2;{};{};{}",
+            register,
+            percent(register / 36),
+            percent(register / 6 % 6),
+            percent(register % 6)
+        ));
+    }
+
+    for band in (0..height).step_by(6) {
+        let mut registers: Vec<u8> = (band..usize::min(band + 6, height))
+            .flat_map(|y| pixels[y * width..(y + 1) * width].iter().flatten().copied())
+            .collect();
+        registers.sort_unstable();
+        registers.dedup();
+
+        for (i, register) in registers.iter().enumerate() {
+            /* Every color of the band is drawn from its start again */
+            if i > 0 {
+                out.push('$');
+            }
+            out.push_str(&format!("#{}", register));
+
+            let (mut run, mut count) = ('?', 0);
+            for x in 0..width {
+                let bits = (0..6)
+                    .filter(|dy| band + dy < height)
+                    .filter(|dy| pixels[(band + dy) * width + x] == Some(*register))
+                    .fold(0u8, |bits, dy| bits | 1 << dy);
+                let sixel = (0x3F + bits) as char;
+
+                if sixel != run {
+                    push_run(&mut out, run, count);
+                    (run, count) = (sixel, 0);
+                }
+                count += 1;
+            }
+            /* Empty sixels at the end of the line do not have to be drawn */
+            if run != '?' {
+                push_run(&mut out, run, count);
+            }
+        }
+        out.push('-');
+    }
+
+    out.push_str("\x1B\\");
+    out
+}
+
+/* Draws a sixel image with its top left corner at the one-indexed `col` and `row`.
+ * The cursor is restored afterwards so that printing does not move it */
+pub fn terminal_sixel_display(col: usize, row: usize, sixel: &str) -> Result<(), String> {
+    let mut handle = stdout().lock();
+
+    write!(handle, "\x1B[s\x1B[{};{}H", row, col).unwrap();
+    handle.write_all(sixel.as_bytes()).unwrap();
+    handle.write_all(b"\x1B[u").unwrap();
+
+    handle
+        .flush()
+        .map_err(|x: std::io::Error| format!("Could not flush stdout: {}", x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sixel() {
+        /* A 2x2 bitmap with a red, a transparent, a white and a black pixel */
+        let data = [
+            255, 0, 0, 255, 0, 0, 0, 0, //
+            255, 255, 255, 255, 0, 0, 0, 255,
+        ];
+        let pixels = sixel_resample(&data, 2, (0, 0, 2, 2), (4, 2));
+        assert!(
+            pixels
+                == vec![
+                    Some(180),
+                    Some(180),
+                    None,
+                    None,
+                    Some(215),
+                    Some(215),
+                    Some(0),
+                    Some(0)
+                ]
+        );
+
+        assert!(
+            sixel_encode(&[Some(0), Some(0), Some(0), Some(0), None], 5, 1)
+                == "\x1BP0;1;0q\"1;1;5;1#0;2;0;0;0#0!4@-\x1B\\"
+        );
+        assert!(
+            sixel_encode(&pixels, 4, 2)
+                == concat!(
+                    "\x1BP0;1;0q\"1;1;4;2#0;2;0;0;0#180;2;100;0;0#215;2;100;100;100",
+                    "#0??AA$#180@@$#215AA-\x1B\\"
+                )
+        );
+    }
+}
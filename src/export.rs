@@ -0,0 +1,334 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use mupdf::{
+    pdf::{Encryption, PdfDocument, PdfWriteOptions, Permission},
+    Colorspace, Device, DocumentWriter, IRect, ImageFormat, Matrix, Page, Pixmap,
+    TextBlockType, TextPageOptions,
+};
+
+use crate::{document::open_document, transform::PageTransform};
+
+/* Scale of the images cut out of the pages, 1.0 corresponding to 72 DPI */
+const EXPORT_IMAGE_SCALE: f32 = 2.0;
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn page_filename(page: usize) -> String {
+    format!("page-{}.html", page + 1)
+}
+
+fn html_document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title),
+        body
+    )
+}
+
+/* Rasterizes the part of the page covered by an image block */
+fn export_image(page: &Page, bounds: mupdf::Rect, path: &Path) -> Result<(), String> {
+    let bbox = IRect::new(
+        (bounds.x0 * EXPORT_IMAGE_SCALE).floor() as i32,
+        (bounds.y0 * EXPORT_IMAGE_SCALE).floor() as i32,
+        (bounds.x1 * EXPORT_IMAGE_SCALE).ceil() as i32,
+        (bounds.y1 * EXPORT_IMAGE_SCALE).ceil() as i32,
+    );
+
+    let pixmap = Pixmap::new_with_rect(&Colorspace::device_rgb(), bbox, false)
+        .map_err(|x| format!("Could not allocate image pixmap: {}", x))?;
+    let _ = pixmap.clear_with(255);
+    {
+        let device = Device::from_pixmap(&pixmap)
+            .map_err(|x| format!("Could not create image device: {}", x))?;
+        page.run(
+            &device,
+            &Matrix::new_scale(EXPORT_IMAGE_SCALE, EXPORT_IMAGE_SCALE),
+        )
+        .map_err(|x| format!("Could not render image: {}", x))?;
+    }
+
+    pixmap
+        .save_as(path.to_str().unwrap(), ImageFormat::PNG)
+        .map_err(|x| format!("Could not write {}: {}", path.display(), x))
+}
+
+/* Converts the structured text of a page to HTML. Text blocks become paragraphs and
+ * image blocks are stored next to the page and referenced from it */
+fn export_page(page: &Page, index: usize, out: &Path) -> Result<String, String> {
+    let text_page = page
+        .to_text_page(TextPageOptions::PRESERVE_IMAGES)
+        .map_err(|x| format!("Could not extract text from page {}: {}", index, x))?;
+
+    let mut body = String::new();
+    let mut images = 0;
+    for block in text_page.blocks() {
+        if matches!(block.r#type(), TextBlockType::Text) {
+            let lines: Vec<String> = block
+                .lines()
+                .map(|line| line.chars().filter_map(|x| x.char()).collect())
+                .collect();
+            let text = lines.join(" ");
+            if !text.trim().is_empty() {
+                body.push_str(&format!("<p>{}</p>\n", escape_html(text.trim())));
+            }
+        } else if matches!(block.r#type(), TextBlockType::Image) {
+            images += 1;
+            let filename = format!("page-{}-image-{}.png", index + 1, images);
+            export_image(page, block.bounds(), &out.join(&filename))?;
+            body.push_str(&format!("<img src=\"{}\">\n", filename));
+        }
+    }
+
+    Ok(body)
+}
+
+/* Writes one HTML file per page and an index linking them into `out` */
+pub fn export_html(file: &str, out: &str) -> Result<(), String> {
+    let out = Path::new(out);
+    std::fs::create_dir_all(out)
+        .map_err(|x| format!("Could not create {}: {}", out.display(), x))?;
+
+    let document = open_document(file)?;
+    let total = document
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?
+        as usize;
+    let title = Path::new(file)
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_else(|| file.to_string());
+
+    let mut index = String::from("<ol>\n");
+    for i in 0..total {
+        let page = document
+            .load_page(i as i32)
+            .map_err(|x| format!("Could not load page {}: {}", i, x))?;
+
+        let mut body = String::from("<nav>");
+        if i > 0 {
+            body.push_str(&format!(
+                "<a href=\"{}\">Previous</a> ",
+                page_filename(i - 1)
+            ));
+        }
+        body.push_str("<a href=\"index.html\">Index</a>");
+        if i + 1 < total {
+            body.push_str(&format!(" <a href=\"{}\">Next</a>", page_filename(i + 1)));
+        }
+        body.push_str("</nav>\n");
+        body.push_str(&export_page(&page, i, out)?);
+
+        let html = html_document(&format!("{} - page {}", title, i + 1), &body);
+        std::fs::write(out.join(page_filename(i)), html)
+            .map_err(|x| format!("Could not write page {}: {}", i, x))?;
+        index.push_str(&format!(
+            "<li><a href=\"{}\">Page {}</a></li>\n",
+            page_filename(i),
+            i + 1
+        ));
+
+        print!("\rExported {}/{} pages", i + 1, total);
+        io::stdout()
+            .flush()
+            .map_err(|x| format!("Could not flush stdout: {}", x))?;
+    }
+    println!();
+
+    index.push_str("</ol>\n");
+    std::fs::write(out.join("index.html"), html_document(&title, &index))
+        .map_err(|x| format!("Could not write the index: {}", x))?;
+
+    Ok(())
+}
+
+/* The file of a page among several exported ones, such as `page-3.png` for `page.png`.
+ * Pages are counted from one */
+pub fn png_filename(out: &str, page: usize, numbered: bool) -> String {
+    if !numbered {
+        return out.to_owned();
+    }
+    match out.strip_suffix(".png") {
+        Some(stem) => format!("{}-{}.png", stem, page),
+        None => format!("{}-{}", out, page),
+    }
+}
+
+/* Rasterizes the zero-indexed pages `first` to `last` at the DPI the way the viewer
+ * renders them and writes them as PNG files. Returns the written files */
+pub fn export_png(
+    file: &str,
+    (first, last): (usize, usize),
+    dpi: f32,
+    out: &str,
+) -> Result<Vec<String>, String> {
+    let document = open_document(file)?;
+    let total = document
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?
+        as usize;
+    if first > last || last >= total {
+        return Err(format!("The document has {} pages", total));
+    }
+
+    let mut written = Vec::new();
+    for i in first..=last {
+        let page = document
+            .load_page(i as i32)
+            .map_err(|x| format!("Could not load page {}: {}", i + 1, x))?;
+        let bounds = page
+            .bounds()
+            .map_err(|x| format!("Could not get the bounds of page {}: {}", i + 1, x))?;
+
+        let transform = PageTransform::new((bounds.x0, bounds.y0, bounds.x1, bounds.y1));
+        let pixmap = transform.render(&page, dpi as f64 / 72.0, false)?;
+        let path = png_filename(out, i + 1, first != last);
+        pixmap
+            .save_as(&path, ImageFormat::PNG)
+            .map_err(|x| format!("Could not write {}: {}", path, x))?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/* Writes a PDF with the annotations, highlights and values of the form fields drawn
+ * into the contents of the pages, so that it looks the same in viewers that do not
+ * display annotations. Returns the number of written pages */
+pub fn export_flat(file: &str, out: &str) -> Result<usize, String> {
+    let same = std::fs::canonicalize(out)
+        .is_ok_and(|x| std::fs::canonicalize(file).is_ok_and(|y| x == y));
+    if same {
+        return Err("The document can not be overwritten by its export".to_string());
+    }
+
+    let document = open_document(file)?;
+    let total = document
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?
+        as usize;
+
+    let mut writer = DocumentWriter::new(out, "pdf", "")
+        .map_err(|x| format!("Could not create {}: {}", out, x))?;
+    for i in 0..total {
+        let page = document
+            .load_page(i as i32)
+            .map_err(|x| format!("Could not load page {}: {}", i, x))?;
+        let bounds = page
+            .bounds()
+            .map_err(|x| format!("Could not get the bounds of page {}: {}", i, x))?;
+
+        /* Running a page draws its annotations and form fields after its contents */
+        let device = writer
+            .begin_page(bounds)
+            .map_err(|x| format!("Could not begin page {}: {}", i, x))?;
+        page.run(&device, &Matrix::IDENTITY)
+            .map_err(|x| format!("Could not write page {}: {}", i, x))?;
+        writer
+            .end_page(device)
+            .map_err(|x| format!("Could not end page {}: {}", i, x))?;
+    }
+
+    Ok(total)
+}
+
+/* The permission flags of encrypted PDFs by their names, with the bits of the `P`
+ * entry of the encryption dictionary */
+const PERMISSIONS: [(&str, u32); 8] = [
+    ("print", 1 << 2),
+    ("modify", 1 << 3),
+    ("copy", 1 << 4),
+    ("annotate", 1 << 5),
+    ("form", 1 << 8),
+    ("accessibility", 1 << 9),
+    ("assemble", 1 << 10),
+    ("print-hq", 1 << 11),
+];
+
+/* Parses permissions separated by commas such as `print,copy`, `none` for none */
+pub fn parse_permissions(permissions: &str) -> Result<u32, String> {
+    if permissions == "none" {
+        return Ok(0);
+    }
+
+    let mut bits = 0;
+    for name in permissions.split(',').map(str::trim) {
+        match PERMISSIONS.iter().find(|x| x.0 == name) {
+            Some((_, bit)) => bits |= bit,
+            None => return Err(format!("Unknown permission `{}`", name)),
+        }
+    }
+    Ok(bits)
+}
+
+/* Writes a copy of a PDF, encrypted with AES-256 when passwords are given. Without
+ * the owner password, readers only get the given permissions */
+pub fn save_copy(
+    file: &str,
+    out: &str,
+    encryption: Option<(&str, &str, u32)>,
+) -> Result<(), String> {
+    let same = std::fs::canonicalize(out)
+        .is_ok_and(|x| std::fs::canonicalize(file).is_ok_and(|y| x == y));
+    if same {
+        return Err("The document can not be overwritten by its copy".to_string());
+    }
+
+    let pdf = PdfDocument::open(file)
+        .map_err(|x| format!("Only PDF documents can be saved: {}", x))?;
+    let mut options = PdfWriteOptions::default();
+    if let Some((owner, user, permissions)) = encryption {
+        options
+            .set_encryption(Encryption::Aes256)
+            .set_owner_password(owner)
+            .set_user_password(user)
+            .set_permissions(Permission::from_bits_truncate(permissions));
+    }
+
+    pdf.save_with_options(out, options)
+        .map_err(|x| format!("Could not save {}: {}", out, x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert!(
+            escape_html("a < b && c > \"d\"")
+                == "a &lt; b &amp;&amp; c &gt; &quot;d&quot;"
+        );
+        assert!(escape_html("plain text") == "plain text");
+    }
+
+    #[test]
+    fn test_png_filename() {
+        assert!(png_filename("page.png", 3, false) == "page.png");
+        assert!(png_filename("out/page.png", 3, true) == "out/page-3.png");
+        assert!(png_filename("page", 12, true) == "page-12");
+    }
+
+    #[test]
+    fn test_parse_permissions() {
+        assert!(parse_permissions("print, copy") == Ok(4 | 16));
+        assert!(parse_permissions("none") == Ok(0));
+        assert!(parse_permissions("print,fly").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use std::process::{Command, Stdio};
+
+/* Quotes a value for the shell so that it stays a single word */
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/* Replaces `%file`, `%page` and `%selection` in the template of a command with the
+ * quoted values and `%%` with `%` */
+pub fn expand_command(
+    template: &str,
+    file: &str,
+    page: usize,
+    selection: &str,
+) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('%') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let placeholders = [
+            ("file", shell_quote(file)),
+            ("page", page.to_string()),
+            ("selection", shell_quote(selection)),
+            ("%", "%".to_string()),
+        ];
+        match placeholders.iter().find(|(x, _)| rest.starts_with(x)) {
+            Some((name, value)) => {
+                expanded.push_str(value);
+                rest = &rest[name.len()..];
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded.push_str(rest);
+
+    expanded
+}
+
+/* Runs the expanded command with `sh` in the background. Its output is discarded
+ * since it would be drawn over the pages */
+pub fn spawn_command(
+    template: &str,
+    file: &str,
+    page: usize,
+    selection: &str,
+) -> Result<(), String> {
+    let command = expand_command(template, file, page, selection);
+    let mut child = Command::new("sh")
+        .args(["-c", &command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|x| format!("Could not run `{}`: {}", command, x))?;
+
+    /* Reaped by another thread so that no zombie is left behind */
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_command() {
+        assert!(
+            expand_command("notes add %file:%page", "/a b.pdf", 3, "")
+                == "notes add '/a b.pdf':3"
+        );
+        assert!(
+            expand_command("echo %selection >> %%s %x", "", 1, "it's")
+                == "echo 'it'\\''s' >> %s %x"
+        );
+        assert!(expand_command("100%", "", 1, "") == "100%");
+    }
+}
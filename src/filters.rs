@@ -0,0 +1,543 @@
+use mupdf::Pixmap;
+use serde::Deserialize;
+
+/* Side length in pixels of the tiles that are compared when diffing two pages */
+pub const DIFF_TILE: usize = 8;
+/* Sum of the absolute RGB differences above which two pixels are different */
+pub const DIFF_THRESHOLD: u32 = 48;
+/* Color and strength that differing tiles are tinted with */
+pub const DIFF_TINT: [u8; 3] = [255, 48, 48];
+pub const DIFF_TINT_ALPHA: f32 = 0.35;
+
+/* A borrowed view into the samples of a pixmap */
+#[derive(Clone, Copy)]
+pub struct Samples<'a> {
+    pub data: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    pub n: usize,
+}
+
+impl<'a> Samples<'a> {
+    pub fn from_pixmap(pixmap: &'a Pixmap) -> Self {
+        Self {
+            data: pixmap.samples(),
+            width: pixmap.width() as usize,
+            height: pixmap.height() as usize,
+            n: pixmap.n() as usize,
+        }
+    }
+
+    fn rgb(&self, x: usize, y: usize) -> Option<[u8; 3]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let i = (y * self.width + x) * self.n;
+        Some([self.data[i], self.data[i + 1], self.data[i + 2]])
+    }
+}
+
+/* Compares two renders of the same page tile by tile. Pixels that only exist in one
+ * of the renders are treated as different. Returns one flag per tile in row order */
+pub fn diff_tiles(a: Samples, b: Option<Samples>) -> Vec<bool> {
+    let tiles_x = a.width.div_ceil(DIFF_TILE);
+    let tiles_y = a.height.div_ceil(DIFF_TILE);
+    let mut tiles = vec![false; tiles_x * tiles_y];
+
+    for (i, tile) in tiles.iter_mut().enumerate() {
+        let (x0, y0) = ((i % tiles_x) * DIFF_TILE, (i / tiles_x) * DIFF_TILE);
+
+        'tile: for y in y0..usize::min(y0 + DIFF_TILE, a.height) {
+            for x in x0..usize::min(x0 + DIFF_TILE, a.width) {
+                let other = match b.and_then(|b| b.rgb(x, y)) {
+                    Some(x) => x,
+                    None => {
+                        *tile = true;
+                        break 'tile;
+                    }
+                };
+
+                let this = a.rgb(x, y).unwrap();
+                let difference: u32 = (0..3)
+                    .map(|c| (this[c] as i32 - other[c] as i32).unsigned_abs())
+                    .sum();
+                if difference > DIFF_THRESHOLD {
+                    *tile = true;
+                    break 'tile;
+                }
+            }
+        }
+    }
+
+    tiles
+}
+
+/* Blends the tiles flagged by `diff_tiles` with the diff tint color */
+pub fn tint_tiles(data: &mut [u8], width: usize, n: usize, tiles: &[bool]) {
+    let tiles_x = width.div_ceil(DIFF_TILE);
+    if tiles_x == 0 {
+        return;
+    }
+
+    for (i, pixel) in data.chunks_mut(n).enumerate() {
+        let (x, y) = (i % width, i / width);
+        if !tiles
+            .get((y / DIFF_TILE) * tiles_x + x / DIFF_TILE)
+            .copied()
+            .unwrap_or(false)
+        {
+            continue;
+        }
+
+        for (channel, tint) in pixel.iter_mut().zip(DIFF_TINT) {
+            *channel = (*channel as f32 * (1.0 - DIFF_TINT_ALPHA)
+                + tint as f32 * DIFF_TINT_ALPHA) as u8;
+        }
+    }
+}
+
+/* Blends the rectangle (x0, y0, x1, y1) given in pixels with a color, clipped to the
+ * bounds of the image given as (width, height, n) */
+pub fn tint_rect(
+    data: &mut [u8],
+    size: (usize, usize, usize),
+    rect: (f32, f32, f32, f32),
+    color: [u8; 3],
+    alpha: f32,
+) {
+    let (width, height, n) = size;
+    let x0 = rect.0.max(0.0) as usize;
+    let y0 = rect.1.max(0.0) as usize;
+    let x1 = usize::min(rect.2.max(0.0).ceil() as usize, width);
+    let y1 = usize::min(rect.3.max(0.0).ceil() as usize, height);
+
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let i = (y * width + x) * n;
+            for (channel, tint) in data[i..i + 3].iter_mut().zip(color) {
+                *channel = (*channel as f32 * (1.0 - alpha) + tint as f32 * alpha) as u8;
+            }
+        }
+    }
+}
+
+/* A per-pixel filter applied to rendered pages, configured as `invert`,
+ * `grayscale`, `gamma=<f32>`, `contrast=<f32>` or `brightness=<f32>` */
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum PixelFilter {
+    Invert,
+    Grayscale,
+    Gamma(f32),
+    Contrast(f32),
+    Brightness(f32),
+}
+
+impl TryFrom<String> for PixelFilter {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (name, argument) = match value.split_once('=') {
+            Some((name, argument)) => {
+                let argument = argument.trim().parse::<f32>().map_err(|x| {
+                    format!("Invalid argument of filter `{}`: {}", value, x)
+                })?;
+                (name.trim(), Some(argument))
+            }
+            None => (value.trim(), None),
+        };
+
+        match (name, argument) {
+            ("invert", None) => Ok(Self::Invert),
+            ("grayscale", None) => Ok(Self::Grayscale),
+            ("gamma", Some(x)) if x > 0.0f32 => Ok(Self::Gamma(x)),
+            ("contrast", Some(x)) if x >= 0.0f32 => Ok(Self::Contrast(x)),
+            ("brightness", Some(x)) => Ok(Self::Brightness(x)),
+            _ => Err(format!("Unknown or invalid filter `{}`", value)),
+        }
+    }
+}
+
+impl PixelFilter {
+    fn per_channel(&self) -> bool {
+        !matches!(self, Self::Grayscale)
+    }
+
+    /* Filters that map every channel independently are applied through a table */
+    fn channel(&self, value: f32) -> Option<f32> {
+        match *self {
+            Self::Invert => Some(1.0 - value),
+            Self::Gamma(gamma) => Some(value.powf(1.0 / gamma)),
+            Self::Contrast(contrast) => Some((value - 0.5) * contrast + 0.5),
+            Self::Brightness(brightness) => Some(value + brightness),
+            Self::Grayscale => None,
+        }
+    }
+}
+
+/* Applies the filters in their given order to the RGB channels of the samples */
+pub fn apply_filters(data: &mut [u8], n: usize, filters: &[PixelFilter]) {
+    /* Consecutive channel filters are merged into a single lookup table */
+    let mut start = 0;
+    while start < filters.len() {
+        let end = filters[start..]
+            .iter()
+            .position(|x| !x.per_channel())
+            .map(|x| start + x)
+            .unwrap_or(filters.len());
+
+        if start < end {
+            let mut table = [0u8; 256];
+            for (i, entry) in table.iter_mut().enumerate() {
+                let value = filters[start..end]
+                    .iter()
+                    .fold(i as f32 / 255.0, |value, filter| {
+                        filter.channel(value).unwrap().clamp(0.0, 1.0)
+                    });
+                *entry = (value * 255.0).round() as u8;
+            }
+
+            for pixel in data.chunks_mut(n) {
+                for channel in pixel.iter_mut().take(3) {
+                    *channel = table[*channel as usize];
+                }
+            }
+        }
+
+        if let Some(PixelFilter::Grayscale) = filters.get(end) {
+            for pixel in data.chunks_mut(n) {
+                let luma = 0.299 * pixel[0] as f32
+                    + 0.587 * pixel[1] as f32
+                    + 0.114 * pixel[2] as f32;
+                pixel[..3].fill(luma.round() as u8);
+            }
+        }
+
+        start = end + 1;
+    }
+}
+
+/* The color modes of `ToggleInverse`. `Full` inverts every channel, `Soft` only
+ * flips the lightness of HSL so that hues and saturations survive, which keeps
+ * photos and colored figures recognizable on a dark page */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InverseMode {
+    #[default]
+    Off,
+    Full,
+    Soft,
+}
+
+impl InverseMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Full,
+            Self::Full => Self::Soft,
+            Self::Soft => Self::Off,
+        }
+    }
+
+    pub fn describe(self) -> String {
+        match self {
+            Self::Off => "Inverse colors off",
+            Self::Full => "Inverted colors",
+            Self::Soft => "Inverted lightness",
+        }
+        .to_string()
+    }
+
+    pub fn apply(self, data: &mut [u8], n: usize) {
+        match self {
+            Self::Off => (),
+            Self::Full => {
+                for pixel in data.chunks_mut(n) {
+                    for channel in pixel.iter_mut().take(3) {
+                        *channel = 255 - *channel;
+                    }
+                }
+            }
+            /* With hue and saturation kept, the chroma `max - min` stays the same and
+             * every channel moves by the change of the lightness `(max + min) / 2` */
+            Self::Soft => {
+                for pixel in data.chunks_mut(n) {
+                    let max = pixel[..3].iter().max().copied().unwrap() as i32;
+                    let min = pixel[..3].iter().min().copied().unwrap() as i32;
+                    for channel in pixel.iter_mut().take(3) {
+                        *channel = (*channel as i32 + 255 - max - min) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/* Draws white paper in the background color `[r, g, b]` by multiplying every pixel
+ * with it, which keeps black text black, and then blends the pixels with the tint
+ * `[r, g, b, strength]`. Empty colors are left out */
+pub fn apply_paper(data: &mut [u8], n: usize, background: &[f32], tint: &[f32]) {
+    let mut tables = [[0u8; 256]; 3];
+    for (c, table) in tables.iter_mut().enumerate() {
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut value = i as f32 / 255.0;
+            if let Some(x) = background.get(c) {
+                value *= x;
+            }
+            if let (Some(x), Some(strength)) = (tint.get(c), tint.get(3)) {
+                value = value * (1.0 - strength) + x * strength;
+            }
+            *entry = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+
+    for pixel in data.chunks_mut(n) {
+        for (channel, table) in pixel.iter_mut().zip(tables.iter()) {
+            *channel = table[*channel as usize];
+        }
+    }
+}
+
+/* Conversion between RGB and the LMS cone responses used for daltonization */
+const RGB2LMS: [[f32; 3]; 3] = [
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.0299566, 0.184309, 1.46709],
+];
+const LMS2RGB: [[f32; 3]; 3] = [
+    [0.0809444479, -0.130504409, 0.116721066],
+    [-0.0102485335, 0.0540193266, -0.113614708],
+    [-0.000365296938, -0.00412161469, 0.693511405],
+];
+/* Shifts the colors that are lost to a deficiency into the visible channels */
+const ERROR_SHIFT: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorDeficiency {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl ColorDeficiency {
+    /* Removes the response of the missing cone in LMS space */
+    fn simulation(&self) -> [[f32; 3]; 3] {
+        match self {
+            Self::Protanopia => {
+                [[0.0, 2.02344, -2.52581], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+            }
+            Self::Deuteranopia => {
+                [[1.0, 0.0, 0.0], [0.494207, 0.0, 1.24827], [0.0, 0.0, 1.0]]
+            }
+            Self::Tritanopia => {
+                [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-0.395913, 0.801109, 0.0]]
+            }
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "protanopia" => Ok(Self::Protanopia),
+            "deuteranopia" => Ok(Self::Deuteranopia),
+            "tritanopia" => Ok(Self::Tritanopia),
+            _ => Err(format!("Unknown color deficiency `{}`", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Protanopia => "protanopia",
+            Self::Deuteranopia => "deuteranopia",
+            Self::Tritanopia => "tritanopia",
+        }
+    }
+}
+
+/* Simulates how a color deficiency perceives a page or corrects the page so that
+ * the colors lost to the deficiency can be told apart */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Daltonize {
+    pub deficiency: ColorDeficiency,
+    pub simulate: bool,
+}
+
+impl Daltonize {
+    /* Cycles through the corrections, then the simulations and then back to none */
+    pub fn next(current: Option<Self>) -> Option<Self> {
+        use ColorDeficiency::*;
+
+        let (deficiency, simulate) = match current {
+            None => (Protanopia, false),
+            Some(Self {
+                deficiency: Protanopia,
+                simulate,
+            }) => (Deuteranopia, simulate),
+            Some(Self {
+                deficiency: Deuteranopia,
+                simulate,
+            }) => (Tritanopia, simulate),
+            Some(Self {
+                deficiency: Tritanopia,
+                simulate: false,
+            }) => (Protanopia, true),
+            Some(Self {
+                deficiency: Tritanopia,
+                simulate: true,
+            }) => return None,
+        };
+
+        Some(Self {
+            deficiency,
+            simulate,
+        })
+    }
+
+    pub fn describe(current: Option<Self>) -> String {
+        match current {
+            Some(x) if x.simulate => format!("Simulating {}", x.deficiency.name()),
+            Some(x) => format!("Correcting {}", x.deficiency.name()),
+            None => "Color filters off".to_string(),
+        }
+    }
+
+    /* The RGB to RGB matrix of the whole filter */
+    fn matrix(&self) -> [[f32; 3]; 3] {
+        let simulation =
+            multiply(&LMS2RGB, &multiply(&self.deficiency.simulation(), &RGB2LMS));
+        if self.simulate {
+            return simulation;
+        }
+
+        /* corrected = rgb + shift * (rgb - simulated) */
+        let mut error = [[0.0f32; 3]; 3];
+        for (i, row) in error.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = if i == j { 1.0 } else { 0.0 } - simulation[i][j];
+            }
+        }
+
+        let mut correction = multiply(&ERROR_SHIFT, &error);
+        for (i, row) in correction.iter_mut().enumerate() {
+            row[i] += 1.0;
+        }
+        correction
+    }
+
+    pub fn apply(&self, data: &mut [u8], n: usize) {
+        let matrix = self.matrix();
+        for pixel in data.chunks_mut(n) {
+            let rgb = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+            for (channel, row) in pixel.iter_mut().zip(matrix.iter()) {
+                let value = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                *channel = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+fn multiply(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut result = [[0.0f32; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(data: &[u8]) -> Samples<'_> {
+        Samples {
+            data,
+            width: DIFF_TILE * 2,
+            height: DIFF_TILE,
+            n: 3,
+        }
+    }
+
+    #[test]
+    fn test_diff_tiles() {
+        let a = vec![255u8; DIFF_TILE * 2 * DIFF_TILE * 3];
+        let mut b = a.clone();
+        /* Change a single pixel in the second tile */
+        b[(DIFF_TILE + 1) * 3] = 0;
+
+        assert!(diff_tiles(samples(&a), Some(samples(&a))) == vec![false, false]);
+        assert!(diff_tiles(samples(&a), Some(samples(&b))) == vec![false, true]);
+        assert!(diff_tiles(samples(&a), None) == vec![true, true]);
+    }
+
+    #[test]
+    fn test_pixel_filters() {
+        let parse = |x: &str| PixelFilter::try_from(x.to_string());
+        assert!(parse("invert") == Ok(PixelFilter::Invert));
+        assert!(parse("gamma = 1.2") == Ok(PixelFilter::Gamma(1.2)));
+        assert!(parse("gamma=0").is_err());
+        assert!(parse("invert=1").is_err());
+        assert!(parse("blur").is_err());
+
+        let mut data = vec![0u8, 128, 255, 10, 20, 30];
+        apply_filters(&mut data, 3, &[PixelFilter::Invert]);
+        assert!(data == vec![255, 127, 0, 245, 235, 225]);
+
+        let mut data = vec![255u8, 0, 0, 255];
+        apply_filters(
+            &mut data,
+            4,
+            &[PixelFilter::Grayscale, PixelFilter::Brightness(1.0)],
+        );
+        assert!(data == vec![255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_apply_paper() {
+        let mut data = vec![255u8, 255, 255, 0, 0, 0];
+        apply_paper(&mut data, 3, &[1.0, 0.6, 0.2], &[]);
+        assert!(data == vec![255, 153, 51, 0, 0, 0]);
+
+        let mut data = vec![255u8, 255, 255, 255, 0, 0, 0, 255];
+        apply_paper(&mut data, 4, &[], &[0.0, 0.0, 1.0, 0.5]);
+        assert!(data == vec![128, 128, 255, 255, 0, 0, 128, 255]);
+    }
+
+    #[test]
+    fn test_inverse_mode() {
+        let mut mode = InverseMode::default();
+        for _ in 0..3 {
+            mode = mode.next();
+        }
+        assert!(mode == InverseMode::Off);
+
+        let mut data = vec![255u8, 255, 255, 255, 0, 0, 0, 255, 255, 200, 100, 50];
+        InverseMode::Soft.apply(&mut data, 3);
+        assert!(data == vec![0, 0, 0, 255, 0, 0, 0, 255, 255, 205, 105, 55]);
+
+        let mut data = vec![10u8, 20, 30, 40];
+        InverseMode::Full.apply(&mut data, 4);
+        assert!(data == vec![245, 235, 225, 40]);
+    }
+
+    #[test]
+    fn test_daltonize() {
+        /* Gray stays gray for every deficiency */
+        let mut current = None;
+        for _ in 0..6 {
+            current = Daltonize::next(current);
+            let mut data = vec![128u8, 128, 128];
+            current.unwrap().apply(&mut data, 3);
+            assert!(data.iter().all(|x| x.abs_diff(128) <= 2));
+        }
+        assert!(Daltonize::next(current).is_none());
+
+        /* Red and green both lose their hue when simulating deuteranopia */
+        let filter = Daltonize {
+            deficiency: ColorDeficiency::Deuteranopia,
+            simulate: true,
+        };
+        let mut data = vec![255u8, 0, 0, 0, 255, 0];
+        filter.apply(&mut data, 3);
+        assert!(data[0].abs_diff(data[1]) <= 1 && data[3].abs_diff(data[4]) <= 1);
+    }
+}
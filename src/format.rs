@@ -0,0 +1,225 @@
+use crate::CONFIG;
+
+/* A date and time of day, in UTC unless it comes from a document */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+/* Converts seconds since the UNIX epoch to a date and time in UTC */
+pub fn date_from_unix(seconds: u64) -> DateTime {
+    /* Converts the days since the epoch to a date of the proleptic Gregorian
+     * calendar, with the years starting in March so that leap days come last */
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    let time = seconds % 86400;
+    DateTime {
+        year,
+        month: month as u32,
+        day: day as u32,
+        hour: (time / 3600) as u32,
+        minute: (time / 60 % 60) as u32,
+        second: (time % 60) as u32,
+    }
+}
+
+/* Parses the `D:YYYYMMDDHHmmSS` dates of PDF metadata. Everything after the year is
+ * optional and the time zone is ignored. Dates of other formats, such as the ones of
+ * EPUB files, are not parsed */
+pub fn parse_pdf_date(date: &str) -> Option<DateTime> {
+    let date = date.trim();
+    let digits = match date.strip_prefix("D:") {
+        Some(x) => x,
+        None if date.chars().all(|x| x.is_ascii_digit()) => date,
+        None => return None,
+    };
+    let digits: String = digits.chars().take_while(|x| x.is_ascii_digit()).collect();
+    if digits.len() < 4 {
+        return None;
+    }
+
+    let field = |start: usize, default: u32| {
+        digits
+            .get(start..start + 2)
+            .map_or(Some(default), |x| x.parse::<u32>().ok())
+    };
+    let parsed = DateTime {
+        year: digits[0..4].parse().ok()?,
+        month: field(4, 1)?,
+        day: field(6, 1)?,
+        hour: field(8, 0)?,
+        minute: field(10, 0)?,
+        second: field(12, 0)?,
+    };
+
+    let valid = (1..=12).contains(&parsed.month)
+        && (1..=31).contains(&parsed.day)
+        && parsed.hour < 24
+        && parsed.minute < 60
+        && parsed.second < 61;
+    valid.then_some(parsed)
+}
+
+/* Replaces `%Y`, `%m`, `%d`, `%H`, `%M`, `%S` and `%%` in a pattern. Other characters
+ * are kept as they are */
+pub fn format_date(date: &DateTime, pattern: &str) -> String {
+    let mut formatted = String::new();
+    let mut chars = pattern.chars();
+
+    while let Some(x) = chars.next() {
+        if x != '%' {
+            formatted.push(x);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => formatted.push_str(&format!("{:04}", date.year)),
+            Some('m') => formatted.push_str(&format!("{:02}", date.month)),
+            Some('d') => formatted.push_str(&format!("{:02}", date.day)),
+            Some('H') => formatted.push_str(&format!("{:02}", date.hour)),
+            Some('M') => formatted.push_str(&format!("{:02}", date.minute)),
+            Some('S') => formatted.push_str(&format!("{:02}", date.second)),
+            Some('%') => formatted.push('%'),
+            Some(x) => {
+                formatted.push('%');
+                formatted.push(x);
+            }
+            None => formatted.push('%'),
+        }
+    }
+
+    formatted
+}
+
+/* The thousands and decimal separators of a locale such as `de_DE.UTF-8` */
+pub fn locale_separators(locale: &str) -> (&'static str, &'static str) {
+    let language = locale.split(['_', '.', '@']).next().unwrap_or("");
+    match language {
+        "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => (".", ","),
+        "fr" | "ru" | "sv" | "pl" | "cs" | "fi" | "nb" | "uk" | "sk" | "hu" => {
+            ("\u{202F}", ",")
+        }
+        _ => (",", "."),
+    }
+}
+
+/* The configured separators with the ones of the locale in place of empty ones */
+fn separators() -> (String, String) {
+    let config = &CONFIG.get().unwrap().viewer.format;
+    let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .iter()
+        .find_map(|x| std::env::var(x).ok().filter(|x| !x.is_empty()))
+        .unwrap_or_default();
+    let (thousands, decimal) = locale_separators(&locale);
+
+    let pick = |configured: &str, fallback: &str| match configured {
+        "" => fallback.to_string(),
+        x => x.to_string(),
+    };
+    (
+        pick(&config.thousands_separator, thousands),
+        pick(&config.decimal_separator, decimal),
+    )
+}
+
+pub fn group_digits(number: u64, separator: &str) -> String {
+    let digits = number.to_string();
+    let mut grouped = String::new();
+
+    for (i, x) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push_str(separator);
+        }
+        grouped.push(x);
+    }
+
+    grouped
+}
+
+/* Formats a size in bytes with binary units and one decimal */
+pub fn bytes_with_unit(bytes: u64, decimal: &str) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]).replace('.', decimal),
+    }
+}
+
+/* A count with the configured thousands separator */
+pub fn format_number(number: u64) -> String {
+    group_digits(number, &separators().0)
+}
+
+/* A size in bytes with the configured separators */
+pub fn format_bytes(bytes: u64) -> String {
+    bytes_with_unit(bytes, &separators().1)
+}
+
+/* A date with the configured pattern */
+pub fn format_config_date(date: &DateTime) -> String {
+    format_date(date, &CONFIG.get().unwrap().viewer.format.date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dates() {
+        let pattern = "%Y-%m-%d";
+        assert!(format_date(&date_from_unix(0), pattern) == "1970-01-01");
+        assert!(format_date(&date_from_unix(951782400), pattern) == "2000-02-29");
+        assert!(format_date(&date_from_unix(1791936000), pattern) == "2026-10-14");
+
+        let date = parse_pdf_date("D:20240131235907+01'00'").unwrap();
+        assert!(
+            format_date(&date, "%d.%m.%Y %H:%M:%S %% %q") == "31.01.2024 23:59:07 % %q"
+        );
+        assert!(parse_pdf_date("D:2024").unwrap().month == 1);
+        assert!(parse_pdf_date("D:20241301").is_none());
+        assert!(parse_pdf_date("2024-01-31").is_none());
+        assert!(parse_pdf_date("yesterday").is_none());
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert!(group_digits(0, ",") == "0");
+        assert!(group_digits(999, ",") == "999");
+        assert!(group_digits(1234567, ".") == "1.234.567");
+
+        assert!(bytes_with_unit(1000, ".") == "1000 B");
+        assert!(bytes_with_unit(1536, ",") == "1,5 KiB");
+        assert!(bytes_with_unit(5 * 1024 * 1024 * 1024, ".") == "5.0 GiB");
+
+        assert!(locale_separators("de_DE.UTF-8") == (".", ","));
+        assert!(locale_separators("en_US.UTF-8") == (",", "."));
+        assert!(locale_separators("C") == (",", "."));
+    }
+}
@@ -0,0 +1,199 @@
+use std::time::{Duration, Instant};
+
+use crate::config::ConfigScrollEasing;
+
+/* Time between the steps of a running scroll animation, about 60 per second */
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+/* Speed in units per second below which a kinetic scroll stops */
+const MIN_VELOCITY: f32 = 5.0;
+
+/* Progress of the animation along the curve for the share of its duration that has
+ * passed, both between 0 and 1 */
+pub fn ease(easing: ConfigScrollEasing, t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    match easing {
+        ConfigScrollEasing::Linear => t,
+        ConfigScrollEasing::EaseOut => 1.0 - (1.0 - t).powi(3),
+        ConfigScrollEasing::EaseInOut if t < 0.5 => 4.0 * t.powi(3),
+        ConfigScrollEasing::EaseInOut => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+    }
+}
+
+/* Scrolling spread over time. The main loop asks for the distance of every frame
+ * until the animation has covered all of it */
+#[derive(Debug, Default)]
+pub struct Glide {
+    /* The distance of the running animation and how much of it has been scrolled */
+    amount: (f32, f32),
+    applied: (f32, f32),
+    start: Option<Instant>,
+}
+
+impl Glide {
+    /* Adds to the distance. The animation starts over with what is left of the
+     * running one, so that held keys keep gliding instead of stuttering */
+    pub fn push(&mut self, amount: (f32, f32), now: Instant) {
+        self.amount = (
+            self.amount.0 - self.applied.0 + amount.0,
+            self.amount.1 - self.applied.1 + amount.1,
+        );
+        self.applied = (0.0f32, 0.0f32);
+        self.start = Some(now);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.start.is_some()
+    }
+
+    /* The distance to scroll by for the animation to reach where it is at `now`. The
+     * last step covers the rest, which ends the animation */
+    pub fn step(
+        &mut self,
+        now: Instant,
+        duration: Duration,
+        easing: ConfigScrollEasing,
+    ) -> (f32, f32) {
+        let Some(start) = self.start else {
+            return (0.0f32, 0.0f32);
+        };
+
+        let t = match duration.is_zero() {
+            true => 1.0f32,
+            false => {
+                now.saturating_duration_since(start).as_secs_f32()
+                    / duration.as_secs_f32()
+            }
+        };
+        let progress = ease(easing, t);
+        let target = (self.amount.0 * progress, self.amount.1 * progress);
+        let delta = (target.0 - self.applied.0, target.1 - self.applied.1);
+        self.applied = target;
+
+        if t >= 1.0 {
+            *self = Self::default();
+        }
+        delta
+    }
+}
+
+/* Kinetic scrolling of the mouse wheel and touchpads. Every event adds to the
+ * velocity, which decays over time by the friction, so rapid events build up speed
+ * and the scroll coasts to a stop */
+#[derive(Debug, Default)]
+pub struct Momentum {
+    /* Units per second along both axes */
+    velocity: (f32, f32),
+    last: Option<Instant>,
+}
+
+impl Momentum {
+    /* Adds an event scrolling by the amount. With the velocity decaying by `friction`
+     * per second, a single event travels just the amount. Scrolling the other way
+     * stops the motion along that axis first */
+    pub fn push(&mut self, amount: (f32, f32), now: Instant, friction: f32, max: f32) {
+        let add = |velocity: f32, amount: f32| {
+            let velocity = match velocity * amount < 0.0 {
+                true => 0.0,
+                false => velocity,
+            };
+            (velocity + amount * friction).clamp(-max, max)
+        };
+
+        if self.last.is_some() {
+            self.step(now, friction);
+        }
+        self.velocity = (
+            add(self.velocity.0, amount.0),
+            add(self.velocity.1, amount.1),
+        );
+        self.last = Some(now);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.last.is_some()
+    }
+
+    /* The distance covered since the last step, as the integral of the decaying
+     * velocity */
+    pub fn step(&mut self, now: Instant, friction: f32) -> (f32, f32) {
+        let Some(last) = self.last else {
+            return (0.0f32, 0.0f32);
+        };
+
+        let elapsed = now.saturating_duration_since(last).as_secs_f32();
+        let decay = (-friction * elapsed).exp();
+        let covered = (1.0 - decay) / friction;
+        let delta = (self.velocity.0 * covered, self.velocity.1 * covered);
+        self.velocity = (self.velocity.0 * decay, self.velocity.1 * decay);
+        self.last = Some(now);
+
+        if f32::hypot(self.velocity.0, self.velocity.1) < MIN_VELOCITY {
+            *self = Self::default();
+        }
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glide() {
+        for easing in [
+            ConfigScrollEasing::Linear,
+            ConfigScrollEasing::EaseOut,
+            ConfigScrollEasing::EaseInOut,
+        ] {
+            assert!(ease(easing, 0.0) == 0.0);
+            assert!(ease(easing, 1.0) == 1.0);
+            assert!(ease(easing, 2.0) == 1.0);
+        }
+        assert!(ease(ConfigScrollEasing::Linear, 0.25) == 0.25);
+        assert!(ease(ConfigScrollEasing::EaseOut, 0.5) == 0.875);
+        assert!(ease(ConfigScrollEasing::EaseInOut, 0.5) == 0.5);
+
+        let start = Instant::now();
+        let duration = Duration::from_millis(100);
+        let linear = ConfigScrollEasing::Linear;
+        let mut glide = Glide::default();
+        assert!(!glide.is_running());
+        assert!(glide.step(start, duration, linear) == (0.0, 0.0));
+
+        glide.push((0.0, 20.0), start);
+        assert!(glide.is_running());
+        let half = start + Duration::from_millis(50);
+        let (x, y) = glide.step(half, duration, linear);
+        assert!(x == 0.0 && (y - 10.0).abs() < 1e-3);
+
+        /* The remaining distance is carried into the next push */
+        glide.push((0.0, 20.0), half);
+        let end = half + duration;
+        let (x, y) = glide.step(end, duration, linear);
+        assert!(x == 0.0 && (y - 30.0).abs() < 1e-3);
+        assert!(!glide.is_running());
+
+        let (friction, max) = (8.0, 1000.0);
+        let mut momentum = Momentum::default();
+        assert!(momentum.step(start, friction) == (0.0, 0.0));
+        momentum.push((0.0, 20.0), start, friction, max);
+        assert!(momentum.velocity == (0.0, 160.0));
+
+        /* A single event comes to a stop after about its amount */
+        let mut covered = 0.0;
+        let mut now = start;
+        while momentum.is_running() {
+            now += FRAME_INTERVAL;
+            covered += momentum.step(now, friction).1;
+        }
+        assert!(covered > 19.0 && covered <= 20.0);
+
+        /* Rapid events build up speed up to the maximum, the other way stops first */
+        for _ in 0..10 {
+            momentum.push((0.0, 20.0), start, friction, max);
+        }
+        assert!(momentum.velocity == (0.0, 1000.0));
+        momentum.push((0.0, -20.0), start, friction, max);
+        assert!(momentum.velocity == (0.0, -160.0));
+    }
+}
@@ -1,28 +1,97 @@
-use crate::{drivers::graphics::GraphicsResponse, Config};
+use crate::{config::Config, drivers::graphics::GraphicsResponse};
 use crossbeam_channel::Receiver;
 use crossterm::terminal::WindowSize;
-use std::sync::{atomic::AtomicBool, Mutex, OnceLock, RwLock};
+use std::{
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Mutex, OnceLock, RwLock},
+};
 
 pub const HELP_MSG: &str = r#"meowpdf kitty terminal document viewer
 
-Usage: meowpdf <file>
+Usage: meowpdf [file ..]
+       meowpdf -
+       meowpdf meowpdf://<file>?page=<page>&zoom=<percent>
+       meowpdf <file>#page=<page>&zoom=<percent>
+       meowpdf <file>#nameddest=<named destination>
+       meowpdf --compare <file> <file>
+       meowpdf --serve <file>
+       meowpdf --remote <file|deep link>
+       meowpdf --query <socket> page
+       meowpdf --prerender <file>
+       meowpdf --export-html <file> <directory>
+       meowpdf --export <png> [--page <page|first-last>] [--dpi <dpi>] <file>
+       meowpdf --dest <named destination> <file>
+       meowpdf --section <outline title> <file>
+       meowpdf --record <recording> <file>
+       meowpdf --replay <recording> <file>
+       meowpdf --safe-mode <file>
+       meowpdf --sandbox <file>
+       meowpdf --check
+       meowpdf [--page <page>] [--scale <scale>] [--inverse] [--alpha]
+               [--config <config>] [--no-watch] <file> ..
 
 Global options:
 -h, --help          Print this usage information.
 -v, --version       Print the current version.
+-                   Read the document from stdin.
+--compare           Display two documents side by side with locked scrolling.
+--serve             Open the documents and positions sent with `--remote`.
+--remote            Make the instance started with `--serve` open a document.
+--query             Print the document, the page and the progress of the instance
+                    listening on the socket, for status lines.
+--prerender         Store every page of a document in the disk cache.
+--check             Probe the features of the terminal and print the results.
+--export-html       Write the text and images of a document as HTML pages.
+--export            Write pages of a document as PNG files, numbered when there
+                    are more than one.
+--dest              Open a document at one of its named destinations.
+--section           Open a document at the section with the given title.
+--record            Write the key and mouse input with its timing to a file.
+--replay            Feed the input of a recording into the viewer.
+--safe-mode         Start with the built-in config, without listening for other
+                    instances, and print every step of the startup.
+--sandbox           Open untrusted documents with their pages rendered in a
+                    restricted process, without opening their links outside of
+                    the viewer, extracting images or running commands, plugins
+                    and scripts.
+
+Session options, which override the config until the viewer quits:
+--page              Start at the page, counted from 1.
+--scale             Start at the scale, such as 1.5.
+--inverse           Start with inverted colors.
+--alpha             Start with the white page backgrounds made transparent.
+--config            Read the config from the file instead of the config directory.
+--no-watch          Do not reload the documents when they change on disk.
+--                  Take the arguments after it as documents.
 "#;
 
 pub const VERSION: &str = "1.2.2";
 pub const RELEASED: &str = "2026-01-13";
 pub const CONFIG_FILENAME: &str = "meowpdf";
+/* Directory next to the config file that the Lua scripts are loaded from */
+pub const SCRIPTS_DIRNAME: &str = "meowpdf-scripts";
 pub const DEFAULT_CONFIG: &str = r#"
 [viewer]
 # Determines how fast the document is scrolled
 scroll_speed = 20.0
 # Determines at what precision the pages are rendered
 render_precision = 1.5
+# Render the pages at the current zoom so that they stay sharp when zooming in,
+# instead of at `render_precision` and the precision tiers
+zoom_rendering = true
+# Highest precision the pages are rendered at when zooming in
+zoom_precision_max = 3.0
+# Milliseconds the zoom has to stay the same before the pages are rendered again
+zoom_debounce = 250
+# Precision of the quick previews shown until pages are rendered in full, 0 disables
+# the previews
+preview_precision = 0.3
 # Determines the image data limit that the software holds in RAM (bytes)
 memory_limit = 314572800
+# Which rendered page is dropped first when the memory limit is reached: the one
+# farthest from the view with "Distance", the least recently displayed one with "Lru"
+# or the first rendered one with "Fifo". Pages on the screen are always kept
+eviction_policy = "Distance"
 # Minimum scale amount allowed
 scale_min = 0.2
 # Determines the default scale of the viewer when starting the viewer
@@ -35,6 +104,67 @@ margin_bottom = 10.0
 pages_preloaded = 3
 # Inverse vertical scroll
 inverse_scroll = false
+# Hand documents over to the instance that already displays them
+reuse_instance = false
+# Render pages with a lower scheduling priority to keep the input responsive
+background_priority = true
+# Render the pages in a separate process, so that a malformed document crashing the
+# renderer only fails the pages instead of the viewer. Every document is opened a
+# second time and its pages are piped back, --sandbox always does it
+isolate_renderer = false
+# Go through every page of a document opened with --sandbox in a restricted process
+# before displaying it, which further covers the links and the text
+sandbox_preflight = true
+# Bytes of rendered pages kept by the renderer, so that flipping a toggle such as
+# ToggleInverse back shows the pages rendered before instead of rendering them again.
+# 0 disables it
+render_cache = 104857600
+# Number of queued pages from which a rendering indicator is shown
+queue_hint_threshold = 4
+# Frames drawn per second at most. The changes in between are drawn together in the
+# next frame, 0 draws after every change
+max_fps = 60.0
+# Turn DOIs, arXiv identifiers and ISBNs printed in the text into links
+detect_identifiers = true
+# Also match the search terms split by a hyphen at the end of a line, like "render-" and
+# "ing" for "rendering"
+search_hyphenation = true
+# Language of the documents as a tag such as "de", which decides the quotation marks
+# that the search matches with plain ones. Taken from the document or detected in its
+# text when empty
+language = ""
+# Replace the quotation marks of the language with plain `"` and `'` in copied text
+plain_quotes = false
+# Resolution in dots per inch of the pages exported as PNG files
+export_dpi = 150.0
+# Directory whose executables are started as plugins, which receive the events of the
+# viewer and register commands as JSON lines on their stdin and stdout. Empty for none
+plugin_directory = ""
+# Characters the pages are drawn with in terminals that can not display images, either
+# colored half blocks with "Blocks" or dithered dots for text-heavy pages with "Braille"
+fallback = "Blocks"
+# What scrolling past the last page does: "Stop", "Wrap" to the first page, display
+# the tab of the "NextDocument" given on the command line or "Quit"
+end_of_document = "Stop"
+# Whether the pages are placed below each other with "Vertical" or next to each other
+# with "Horizontal", where moving left and right scrolls through them
+page_layout = "Vertical"
+# How the rendered pages are sent to the terminal, one of "TempFile", "SharedMemory"
+# and "Direct". "Auto" measures all of them on the first run in a terminal and uses
+# the fastest one from then on
+transfer_medium = "Auto"
+# Render precisions used up to the given zoom, `render_precision` is used above them,
+# e.g. [{ zoom = 0.75, precision = 1.0 }, { zoom = 1.5, precision = 1.5 }]
+precision_tiers = []
+# Filters applied in order to the rendered pages. Available filters are `invert`,
+# `grayscale`, `gamma=<value>`, `contrast=<value>` and `brightness=<value>`
+filters = []
+# Color in [r, g, b] between 0 and 1 that white paper is drawn with, black text stays
+# black, e.g. [0.98, 0.94, 0.84] for sepia or [0.99, 0.96, 0.89] for solarized light
+page_background = []
+# Color in [r, g, b, strength] between 0 and 1 that the pages are blended with after
+# the background and the inversion, e.g. [1.0, 0.6, 0.2, 0.15] for a warm tint
+page_tint = []
 
 [viewer.uri_hint]
 # Enabled URI hints
@@ -45,10 +175,106 @@ background = "blue"
 foreground = "white"
 # Hint bar width percentage based on terminal width
 width = 0.2 
+# Seconds without mouse movement after which the hint is hidden and the pointer shape
+# is reset, 0 keeps the hint until the mouse leaves the link
+hide_after = 3.0
+
+[viewer.quirks]
+# Workarounds for terminals deviating from the graphics protocol are looked up by the
+# name the terminal reports. Pixels every image is shifted by as [x, y], [] keeps the
+# built-in value
+placement_offset = []
+# Largest chunk of image data sent in one escape sequence, 0 keeps the built-in value
+chunk_size = 0
+
+[viewer.layout]
+# Size of the pages in points that reflowable documents such as EPUB and FB2 files are
+# laid out on, and the size of their text
+width = 450.0
+height = 600.0
+font_size = 11.0
+
+[viewer.format]
+# Pattern of the dates in the overlays, in which `%Y`, `%m`, `%d`, `%H`, `%M` and `%S`
+# are replaced with the year, month, day, hour, minute and second
+date = "%Y-%m-%d %H:%M"
+# Separators of large numbers such as file sizes, taken from the locale in `LC_ALL`,
+# `LC_NUMERIC` or `LANG` when empty
+thousands_separator = ""
+decimal_separator = ""
+
+[viewer.stamp]
+# Text that `:stamp` puts onto the pages, in which `%page` and `%pages` are replaced
+# with the number of the page and the number of pages
+text = "DRAFT"
+# Path of an image that is stamped instead of the text when given
+image = ""
+font_size = 48.0
+# Opacity of the stamp between 0 and 1
+opacity = 0.3
+# Color of the text as [r, g, b] between 0 and 1
+color = [0.8, 0.0, 0.0]
+# Where the stamp is put onto the pages, either "Center", "Top" or "Bottom"
+position = "Center"
+
+[viewer.citation]
+# Citation that `CopyCitation` copies, either "BibTeX" or "Plain"
+style = "BibTeX"
+# Pattern of the "Plain" style, in which `%title`, `%author`, `%year` and `%doi` are
+# replaced with the title, the authors, the year and the DOI of the document
+template = "%author (%year). %title. %doi"
+
+[viewer.smooth_scroll]
+# Glide to the new position when scrolling with the keys and the mouse wheel instead of
+# jumping there at once
+enabled = true
+# Seconds that every scroll is spread over, 0 jumps at once
+duration = 0.12
+# How the scroll speeds up and slows down: "Linear", "EaseOut" or "EaseInOut"
+easing = "EaseOut"
+
+[viewer.kinetic_scroll]
+# Let the mouse wheel and touchpads build up speed with rapid events and coast to a
+# stop, instead of scrolling by `scroll_speed` with every event
+enabled = true
+# How fast the scroll slows down, as the rate per second at which its speed decays
+friction = 8.0
+# Highest speed of the scroll in units per second
+max_velocity = 3000.0
+
+[viewer.context]
+# Mirror the page, the section, the hovered link and the selected text as a line of
+# text for screen readers and status bars: "Off", a "File" at `path` rewritten with
+# every change, an existing "Fifo" at `path` getting a line for every change or "Osc"
+# notifications of the terminal, written as OSC 777
+output = "Off"
+path = "/tmp/meowpdf-context"
+
+[bar]
+# Enabled status bar
+enabled = true
+# Line of the terminal the bar is shown on, either "Top" or "Bottom"
+position = "Bottom"
+# Background color of the status bar
+background = "dark_grey"
+# Foreground color of the status bar text
+foreground = "white"
+# Segments aligned to the left and to the right of the bar. `{page}`, `{pages}`,
+# `{scale}`, `{file}`, `{memory}`, `{tab}` and `{tabs}` are replaced with the current
+# page, the number of pages, the zoom in percent, the name of the document, the memory
+# taken by the rendered pages, the displayed tab and the number of tabs. `{toggles}` is
+# replaced with letters for the active toggles, which are turned off by clicking them,
+# and `{script}` with the status segments of the Lua scripts
+left = "{file}"
+right = "{toggles} {page}/{pages} {scale}% "
 
 [bindings]
 "Ctrl+a" = "ToggleAlpha"
-"Ctrl+o" = "ToggleInverse"
+"Ctrl+n" = "ToggleInverse"
+"Ctrl+d" = "ToggleDiff"
+"Ctrl+p" = "TogglePaper"
+"Ctrl+t" = "ToggleTextMode"
+"Ctrl+k" = "CycleColorBlind"
 "C" = "CenterViewer"
 "h" = "MoveLeft"
 "j" = "MoveDown"
@@ -62,21 +288,65 @@ width = 0.2
 "-" = "ZoomOut"
 "g g" = "JumpFirstPage"
 "G" = "JumpLastPage"
+"g t" = "NextTab"
+"g T" = "PrevTab"
+"m" = "SetMark"
+"'" = "JumpMark"
+"Ctrl+o" = "JumpBack"
+"x" = "MarkPage"
 "PageUp" = "PrevPage"
 "PageDown" = "NextPage"
 "Ctrl+b" = "PrevPage"
 "Ctrl+f" = "NextPage"
+"[ f" = "PrevFigure"
+"] f" = "NextFigure"
+"z" = "ZoomBlock"
+"Esc" = "ClosePopup"
+"y i" = "CopyImage"
+"r" = "InvertRegion"
+"R" = "ClearRegions"
+"Y" = "CopyDeepLink"
+"E" = "ExportPage"
+"I" = "ShowInfo"
+"f" = "HintLinks"
+":" = "CommandLine"
+"/" = "Search"
+"n" = "NextMatch"
+"N" = "PrevMatch"
 "q" = "Quit"
 "Q" = "Quit"
 "#;
 
 /* Hate on me for those global singletons as much as you want. */
+/** The config that everything reads, set once at startup */
 pub static CONFIG: OnceLock<Config> = OnceLock::new();
+/** The answers of the terminal to the kitty graphics commands */
 pub static RECEIVER_GR: OnceLock<Mutex<Receiver<GraphicsResponse>>> = OnceLock::new();
+/** The size of the terminal in cells and in pixels, updated when it is resized */
 pub static TERMINAL_SIZE: OnceLock<RwLock<WindowSize>> = OnceLock::new();
+/** The padding in pixels around every rendered page */
 pub static IMAGE_PADDING: OnceLock<usize> = OnceLock::new();
+/** A random ID unique to every instance, which tells its shared memory apart */
 pub static SOFTWARE_ID: OnceLock<String> = OnceLock::new();
 pub static RUNNING: AtomicBool = AtomicBool::new(true);
+/* Set when the terminal does not support the Kitty graphics protocol and the pages
+ * are drawn out of colored cells instead */
+pub static BLOCK_ART: AtomicBool = AtomicBool::new(false);
+/* Set while another program, such as the editor of the notes, reads the terminal */
+pub static INPUT_PAUSED: AtomicBool = AtomicBool::new(false);
+/* Set by `--safe-mode`, which logs the steps of the startup to `STARTUP_LOG` */
+pub static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+pub static STARTUP_LOG: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/* The temporary file of a document read from stdin, which is not watched for changes
+ * and is removed at exit */
+pub static STDIN_DOCUMENT: OnceLock<String> = OnceLock::new();
+/* The config file given with `--config` instead of the one in the config directory */
+pub static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+/* Set by `--sandbox` for documents from untrusted sources, whose links are not opened
+ * outside of the viewer, whose images are not extracted and which run no hooks */
+pub static SANDBOX: AtomicBool = AtomicBool::new(false);
+/* Set by `--no-watch`, which leaves changed documents alone until they are reloaded */
+pub static NO_WATCH: AtomicBool = AtomicBool::new(false);
 
 #[macro_export]
 macro_rules! chan_has {
@@ -0,0 +1,154 @@
+use std::io::{self, Write};
+
+use crossterm::{
+    cursor::{MoveTo, RestorePosition, SavePosition},
+    queue,
+    style::{Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+};
+use mupdf::Link;
+
+use crate::CONFIG;
+
+/* The keys that the labels are made of, the home row first */
+const HINT_KEYS: [char; 9] = ['a', 's', 'd', 'f', 'g', 'h', 'j', 'k', 'l'];
+
+/* Labels of the same length for `count` links, so that no label is the beginning of
+ * another one and a link is followed as soon as its label has been typed */
+fn hint_labels(count: usize) -> Vec<String> {
+    let mut length = 1;
+    while HINT_KEYS.len().pow(length) < count {
+        length += 1;
+    }
+
+    (0..count)
+        .map(|mut i| {
+            let mut label = vec![' '; length as usize];
+            for x in label.iter_mut().rev() {
+                *x = HINT_KEYS[i % HINT_KEYS.len()];
+                i /= HINT_KEYS.len();
+            }
+            label.into_iter().collect()
+        })
+        .collect()
+}
+
+/* What typing a key in the hint mode leads to */
+pub enum HintInput {
+    Pending,
+    /* The pane and the link whose label has been typed in full */
+    Follow(usize, Link),
+    /* No label starts with what has been typed */
+    Cancel,
+}
+
+/* The labels shown on the links of the screen while `HintLinks` waits for one of them
+ * to be typed */
+pub struct LinkHints {
+    /* The label, the pane, the page and the link of every hint */
+    hints: Vec<(String, usize, usize, Link)>,
+    typed: String,
+}
+
+impl LinkHints {
+    /* Labels the links given as their pane, their page and the link. `None` when there
+     * is no link to label */
+    pub fn new(links: Vec<(usize, usize, Link)>) -> Option<Self> {
+        if links.is_empty() {
+            return None;
+        }
+
+        let hints = hint_labels(links.len())
+            .into_iter()
+            .zip(links)
+            .map(|(label, (pane, page, link))| (label, pane, page, link))
+            .collect();
+        Some(Self {
+            hints,
+            typed: String::new(),
+        })
+    }
+
+    pub fn type_key(&mut self, key: char) -> HintInput {
+        self.typed.push(key.to_ascii_lowercase());
+
+        let mut matching = self.hints.iter().filter(|x| x.0.starts_with(&self.typed));
+        match matching.next() {
+            Some((label, pane, _, link)) if *label == self.typed => {
+                HintInput::Follow(*pane, link.clone())
+            }
+            Some(_) => HintInput::Pending,
+            None => HintInput::Cancel,
+        }
+    }
+
+    /* Forgets the last typed key */
+    pub fn back(&mut self) {
+        self.typed.pop();
+    }
+
+    /* Prints what is left to type of the matching labels at the top left corner of
+     * their links. `cell` places the link of the page of the pane on the screen */
+    pub fn display(&self, cell: impl Fn(usize, usize, &Link) -> Option<(u16, u16)>) {
+        let config = CONFIG.get().unwrap();
+
+        let mut handle = io::stdout().lock();
+        for (label, pane, page, link) in self.hints.iter() {
+            let Some(rest) = label.strip_prefix(&self.typed) else {
+                continue;
+            };
+            let Some((column, row)) = cell(*pane, *page, link) else {
+                continue;
+            };
+
+            let _ = queue!(
+                handle,
+                SavePosition,
+                MoveTo(column, row),
+                SetBackgroundColor(config.viewer.uri_hint.foreground),
+                SetForegroundColor(config.viewer.uri_hint.background),
+                Print(rest),
+                ResetColor,
+                RestorePosition
+            );
+        }
+        let _ = handle.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(uri: &str) -> Link {
+        Link {
+            bounds: mupdf::Rect {
+                x0: 0.0,
+                y0: 0.0,
+                x1: 10.0,
+                y1: 10.0,
+            },
+            page: 0,
+            uri: uri.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_link_hints() {
+        assert!(hint_labels(3) == vec!["a", "s", "d"]);
+        let labels = hint_labels(10);
+        assert!(labels[0] == "aa" && labels[8] == "al" && labels[9] == "sa");
+        assert!(hint_labels(81).iter().all(|x| x.len() == 2));
+        assert!(hint_labels(82)[81] == "saa");
+
+        assert!(LinkHints::new(Vec::new()).is_none());
+        let links = (0..10).map(|i| (i % 2, 0, link(&i.to_string()))).collect();
+        let mut hints = LinkHints::new(links).unwrap();
+        assert!(matches!(hints.type_key('s'), HintInput::Pending));
+        assert!(matches!(hints.type_key('A'), HintInput::Follow(1, x) if x.uri == "9"));
+
+        hints.back();
+        hints.back();
+        assert!(matches!(hints.type_key('a'), HintInput::Pending));
+        assert!(matches!(hints.type_key('z'), HintInput::Cancel));
+    }
+}
@@ -1,21 +1,40 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::{
-    drivers::graphics::*, viewer::DisplayRect, CONFIG, IMAGE_PADDING, TERMINAL_SIZE,
+    config::ConfigFallback,
+    drivers::{
+        blockart::{terminal_blockart_display, terminal_braille_display},
+        graphics::*,
+    },
+    globals::BLOCK_ART,
+    viewer::DisplayRect,
+    CONFIG, IMAGE_PADDING, TERMINAL_SIZE,
 };
 
 use mupdf::Pixmap;
 
+/* Z-index < -1,073,741,824 will make the images to be drawn behind
+ * cells with colored background */
+pub const Z_INDEX_PAGE: i32 = -1073741826;
+/* Inverted regions are composited above the pages they belong to */
+pub const Z_INDEX_REGION: i32 = -1073741825;
+/* Popups are drawn above the pages but still behind the text */
+pub const Z_INDEX_POPUP: i32 = -1073741824;
+
+/** A rendered page kept in the memory of the viewer until it is displayed, and
+ * transferred to the terminal through the graphics backend in use */
 pub struct Image {
     id: usize,
     /* Stores the dimension of the zoomed in bitmap WITHOUT padding */
     dimensions: (i32, i32),
+    /* The precision that the bitmap has been rasterized at */
+    precision: f64,
     data: Vec<u8>,
 }
 
 impl Image {
-    pub fn new(pixmap: &Pixmap) -> Result<Self, String> {
-        static ID: AtomicUsize = AtomicUsize::new(1);
+    pub fn new(pixmap: &Pixmap, precision: f64) -> Result<Self, String> {
+        static ID: AtomicUsize = AtomicUsize::new(0);
 
         const PADDING_CLR: u8 = 0u8;
         let padding = *IMAGE_PADDING.get().unwrap();
@@ -57,8 +76,9 @@ impl Image {
         ));
 
         let image = Self {
-            id: ID.load(Ordering::Acquire),
+            id: instance_id(ID.load(Ordering::Acquire)),
             dimensions: (pixmap.width() as i32, pixmap.height() as i32),
+            precision,
             data,
         };
 
@@ -72,113 +92,173 @@ impl Image {
         self.id
     }
 
+    pub fn dimensions(&self) -> (i32, i32) {
+        self.dimensions
+    }
+
     #[allow(dead_code)]
     pub fn size(&self) -> usize {
         self.data.len()
     }
 
-    #[allow(dead_code)]
-    pub fn check(&self) -> Result<(), String> {
-        /* The first pixels should be invisible and therefore we have an easy if
-         * the image still exists */
-        terminal_graphics_display_image(self.id, 1, 1, (1, 1, 1, 1), 2, 2)?;
-        Ok(())
+    /* Returns `true` if a check has been issued and a graphics response should be
+     * expected */
+    pub fn check(&self) -> Result<bool, String> {
+        if BLOCK_ART.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+
+        graphics_backend().check(&self.bitmap())
+    }
+
+    pub fn display(&self, rect: DisplayRect, clip: DisplayRect) -> Result<bool, String> {
+        self.display_layer(rect, clip, Z_INDEX_PAGE)
     }
 
-    pub fn display(&self, rect: DisplayRect) -> Result<bool, String> {
+    /* Displays the image on the given rectangle while only showing the part inside
+     * of `clip`. The clipping rectangle is expected to be aligned to terminal cells */
+    pub fn display_layer(
+        &self,
+        rect: DisplayRect,
+        clip: DisplayRect,
+        z: i32,
+    ) -> Result<bool, String> {
         /* `true` indicates that the image was actually displayed and was not
          * tried to be displayed outside of the viewpoint */
 
-        let config = CONFIG.get().unwrap();
         let padding = *IMAGE_PADDING.get().unwrap();
-        let render_precision = config.viewer.render_precision;
+        let render_precision = self.precision;
 
         let scale = rect.height as f64 / (self.dimensions.1 as f64 / render_precision);
         let render_precision_norm = render_precision / scale;
 
         let (pxpercol, pxperrow);
+        let (visible_x0, visible_x1, visible_y0, visible_y1);
         let (col0, col1, row0, row1);
         let (padding_top, padding_bottom);
         let (padding_left, padding_right);
         let (cropx, cropy, cropw, croph);
 
-        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        {
+            let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+            pxpercol = terminal_size.width as f64 / terminal_size.columns as f64;
+            pxperrow = terminal_size.height as f64 / terminal_size.rows as f64;
+        }
 
-        pxpercol = terminal_size.width as f64 / terminal_size.columns as f64;
-        pxperrow = terminal_size.height as f64 / terminal_size.rows as f64;
+        /* The part of the image rectangle that is inside the clipping rectangle */
+        visible_x0 = i32::max(rect.x, clip.x) as f64;
+        visible_x1 = i32::min(rect.x + rect.width, clip.x + clip.width) as f64;
+        visible_y0 = i32::max(rect.y, clip.y) as f64;
+        visible_y1 = i32::min(rect.y + rect.height, clip.y + clip.height) as f64;
 
-        if rect.x < 0 {
-            col0 = 0.0f64;
-        } else {
-            col0 = rect.x as f64 / pxpercol;
+        /* If trying to display outside of the clipping rectangle just return */
+        if visible_x1 <= visible_x0 || visible_y1 <= visible_y0 {
+            return Ok(false);
         }
-        col1 = (rect.x as f64 + rect.width as f64) / pxpercol;
 
-        if rect.y < 0 {
-            row0 = 0.0f64;
-        } else {
-            row0 = rect.y as f64 / pxperrow;
-        }
-        row1 = (rect.y as f64 + rect.height as f64) / pxperrow;
+        col0 = visible_x0 / pxpercol;
+        col1 = visible_x1 / pxpercol;
+        row0 = visible_y0 / pxperrow;
+        row1 = visible_y1 / pxperrow;
 
         /* Round up to the nearest whole col and row so that is guaranteed that the
          * the whole image is rendered without being shrinked down. `padding_*` values
          * tell how much of the image's invinsible padding should be included at each
-         * side when displaying the image on an area of integer rows and column */
+         * side when displaying the image on an area of integer rows and column.
+         * Clipped sides lie on cell boundaries and therefore get no padding */
         padding_left = (col0 - col0.floor()) * pxpercol * render_precision_norm;
         padding_right = (col1.ceil() - col1) * pxpercol * render_precision_norm;
         padding_top = (row0 - row0.floor()) * pxperrow * render_precision_norm;
         padding_bottom = (row1.ceil() - row1) * pxperrow * render_precision_norm;
 
-        if rect.x < 0 {
-            cropx = (padding as f64 - rect.x as f64 * render_precision_norm) as usize;
-            cropw = ((col1 * pxpercol * render_precision_norm) + padding_right) as usize;
-        } else {
-            cropx = (padding as f64 - padding_left) as usize;
-            cropw = (padding_left + padding_right + self.dimensions.0 as f64) as usize;
-        }
-
-        if rect.y < 0 {
-            cropy = (padding as f64 - rect.y as f64 * render_precision_norm) as usize;
-            croph = ((row1 * pxperrow * render_precision_norm) + padding_bottom) as usize;
-        } else {
-            cropy = (padding as f64 - padding_top) as usize;
-            croph = (padding_top + padding_bottom + self.dimensions.1 as f64) as usize;
-        }
-
-        /* If trying to display outside of terminal just return */
-        if col1 < 0.0f64
-            || row1 < 0.0f64
-            || col0 > terminal_size.columns as f64
-            || row0 > terminal_size.rows as f64
-        {
+        cropx = (padding as f64 + (visible_x0 - rect.x as f64) * render_precision_norm
+            - padding_left) as usize;
+        cropw = ((visible_x1 - visible_x0) * render_precision_norm
+            + padding_left
+            + padding_right) as usize;
+        cropy = (padding as f64 + (visible_y0 - rect.y as f64) * render_precision_norm
+            - padding_top) as usize;
+        croph = ((visible_y1 - visible_y0) * render_precision_norm
+            + padding_top
+            + padding_bottom) as usize;
+
+        if BLOCK_ART.load(Ordering::Acquire) {
+            self.display_blockart(
+                (col0.floor() as u16, row0.floor() as u16),
+                (
+                    (col1.ceil() - col0.floor()) as u16,
+                    (row1.ceil() - row0.floor()) as u16,
+                ),
+                (cropx, cropy, cropw, croph),
+            )?;
+            /* No graphics response is sent for cells */
             return Ok(false);
         }
 
         /* Do not forget that columns and rows are one-indexed in terminals */
-        terminal_graphics_display_image(
-            self.id,
-            1 + col0.floor() as usize,
-            1 + row0.floor() as usize,
+        graphics_backend().display(
+            &self.bitmap(),
+            (1 + col0.floor() as usize, 1 + row0.floor() as usize),
             (cropx, cropy, cropw, croph),
-            (col1.ceil() - col0.floor()) as usize,
-            (row1.ceil() - row0.floor()) as usize,
-        )?;
+            (
+                (col1.ceil() - col0.floor()) as usize,
+                (row1.ceil() - row0.floor()) as usize,
+            ),
+            z,
+        )
+    }
+
+    /* Draws the cropped part of the bitmap on the given cells */
+    fn display_blockart(
+        &self,
+        cell: (u16, u16),
+        cells: (u16, u16),
+        crop: (usize, usize, usize, usize),
+    ) -> Result<(), String> {
+        let padding = *IMAGE_PADDING.get().unwrap();
+        let stride = self.dimensions.0 as usize + 2 * padding;
+        let rows = self.dimensions.1 as usize + 2 * padding;
 
-        Ok(true)
+        let sample = |x: f64, y: f64| {
+            let px = usize::min(crop.0 + (x * crop.2 as f64) as usize, stride - 1);
+            let py = usize::min(crop.1 + (y * crop.3 as f64) as usize, rows - 1);
+            let i = (py * stride + px) * 4;
+
+            /* The padding and transparent parts show the terminal background */
+            if self.data[i + 3] < 128 {
+                None
+            } else {
+                Some([self.data[i], self.data[i + 1], self.data[i + 2]])
+            }
+        };
+
+        match CONFIG.get().unwrap().viewer.fallback {
+            ConfigFallback::Blocks => {
+                terminal_blockart_display(cell.0, cell.1, cells.0, cells.1, sample)
+            }
+            ConfigFallback::Braille => {
+                terminal_braille_display(cell.0, cell.1, cells.0, cells.1, sample)
+            }
+        }
     }
 
     pub fn transfer(&self) -> Result<(), String> {
-        let padding = *IMAGE_PADDING.get().unwrap();
+        if BLOCK_ART.load(Ordering::Acquire) {
+            return Ok(());
+        }
 
-        terminal_graphics_transfer_bitmap(
-            self.id,
-            self.dimensions.0 as usize + 2 * padding,
-            self.dimensions.1 as usize + 2 * padding,
-            self.data.as_slice(),
-            true,
-        )?;
+        graphics_backend().transfer(&self.bitmap())
+    }
+
+    /* The padded bitmap as the graphics backends take it */
+    fn bitmap(&self) -> Bitmap<'_> {
+        let padding = *IMAGE_PADDING.get().unwrap();
 
-        Ok(())
+        Bitmap {
+            id: self.id,
+            width: self.dimensions.0 as usize + 2 * padding,
+            height: self.dimensions.1 as usize + 2 * padding,
+            data: self.data.as_slice(),
+        }
     }
 }
@@ -0,0 +1,195 @@
+use std::{cell::RefCell, io, path::Path, rc::Rc};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crossterm::execute;
+use mupdf::{
+    native_device::NativeDevice, ColorParams, Device, Image, ImageFormat, Matrix, Pixmap,
+};
+
+use crate::{document::open_document, drivers::commands::CopyToClipboard};
+
+/* Largest data URI in bytes that is sent through the clipboard escape sequence, many
+ * terminals drop larger ones */
+const CLIPBOARD_LIMIT: usize = 1 << 20;
+
+/* An embedded raster image at its native resolution and its bounds on the page given
+ * as (x0, y0, x1, y1) in page units */
+pub struct PageImage {
+    pub pixmap: Pixmap,
+    pub bounds: (f32, f32, f32, f32),
+}
+
+/* Images are drawn into the unit square, so their bounds are the unit square mapped
+ * through the transformation they are drawn with */
+pub fn unit_square_bounds(ctm: [f32; 6]) -> (f32, f32, f32, f32) {
+    let [a, b, c, d, e, f] = ctm;
+    let corners = [
+        (e, f),
+        (a + e, b + f),
+        (c + e, d + f),
+        (a + c + e, b + d + f),
+    ];
+
+    corners.iter().fold(
+        (f32::INFINITY, f32::INFINITY, -f32::INFINITY, -f32::INFINITY),
+        |bounds, corner| {
+            (
+                f32::min(bounds.0, corner.0),
+                f32::min(bounds.1, corner.1),
+                f32::max(bounds.2, corner.0),
+                f32::max(bounds.3, corner.1),
+            )
+        },
+    )
+}
+
+/* Collects the raster images drawn on a page at their native resolution */
+struct ImageCollector {
+    images: Rc<RefCell<Vec<PageImage>>>,
+}
+
+impl NativeDevice for ImageCollector {
+    fn fill_image(&mut self, image: &Image, ctm: Matrix, _alpha: f32, _cp: ColorParams) {
+        if let Ok(pixmap) = image.to_pixmap() {
+            self.images.borrow_mut().push(PageImage {
+                pixmap,
+                bounds: unit_square_bounds([ctm.a, ctm.b, ctm.c, ctm.d, ctm.e, ctm.f]),
+            });
+        }
+    }
+}
+
+/* Extracts the embedded raster images of a page in the order they are drawn */
+pub fn page_images(file: &str, page: usize) -> Result<Vec<PageImage>, String> {
+    let document = open_document(file)?;
+    let page_loaded = document
+        .load_page(page as i32)
+        .map_err(|x| format!("Could not load page {}: {}", page + 1, x))?;
+
+    let images = Rc::new(RefCell::new(Vec::new()));
+    {
+        let device = Device::from_native(ImageCollector {
+            images: images.clone(),
+        })
+        .map_err(|x| format!("Could not create image device: {}", x))?;
+        page_loaded
+            .run(&device, &Matrix::IDENTITY)
+            .map_err(|x| format!("Could not extract images: {}", x))?;
+    }
+
+    Ok(images.take())
+}
+
+/* The topmost image drawn under the given point of a page */
+pub fn image_at(
+    file: &str,
+    page: usize,
+    point: (f32, f32),
+) -> Result<Option<PageImage>, String> {
+    let images = page_images(file, page)?;
+    Ok(images.into_iter().rev().find(|x| {
+        point.0 >= x.bounds.0
+            && point.0 <= x.bounds.2
+            && point.1 >= x.bounds.1
+            && point.1 <= x.bounds.3
+    }))
+}
+
+/* Copies an image to the clipboard as a PNG data URI. Images too large for the
+ * clipboard are left in the temporary directory and their path is copied instead.
+ * Returns the message describing what has been copied */
+pub fn copy_image(image: &PageImage, page: usize) -> Result<String, String> {
+    let path = std::env::temp_dir().join(format!("meowpdf-page{}-image.png", page + 1));
+    let path_str = path.to_str().ok_or("Invalid temporary directory")?;
+    image
+        .pixmap
+        .save_as(path_str, ImageFormat::PNG)
+        .map_err(|x| format!("Could not write {}: {}", path_str, x))?;
+
+    let data = std::fs::read(&path)
+        .map_err(|x| format!("Could not read {}: {}", path_str, x))?;
+    let uri = format!("data:image/png;base64,{}", STANDARD.encode(data));
+    if uri.len() > CLIPBOARD_LIMIT {
+        execute!(io::stdout(), CopyToClipboard(path_str.to_string()))
+            .map_err(|x| format!("Could not copy to clipboard: {}", x))?;
+        return Ok(format!(
+            "Image too large for the clipboard, saved to {}",
+            path_str
+        ));
+    }
+
+    let _ = std::fs::remove_file(&path);
+    execute!(io::stdout(), CopyToClipboard(uri))
+        .map_err(|x| format!("Could not copy to clipboard: {}", x))?;
+    Ok(format!(
+        "Copied {}x{} image",
+        image.pixmap.width(),
+        image.pixmap.height()
+    ))
+}
+
+/* Describes the images of a page, e.g. `Page 3: 1) 640x480 2) 1200x800` */
+pub fn list_images(file: &str, page: usize) -> Result<String, String> {
+    let images = page_images(file, page)?;
+    if images.is_empty() {
+        return Ok(format!("Page {} has no embedded images", page + 1));
+    }
+
+    let sizes: Vec<String> = images
+        .iter()
+        .enumerate()
+        .map(|(i, x)| format!("{}) {}x{}", i + 1, x.pixmap.width(), x.pixmap.height()))
+        .collect();
+    Ok(format!("Page {}: {}", page + 1, sizes.join(" ")))
+}
+
+/* Saves the selected images of a page, given as one-based indices, into the current
+ * working directory as `<document>-page<page>-image<index>.png` */
+pub fn save_images(
+    file: &str,
+    page: usize,
+    selection: &[usize],
+) -> Result<String, String> {
+    let images = page_images(file, page)?;
+    let stem = Path::new(file)
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "document".to_string());
+
+    let mut saved = Vec::new();
+    for index in selection {
+        let image = images.get(index - 1).ok_or(format!(
+            "Page {} has no image {}",
+            page + 1,
+            index
+        ))?;
+
+        let filename = format!("{}-page{}-image{}.png", stem, page + 1, index);
+        image
+            .pixmap
+            .save_as(&filename, ImageFormat::PNG)
+            .map_err(|x| format!("Could not write {}: {}", filename, x))?;
+        saved.push(filename);
+    }
+
+    Ok(format!("Saved {}", saved.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_square_bounds() {
+        /* A 200x100 image placed at (50, 20) */
+        assert!(
+            unit_square_bounds([200.0, 0.0, 0.0, 100.0, 50.0, 20.0])
+                == (50.0, 20.0, 250.0, 120.0)
+        );
+        /* Images flipped vertically as is common in PDF files */
+        assert!(
+            unit_square_bounds([200.0, 0.0, 0.0, -100.0, 50.0, 120.0])
+                == (50.0, 20.0, 250.0, 120.0)
+        );
+    }
+}
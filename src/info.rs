@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use mupdf::MetadataName;
+
+use crate::{
+    document::open_document,
+    format::{format_bytes, format_config_date, format_number, parse_pdf_date},
+    language::document_language,
+};
+
+/* Paper sizes in points that pages are named by, in portrait */
+const PAPER_SIZES: [(&str, (f32, f32)); 6] = [
+    ("A3", (842.0, 1191.0)),
+    ("A4", (595.0, 842.0)),
+    ("A5", (420.0, 595.0)),
+    ("Letter", (612.0, 792.0)),
+    ("Legal", (612.0, 1008.0)),
+    ("Tabloid", (792.0, 1224.0)),
+];
+
+/* A page size in points such as `595 × 842 pt (210 × 297 mm, A4)`. Sizes within a
+ * point of a paper size, in either orientation, are named by it */
+fn format_page_size((width, height): (f32, f32)) -> String {
+    let mm = |x: f32| (x * 25.4 / 72.0).round();
+    let portrait = (width.min(height), width.max(height));
+    let paper = PAPER_SIZES.iter().find(|(_, x)| {
+        (x.0 - portrait.0).abs() <= 1.0 && (x.1 - portrait.1).abs() <= 1.0
+    });
+
+    let mut size = format!(
+        "{:.0} × {:.0} pt ({} × {} mm",
+        width,
+        height,
+        mm(width),
+        mm(height)
+    );
+    if let Some((name, _)) = paper {
+        size.push_str(", ");
+        size.push_str(name);
+    }
+    size.push(')');
+    size
+}
+
+/* The lines of the `info` overlay with the metadata of a document with `pages` pages,
+ * the memory its rendered pages take, the size of the current page and the render
+ * settings of the viewer */
+pub fn info_summary(
+    file: &str,
+    pages: usize,
+    memory: usize,
+    page_size: Option<(f32, f32)>,
+    settings: &[(&str, String)],
+) -> Result<Vec<String>, String> {
+    let document = open_document(file)?;
+    let metadata = |name| document.metadata(name).unwrap_or_default();
+    /* Dates that can not be parsed are shown as the document gives them */
+    let date = |name| {
+        let raw = metadata(name);
+        parse_pdf_date(&raw).map_or(raw, |x| format_config_date(&x))
+    };
+
+    let name = Path::new(file)
+        .file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or(file.to_string());
+    let size = std::fs::metadata(file)
+        .map_err(|x| format!("Could not read metadata of {}: {}", file, x))?
+        .len();
+
+    let fields = [
+        ("Title", metadata(MetadataName::Title)),
+        ("Author", metadata(MetadataName::Author)),
+        ("Subject", metadata(MetadataName::Subject)),
+        ("Creator", metadata(MetadataName::Creator)),
+        ("Producer", metadata(MetadataName::Producer)),
+        ("Created", date(MetadataName::CreationDate)),
+        ("Modified", date(MetadataName::ModDate)),
+        ("Format", metadata(MetadataName::Format)),
+        ("Encryption", metadata(MetadataName::Encryption)),
+        (
+            "Language",
+            document_language(file, &document)
+                .map(|x| x.name().to_string())
+                .unwrap_or_default(),
+        ),
+        ("Pages", format_number(pages as u64)),
+        (
+            "Page size",
+            page_size.map(format_page_size).unwrap_or_default(),
+        ),
+        (
+            "File size",
+            format!("{} ({} bytes)", format_bytes(size), format_number(size)),
+        ),
+        ("Memory used", format_bytes(memory as u64)),
+    ];
+
+    let mut lines = vec![format!("Information about {}", name)];
+    for (label, value) in fields.iter().filter(|x| !x.1.trim().is_empty()) {
+        lines.push(format!("{:<14} {}", label, value.trim()));
+    }
+
+    lines.push(String::new());
+    lines.push("Render settings".to_string());
+    for (label, value) in settings {
+        lines.push(format!("{:<14} {}", label, value));
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_page_size() {
+        assert!(format_page_size((595.0, 842.0)) == "595 × 842 pt (210 × 297 mm, A4)");
+        assert!(
+            format_page_size((792.0, 612.3)) == "792 × 612 pt (279 × 216 mm, Letter)"
+        );
+        assert!(format_page_size((300.0, 400.0)) == "300 × 400 pt (106 × 141 mm)");
+    }
+}
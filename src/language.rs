@@ -0,0 +1,292 @@
+use mupdf::{pdf::PdfDocument, Document};
+
+use crate::text::page_paragraphs;
+
+/* How many of the first pages are sampled when the document does not name its
+ * language */
+const SAMPLE_PAGES: i32 = 3;
+/* Common words that have to be found in the sample before a language is detected */
+const MIN_HITS: usize = 5;
+
+/* Languages whose typography is taken into account in the search and in copied text */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Dutch,
+}
+
+/* Words that are common in a language and rare in the others */
+const STOPWORDS: [(Language, &[&str]); 6] = [
+    (
+        Language::English,
+        &["the", "and", "of", "is", "that", "with", "this", "are"],
+    ),
+    (
+        Language::German,
+        &["der", "die", "und", "ist", "nicht", "mit", "ein", "das"],
+    ),
+    (
+        Language::French,
+        &["le", "les", "et", "est", "des", "une", "dans", "pour"],
+    ),
+    (
+        Language::Spanish,
+        &["el", "los", "y", "es", "las", "una", "por", "del"],
+    ),
+    (
+        Language::Italian,
+        &["il", "di", "che", "è", "gli", "della", "sono", "per"],
+    ),
+    (
+        Language::Dutch,
+        &["het", "een", "van", "niet", "zijn", "wordt", "ook", "voor"],
+    ),
+];
+
+impl Language {
+    /* Reads a language tag such as `de-DE` or `fr` */
+    pub fn from_tag(tag: &str) -> Option<Language> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_lowercase();
+        match primary.as_str() {
+            "en" | "english" => Some(Language::English),
+            "de" | "german" => Some(Language::German),
+            "fr" | "french" => Some(Language::French),
+            "es" | "spanish" => Some(Language::Spanish),
+            "it" | "italian" => Some(Language::Italian),
+            "nl" | "dutch" => Some(Language::Dutch),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::German => "German",
+            Language::French => "French",
+            Language::Spanish => "Spanish",
+            Language::Italian => "Italian",
+            Language::Dutch => "Dutch",
+        }
+    }
+
+    /* The quotation marks of the language with the plain marks they stand for */
+    pub fn quotes(&self) -> &'static [(char, char)] {
+        match self {
+            Language::English => &[('“', '"'), ('”', '"'), ('‘', '\''), ('’', '\'')],
+            Language::German => &[
+                ('„', '"'),
+                ('“', '"'),
+                ('»', '"'),
+                ('«', '"'),
+                ('‚', '\''),
+                ('‘', '\''),
+                ('’', '\''),
+            ],
+            Language::French => &[
+                ('«', '"'),
+                ('»', '"'),
+                ('“', '"'),
+                ('”', '"'),
+                ('‹', '\''),
+                ('›', '\''),
+                ('’', '\''),
+            ],
+            Language::Spanish | Language::Italian => &[
+                ('«', '"'),
+                ('»', '"'),
+                ('“', '"'),
+                ('”', '"'),
+                ('‘', '\''),
+                ('’', '\''),
+            ],
+            Language::Dutch => &[
+                ('„', '"'),
+                ('“', '"'),
+                ('”', '"'),
+                ('‚', '\''),
+                ('‘', '\''),
+                ('’', '\''),
+            ],
+        }
+    }
+
+    pub fn plain_quote(&self, x: char) -> Option<char> {
+        self.quotes().iter().find(|(y, _)| *y == x).map(|(_, y)| *y)
+    }
+}
+
+/* Guesses the language of the text by the common words it contains */
+pub fn detect_language(text: &str) -> Option<Language> {
+    let words: Vec<String> = text
+        .split(|x: char| !x.is_alphabetic())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect();
+
+    STOPWORDS
+        .iter()
+        .map(|(language, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|x| stopwords.contains(&x.as_str()))
+                .count();
+            (*language, hits)
+        })
+        .filter(|(_, hits)| *hits >= MIN_HITS)
+        .max_by_key(|(_, hits)| *hits)
+        .map(|(language, _)| language)
+}
+
+/* The language that the `/Lang` of a PDF names or else the one detected in the text
+ * of the first pages */
+pub fn document_language(file: &str, document: &Document) -> Option<Language> {
+    let tagged = PdfDocument::open(file)
+        .ok()
+        .and_then(|x| x.catalog().ok()?.get_dict("Lang").ok()?)
+        .and_then(|x| x.as_string().ok().map(|x| x.to_string()))
+        .and_then(|x| Language::from_tag(&x));
+    if tagged.is_some() {
+        return tagged;
+    }
+
+    let pages = document.page_count().unwrap_or(0);
+    let mut sample = String::new();
+    for i in 0..i32::min(pages, SAMPLE_PAGES) {
+        let Ok(page) = document.load_page(i) else {
+            continue;
+        };
+        for paragraph in page_paragraphs(&page).unwrap_or_default() {
+            sample.push_str(&paragraph);
+            sample.push('\n');
+        }
+    }
+    detect_language(&sample)
+}
+
+/* Expands the ligatures that fonts map to single characters */
+fn ligature(x: char) -> Option<&'static str> {
+    match x {
+        'ﬀ' => Some("ff"),
+        'ﬁ' => Some("fi"),
+        'ﬂ' => Some("fl"),
+        'ﬃ' => Some("ffi"),
+        'ﬄ' => Some("ffl"),
+        'ﬅ' | 'ﬆ' => Some("st"),
+        _ => None,
+    }
+}
+
+/* Spaces that French typography puts inside of guillemets */
+fn is_guillemet_space(x: char) -> bool {
+    matches!(x, ' ' | '\u{00A0}' | '\u{202F}' | '\u{2009}')
+}
+
+/* Normalizes the text copied from the pages. Ligatures are expanded and soft hyphens
+ * are dropped, joining the words they split at the end of a line. With `plain_quotes`
+ * the quotation marks of the language are replaced with `"` and `'` */
+pub fn normalize_text(
+    text: &str,
+    language: Option<Language>,
+    plain_quotes: bool,
+) -> String {
+    let mut normalized = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(x) = chars.next() {
+        if x == '\u{00AD}' {
+            if chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            continue;
+        }
+        if let Some(expanded) = ligature(x) {
+            normalized.push_str(expanded);
+            continue;
+        }
+
+        let quote = language
+            .filter(|_| plain_quotes)
+            .and_then(|y| y.plain_quote(x));
+        match (quote, language) {
+            (Some(quote), Some(Language::French)) => {
+                /* `« mot »` becomes `"mot"` */
+                if matches!(x, '»' | '›') && normalized.ends_with(is_guillemet_space) {
+                    normalized.pop();
+                }
+                normalized.push(quote);
+                if matches!(x, '«' | '‹')
+                    && chars.peek().is_some_and(|x| is_guillemet_space(*x))
+                {
+                    chars.next();
+                }
+            }
+            (Some(quote), _) => normalized.push(quote),
+            (None, _) => normalized.push(x),
+        }
+    }
+    normalized
+}
+
+/* Folds a character for the search, ignoring case and the difference between the
+ * quotation marks of the language and plain ones. Soft hyphens are skipped */
+pub fn fold_char(x: char, language: Option<Language>) -> Option<char> {
+    if x == '\u{00AD}' {
+        return None;
+    }
+    let x = language.and_then(|y| y.plain_quote(x)).unwrap_or(x);
+    Some(x.to_lowercase().next().unwrap_or(x))
+}
+
+/* Queries with quotation marks are searched by the folded characters, since the
+ * search of mupdf tells typographic marks apart from plain ones */
+pub fn needs_folding(query: &str, language: Option<Language>) -> bool {
+    query.chars().any(|x| {
+        matches!(x, '"' | '\'') || language.is_some_and(|y| y.plain_quote(x).is_some())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language() {
+        assert!(Language::from_tag("de-DE") == Some(Language::German));
+        assert!(Language::from_tag("EN_us") == Some(Language::English));
+        assert!(Language::from_tag("ja").is_none());
+        assert!(Language::from_tag("").is_none());
+
+        let german = "Die Katze ist nicht mit dem Hund und der Maus in das Haus \
+                      gegangen, weil die Tür nicht offen ist.";
+        assert!(detect_language(german) == Some(Language::German));
+        let english =
+            "The cat is on the mat and the dog is with the owner of this house.";
+        assert!(detect_language(english) == Some(Language::English));
+        assert!(detect_language("Lorem ipsum dolor sit amet").is_none());
+
+        let copied = "Die „Ef\u{FB01}zienz“ der Ver\u{00AD}\narbeitung";
+        assert!(
+            normalize_text(copied, Some(Language::German), false)
+                == "Die „Effizienz“ der Verarbeitung"
+        );
+        assert!(
+            normalize_text(copied, Some(Language::German), true)
+                == "Die \"Effizienz\" der Verarbeitung"
+        );
+        assert!(
+            normalize_text("« mot\u{202F}»", Some(Language::French), true) == "\"mot\""
+        );
+        assert!(normalize_text("« mot »", Some(Language::French), true) == "\"mot\"");
+        assert!(normalize_text("« mot »", None, true) == "« mot »");
+
+        assert!(fold_char('„', Some(Language::German)) == Some('"'));
+        assert!(fold_char('„', Some(Language::English)) == Some('„'));
+        assert!(fold_char('A', None) == Some('a'));
+        assert!(fold_char('\u{00AD}', None).is_none());
+        assert!(needs_folding("it's", None));
+        assert!(!needs_folding("its", Some(Language::English)));
+    }
+}
@@ -0,0 +1,61 @@
+/*! The core of MeowPDF without the terminal user interface: opening and rendering
+ * documents, laying the pages out in a `Viewer` and putting them onto the terminal
+ * through one of the graphics backends.
+ *
+ * A frontend sets `CONFIG` first, e.g. with `CONFIG.set(config_default()?)`, followed
+ * by `TERMINAL_SIZE`, `IMAGE_PADDING` and `SOFTWARE_ID`. The kitty backend further
+ * reads the answers of the terminal from `RECEIVER_GR`, which `threads::event` fills.
+ * Every document then gets a `Renderer` thread, whose results are handed to the
+ * `Viewer` that displays them. */
+
+/** The graphics backends: the kitty graphics protocol, sixel, iTerm2 inline images,
+ * block art and the frame capture of `--capture`, and the escape sequences around
+ * them */
+pub mod drivers;
+/** The threads of a viewer: the renderer with its worker process, the terminal
+ * input, the file watcher and the socket of `--serve` */
+pub mod threads;
+
+/** Rendered pages as they are transferred to the terminal */
+pub mod image;
+/** The layout of the pages of a document on the screen */
+pub mod viewer;
+
+/** The config file with its keybindings */
+pub mod config;
+/** The default config, the global state that the modules share and its helpers */
+pub mod globals;
+
+/** Highlight and underline annotations written into the document */
+pub mod annotate;
+/** The disk cache of prerendered pages and the memory cache of rendered ones */
+pub mod cache;
+/** Positions in documents shared as `meowpdf://` URIs */
+pub mod deeplink;
+/** Opening documents, including the ones read from stdin */
+pub mod document;
+/** Writing pages and documents, as PNG files, HTML pages or PDF copies */
+pub mod export;
+/** The per-pixel filters applied to the rendered pages */
+pub mod filters;
+/** Smooth and kinetic scrolling */
+pub mod glide;
+/** The language of a document and the folding of its text for searches */
+pub mod language;
+/** The outline of a document and its named destinations */
+pub mod outline;
+/** Recording the input of a session and replaying it */
+pub mod replay;
+/** The text of the pages: selections, matches, paragraphs and captions */
+pub mod text;
+/** Rotation and crop of the pages */
+pub mod transform;
+
+pub(crate) mod memory;
+pub(crate) mod stamp;
+
+pub use config::{config_default, config_load_or_create, Config};
+pub use globals::{CONFIG, IMAGE_PADDING, RECEIVER_GR, SOFTWARE_ID, TERMINAL_SIZE};
+pub use image::Image;
+pub use threads::renderer::Renderer;
+pub use viewer::Viewer;
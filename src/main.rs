@@ -1,70 +1,1532 @@
-mod drivers;
-use crate::drivers::commands::ClearImages;
+use meowpdf_core::*;
+use meowpdf_core::{
+    config::{
+        config_save_binding, ConfigAction, ConfigEndOfDocument, ConfigPageLayout,
+        ConfigTransferMedium, ConfigViewerQuirks,
+    },
+    globals::{
+        BLOCK_ART, CONFIG_FILE, HELP_MSG, INPUT_PAUSED, NO_WATCH, RELEASED, RUNNING,
+        SAFE_MODE, SANDBOX, SCRIPTS_DIRNAME, STARTUP_LOG, STDIN_DOCUMENT, VERSION,
+    },
+    viewer::{resize_placeholder, status_hint},
+};
+
+use crossbeam_channel::{after, never, tick, unbounded, Receiver, Select};
 use crossterm::cursor::{Hide, Show};
-use crossterm::event::{KeyEvent, KeyModifiers, MouseEvent, MouseEventKind};
+use crossterm::event::{
+    KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, MouseButton,
+    MouseEvent, MouseEventKind, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, window_size, Clear, ClearType,
     EnterAlternateScreen, LeaveAlternateScreen, WindowSize,
 };
+use deeplink::DeepLink;
+use drivers::blockart::BLOCKART_CELL;
+use drivers::capture::{CaptureBackend, CAPTURE_SIZE};
+use drivers::commands::ClearImages;
 use drivers::commands::{
-    DisableMouseCapturePixels, EnableMouseCapturePixels, PointerShape, SetPointerShape,
+    CopyToClipboard, DisableMouseCapturePixels, EnableMouseCapturePixels, PointerShape,
+    RequestAttention, SetPointerShape,
 };
-use drivers::graphics::terminal_graphics_test_support;
-use keybinds::{KeyInput, Keybinds};
-
-mod threads;
-use threads::event::InputEvent;
-
-mod image;
-use crate::image::*;
+use drivers::graphics::{
+    graphics_backend, instance_ids, terminal_graphics_benchmark,
+    terminal_graphics_deallocate_range, terminal_graphics_placed,
+    terminal_graphics_test_support, terminal_graphics_transfer_failed,
+    terminal_graphics_transfer_response, terminal_identity, terminal_supports_iterm2,
+    terminal_supports_sixel, GraphicsResponse, Iterm2Backend, KittyBackend, SixelBackend,
+    TerminalQuirks, TransferMedium, GRAPHICS_BACKEND, TERMINAL_QUIRKS, TRANSFER_MEDIUM,
+};
+use drivers::priority_channel::PriorityReceiver;
+use hints::{HintInput, LinkHints};
+use keybinds::{KeyInput, KeySeq, Keybind, Keybinds};
+use mupdf::Link;
 
-mod viewer;
-use crate::viewer::*;
+use threads::event::{AppEvent, InputEvent};
+use threads::fnotify::FileEvent;
+use threads::ipc::{file_socket, socket_dir, Request, Status, SERVE_SOCKET};
+use threads::renderer::{Renderer, RendererAction, RendererResult};
 
-mod globals;
-use crate::globals::*;
+mod args;
+mod bar;
+mod check;
+mod cite;
+mod command;
+mod context;
+mod external;
+mod format;
+mod hints;
+mod images;
+mod info;
+mod notes;
+mod organize;
+mod picker;
+mod plugin;
+mod qr;
+mod sandbox;
+mod script;
+mod state;
+mod stats;
 
-mod config;
-use crate::config::*;
+use args::Mode;
+use bar::{bar_toggle, display_bar, BarState, BarToggle};
+use cache::DiskCache;
+use command::{
+    count_input, parse_command, Command, CommandLine, CountInput, NoteEdit, OrganizeEdit,
+    OutlineEdit,
+};
+use context::{Context, ContextMirror};
+use document::open_document;
+use filters::{Daltonize, InverseMode};
+use notes::Notes;
+use organize::{describe_pages, Organizer};
+use outline::OutlineEditor;
+use plugin::{PluginEvent, Plugins};
+use replay::Recorder;
+use script::{ScriptRequest, ScriptState, Scripts};
+use state::{Bookmark, State};
+use stats::ReadingSession;
+use transform::PageTransform;
 
 use std::hash::RandomState;
 use std::hash::{BuildHasher, Hasher};
-use std::io;
-use std::sync::atomic::Ordering;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::sync::RwLock;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
+
+/* Relative change of the width of the cells in pixels from which the pages are scaled
+ * along with the cells */
+const CELL_CHANGE: f32 = 0.05;
+/* Time without resize events after which the final size of the terminal is applied */
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(100);
+/* Time without any events after which the stale previews are refreshed */
+const PREVIEW_IDLE: Duration = Duration::from_millis(500);
+/* Drawn into the corner of the pages that have notes */
+const NOTE_MARKER: &str = "✎";
 
 /* Tracks the last executed times of signals for throattling */
 struct LastExecuted {
     pub load: SystemTime,
-    pub alpha: SystemTime,
-    pub inverse: SystemTime,
+    /* Terminals that report released keys mark the repeated ones too. The others
+     * send repeats like presses, which are told apart by the time of the last toggle */
+    pub event_types: bool,
+    pub toggle: SystemTime,
+}
+
+impl LastExecuted {
+    /* Whether a toggle bound to the key would fire again while the key is held */
+    fn repeated_toggle(&mut self, key: &KeyEvent) -> bool {
+        if key.kind == KeyEventKind::Repeat {
+            return true;
+        }
+        if !self.event_types
+            && self.toggle.elapsed().unwrap() < Duration::from_millis(500)
+        {
+            return true;
+        }
+
+        self.toggle = SystemTime::now();
+        false
+    }
+}
+
+/* A document displayed in one pane of the terminal and the threads serving it */
+struct Pane {
+    pub file: String,
+    pub viewer: Viewer,
+    pub renderer: Renderer,
+    pub results: PriorityReceiver<RendererResult, 2>,
+    /* Tags the reloads and the rerender signals of the pane among the events */
+    pub id: usize,
+    /* Set while the document is removed, its last pages stay displayed meanwhile */
+    pub removed: bool,
+    /* Set while a changed document does not open yet, e.g. while it is written */
+    pub rebuilding: bool,
+    pub daltonize: Option<Daltonize>,
+    /* The toggles of the renderer, which are flipped together with it */
+    pub alpha: bool,
+    pub inverse: InverseMode,
+    pub diff: bool,
+    pub paper: bool,
+    pub reading: ReadingSession,
+    /* The position recorded when the document was closed the last time */
+    pub bookmark: Option<Bookmark>,
+    pub organizer: Organizer,
+    /* The outline that is being edited, read from the document on the first edit */
+    pub outline: Option<OutlineEditor>,
+    pub notes: Notes,
+}
+
+impl Pane {
+    pub fn open(
+        file: &str,
+        compare: Option<&str>,
+        index: usize,
+        count: usize,
+    ) -> Result<Self, String> {
+        /* Panes are never given the id of one that has been closed, whose events
+         * may still arrive */
+        static PANE_IDS: AtomicUsize = AtomicUsize::new(0);
+        let id = PANE_IDS.fetch_add(1, Ordering::Relaxed);
+
+        let mut viewer = Viewer::new(id);
+        viewer.set_pane(index, count);
+
+        let (mut renderer, results) = Renderer::new();
+        renderer.run(file, compare)?;
+        renderer.send_and_confirm_action(RendererAction::Load)?;
+
+        /* Nothing writes to the copy of a document read from stdin, `--no-watch`
+         * leaves the other documents alone as well */
+        if !(STDIN_DOCUMENT.get().is_some_and(|x| x == file)
+            || NO_WATCH.load(Ordering::Acquire))
+        {
+            threads::fnotify::spawn(file, id)?;
+        }
+
+        Ok(Self {
+            file: file.to_owned(),
+            viewer,
+            renderer,
+            results,
+            id,
+            removed: false,
+            rebuilding: false,
+            daltonize: None,
+            alpha: false,
+            inverse: InverseMode::Off,
+            diff: false,
+            paper: true,
+            reading: ReadingSession::start(&canonical(file)),
+            bookmark: State::load().bookmarks.remove(&canonical(file)),
+            organizer: Organizer::default(),
+            outline: None,
+            notes: Notes::open(&canonical(file)),
+        })
+    }
+}
+
+impl Drop for Pane {
+    fn drop(&mut self) {
+        threads::fnotify::unwatch(&self.file);
+        /* The statistics are lost when the state file can not be written */
+        let _ = self.reading.save();
+
+        /* Documents that have not been loaded yet keep their previous position, the
+         * copies of the ones read from stdin are not opened again */
+        if !self.viewer.is_uninit() && STDIN_DOCUMENT.get() != Some(&self.file) {
+            let mut state = State::load();
+            let bookmark = Bookmark {
+                page: self.viewer.page_first(),
+                scale: self.viewer.get_scale(),
+            };
+            state.bookmarks.insert(canonical(&self.file), bookmark);
+            let _ = state.save();
+        }
+    }
+}
+
+/* Documents given on the command line are opened in tabs, of which one is displayed
+ * at a time. The panes of the displayed tab are kept in the `panes` of the main loop
+ * while its slot is empty, the other tabs are opened when they are displayed first */
+struct Tabs {
+    files: Vec<String>,
+    panes: Vec<Vec<Pane>>,
+    current: usize,
+}
+
+impl Tabs {
+    fn new(files: Vec<String>) -> Self {
+        Self {
+            panes: files.iter().map(|_| Vec::new()).collect(),
+            files,
+            current: 0,
+        }
+    }
+
+    /* The tab `offset` tabs away from the current one, wrapping around at the ends */
+    fn offset(&self, offset: isize) -> usize {
+        (self.current as isize + offset).rem_euclid(self.files.len() as isize) as usize
+    }
+
+    /* Displays the tab by moving its panes into `panes` and the displayed ones back */
+    fn switch(&mut self, panes: &mut Vec<Pane>, tab: usize) -> Result<(), String> {
+        if tab == self.current {
+            return Ok(());
+        }
+        if self.panes[tab].is_empty() {
+            let pane = Pane::open(&self.files[tab], None, 0, 1)?;
+            self.panes[tab].push(pane);
+        }
+
+        std::mem::swap(panes, &mut self.panes[self.current]);
+        std::mem::swap(panes, &mut self.panes[tab]);
+        self.current = tab;
+        Ok(())
+    }
+}
+
+/* Notes a step of the startup in safe mode. The steps are printed by
+ * `flush_startup_log` whenever the terminal is cooked, since they would be drawn
+ * over by the pages otherwise */
+fn startup_log(step: String) {
+    if SAFE_MODE.load(Ordering::Acquire) {
+        STARTUP_LOG.lock().unwrap().push(step);
+    }
+}
+
+fn flush_startup_log() {
+    let Ok(mut log) = STARTUP_LOG.lock() else {
+        return;
+    };
+    for step in log.drain(..) {
+        eprintln!("[safe mode] {}", step);
+    }
+}
+
+/* Gives every cell the same pixel size when the pages are drawn out of cells, since
+ * terminals without image support often do not report their size in pixels */
+fn blockart_window_size(size: WindowSize) -> WindowSize {
+    if !BLOCK_ART.load(Ordering::Acquire) {
+        return size;
+    }
+
+    WindowSize {
+        rows: size.rows,
+        columns: size.columns,
+        width: size.columns * BLOCKART_CELL.0,
+        height: size.rows * BLOCKART_CELL.1,
+    }
+}
+
+/* The quirks known for the terminal with the overrides of the config applied */
+fn terminal_quirks(identity: &str, config: &ConfigViewerQuirks) -> TerminalQuirks {
+    let mut quirks = TerminalQuirks::lookup(identity);
+    if let [x, y] = config.placement_offset[..] {
+        quirks.placement_offset = (x, y);
+    }
+    if config.chunk_size > 0 {
+        quirks.chunk_size = config.chunk_size;
+    }
+    quirks
+}
+
+/* The configured transfer medium, or the fastest one measured in this terminal. The
+ * measurement is done once per terminal identity and remembered in the state file */
+fn select_transfer_medium(
+    config: ConfigTransferMedium,
+    identity: &str,
+) -> TransferMedium {
+    match config {
+        ConfigTransferMedium::TempFile => return TransferMedium::TempFile,
+        ConfigTransferMedium::SharedMemory => return TransferMedium::SharedMemory,
+        ConfigTransferMedium::Direct => return TransferMedium::Direct,
+        ConfigTransferMedium::Auto => {}
+    }
+
+    let mut state = State::load();
+    if let Some(medium) = state.transfer.get(identity) {
+        return *medium;
+    }
+
+    /* Keep the medium that has always been used when the measurement fails */
+    let medium = terminal_graphics_benchmark().unwrap_or(TransferMedium::TempFile);
+    state.transfer.insert(identity.to_string(), medium);
+    let _ = state.save();
+    medium
+}
+
+/* Hands the terminal over to `$VISUAL` or `$EDITOR`, `vi` if neither is set, to edit
+ * the text in a temporary file. Returns the edited text */
+fn edit_text(text: &str) -> Result<String, String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let path =
+        std::env::temp_dir().join(format!("meowpdf-note-{}.txt", std::process::id()));
+    std::fs::write(&path, text)
+        .map_err(|x| format!("Could not write {}: {}", path.display(), x))?;
+
+    INPUT_PAUSED.store(true, Ordering::Release);
+    let _ = execute!(
+        io::stdout(),
+        PopKeyboardEnhancementFlags,
+        DisableMouseCapturePixels,
+        LeaveAlternateScreen,
+        Show
+    );
+    let _ = disable_raw_mode();
+
+    /* The editor may be given with arguments, such as `code --wait` */
+    let status = std::process::Command::new("sh")
+        .args(["-c", &format!("{} \"$1\"", editor), "sh"])
+        .arg(&path)
+        .status();
+
+    let _ = enable_raw_mode();
+    let _ = execute!(
+        io::stdout(),
+        EnterAlternateScreen,
+        Hide,
+        Clear(ClearType::All),
+        EnableMouseCapturePixels,
+        PushKeyboardEnhancementFlags(
+            KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+        )
+    );
+    INPUT_PAUSED.store(false, Ordering::Release);
+
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    match status {
+        Ok(x) if x.success() => {
+            edited.map_err(|x| format!("Could not read the edited note: {}", x))
+        }
+        Ok(x) => Err(format!("`{}` has failed with {}", editor, x)),
+        Err(x) => Err(format!("Could not run `{}`: {}", editor, x)),
+    }
+}
+
+/* Copies text to the clipboard through the terminal and, for terminals that ignore
+ * OSC 52, through `wl-copy` or `xclip` when they are installed */
+fn copy_text(text: &str) -> Result<(), String> {
+    execute!(io::stdout(), CopyToClipboard(text.to_string()))
+        .map_err(|x| format!("Could not copy to clipboard: {}", x))?;
+
+    let program: &[&str] = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        &["wl-copy"]
+    } else if std::env::var_os("DISPLAY").is_some() {
+        &["xclip", "-selection", "clipboard"]
+    } else {
+        return Ok(());
+    };
+
+    let child = std::process::Command::new(program[0])
+        .args(&program[1..])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    /* The copy through the terminal is enough when neither tool is installed */
+    if let Ok(mut child) = child {
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        let _ = child.wait();
+    }
+    Ok(())
+}
+
+/* Selects text by dragging the mouse over a page, which is copied to the clipboard once
+ * the button is released and the renderer has answered with the selected text */
+fn handle_selection(mouse: MouseEvent, panes: &mut [Pane]) {
+    match mouse.kind {
+        MouseEventKind::Down(MouseButton::Left) => {
+            /* Clicks on links open them instead */
+            for pane in panes.iter_mut() {
+                if pane.viewer.intersect_link(mouse).is_some() {
+                    return;
+                }
+                if pane.viewer.start_selection(mouse, &pane.renderer) {
+                    break;
+                }
+            }
+        }
+        MouseEventKind::Drag(MouseButton::Left) => {
+            for pane in panes.iter() {
+                pane.viewer.drag_selection(mouse, &pane.renderer);
+            }
+        }
+        MouseEventKind::Up(MouseButton::Left) => {
+            for pane in panes.iter_mut() {
+                if pane.viewer.finish_selection(&pane.renderer) {
+                    break;
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/* Copies the text of a finished selection. Returns the message to show after copying,
+ * `None` when nothing has been selected */
+fn copy_selection(
+    file: &str,
+    text: &str,
+    plugins: &Plugins,
+    scripts: &Scripts,
+) -> Option<String> {
+    if text.is_empty() {
+        return None;
+    }
+
+    let file = canonical(file);
+    plugins.send(&PluginEvent::SelectionMade { file: &file, text });
+    if let Err(x) = scripts.emit("selection-made", text.to_string()) {
+        return Some(x);
+    }
+    Some(match copy_text(text) {
+        Ok(()) => format!("Copied {} characters", text.chars().count()),
+        Err(x) => x,
+    })
+}
+
+/* The cell of the terminal under the mouse, whose position is reported in pixels */
+fn mouse_cell(mouse: MouseEvent) -> (u16, u16) {
+    let size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+    let column = mouse.column as u32 * size.columns as u32 / size.width.max(1) as u32;
+    let row = mouse.row as u32 * size.rows as u32 / size.height.max(1) as u32;
+    (column as u16, row as u16)
+}
+
+/* Jumps to the page in the document that the link of the pane points to, or opens its
+ * URI */
+fn follow_link(
+    panes: &mut [Pane],
+    i: usize,
+    link: &Link,
+    command_line: &mut CommandLine,
+) {
+    /* URI points to page in this document */
+    if link.uri.starts_with('#') {
+        panes[i].viewer.push_jump();
+        let _ = panes[i].viewer.jump_link(link);
+        sync_panes(panes, i);
+    } else if SANDBOX.load(Ordering::Acquire) {
+        command_line.set_message(format!("Not opening {} in sandbox mode", link.uri));
+    } else {
+        let _ = open::that_detached(&link.uri);
+    }
+}
+
+/* The action that waits for the name of a mark as the next key */
+#[derive(Clone, Copy)]
+enum PendingMark {
+    Set,
+    Jump,
+}
+
+/* Jumps to the position the document was left at. Jumping again returns to where the
+ * jump was made */
+fn jump_bookmark(pane: &mut Pane, command_line: &mut CommandLine) {
+    let viewer = &mut pane.viewer;
+    match pane.bookmark.take() {
+        Some(bookmark) => {
+            pane.bookmark = Some(Bookmark {
+                page: viewer.page_first(),
+                scale: viewer.get_scale(),
+            });
+            viewer.set_start(bookmark.page, Some(bookmark.scale));
+            viewer.apply_start();
+        }
+        None => command_line
+            .set_message("No position has been recorded for this document".to_string()),
+    }
+}
+
+/* Sets or jumps to the mark named by the key typed after `m` or `'`. `''` returns to
+ * where the last jump was made, or to the bookmark before any jump has been made */
+fn handle_mark(
+    pending: PendingMark,
+    name: char,
+    panes: &mut [Pane],
+    command_line: &mut CommandLine,
+) {
+    let pane = &mut panes[0];
+    let result = match (pending, name) {
+        (PendingMark::Set, 'a'..='z') => {
+            pane.viewer.set_mark(name);
+            Ok(())
+        }
+        (PendingMark::Jump, '\'') => {
+            if !pane.viewer.jump_previous() {
+                jump_bookmark(pane, command_line);
+            }
+            Ok(())
+        }
+        (PendingMark::Jump, 'a'..='z') => pane.viewer.jump_mark(name),
+        _ => Err(format!("Invalid mark `{}`", name)),
+    };
+
+    if let Err(x) = result {
+        command_line.set_message(x);
+    }
+    sync_panes(panes, 0);
+}
+
+/* Collects the digits of a page number and jumps to the page on Enter, like `:<page>`.
+ * Returns whether the key has been taken by the number */
+fn handle_count(
+    key: &KeyEvent,
+    pending_count: &mut Option<usize>,
+    panes: &mut [Pane],
+    command_line: &mut CommandLine,
+) -> bool {
+    if key.kind == KeyEventKind::Release {
+        return false;
+    }
+
+    match count_input(*pending_count, key) {
+        CountInput::Typing(count) => {
+            *pending_count = Some(count);
+            command_line.set_message(count.to_string());
+        }
+        CountInput::Submit(page) => {
+            *pending_count = None;
+            panes[0].viewer.push_jump();
+            if let Err(x) = panes[0].viewer.jump(page.saturating_sub(1)) {
+                command_line.set_message(x);
+            }
+            sync_panes(panes, 0);
+        }
+        CountInput::Cancel => *pending_count = None,
+        CountInput::Pass => {
+            *pending_count = None;
+            return false;
+        }
+    }
+    true
+}
+
+/* Switches the color deficiency filter of every pane */
+fn set_daltonize(panes: &mut [Pane], daltonize: Option<Daltonize>) -> Result<(), String> {
+    for pane in panes.iter_mut() {
+        pane.renderer
+            .send_and_confirm_action(RendererAction::SetDaltonize(daltonize))?;
+        pane.daltonize = daltonize;
+        pane.viewer.invalidate_registry();
+    }
+
+    Ok(())
+}
+
+/* The metadata of the document of the pane with the size of its current page and the
+ * render settings, shown by `:info` and `ShowInfo` */
+fn info_overlay(pane: &Pane) -> Result<Vec<String>, String> {
+    let viewer = &CONFIG.get().unwrap().viewer;
+    let page = pane.viewer.page_first();
+    let page_size = match (pane.viewer.page_width(page), pane.viewer.page_height(page)) {
+        (Ok(width), Ok(height)) => Some((width, height - viewer.margin_bottom)),
+        _ => None,
+    };
+
+    let on = |x: bool| if x { "on" } else { "off" }.to_string();
+    let settings = [
+        ("Zoom", format!("{:.0}%", pane.viewer.get_scale() * 100.0)),
+        ("Precision", format!("{:.2}", pane.viewer.precision())),
+        ("Colors", pane.inverse.describe()),
+        ("Color filter", Daltonize::describe(pane.daltonize)),
+        ("Alpha", on(pane.alpha)),
+        ("Paper", on(pane.paper)),
+        ("Diff", on(pane.diff)),
+        (
+            "Renderer",
+            match threads::worker::isolated() {
+                true => "separate process".to_string(),
+                false => "viewer process".to_string(),
+            },
+        ),
+    ];
+    info::info_summary(
+        &pane.file,
+        pane.viewer.pages(),
+        pane.viewer.memory_used(),
+        page_size,
+        &settings,
+    )
+}
+
+/* Switches the inverse color mode of every pane */
+fn set_inverse(panes: &mut [Pane], inverse: InverseMode) -> Result<(), String> {
+    for pane in panes.iter_mut() {
+        pane.renderer
+            .send_and_confirm_action(RendererAction::SetInverse(inverse))?;
+        pane.inverse = inverse;
+        pane.viewer.invalidate_registry();
+    }
+
+    Ok(())
+}
+
+/* Flips `ToggleAlpha`, `ToggleDiff` or `TogglePaper` in the renderers of the panes */
+fn toggle_renderers(panes: &mut [Pane], action: RendererAction) -> Result<(), String> {
+    for pane in panes.iter_mut() {
+        pane.renderer.send_and_confirm_action(action.clone())?;
+        pane.viewer.invalidate_registry();
+        match action {
+            RendererAction::ToggleAlpha => pane.alpha = !pane.alpha,
+            RendererAction::ToggleDiff => pane.diff = !pane.diff,
+            RendererAction::TogglePaper => pane.paper = !pane.paper,
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/* What is shown in the bar, with the formatted size of the rendered pages */
+fn bar_state<'a>(
+    panes: &'a [Pane],
+    tabs: &Tabs,
+    memory: &'a str,
+    script: &'a str,
+) -> BarState<'a> {
+    let pane = &panes[0];
+    let viewer = &CONFIG.get().unwrap().viewer;
+    let toggles = [
+        (pane.alpha, BarToggle::Alpha),
+        (pane.inverse != InverseMode::Off, BarToggle::Inverse),
+        (pane.diff, BarToggle::Diff),
+        (
+            pane.paper
+                && !(viewer.page_background.is_empty() && viewer.page_tint.is_empty()),
+            BarToggle::Paper,
+        ),
+        (pane.viewer.text_mode(), BarToggle::TextMode),
+        (pane.daltonize.is_some(), BarToggle::ColorFilter),
+        (
+            viewer.page_layout == ConfigPageLayout::Horizontal,
+            BarToggle::Horizontal,
+        ),
+    ];
+
+    BarState {
+        page: pane.viewer.page_view() + 1,
+        pages: pane.viewer.pages(),
+        scale: pane.viewer.get_scale() * 100.0,
+        file: &pane.file,
+        memory,
+        script,
+        tab: tabs.current + 1,
+        tabs: tabs.files.len(),
+        toggles: toggles.iter().filter(|x| x.0).map(|x| x.1).collect(),
+    }
+}
+
+fn memory_used(panes: &[Pane]) -> String {
+    format::format_bytes(panes.iter().map(|x| x.viewer.memory_used() as u64).sum())
+}
+
+/* Turns off the toggle clicked in the bar. Returns the message to show */
+fn handle_bar_toggle(toggle: BarToggle, panes: &mut [Pane]) -> Result<String, String> {
+    let action = match toggle {
+        BarToggle::Alpha => RendererAction::ToggleAlpha,
+        BarToggle::Inverse => {
+            set_inverse(panes, InverseMode::Off)?;
+            return Ok(InverseMode::Off.describe());
+        }
+        BarToggle::Diff => RendererAction::ToggleDiff,
+        BarToggle::Paper => RendererAction::TogglePaper,
+        BarToggle::TextMode => {
+            panes[0].viewer.toggle_text_mode();
+            sync_panes(panes, 0);
+            return Ok("Page mode".to_string());
+        }
+        BarToggle::ColorFilter => {
+            set_daltonize(panes, None)?;
+            return Ok(Daltonize::describe(None));
+        }
+        BarToggle::Horizontal => {
+            return Ok("The layout is set with `page_layout` in the config".to_string())
+        }
+    };
+    toggle_renderers(panes, action)?;
+    Ok(format!("Turned off {:?}", toggle))
+}
+
+/* Searches the documents for the given query, an empty query clears the matches */
+fn search(query: &str, panes: &[Pane]) -> Result<Option<String>, String> {
+    for pane in panes.iter() {
+        pane.renderer
+            .send_action(RendererAction::Search(query.to_string()))?;
+    }
+
+    Ok(None)
+}
+
+/* Replaces the binding of the key sequence, `None` removes it. Returns whether the
+ * key sequence has been bound before */
+fn rebind(
+    key_matcher: &mut Keybinds<ConfigAction>,
+    keys: &str,
+    action: Option<ConfigAction>,
+) -> Result<bool, String> {
+    let seq = keys
+        .parse::<KeySeq>()
+        .map_err(|x| format!("Invalid keys `{}`: {}", keys, x))?;
+
+    let mut bindings = std::mem::take(key_matcher).into_vec();
+    let count = bindings.len();
+    bindings.retain(|x| x.seq != seq);
+    let bound = bindings.len() != count;
+
+    if let Some(action) = action {
+        bindings.push(Keybind::new(seq, action));
+    }
+    *key_matcher = Keybinds::new(bindings);
+    Ok(bound)
+}
+
+/* An encrypted copy waiting for its passwords to be entered */
+struct PendingSave {
+    out: String,
+    permissions: u32,
+    owner: Option<String>,
+}
+
+/* Takes the owner and then the user password of an encrypted copy, which is saved
+ * once both have been entered */
+fn enter_password(
+    password: String,
+    panes: &[Pane],
+    command_line: &mut CommandLine,
+    pending_save: &mut Option<PendingSave>,
+) -> Result<Option<String>, String> {
+    let Some(pending) = pending_save.as_mut() else {
+        return Ok(None);
+    };
+
+    if pending.owner.is_none() {
+        if password.is_empty() {
+            *pending_save = None;
+            return Err("The owner password can not be empty".to_string());
+        }
+        pending.owner = Some(password);
+        command_line.open_secret("User password (empty to open without one): ");
+        return Ok(None);
+    }
+
+    let pending = pending_save.take().unwrap();
+    let owner = pending.owner.as_deref().unwrap_or_default();
+    export::save_copy(
+        &panes[0].file,
+        &pending.out,
+        Some((owner, &password, pending.permissions)),
+    )?;
+    Ok(Some(format!("Saved an encrypted copy to {}", pending.out)))
+}
+
+/* Writes the annotations into the document once the question of `:w` has been
+ * answered with yes */
+fn confirm_annotations(answer: &str, panes: &[Pane]) -> Result<Option<String>, String> {
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        return Ok(Some("The annotations have not been written".to_string()));
+    }
+    panes[0]
+        .renderer
+        .send_action(RendererAction::SaveAnnotations)?;
+    Ok(Some("Writing the annotations..".to_string()))
+}
+
+/* Executes a command entered in the command line. Returns the message to show in
+ * the command line afterwards */
+fn run_command(
+    line: &str,
+    panes: &mut [Pane],
+    command_line: &mut CommandLine,
+    key_matcher: &mut Keybinds<ConfigAction>,
+    pending_save: &mut Option<PendingSave>,
+) -> Result<Option<String>, String> {
+    match parse_command(line)? {
+        Command::GoTo(page) => {
+            panes[0].viewer.push_jump();
+            panes[0].viewer.jump(page - 1)?;
+            sync_panes(panes, 0);
+        }
+        Command::Prerender => {
+            /* The viewer only reads the pages cached at the precision it requests */
+            for pane in panes.iter() {
+                let precision = pane.viewer.precision();
+                pane.renderer
+                    .send_action(RendererAction::Prerender(precision))?;
+            }
+        }
+        Command::ColorBlind(daltonize) => set_daltonize(panes, daltonize)?,
+        Command::Images { page, selection } => {
+            let pane = &panes[0];
+            let page = match page {
+                Some(x) => x - 1,
+                None => pane.viewer.page_first(),
+            };
+            if page >= pane.viewer.pages() {
+                return Err(format!("Page {} does not exist", page + 1));
+            }
+
+            let message = if selection.is_empty() {
+                images::list_images(&pane.file, page)?
+            } else if SANDBOX.load(Ordering::Acquire) {
+                return Err("Images are not extracted in sandbox mode".to_string());
+            } else {
+                images::save_images(&pane.file, page, &selection)?
+            };
+            return Ok(Some(message));
+        }
+        Command::Stats => {
+            let pane = &panes[0];
+            command_line.set_overlay(pane.reading.summary(pane.viewer.pages()));
+        }
+        Command::Retry(page) => {
+            let page = page.map(|x| x - 1);
+            if page.is_some_and(|x| x >= panes[0].viewer.pages()) {
+                return Err(format!("Page {} does not exist", page.unwrap() + 1));
+            }
+            for pane in panes.iter_mut() {
+                pane.viewer.retry_pages(page);
+            }
+        }
+        Command::Bind { keys, action, save } => {
+            rebind(key_matcher, &keys, Some(action.clone()))?;
+            if save {
+                config_save_binding(&keys, Some(&action))?;
+            }
+            return Ok(Some(format!("Bound {} to {:?}", keys, action)));
+        }
+        Command::Unbind { keys, save } => {
+            if !rebind(key_matcher, &keys, None)? {
+                return Err(format!("{} is not bound", keys));
+            }
+            if save {
+                config_save_binding(&keys, None)?;
+            }
+            return Ok(Some(format!("Unbound {}", keys)));
+        }
+        Command::ExportFlat(path) => {
+            let pages = export::export_flat(&panes[0].file, &path)?;
+            return Ok(Some(format!("Exported {} pages to {}", pages, path)));
+        }
+        Command::Stamp { out, pages } => {
+            let pane = &panes[0];
+            let (first, last) = pages.unwrap_or((1, pane.viewer.pages()));
+            pane.renderer
+                .send_action(RendererAction::Stamp((first - 1, last - 1), out))?;
+            return Ok(Some("Stamping pages..".to_string()));
+        }
+        Command::Organize(edit) => {
+            let pane = &mut panes[0];
+            let current = pane.viewer.page_first();
+            pane.organizer.sync(pane.viewer.pages());
+            let organizer = &mut pane.organizer;
+
+            let message = match edit {
+                OrganizeEdit::Show => {
+                    let pages = describe_pages(organizer.pages());
+                    let mut lines =
+                        vec![format!("Organized pages ({})", organizer.pages().len())];
+                    lines
+                        .extend(textwrap::wrap(&pages, 60).iter().map(|x| x.to_string()));
+                    command_line.set_overlay(lines);
+                    return Ok(None);
+                }
+                OrganizeEdit::Delete => {
+                    format!("Deleted {} pages", organizer.delete(current)?)
+                }
+                OrganizeEdit::Rotate(degrees) => {
+                    format!("Rotated {} pages", organizer.rotate(current, degrees))
+                }
+                OrganizeEdit::Move(page) => {
+                    let moved = organizer.move_after(current, page.checked_sub(1))?;
+                    format!("Moved {} pages", moved)
+                }
+                OrganizeEdit::Reset => {
+                    organizer.reset();
+                    "Restored the pages of the document".to_string()
+                }
+                OrganizeEdit::Save(out) => {
+                    let pages = organizer.save(&pane.file, &out)?;
+                    format!("Saved {} pages to {}", pages, out)
+                }
+            };
+            return Ok(Some(message));
+        }
+        Command::Append { file, after } => {
+            let pane = &panes[0];
+            if canonical(&file) == canonical(&pane.file) {
+                return Err("A document can not be appended to itself".to_string());
+            }
+
+            let pages = organize::append_document(&pane.file, &file, after)?;
+            /* Reloaded here since documents read from stdin and the ones of
+             * `--no-watch` are not watched. The compared documents are diffed against
+             * the new pages as well */
+            for pane in panes.iter() {
+                pane.renderer
+                    .send_and_confirm_action(RendererAction::Load)?;
+            }
+            return Ok(Some(format!("Appended {} pages of {}", pages, file)));
+        }
+        Command::Outline(edit) => {
+            let pane = &mut panes[0];
+            if pane.outline.is_none() {
+                pane.outline = Some(OutlineEditor::open(&pane.file)?);
+            }
+            let editor = pane.outline.as_mut().unwrap();
+
+            let message = match edit {
+                OutlineEdit::Show => {
+                    command_line.set_overlay(editor.lines());
+                    return Ok(None);
+                }
+                OutlineEdit::Add(title) => {
+                    let (page, progress) = pane.viewer.page_progress();
+                    /* Views of sideways pages point at the top of the page */
+                    let progress = match CONFIG.get().unwrap().viewer.page_layout {
+                        ConfigPageLayout::Vertical => progress,
+                        ConfigPageLayout::Horizontal => 0.0,
+                    };
+                    let number = editor.add(&title, page, progress)?;
+                    format!("Added outline entry {}", number)
+                }
+                OutlineEdit::Rename(number, title) => {
+                    editor.rename(number, &title)?;
+                    format!("Renamed outline entry {}", number)
+                }
+                OutlineEdit::Remove(number) => {
+                    format!("Removed `{}`", editor.remove(number)?)
+                }
+                OutlineEdit::Save => {
+                    let entries = editor.save()?;
+                    /* Read again from the reloaded document on the next edit */
+                    pane.outline = None;
+                    format!("Saved an outline of {} entries", entries)
+                }
+            };
+            return Ok(Some(message));
+        }
+        Command::Write { out, encrypt: None } => {
+            export::save_copy(&panes[0].file, &out, None)?;
+            return Ok(Some(format!("Saved a copy to {}", out)));
+        }
+        Command::Write {
+            out,
+            encrypt: Some(permissions),
+        } => {
+            *pending_save = Some(PendingSave {
+                out,
+                permissions,
+                owner: None,
+            });
+            command_line.open_secret("Owner password: ");
+        }
+        Command::Info => command_line.set_overlay(info_overlay(&panes[0])?),
+        Command::Note(edit) => {
+            let pane = &mut panes[0];
+            let page = pane.viewer.page_first();
+            let text = match edit {
+                NoteEdit::Edit => {
+                    let text = edit_text(pane.notes.get(page).unwrap_or_default());
+                    /* The editor has drawn over the pages */
+                    for pane in panes.iter_mut() {
+                        pane.viewer.invalidate_registry();
+                    }
+                    text?
+                }
+                NoteEdit::Set(text) => text,
+                NoteEdit::Delete => String::new(),
+            };
+            panes[0].notes.set(page, &text)?;
+            return Ok(Some(match text.trim().is_empty() {
+                true => format!("Removed the note of page {}", page + 1),
+                false => format!("Saved the note of page {}", page + 1),
+            }));
+        }
+        Command::Notes => command_line.set_overlay(panes[0].notes.summary()),
+        Command::Annotate(kind) => {
+            panes[0]
+                .renderer
+                .send_action(RendererAction::Annotate(kind))?;
+        }
+        Command::UndoAnnotation => {
+            panes[0]
+                .renderer
+                .send_action(RendererAction::UndoAnnotation)?;
+        }
+        Command::WriteAnnotations => {
+            command_line.open_question(&format!(
+                "Write the annotations into {}? [y/N] ",
+                panes[0].file
+            ));
+        }
+    }
+
+    Ok(None)
+}
+
+/* Stores every page of a document in the disk cache without opening the viewer, at
+ * the precision of the zoom that documents are opened at */
+fn prerender(file: &str) -> Result<(), String> {
+    let config = CONFIG.get().unwrap();
+    let precision = config.viewer.precision_at(1.0);
+
+    let document = open_document(file)?;
+    let total = document
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?;
+
+    let disk_cache = DiskCache::open(file)?;
+    disk_cache.prune();
+
+    for i in 0..total {
+        let page = document
+            .load_page(i)
+            .map_err(|x| format!("Could not load page {}: {}", i, x))?;
+        let bounds = page
+            .bounds()
+            .map_err(|x| format!("Could not get bounds for page {}: {}", i, x))?;
+
+        let transform = PageTransform::new((bounds.x0, bounds.y0, bounds.x1, bounds.y1));
+        if !disk_cache.contains(i as usize, precision, false, &transform) {
+            let pixmap = transform.render(&page, precision, false)?;
+            disk_cache.store(i as usize, precision, false, &transform, &pixmap)?;
+        }
+
+        print!("\rPrerendered {}/{} pages", i + 1, total);
+        io::stdout()
+            .flush()
+            .map_err(|x| format!("Could not flush stdout: {}", x))?;
+    }
+    println!();
+
+    Ok(())
+}
+
+/* Parses `<png> [--page <page|first-last>] [--dpi <dpi>] <file>` of `--export` into
+ * the file to write, the zero-indexed pages, the DPI and the document */
+fn parse_export(
+    args: &[String],
+) -> Result<(String, (usize, usize), f32, String), String> {
+    let mut pages = (0, 0);
+    let mut dpi = CONFIG.get().unwrap().viewer.export_dpi;
+    let mut rest = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--page" => {
+                let range = args.next().ok_or("`--page` expects a page")?;
+                let (first, last) = command::parse_range(range)?;
+                pages = (first - 1, last - 1);
+            }
+            "--dpi" => {
+                let value = args.next().ok_or("`--dpi` expects a resolution")?;
+                dpi = match value.parse::<f32>() {
+                    Ok(x) if x > 0.0 => x,
+                    _ => return Err(format!("Invalid resolution `{}`", value)),
+                };
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    match <[String; 2]>::try_from(rest) {
+        Ok([out, file]) => Ok((out, pages, dpi, file)),
+        Err(_) => Err("`--export` expects a PNG file and a document".to_string()),
+    }
+}
+
+/* Resolves a document path so that it can be used as an identifier outside of the
+ * current working directory */
+fn canonical(file: &str) -> String {
+    std::fs::canonicalize(file)
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or(file.to_owned())
+}
+
+/* Displays the requested document, replacing the displayed documents if another
+ * document is requested, and asks the terminal for the attention of the user */
+fn open_request(panes: &mut Vec<Pane>, request: Request) {
+    if canonical(&panes[0].file) != canonical(&request.file) {
+        if SANDBOX.load(Ordering::Acquire)
+            && CONFIG.get().unwrap().viewer.sandbox_preflight
+            && sandbox::preflight(&request.file).is_err()
+        {
+            return;
+        }
+        match Pane::open(&request.file, None, 0, 1) {
+            Ok(pane) => *panes = vec![pane],
+            Err(_) => return,
+        }
+    }
+
+    if let Some((page, scale)) = request.position {
+        let viewer = &mut panes[0].viewer;
+        viewer.set_start(page, scale);
+        if !viewer.is_uninit() {
+            viewer.apply_start();
+            sync_panes(panes, 0);
+        }
+    }
+
+    let _ = execute!(io::stdout(), RequestAttention);
+}
+
+/* Applies `end_of_document` once the first pane has been scrolled past its last page.
+ * Returns `true` if the viewer should quit */
+fn handle_document_end(
+    panes: &mut Vec<Pane>,
+    tabs: &mut Tabs,
+    command_line: &mut CommandLine,
+) -> bool {
+    if !panes[0].viewer.take_past_end() {
+        return false;
+    }
+
+    match CONFIG.get().unwrap().viewer.end_of_document {
+        ConfigEndOfDocument::Stop => false,
+        ConfigEndOfDocument::Wrap => {
+            let _ = panes[0].viewer.jump(0);
+            sync_panes(panes, 0);
+            false
+        }
+        ConfigEndOfDocument::NextDocument => {
+            if tabs.current + 1 == tabs.files.len() {
+                command_line.set_message("This is the last document".to_string());
+            } else if let Err(x) = tabs.switch(panes, tabs.current + 1) {
+                command_line.set_message(x);
+            }
+            false
+        }
+        ConfigEndOfDocument::Quit => true,
+    }
+}
+
+/* Makes every pane follow the position of the `leader` pane */
+fn sync_panes(panes: &mut [Pane], leader: usize) {
+    for i in 0..panes.len() {
+        if i == leader {
+            continue;
+        }
+
+        let (follower, leader) = if i < leader {
+            let (left, right) = panes.split_at_mut(leader);
+            (&mut left[i], &right[0])
+        } else {
+            let (left, right) = panes.split_at_mut(i);
+            (&mut right[0], &left[leader])
+        };
+        follower.viewer.sync_with(&leader.viewer);
+    }
 }
 
 fn main() {
     /* ============================= Check the argument ============================= */
-    let arg = std::env::args().nth(1).unwrap_or("".to_owned());
-    match arg.as_str() {
-        "-h" | "--help" | "" => {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut args = args::parse_args(&args).unwrap_or_else(|x| {
+        eprintln!("{}\n\n{}", x, HELP_MSG);
+        std::process::exit(1);
+    });
+    SAFE_MODE.store(args.safe_mode, Ordering::Release);
+    NO_WATCH.store(args.no_watch, Ordering::Release);
+    SANDBOX.store(args.sandbox, Ordering::Release);
+    if let Some(config) = &args.config {
+        CONFIG_FILE.get_or_init(|| PathBuf::from(config));
+    }
+
+    match args.mode {
+        Mode::Help => {
             println!("{}", HELP_MSG);
             return;
         }
-        "-v" | "--version" => {
+        Mode::Version => {
             println!("meowpdf v{} ({})", VERSION, RELEASED);
             return;
         }
         _ => (),
     }
 
+    /* Started without a document, it is picked from the working directory */
+    if args.mode == Mode::View(Vec::new()) {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            println!("{}", HELP_MSG);
+            return;
+        }
+        match picker::pick() {
+            Ok(Some(x)) => args.mode = Mode::View(vec![x]),
+            Ok(None) => return,
+            Err(x) => {
+                eprintln!("{}\n\n{}", x, HELP_MSG);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /* ================================= Load config ================================= */
+    /* Loaded before the other options since reflowable documents are laid out with
+     * it, even when they are not displayed */
+    let mut key_matcher;
+    {
+        /* Safe mode neither reads nor writes the config of the user, which is the
+         * first thing to rule out when the viewer does not start */
+        let mut config = match SAFE_MODE.load(Ordering::Acquire) {
+            true => {
+                startup_log("Using the built-in config".to_string());
+                config_default().expect("Could not load the built-in config")
+            }
+            false => config_load_or_create().expect("Could not load config"),
+        };
+        key_matcher = config.bindings.unwrap();
+        config.bindings = None;
+        CONFIG.get_or_init(|| config);
+    }
+
+    match &args.mode {
+        Mode::Remote(link) => {
+            let mut request = Request::parse(link).unwrap_or_else(|x| {
+                eprintln!("{}", x);
+                std::process::exit(1);
+            });
+            request.file = canonical(&request.file);
+
+            if let Err(x) = threads::ipc::send(&socket_dir().join(SERVE_SOCKET), &request)
+            {
+                eprintln!("{}", x);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Mode::Query(socket, query) => {
+            /* Bare names are the sockets in the socket directory */
+            let socket = match socket.contains('/') {
+                true => PathBuf::from(socket),
+                false => socket_dir().join(socket),
+            };
+
+            match threads::ipc::query(&socket, query) {
+                Ok(x) => println!("{}", x),
+                Err(x) => {
+                    eprintln!("{}", x);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Mode::Check => match check::check(|x| {
+            terminal_quirks(x, &CONFIG.get().unwrap().viewer.quirks)
+        }) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(x) => {
+                eprintln!("{}", x);
+                std::process::exit(1);
+            }
+        },
+        Mode::Export(rest) => {
+            let (out, pages, dpi, file) = parse_export(rest).unwrap_or_else(|x| {
+                eprintln!("{}\n\n{}", x, HELP_MSG);
+                std::process::exit(1);
+            });
+
+            match export::export_png(&file, pages, dpi, &out) {
+                Ok(x) => println!("{}", x.join("\n")),
+                Err(x) => {
+                    eprintln!("{}", x);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Mode::ExportHtml(file, directory) => {
+            if let Err(x) = export::export_html(file, directory) {
+                eprintln!("\n{}", x);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Mode::RenderWorker(file) => {
+            if let Err(x) = threads::worker::serve(file) {
+                eprintln!("{}", x);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Mode::Preflight(file) => {
+            if let Err(x) = sandbox::preflight_pages(file) {
+                eprintln!("{}", x);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Mode::Prerender(file) => {
+            if let Err(x) = prerender(file) {
+                eprintln!("\n{}", x);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    let mut start = None;
+    let mut queued = Vec::new();
+    let files = match &args.mode {
+        Mode::Compare(a, b) => vec![a.clone(), b.clone()],
+        Mode::Dest(name, file) | Mode::Section(name, file) => {
+            let page = match args.mode {
+                Mode::Dest(..) => outline::resolve_destination(file, name),
+                _ => outline::resolve_section(file, name),
+            };
+            start = Some((
+                page.unwrap_or_else(|x| {
+                    eprintln!("{}", x);
+                    std::process::exit(1);
+                }),
+                None,
+            ));
+            vec![file.clone()]
+        }
+        Mode::View(documents) => {
+            /* `-` reads the document from stdin, the input still comes from the
+             * terminal since crossterm falls back to `/dev/tty` */
+            let request = match documents[0].as_str() {
+                "-" => Request {
+                    file: document::buffer_stdin().unwrap_or_else(|x| {
+                        eprintln!("{}", x);
+                        std::process::exit(1);
+                    }),
+                    position: None,
+                },
+                x => Request::parse(x).unwrap_or_else(|x| {
+                    eprintln!("{}", x);
+                    std::process::exit(1);
+                }),
+            };
+            if documents[0] == "-" {
+                STDIN_DOCUMENT.get_or_init(|| request.file.clone());
+            }
+            start = request.position;
+
+            /* Further documents are opened in tabs */
+            for document in documents.iter().skip(1) {
+                let request = Request::parse(document).unwrap_or_else(|x| {
+                    eprintln!("{}", x);
+                    std::process::exit(1);
+                });
+                queued.push(request.file);
+            }
+            vec![request.file]
+        }
+        _ => unreachable!("The other modes have returned already"),
+    };
+    let documents: Vec<String> = files.iter().chain(queued.iter()).cloned().collect();
+
+    /* Untrusted documents are opened once every page has been checked in a separate
+     * process without crashing or hanging it */
+    if args.sandbox && CONFIG.get().unwrap().viewer.sandbox_preflight {
+        for document in documents.iter() {
+            startup_log(format!("Checking {} in a separate process", document));
+            if let Err(x) = sandbox::preflight(document) {
+                eprintln!("{}", x);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /* `--page` and `--scale` take the place of the position given with the document */
+    if args.page.is_some() || args.scale.is_some() {
+        let (page, scale) = start.unwrap_or((0, None));
+        start = Some((args.page.unwrap_or(page), args.scale.or(scale)));
+    }
+
+    /* ============================ Record or replay input =========================== */
+    let recorder = args.record.as_ref().map(|x| {
+        Recorder::create(x).unwrap_or_else(|x| {
+            eprintln!("{}", x);
+            std::process::exit(1);
+        })
+    });
+    let replayed = match &args.replay {
+        Some(x) => replay::load(x).unwrap_or_else(|x| {
+            eprintln!("{}", x);
+            std::process::exit(1);
+        }),
+        None => Vec::new(),
+    };
+
+    /* The hidden `--capture` mode runs without a terminal and writes the frames to PNG
+     * files, usually with the input of a recording, for comparing them against frames
+     * that are known to be right */
+    let capture = args.capture.as_ref().map(|x| {
+        CaptureBackend::new(x).unwrap_or_else(|x| {
+            eprintln!("{}", x);
+            std::process::exit(1);
+        })
+    });
+    let headless = capture.is_some();
+    if headless && replayed.is_empty() {
+        eprintln!("`--capture` expects a recording given with `--replay`");
+        std::process::exit(1);
+    }
+
+    /* ============================ Listen for requests ============================== */
+    /* The servers remove their sockets when they are dropped at exit */
+    let (sender_request, requests) = unbounded::<Request>();
+    let mut servers = Vec::new();
+
+    let safe_mode = SAFE_MODE.load(Ordering::Acquire);
+    if safe_mode {
+        startup_log("Not listening for other instances".to_string());
+    }
+
+    /* Instances started with `--serve` open the documents sent by `--remote` */
+    if args.serve && !safe_mode {
+        let server =
+            threads::ipc::spawn(&socket_dir().join(SERVE_SOCKET), sender_request.clone())
+                .unwrap_or_else(|x| {
+                    eprintln!("{}", x);
+                    std::process::exit(1);
+                });
+        servers.push(server);
+    }
+
+    /* Hand the document over to the instance that already displays it instead of
+     * opening it twice. The socket of the document is owned by this instance otherwise */
+    if CONFIG.get().unwrap().viewer.reuse_instance
+        && files.len() == 1
+        && STDIN_DOCUMENT.get().is_none()
+        && !headless
+        && !safe_mode
+        && !args.sandbox
+    {
+        let request = Request {
+            file: canonical(&files[0]),
+            position: start,
+        };
+        let socket = file_socket(&request.file);
+
+        if threads::ipc::send(&socket, &request).is_ok() {
+            return;
+        }
+        if let Ok(server) = threads::ipc::spawn(&socket, sender_request.clone()) {
+            servers.push(server);
+        }
+    }
+
     /* ============================= Uncook the terminal ============================= */
-    enable_raw_mode().expect("Could not cook the terminal");
-    execute!(io::stdout(), EnterAlternateScreen).expect("Could not enter alt mode");
-    execute!(io::stdout(), Hide).expect("Could not hide cursor");
-    execute!(io::stdout(), Clear(ClearType::All)).expect("Could not clear terminal");
-    execute!(io::stdout(), EnableMouseCapturePixels)
-        .expect("Could not enable mouse capture");
+    startup_log(format!("Opening {}", documents.join(", ")));
+    flush_startup_log();
+    if !headless {
+        enable_raw_mode().expect("Could not cook the terminal");
+        execute!(io::stdout(), EnterAlternateScreen).expect("Could not enter alt mode");
+        execute!(io::stdout(), Hide).expect("Could not hide cursor");
+        execute!(io::stdout(), Clear(ClearType::All)).expect("Could not clear terminal");
+        execute!(io::stdout(), EnableMouseCapturePixels)
+            .expect("Could not enable mouse capture");
+        /* Makes the terminals using the kitty keyboard protocol report whether keys
+         * are pressed, held or released, the others ignore it */
+        execute!(
+            io::stdout(),
+            PushKeyboardEnhancementFlags(
+                KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                    | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+            )
+        )
+        .expect("Could not enable the key event types");
+    }
 
     /* ========================== Cook the terminal on panic ========================= */
     let default_panic = std::panic::take_hook();
@@ -72,38 +1534,90 @@ fn main() {
         /* Atleast try to cook the terminal on error before printing the message.
          * Do not handle the error to prevent possible infinite loops when panicking. */
 
+        let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
         let _ = execute!(io::stdout(), DisableMouseCapturePixels);
         let _ = execute!(io::stdout(), LeaveAlternateScreen);
         let _ = execute!(io::stdout(), Show);
         let _ = disable_raw_mode();
+        flush_startup_log();
         default_panic(info);
     }));
 
+    /* =========================== Identify the terminal ============================= */
+    /* Has to be done before the event thread starts reading the responses */
+    let identity = match headless {
+        true => "capture".to_string(),
+        false => terminal_identity(),
+    };
+    let quirks = terminal_quirks(&identity, &CONFIG.get().unwrap().viewer.quirks);
+    startup_log(format!(
+        "Identified the terminal as `{}` with {:?}",
+        identity, quirks
+    ));
+    TERMINAL_QUIRKS.get_or_init(|| quirks);
+
     /* ============================= STDIN parser thread ============================= */
-    let event_inputs = threads::event::spawn();
-    RECEIVER_GR.get_or_init(|| Mutex::new(event_inputs.2));
+    let event_inputs = threads::event::spawn(recorder, replayed, !headless);
+    RECEIVER_GR.get_or_init(|| Mutex::new(event_inputs.graphics));
+
+    /* ========== Check if the terminal supports the Kitty graphics protocol ========= */
+    /* Terminals without it get the pages drawn as iTerm2 inline images or as sixel
+     * images, which both need the size of the cells in pixels, or out of colored
+     * cells */
+    if let Some(capture) = capture {
+        startup_log("Capturing the frames".to_string());
+        GRAPHICS_BACKEND.get_or_init(|| Box::new(capture));
+    } else if terminal_graphics_test_support().is_err() {
+        let pixels = window_size().is_ok_and(|x| x.width > 0 && x.height > 0);
+        if terminal_supports_iterm2() && pixels {
+            startup_log("Displaying the pages as iTerm2 inline images".to_string());
+            GRAPHICS_BACKEND.get_or_init(|| Box::new(Iterm2Backend));
+        } else if terminal_supports_sixel() && pixels {
+            startup_log("Displaying the pages as sixel images".to_string());
+            GRAPHICS_BACKEND.get_or_init(|| Box::new(SixelBackend));
+        } else {
+            startup_log("Drawing the pages out of cells".to_string());
+            BLOCK_ART.store(true, Ordering::Release);
+        }
+    } else {
+        GRAPHICS_BACKEND.get_or_init(|| Box::new(KittyBackend));
 
-    /* ========== Check if the terminal supports the Kitty graphics protocol ========= */
-    terminal_graphics_test_support()
-        .expect("Error when testing terminal support of the Kitty graphics protocol");
+        let config = CONFIG.get().unwrap().viewer.transfer_medium;
+        let medium = select_transfer_medium(config, &identity);
+        startup_log(format!(
+            "Displaying the pages through the Kitty graphics protocol with the {:?} \
+             medium",
+            medium
+        ));
+        TRANSFER_MEDIUM.get_or_init(|| medium);
 
-    /* ================================= Load config ================================= */
-    let mut key_matcher;
-    {
-        let mut config = config_load_or_create().expect("Could not load config");
-        key_matcher = config.bindings.unwrap();
-        config.bindings = None;
-        CONFIG.get_or_init(|| config);
+        /* Images of a crashed instance that has had the same block of IDs would be
+         * displayed instead of the new ones */
+        if let Err(x) = terminal_graphics_deallocate_range(instance_ids()) {
+            startup_log(x);
+        }
     }
 
     /* ======================= Calculate padding for all images ====================== */
-    let winsize_tmp = window_size().expect("Could not get win size");
+    let winsize_tmp = match headless {
+        true => WindowSize {
+            width: CAPTURE_SIZE.0,
+            height: CAPTURE_SIZE.1,
+            columns: CAPTURE_SIZE.2,
+            rows: CAPTURE_SIZE.3,
+        },
+        false => blockart_window_size(window_size().expect("Could not get win size")),
+    };
     let winsize = WindowSize {
         rows: winsize_tmp.rows,
         columns: winsize_tmp.columns,
         width: winsize_tmp.width,
         height: winsize_tmp.height,
     };
+    startup_log(format!(
+        "Window of {}x{} cells and {}x{} pixels",
+        winsize.columns, winsize.rows, winsize.width, winsize.height
+    ));
     TERMINAL_SIZE.get_or_init(|| RwLock::new(winsize_tmp));
 
     let config = CONFIG.get().unwrap();
@@ -111,10 +1625,10 @@ fn main() {
     let pxpercol = winsize.width as f64 / winsize.columns as f64;
     let pxperrow = winsize.height as f64 / winsize.rows as f64;
 
-    let paddingcol = (pxpercol * config.viewer.render_precision
+    let paddingcol = (pxpercol * config.viewer.precision_max()
         / config.viewer.scale_min as f64)
         .ceil() as usize;
-    let paddingrow = (pxperrow * config.viewer.render_precision
+    let paddingrow = (pxperrow * config.viewer.precision_max()
         / config.viewer.scale_min as f64)
         .ceil() as usize;
 
@@ -125,24 +1639,92 @@ fn main() {
     SOFTWARE_ID.get_or_init(|| format!("{random_u64:X}"));
 
     /* ====================== Viewer - The core of this program ====================== */
-    let (mut viewer, sender_rerender) = Viewer::new();
-
-    let (mut renderer, result_receiver) = threads::renderer::Renderer::new();
-    renderer.run(&arg).expect("Couldn't start renderer thread");
-    renderer
-        .send_and_confirm_action(threads::renderer::RendererAction::Load)
-        .expect("Cannot send action to renderer thread");
+    /* Every given document gets its own pane with a viewer, a renderer thread and a
+     * file watcher. Multiple panes are displayed side by side with locked scrolling */
+    let mut panes = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        /* In comparison mode every document is diffed against the other one */
+        let compare = if files.len() == 2 {
+            Some(files[1 - i].as_str())
+        } else {
+            None
+        };
 
-    /* ========================= Thread notifying file change ======================== */
-    let file_reload = threads::fnotify::spawn(&arg).expect("Could not init file watcher");
+        panes.push(
+            Pane::open(file, compare, i, files.len()).expect("Could not open document"),
+        );
+        startup_log(format!("Opened {} in pane {}", file, i));
+    }
+    if let Some((page, scale)) = start {
+        panes[0].viewer.set_start(page, scale);
+    }
+    if args.inverse {
+        set_inverse(&mut panes, InverseMode::Full).expect("Could not invert the colors");
+    }
+    if args.alpha {
+        toggle_renderers(&mut panes, RendererAction::ToggleAlpha)
+            .expect("Could not toggle the transparency");
+    }
+    /* Compared documents share the first tab */
+    let mut tabs = Tabs::new([files[0].clone()].into_iter().chain(queued).collect());
 
     /* ============================== Main program loop ============================== */
     let mut throttle_data = LastExecuted {
         load: SystemTime::now() - Duration::from_millis(500),
-        alpha: SystemTime::now() - Duration::from_millis(500),
-        inverse: SystemTime::now() - Duration::from_millis(500),
+        event_types: false,
+        toggle: SystemTime::now() - Duration::from_millis(500),
     };
 
+    let mut command_line = CommandLine::default();
+    let mut pending_mark = None;
+    /* The labels of the links while one is picked with `HintLinks` */
+    let mut link_hints: Option<LinkHints> = None;
+    let mut pending_count = None;
+    let mut pending_save = None;
+
+    /* Documents opened with `--sandbox` run neither plugins nor scripts, which could
+     * be made to act on what they contain */
+    let mut plugins = Plugins::default();
+    if !args.sandbox {
+        if let Err(x) = plugins.load(&config.viewer.plugin_directory) {
+            command_line.set_message(x);
+        }
+    }
+
+    let mut context = ContextMirror::default();
+    if let Err(x) =
+        context.open(config.viewer.context.output, &config.viewer.context.path)
+    {
+        command_line.set_message(x);
+    }
+
+    /* Scripts are not run in safe mode, like the config of the user is not read */
+    let mut scripts = Scripts::new().expect("Could not set up the scripts");
+    if let Some(directory) = dirs::config_dir().filter(|_| !safe_mode && !args.sandbox) {
+        if let Err(x) = scripts.load(&directory.join(SCRIPTS_DIRNAME)) {
+            command_line.set_message(x);
+        }
+    }
+
+    /* Fires once the mouse has not been moved for `hide_after` seconds */
+    let mut hint_timeout = never();
+    let mut hint_hidden = false;
+    /* Frames are drawn at most every `frame_interval`. The captured frames of the
+     * headless mode are not limited */
+    let frame_interval = match config.viewer.max_fps {
+        x if x > 0.0f32 && !headless => Duration::from_secs_f32(1.0 / x),
+        _ => Duration::ZERO,
+    };
+    let mut last_frame = Instant::now() - frame_interval;
+    let mut frame_timeout = never();
+    let mut frame_pending = false;
+    /* The latest size of the terminal while it is being resized */
+    let mut resize_timeout = never();
+    let mut pending_resize = None;
+    /* Steps the smooth and kinetic scrolls, only listened to while they run */
+    let frames = tick(glide::FRAME_INTERVAL);
+    let idle = never::<Instant>();
+
     let mut current_mouse = MouseEvent {
         kind: MouseEventKind::Moved,
         column: u16::MAX,
@@ -151,124 +1733,424 @@ fn main() {
     };
 
     'main: loop {
-        /* sel[0..1] are the results from the renderer thread */
-        let mut sel = result_receiver.construct_biased_select();
-        sel.recv(&file_reload);
-        sel.recv(&sender_rerender);
-        /* Key event */
-        sel.recv(&event_inputs.0);
-        /* Mouse event */
-        sel.recv(&event_inputs.1);
-        /* Window size change input */
-        sel.recv(&event_inputs.3);
-
-        let index_ready = sel.ready();
+        let n = panes.len();
+        /* Starts over with every event, so it only fires once nothing else happens. It
+         * is skipped while none of the previews are stale */
+        let refresh = match panes.iter().any(|x| x.viewer.stale_previews()) {
+            true => after(PREVIEW_IDLE),
+            false => never(),
+        };
+        let index_ready = {
+            /* sel[0..2n] are the results from the renderer threads */
+            let mut sel = Select::new_biased();
+            for pane in panes.iter() {
+                pane.results.register(&mut sel);
+            }
+            /* sel[2n] are the input, the resizes, the reloads and the rerender
+             * signals of every pane */
+            sel.recv(&event_inputs.events);
+            /* Requests from other instances */
+            sel.recv(&requests);
+            /* Mouse inactivity */
+            sel.recv(&hint_timeout);
+            /* Messages of the plugins */
+            sel.recv(plugins.messages());
+            /* Frames of the smooth and kinetic scrolls */
+            match panes[0].viewer.is_gliding() {
+                true => sel.recv(&frames),
+                false => sel.recv(&idle),
+            };
+            /* End of the resizing */
+            sel.recv(&resize_timeout);
+            /* Deferred frame */
+            sel.recv(&frame_timeout);
+            /* Idle refresh of the previews */
+            sel.recv(&refresh);
 
-        execute!(io::stdout(), ClearImages, Clear(ClearType::FromCursorDown))
-            .expect("Could not clear images");
+            sel.ready()
+        };
 
         match index_ready {
-            0 | 1 => {
-                let result = result_receiver
-                    .try_recv_priority(index_ready)
+            x if x < 2 * n => {
+                let (i, priority) = (x / 2, x % 2);
+                let pane = &mut panes[i];
+                let result = pane
+                    .results
+                    .try_recv_priority(priority)
                     .expect("Could not receive priority");
 
                 match result {
-                    threads::renderer::RendererResult::PageMetadata {
+                    RendererResult::PageMetadata {
                         max_page_width,
                         cumulative_heights,
                         widths,
                         links,
+                        transforms,
                     } => {
-                        let uninit = viewer.is_uninit();
+                        let uninit = pane.viewer.is_uninit();
 
-                        viewer.update_metadata(
+                        pane.viewer.update_metadata(
                             max_page_width,
                             &cumulative_heights,
                             &widths,
                             &links,
+                            &transforms,
                         );
-                        viewer.invalidate_registry();
-                        viewer.center_viewer();
+                        pane.viewer.invalidate_registry();
                         if uninit {
-                            viewer.scale_page2terminal();
+                            pane.viewer.center_viewer();
+                            pane.viewer.scale_page2terminal();
+                            pane.viewer.apply_start();
+                        }
+                        pane.results.clear_priority(1);
+                        pane.rebuilding = false;
+                        sync_panes(&mut panes, 0);
+                    }
+                    RendererResult::Rebuilding => {
+                        pane.rebuilding = true;
+                    }
+                    RendererResult::ReloadFailed(message) => {
+                        pane.rebuilding = false;
+                        command_line.set_message(message);
+                    }
+                    RendererResult::Image { page, data } => {
+                        pane.viewer.handle_image(page, data);
+                    }
+                    RendererResult::ImagePreview { page, data } => {
+                        pane.viewer.handle_preview(page, data);
+                    }
+                    RendererResult::Captions(captions) => {
+                        pane.viewer.update_captions(captions);
+                        sync_panes(&mut panes, i);
+                    }
+                    RendererResult::Popup(image) => {
+                        pane.viewer.set_popup(image);
+                    }
+                    RendererResult::Region { page, crop, data } => {
+                        pane.viewer.handle_region(page, crop, data);
+                    }
+                    RendererResult::Prerender { done, total } => {
+                        pane.viewer.set_prerendered(done, total);
+                    }
+                    RendererResult::Identifiers { page, links } => {
+                        pane.viewer.update_identifiers(page, links);
+                    }
+                    RendererResult::Selection { page, text } => {
+                        pane.viewer.handle_selection(page, text);
+                    }
+                    RendererResult::SelectionFinished(text) => {
+                        if let Some(message) =
+                            copy_selection(&pane.file, &text, &plugins, &scripts)
+                        {
+                            command_line.set_message(message);
                         }
-                        result_receiver.clear_priority(1);
                     }
-                    threads::renderer::RendererResult::Image { page, data } => {
-                        viewer.handle_image(page, data);
+                    RendererResult::Annotations { page, message } => {
+                        pane.viewer.invalidate_page(page);
+                        command_line.set_message(message);
+                    }
+                    RendererResult::Message(message) => {
+                        command_line.set_message(message);
+                    }
+                    RendererResult::RenderFailed { page, message } => {
+                        pane.viewer.render_failed(page);
+                        command_line.set_message(message);
+                    }
+                    RendererResult::RegionFailed {
+                        page,
+                        crop,
+                        message,
+                    } => {
+                        pane.viewer.region_failed(page, crop);
+                        command_line.set_message(message);
+                    }
+                    RendererResult::Text { page, paragraphs } => {
+                        pane.viewer.handle_text(page, paragraphs);
+                    }
+                    RendererResult::Search { query, hits } => {
+                        pane.viewer.set_search(hits);
+                        pane.viewer.invalidate_registry();
+
+                        /* Navigation follows the first pane */
+                        if i == 0 && !query.is_empty() {
+                            let message = match pane.viewer.jump_search(true) {
+                                Some((current, total)) => {
+                                    format!("Match {}/{}", current + 1, total)
+                                }
+                                None => format!("No matches for `{}`", query),
+                            };
+                            command_line.set_message(message);
+                            sync_panes(&mut panes, 0);
+                        }
                     }
                 }
             }
-            2 => {
-                file_reload
-                    .try_recv()
-                    .expect("Could not receive file reload");
-                if throttle_data.load.elapsed().unwrap() >= Duration::from_millis(1000) {
-                    renderer
-                        .send_and_confirm_action(threads::renderer::RendererAction::Load)
-                        .expect("Cannot send action to renderer thread");
+            x if x == 2 * n => match event_inputs
+                .events
+                .try_recv()
+                .expect("Could not receive event")
+            {
+                AppEvent::Reload(id, event) => {
+                    /* The events of panes that have been closed since are dropped */
+                    let Some(i) = panes.iter().position(|x| x.id == id) else {
+                        continue 'main;
+                    };
+                    panes[i].removed = event == FileEvent::Removed;
+                    /* The links may have moved with the new pages */
+                    link_hints = None;
+
+                    /* Compared documents are diffed against each other, so all of them
+                     * are reloaded when one of them changes */
+                    if event == FileEvent::Changed
+                        && throttle_data.load.elapsed().unwrap()
+                            >= Duration::from_millis(1000)
+                    {
+                        for pane in panes.iter() {
+                            pane.renderer
+                                .send_and_confirm_action(RendererAction::Load)
+                                .expect("Cannot send action to renderer thread");
+                        }
+                    }
                 }
-            }
-            3 => {
-                sender_rerender
-                    .try_recv()
-                    .expect("Could not receive rerender");
-            }
-            4 => {
-                let input = event_inputs.0.try_recv().expect("Could not receive input");
-                match input {
-                    InputEvent::Key(key) => {
-                        if handle_key(
-                            key,
-                            &mut key_matcher,
-                            &mut viewer,
-                            &renderer,
-                            &mut throttle_data,
-                        ) {
-                            break 'main;
+                /* The pages are drawn again after every event */
+                AppEvent::Rerender(_) => (),
+                AppEvent::Input(input) => {
+                    match input {
+                        InputEvent::Key(key) => {
+                            /* Keys are typed into the command line while it is open */
+                            let typing = command_line.is_open();
+                            if let Some(line) = command_line.handle_key(key) {
+                                let result = match command_line.prompt() {
+                                    '/' => search(&line, &panes),
+                                    '*' => enter_password(
+                                        line,
+                                        &panes,
+                                        &mut command_line,
+                                        &mut pending_save,
+                                    ),
+                                    '?' => confirm_annotations(&line, &panes),
+                                    _ => match plugins.run_command(
+                                        &line,
+                                        &canonical(&panes[0].file),
+                                        panes[0].viewer.page_first() + 1,
+                                    ) {
+                                        Some(x) => x.map(|_| None),
+                                        None => run_command(
+                                            &line,
+                                            &mut panes,
+                                            &mut command_line,
+                                            &mut key_matcher,
+                                            &mut pending_save,
+                                        ),
+                                    },
+                                };
+                                match result {
+                                    Ok(Some(x)) | Err(x) => command_line.set_message(x),
+                                    Ok(None) => (),
+                                }
+                            } else if let Some(hints) = link_hints.as_mut() {
+                                /* Other keys than the ones of the labels stop picking */
+                                if key.kind != KeyEventKind::Release {
+                                    let input = match key.code {
+                                        KeyCode::Char(x) => hints.type_key(x),
+                                        KeyCode::Backspace => {
+                                            hints.back();
+                                            HintInput::Pending
+                                        }
+                                        _ => HintInput::Cancel,
+                                    };
+                                    match input {
+                                        HintInput::Pending => (),
+                                        HintInput::Follow(i, link) => {
+                                            link_hints = None;
+                                            follow_link(
+                                                &mut panes,
+                                                i,
+                                                &link,
+                                                &mut command_line,
+                                            );
+                                        }
+                                        HintInput::Cancel => link_hints = None,
+                                    }
+                                }
+                            } else if let Some(pending) = pending_mark {
+                                if key.kind != KeyEventKind::Release {
+                                    pending_mark = None;
+                                    if let KeyCode::Char(name) = key.code {
+                                        handle_mark(
+                                            pending,
+                                            name,
+                                            &mut panes,
+                                            &mut command_line,
+                                        );
+                                    }
+                                }
+                            } else if !typing
+                                && !handle_count(
+                                    &key,
+                                    &mut pending_count,
+                                    &mut panes,
+                                    &mut command_line,
+                                )
+                                && handle_key(
+                                    key,
+                                    &mut key_matcher,
+                                    &mut panes,
+                                    &mut throttle_data,
+                                    current_mouse,
+                                    &mut command_line,
+                                    &mut pending_mark,
+                                    &mut link_hints,
+                                    &mut tabs,
+                                    &scripts,
+                                )
+                            {
+                                break 'main;
+                            }
+                        }
+                        InputEvent::MouseScroll(kind, modifiers) => {
+                            if handle_mouse_scroll(
+                                kind,
+                                modifiers,
+                                current_mouse,
+                                &mut panes[0].viewer,
+                            ) {
+                                break 'main;
+                            }
+                            sync_panes(&mut panes, 0);
                         }
+                        InputEvent::End => break 'main,
+                    }
+
+                    if handle_document_end(&mut panes, &mut tabs, &mut command_line) {
+                        break 'main;
+                    }
+                }
+                AppEvent::Mouse(mouse) => {
+                    current_mouse = mouse;
+
+                    let hide_after = CONFIG.get().unwrap().viewer.uri_hint.hide_after;
+                    if hide_after > 0.0 {
+                        hint_timeout = after(Duration::from_secs_f32(hide_after));
+                    }
+                    hint_hidden = false;
+
+                    /* Terminals without image support report the mouse position in
+                     * cells */
+                    if BLOCK_ART.load(Ordering::Acquire) {
+                        current_mouse.column *= BLOCKART_CELL.0;
+                        current_mouse.row *= BLOCKART_CELL.1;
                     }
-                    InputEvent::MouseScroll(kind, _modifiers) => {
-                        if handle_mouse_scroll(kind, &mut viewer) {
-                            break 'main;
+
+                    /* Clicks on the toggles of the bar turn them off */
+                    let toggle = match current_mouse.kind {
+                        MouseEventKind::Down(MouseButton::Left) => {
+                            let memory = memory_used(&panes);
+                            bar_toggle(
+                                &bar_state(&panes, &tabs, &memory, &scripts.segments()),
+                                mouse_cell(current_mouse),
+                            )
                         }
+                        _ => None,
+                    };
+                    if let Some(toggle) = toggle {
+                        match handle_bar_toggle(toggle, &mut panes) {
+                            Ok(x) | Err(x) => command_line.set_message(x),
+                        }
+                    } else {
+                        handle_selection(current_mouse, &mut panes);
                     }
                 }
+                AppEvent::Resize(width, height) => {
+                    /* Only the last size is applied once the resizing has stopped */
+                    pending_resize = Some((width, height));
+                    resize_timeout = after(RESIZE_DEBOUNCE);
+                }
+            },
+            x if x == 2 * n + 1 => {
+                let request = requests.try_recv().expect("Could not receive request");
+                open_request(&mut panes, request);
+            }
+            x if x == 2 * n + 2 => {
+                hint_timeout
+                    .try_recv()
+                    .expect("Could not receive the hint timeout");
+                hint_timeout = never();
+                hint_hidden = true;
+            }
+            x if x == 2 * n + 3 => {
+                if let Some(message) = plugins.receive() {
+                    command_line.set_message(message);
+                }
             }
-            5 => {
-                current_mouse =
-                    event_inputs.1.try_recv().expect("Could not receive mouse");
+            x if x == 2 * n + 4 => {
+                frames
+                    .try_recv()
+                    .expect("Could not receive the scroll frame");
+                panes[0].viewer.glide();
+                sync_panes(&mut panes, 0);
             }
-            6 => {
-                let (width, height) = event_inputs
-                    .3
+            x if x == 2 * n + 5 => {
+                resize_timeout
                     .try_recv()
-                    .expect("Could not receive from win-size");
-
-                let mut handle = TERMINAL_SIZE
-                    .get()
-                    .unwrap()
-                    .write()
-                    .expect("Could not get win sie handle");
-                handle.width = width;
-                handle.height = height;
+                    .expect("Could not receive the resize timeout");
+                resize_timeout = never();
+                if let Some(size) = pending_resize.take() {
+                    apply_resize(size, &mut panes);
+                }
+            }
+            x if x == 2 * n + 6 => {
+                frame_timeout
+                    .try_recv()
+                    .expect("Could not receive the frame timeout");
+            }
+            x if x == 2 * n + 7 => {
+                refresh
+                    .try_recv()
+                    .expect("Could not receive the preview refresh");
+                for pane in panes.iter_mut() {
+                    pane.viewer.refresh_previews(&pane.renderer);
+                }
             }
             _ => unreachable!(),
         };
 
-        if let Some(link) = viewer.intersect_link(current_mouse) {
+        /* The pages are laid out and sent again only once the size has settled */
+        if pending_resize.is_some() {
+            execute!(io::stdout(), ClearImages, Clear(ClearType::FromCursorDown))
+                .expect("Could not clear images");
+            resize_placeholder(crossterm::terminal::size().unwrap_or_default());
+            continue 'main;
+        }
+
+        if run_scripts(
+            &mut scripts,
+            &mut panes,
+            current_mouse,
+            &mut command_line,
+            &mut pending_mark,
+            &mut link_hints,
+            &mut tabs,
+        ) {
+            break 'main;
+        }
+
+        let hovered = panes.iter().enumerate().find_map(|(i, pane)| {
+            pane.viewer
+                .intersect_link(current_mouse)
+                .filter(|_| !hint_hidden)
+                .map(|link| (i, link))
+        });
+        /* The hint is printed after the pages, since pages drawn out of cells would
+         * overwrite it */
+        let mut hint = None;
+        if let Some((i, link)) = hovered {
             execute!(io::stdout(), SetPointerShape(PointerShape::Pointer))
                 .expect("Could not set pointer shape");
 
-            viewer.uri_hint(&link);
+            hint = Some((i, link.clone()));
             if current_mouse.kind.is_down() {
-                /* URI points to page in this document */
-                if link.uri.starts_with('#') {
-                    let _ = viewer.jump(link.page as usize);
-                } else {
-                    let _ = open::that_detached(link.uri);
-                }
+                follow_link(&mut panes, i, &link, &mut command_line);
 
                 execute!(io::stdout(), SetPointerShape(PointerShape::Default))
                     .expect("Could not set pointer shape");
@@ -283,33 +2165,222 @@ fn main() {
                 .expect("Could not set pointer shape");
         }
 
+        /* Changes arriving within a frame interval are drawn together in one frame */
+        let since = last_frame.elapsed();
+        if since < frame_interval {
+            if !frame_pending {
+                frame_timeout = after(frame_interval - since);
+                frame_pending = true;
+            }
+            continue 'main;
+        }
+        frame_timeout = never();
+        frame_pending = false;
+        last_frame = Instant::now();
+
+        execute!(io::stdout(), ClearImages, Clear(ClearType::FromCursorDown))
+            .expect("Could not clear images");
+
         let gr = RECEIVER_GR.get().unwrap().lock().unwrap();
-        let displayed = viewer
-            .display_pages(&renderer)
-            .expect("Could not display pages");
-        for page in displayed {
-            let res = gr.recv().unwrap();
-            if res.payload().contains("OK") {
+        for pane in panes.iter_mut() {
+            if !pane.viewer.is_uninit() {
+                pane.reading.record(pane.viewer.page_view());
+            }
+
+            /* No images are sent at all in the text mode */
+            if pane.viewer.text_mode() {
+                pane.viewer
+                    .display_text(&pane.renderer)
+                    .expect("Could not display text");
                 continue;
             }
 
-            viewer.schedule_transfer(page);
+            let displayed = pane
+                .viewer
+                .display_pages(&pane.renderer)
+                .expect("Could not display pages");
+            for page in displayed {
+                let mut res = placement_response(&gr, &mut command_line);
+                if terminal_graphics_placed(&mut res) {
+                    pane.viewer.transfer_placed(page);
+                    continue;
+                }
+
+                pane.viewer.schedule_transfer(page);
+            }
+
+            let regions = pane
+                .viewer
+                .display_regions(&pane.renderer)
+                .expect("Could not display inverted regions");
+            for region in regions {
+                let mut res = placement_response(&gr, &mut command_line);
+                if !terminal_graphics_placed(&mut res) {
+                    pane.viewer.transfer_region(region);
+                }
+            }
+
+            if pane
+                .viewer
+                .display_popup()
+                .expect("Could not display popup")
+            {
+                let mut res = placement_response(&gr, &mut command_line);
+                if !terminal_graphics_placed(&mut res) {
+                    pane.viewer.transfer_popup();
+                }
+            }
+
+            pane.viewer.mark_pages(|x| pane.notes.has(x), NOTE_MARKER);
+            pane.viewer.mark_failed_pages();
+        }
+
+        if !panes[0].viewer.is_uninit() {
+            let viewer = &panes[0].viewer;
+            let file = canonical(&panes[0].file);
+            plugins.page_changed(&file, viewer.page_first() + 1, viewer.pages());
+            threads::ipc::set_status(Status {
+                file,
+                page: viewer.page_view() + 1,
+                pages: viewer.pages(),
+            });
+        }
+        if context.is_enabled() && !panes[0].viewer.is_uninit() {
+            let viewer = &panes[0].viewer;
+            let link = hint.as_ref().map(|(_, x)| match x.uri.starts_with('#') {
+                true => format!("page {}", x.page + 1),
+                false => x.uri.clone(),
+            });
+            let page = viewer.page_view();
+            let section = context.section(&panes[0].file, page).map(str::to_string);
+            context.update(&Context {
+                page: page + 1,
+                pages: viewer.pages(),
+                section: section.as_deref(),
+                link: link.as_deref(),
+                selection: Some(viewer.selected_text()),
+            });
+        }
+
+        /* The hints are shown above the bar */
+        display_bar(&bar_state(
+            &panes,
+            &tabs,
+            &memory_used(&panes),
+            &scripts.segments(),
+        ));
+        if let Some((i, link)) = hint {
+            panes[i].viewer.uri_hint(&link);
         }
+        if let Some(hints) = &link_hints {
+            hints.display(|i, page, link| panes.get(i)?.viewer.link_cell(page, link));
+        }
+        status_hint(
+            panes.iter().map(|x| x.viewer.queued()).sum(),
+            panes.iter().find_map(|x| x.viewer.prerendered()),
+            panes.iter().any(|x| x.removed),
+            panes.iter().any(|x| x.rebuilding),
+        );
+        command_line.display();
+
+        graphics_backend()
+            .present()
+            .expect("Could not present the frame");
     }
 
     RUNNING.store(false, Ordering::Release);
 
     /* ========================== Cook the terminal on exit ========================== */
-    execute!(io::stdout(), DisableMouseCapturePixels)
-        .expect("Could not disable mouse capture");
-    execute!(io::stdout(), LeaveAlternateScreen).expect("Could not leave alt mode");
-    execute!(io::stdout(), Show).expect("Could not show cursor");
-    disable_raw_mode().expect("Could not uncook the terminal");
+    if !headless {
+        execute!(io::stdout(), PopKeyboardEnhancementFlags)
+            .expect("Could not disable the key event types");
+        execute!(io::stdout(), DisableMouseCapturePixels)
+            .expect("Could not disable mouse capture");
+        execute!(io::stdout(), LeaveAlternateScreen).expect("Could not leave alt mode");
+        execute!(io::stdout(), Show).expect("Could not show cursor");
+        disable_raw_mode().expect("Could not uncook the terminal");
+    }
+    flush_startup_log();
+
+    /* The panes are dropped first so that nothing reads the copy anymore */
+    drop(panes);
+    drop(tabs);
+    if let Some(file) = STDIN_DOCUMENT.get() {
+        let _ = std::fs::remove_file(file);
+    }
+}
+
+/* Receives the response to the next placement. The responses to rejected transfers
+ * that arrive in between lower the chunk size of the transfers */
+fn placement_response(
+    gr: &Receiver<GraphicsResponse>,
+    command_line: &mut CommandLine,
+) -> GraphicsResponse {
+    loop {
+        let mut res = gr.recv().unwrap();
+        if !terminal_graphics_transfer_response(&mut res) {
+            return res;
+        }
+        if let Err(x) = terminal_graphics_transfer_failed(&mut res) {
+            command_line.set_message(x);
+        }
+    }
+}
+
+/* Applies the size of the terminal in pixels, which the pages follow */
+fn apply_resize((width, height): (u16, u16), panes: &mut [Pane]) {
+    let factor = {
+        let mut handle = TERMINAL_SIZE
+            .get()
+            .unwrap()
+            .write()
+            .expect("Could not get win sie handle");
+        let size = window_size().expect("Could not get win size");
+        if BLOCK_ART.load(Ordering::Acquire) {
+            *handle = blockart_window_size(size);
+            1.0f32
+        } else {
+            let cell_width = |x: &WindowSize| x.width as f32 / x.columns as f32;
+            let before = cell_width(&handle);
+            handle.columns = size.columns;
+            handle.rows = size.rows;
+            handle.width = width;
+            handle.height = height;
+            cell_width(&handle) / before
+        }
+    };
+
+    /* Resizing the window by a part of a cell changes the size of the cells only
+     * slightly, the font size or the DPI change it by more */
+    if factor.is_finite() && (factor - 1.0).abs() > CELL_CHANGE {
+        for pane in panes.iter_mut() {
+            pane.viewer.rescale_cells(factor);
+        }
+    }
 }
 
-fn handle_mouse_scroll(kind: MouseEventKind, viewer: &mut Viewer) -> bool {
+fn handle_mouse_scroll(
+    kind: MouseEventKind,
+    modifiers: KeyModifiers,
+    mouse: MouseEvent,
+    viewer: &mut Viewer,
+) -> bool {
     let config = CONFIG.get().unwrap();
 
+    /* Ctrl with the wheel zooms around the point under the mouse */
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        match kind {
+            MouseEventKind::ScrollUp => {
+                viewer.scale_at(config.viewer.scale_amount, mouse)
+            }
+            MouseEventKind::ScrollDown => {
+                viewer.scale_at(-config.viewer.scale_amount, mouse)
+            }
+            _ => (),
+        }
+        return false;
+    }
+
     let inverse_factor = if config.viewer.inverse_scroll {
         1.0
     } else {
@@ -318,33 +2389,119 @@ fn handle_mouse_scroll(kind: MouseEventKind, viewer: &mut Viewer) -> bool {
 
     match kind {
         MouseEventKind::ScrollUp => {
-            viewer.scroll((0.0f32, inverse_factor * config.viewer.scroll_speed));
+            viewer.fling((0.0f32, inverse_factor * config.viewer.scroll_speed));
             false
         }
         MouseEventKind::ScrollDown => {
-            viewer.scroll((0.0f32, -inverse_factor * config.viewer.scroll_speed));
+            viewer.fling((0.0f32, -inverse_factor * config.viewer.scroll_speed));
             false
         }
         MouseEventKind::ScrollLeft => {
-            viewer.scroll((-config.viewer.scroll_speed, 0.0f32));
+            viewer.fling((-config.viewer.scroll_speed, 0.0f32));
             false
         }
         MouseEventKind::ScrollRight => {
-            viewer.scroll((config.viewer.scroll_speed, 0.0f32));
+            viewer.fling((config.viewer.scroll_speed, 0.0f32));
             false
         }
         _ => false,
     }
 }
 
+fn update_scripts(scripts: &Scripts, panes: &[Pane]) -> Result<(), String> {
+    let viewer = &panes[0].viewer;
+    scripts.update(&ScriptState {
+        file: &canonical(&panes[0].file),
+        page: viewer.page_first() + 1,
+        pages: viewer.pages(),
+        zoom: viewer.get_scale() * 100.0,
+        offset: viewer.offset(),
+    })
+}
+
+/* Lets the scripts see the first pane after an event and does what they have asked
+ * for. Returns `true` when an action of theirs quits the viewer */
+fn run_scripts(
+    scripts: &mut Scripts,
+    panes: &mut Vec<Pane>,
+    mouse: MouseEvent,
+    command_line: &mut CommandLine,
+    pending_mark: &mut Option<PendingMark>,
+    link_hints: &mut Option<LinkHints>,
+    tabs: &mut Tabs,
+) -> bool {
+    let result = update_scripts(scripts, panes).and_then(|_| {
+        let viewer = &panes[0].viewer;
+        if viewer.is_uninit() {
+            return Ok(());
+        }
+        scripts.page_changed(
+            &canonical(&panes[0].file),
+            viewer.page_first() + 1,
+            viewer.pages(),
+        )
+    });
+    let requests = match result.and_then(|_| scripts.requests()) {
+        Ok(x) => x,
+        Err(x) => {
+            command_line.set_message(x);
+            return false;
+        }
+    };
+
+    for request in requests {
+        match request {
+            /* Scripts call the functions of each other directly */
+            ScriptRequest::Action(ConfigAction::RunScript(name)) => command_line
+                .set_message(format!("`RunScript` of `{}` is for keys only", name)),
+            ScriptRequest::Action(action) => {
+                if handle_action(
+                    &action,
+                    panes,
+                    mouse,
+                    command_line,
+                    pending_mark,
+                    link_hints,
+                    tabs,
+                    scripts,
+                ) {
+                    return true;
+                }
+            }
+            ScriptRequest::Jump(page) => {
+                panes[0].viewer.push_jump();
+                if let Err(x) = panes[0].viewer.jump(page - 1) {
+                    command_line.set_message(x);
+                }
+                sync_panes(panes, 0);
+            }
+            ScriptRequest::Message(x) => command_line.set_message(x),
+        }
+    }
+
+    /* The status segments see what the requests have changed */
+    let _ = update_scripts(scripts, panes);
+    false
+}
+
+#[allow(clippy::too_many_arguments)]
 fn handle_key(
     key: KeyEvent,
     key_matcher: &mut Keybinds<ConfigAction>,
-    viewer: &mut Viewer,
-    renderer: &threads::renderer::Renderer,
+    panes: &mut Vec<Pane>,
     throttle_data: &mut LastExecuted,
+    mouse: MouseEvent,
+    command_line: &mut CommandLine,
+    pending_mark: &mut Option<PendingMark>,
+    link_hints: &mut Option<LinkHints>,
+    tabs: &mut Tabs,
+    scripts: &Scripts,
 ) -> bool {
-    let config = CONFIG.get().unwrap();
+    /* Actions fire when keys are pressed or held, never when they are released */
+    if key.kind == KeyEventKind::Release {
+        throttle_data.event_types = true;
+        return false;
+    }
 
     let possible_action = key_matcher.dispatch(KeyInput::from(key));
     if possible_action.is_none() {
@@ -353,22 +2510,68 @@ fn handle_key(
 
     let action = possible_action.unwrap();
 
+    /* Held keys scroll and zoom on, but toggles only flip once per press */
+    let toggle = matches!(
+        action,
+        ConfigAction::ToggleAlpha
+            | ConfigAction::ToggleInverse
+            | ConfigAction::ToggleDiff
+            | ConfigAction::TogglePaper
+            | ConfigAction::ToggleTextMode
+            | ConfigAction::CycleColorBlind
+    );
+    if toggle && throttle_data.repeated_toggle(&key) {
+        return false;
+    }
+
+    handle_action(
+        action,
+        panes,
+        mouse,
+        command_line,
+        pending_mark,
+        link_hints,
+        tabs,
+        scripts,
+    )
+}
+
+/* Does what the action of a key or a script stands for. Returns `true` when the
+ * viewer should quit */
+#[allow(clippy::too_many_arguments)]
+fn handle_action(
+    action: &ConfigAction,
+    panes: &mut Vec<Pane>,
+    mouse: MouseEvent,
+    command_line: &mut CommandLine,
+    pending_mark: &mut Option<PendingMark>,
+    link_hints: &mut Option<LinkHints>,
+    tabs: &mut Tabs,
+    scripts: &Scripts,
+) -> bool {
+    let config = CONFIG.get().unwrap();
+
+    /* Navigation applies to the first pane, the other panes follow it */
+    let pane = &mut panes[0];
+    let viewer = &mut pane.viewer;
+    let renderer = &pane.renderer;
+
     /* `true` indicates that the caller should exit *safely* the current process */
-    match action {
+    let quit = match action {
         ConfigAction::MoveUp => {
-            viewer.scroll((0.0f32, -config.viewer.scroll_speed));
+            viewer.scroll_smooth((0.0f32, -config.viewer.scroll_speed));
             false
         }
         ConfigAction::MoveDown => {
-            viewer.scroll((0.0f32, config.viewer.scroll_speed));
+            viewer.scroll_smooth((0.0f32, config.viewer.scroll_speed));
             false
         }
         ConfigAction::MoveLeft => {
-            viewer.scroll((-config.viewer.scroll_speed, 0.0f32));
+            viewer.scroll_smooth((-config.viewer.scroll_speed, 0.0f32));
             false
         }
         ConfigAction::MoveRight => {
-            viewer.scroll((config.viewer.scroll_speed, 0.0f32));
+            viewer.scroll_smooth((config.viewer.scroll_speed, 0.0f32));
             false
         }
         ConfigAction::PrevPage => {
@@ -383,6 +2586,93 @@ fn handle_key(
             let last_page = viewer.pages() - 1;
             if current_page < last_page {
                 let _ = viewer.jump(current_page + 1);
+            } else {
+                /* The last page is scrolled through so that its end can be passed */
+                let layout = config.viewer.page_layout;
+                viewer.scroll(layout.join(config.viewer.scroll_speed, 0.0f32));
+            }
+            false
+        }
+        ConfigAction::NextFigure => {
+            viewer.jump_caption(true, Some(renderer));
+            false
+        }
+        ConfigAction::PrevFigure => {
+            viewer.jump_caption(false, Some(renderer));
+            false
+        }
+        ConfigAction::ZoomBlock => {
+            let hovered = panes.iter().find(|x| x.viewer.mouse2page(mouse).is_some());
+            if let Some(pane) = hovered {
+                pane.viewer.open_popup(mouse, &pane.renderer);
+            }
+            false
+        }
+        ConfigAction::InvertRegion => {
+            let hovered = panes
+                .iter_mut()
+                .find(|x| x.viewer.mouse2page(mouse).is_some());
+            if let Some(pane) = hovered {
+                match pane.viewer.mark_region(mouse) {
+                    Ok(false) => command_line.set_message(
+                        "Mark the opposite corner of the region".to_string(),
+                    ),
+                    Ok(true) => (),
+                    Err(x) => command_line.set_message(x),
+                }
+            }
+            false
+        }
+        ConfigAction::ClearRegions => {
+            for pane in panes.iter_mut() {
+                pane.viewer.clear_regions();
+            }
+            false
+        }
+        ConfigAction::CopyImage if SANDBOX.load(Ordering::Acquire) => {
+            command_line
+                .set_message("Images are not extracted in sandbox mode".to_string());
+            false
+        }
+        ConfigAction::CopyImage => {
+            let hovered = panes
+                .iter()
+                .find_map(|x| Some((x, x.viewer.mouse2content(mouse)?)));
+            let message = match hovered {
+                Some((pane, (page, point))) => {
+                    match images::image_at(&pane.file, page, point) {
+                        Ok(Some(image)) => images::copy_image(&image, page),
+                        Ok(None) => Err("No image under the mouse".to_string()),
+                        Err(x) => Err(x),
+                    }
+                }
+                None => Err("No page under the mouse".to_string()),
+            };
+            command_line.set_message(message.unwrap_or_else(|x| x));
+            false
+        }
+        ConfigAction::LinkQrCode => {
+            let hovered = panes
+                .iter_mut()
+                .find_map(|x| Some((x.viewer.intersect_link(mouse)?, x)));
+            let shown = match hovered {
+                /* Links into the document have nothing to scan */
+                Some((link, _)) if link.uri.starts_with('#') => {
+                    Err("The link under the mouse points into the document".to_string())
+                }
+                Some((link, pane)) => qr::qr_image(&link.uri).map(|x| {
+                    pane.viewer.set_popup(Some(x));
+                }),
+                None => Err("No link under the mouse".to_string()),
+            };
+            if let Err(x) = shown {
+                command_line.set_message(x);
+            }
+            false
+        }
+        ConfigAction::ClosePopup => {
+            for pane in panes.iter_mut() {
+                pane.viewer.close_popup();
             }
             false
         }
@@ -391,47 +2681,205 @@ fn handle_key(
             false
         }
         ConfigAction::JumpFirstPage => {
+            viewer.push_jump();
             let _ = viewer.jump(0);
             false
         }
         ConfigAction::JumpLastPage => {
             let last_page = viewer.pages() - 1;
+            viewer.push_jump();
             let _ = viewer.jump(last_page);
             false
         }
+        ConfigAction::JumpBookmark => {
+            jump_bookmark(pane, command_line);
+            false
+        }
+        ConfigAction::SetMark => {
+            *pending_mark = Some(PendingMark::Set);
+            false
+        }
+        ConfigAction::JumpMark => {
+            *pending_mark = Some(PendingMark::Jump);
+            false
+        }
+        ConfigAction::ToggleTextMode => {
+            let message = match viewer.toggle_text_mode() {
+                true => "Text mode",
+                false => "Page mode",
+            };
+            command_line.set_message(message.to_string());
+            false
+        }
+        ConfigAction::MarkPage => {
+            let page = viewer.page_first();
+            pane.organizer.sync(viewer.pages());
+            let message = match pane.organizer.toggle_mark(page) {
+                true => format!("Marked page {}", page + 1),
+                false => format!("Unmarked page {}", page + 1),
+            };
+            command_line.set_message(format!(
+                "{}, marked: {}",
+                message,
+                pane.organizer.marked()
+            ));
+            false
+        }
+        ConfigAction::JumpBack => {
+            if !viewer.jump_back() {
+                command_line.set_message("The jump list is empty".to_string());
+            }
+            false
+        }
+        ConfigAction::CommandLine => {
+            command_line.open(':');
+            false
+        }
+        ConfigAction::Search => {
+            command_line.open('/');
+            false
+        }
+        ConfigAction::NextMatch | ConfigAction::PrevMatch => {
+            let forward = matches!(action, ConfigAction::NextMatch);
+            match viewer.jump_search(forward) {
+                Some((current, total)) => {
+                    command_line.set_message(format!("Match {}/{}", current + 1, total))
+                }
+                None => command_line.set_message("No matches".to_string()),
+            }
+            false
+        }
+        ConfigAction::NextTab | ConfigAction::PrevTab => {
+            let offset = match action {
+                ConfigAction::NextTab => 1,
+                _ => -1,
+            };
+            match tabs.files.len() {
+                1 => command_line.set_message("No other document is open".to_string()),
+                _ => {
+                    if let Err(x) = tabs.switch(panes, tabs.offset(offset)) {
+                        command_line.set_message(x);
+                    }
+                }
+            }
+            false
+        }
         ConfigAction::Quit => true,
-        ConfigAction::ToggleAlpha => {
-            if throttle_data.alpha.elapsed().unwrap() < Duration::from_millis(500) {
-                return false;
+        ConfigAction::Unbound => false,
+        ConfigAction::RunCommand(_) if SANDBOX.load(Ordering::Acquire) => {
+            command_line.set_message("Commands are not run in sandbox mode".to_string());
+            false
+        }
+        ConfigAction::RunCommand(template) => {
+            let selection = panes
+                .iter()
+                .map(|x| x.viewer.selected_text())
+                .find(|x| !x.is_empty())
+                .unwrap_or_default();
+            let pane = &panes[0];
+            let result = external::spawn_command(
+                template,
+                &canonical(&pane.file),
+                pane.viewer.page_first() + 1,
+                selection,
+            );
+            if let Err(x) = result {
+                command_line.set_message(x);
             }
-
-            throttle_data.alpha = SystemTime::now();
-
-            renderer
-                .send_and_confirm_action(threads::renderer::RendererAction::ToggleAlpha)
+            false
+        }
+        ConfigAction::RunScript(name) => {
+            if let Err(x) = scripts.run_action(name) {
+                command_line.set_message(x);
+            }
+            false
+        }
+        ConfigAction::ToggleAlpha => {
+            toggle_renderers(panes, RendererAction::ToggleAlpha)
                 .expect("Could not send action to renderer");
-            viewer.invalidate_registry();
             false
         }
         ConfigAction::ToggleInverse => {
-            if throttle_data.inverse.elapsed().unwrap() < Duration::from_millis(500) {
-                return false;
-            }
-
-            throttle_data.inverse = SystemTime::now();
+            let inverse = panes[0].inverse.next();
+            set_inverse(panes, inverse).expect("Could not send action to renderer");
+            command_line.set_message(inverse.describe());
+            false
+        }
+        ConfigAction::ToggleDiff => {
+            toggle_renderers(panes, RendererAction::ToggleDiff)
+                .expect("Could not send action to renderer");
+            false
+        }
+        ConfigAction::TogglePaper => {
+            toggle_renderers(panes, RendererAction::TogglePaper)
+                .expect("Could not send action to renderer");
+            false
+        }
+        ConfigAction::CycleColorBlind => {
+            let daltonize = Daltonize::next(pane.daltonize);
+            set_daltonize(panes, daltonize).expect("Could not send action to renderer");
+            command_line.set_message(Daltonize::describe(daltonize));
+            false
+        }
+        ConfigAction::ExportPage => {
             renderer
-                .send_and_confirm_action(threads::renderer::RendererAction::ToggleInverse)
+                .send_action(RendererAction::ExportPage(viewer.page_first()))
                 .expect("Could not send action to renderer");
-            viewer.invalidate_registry();
+            false
+        }
+        ConfigAction::HintLinks => {
+            let links = panes
+                .iter()
+                .enumerate()
+                .flat_map(|(i, x)| {
+                    x.viewer
+                        .visible_links()
+                        .into_iter()
+                        .map(move |(page, link)| (i, page, link))
+                })
+                .collect();
+            *link_hints = LinkHints::new(links);
+            if link_hints.is_none() {
+                command_line.set_message("No links on the screen".to_string());
+            }
+            false
+        }
+        ConfigAction::ShowInfo => {
+            match info_overlay(pane) {
+                Ok(lines) => command_line.set_overlay(lines),
+                Err(x) => command_line.set_message(x),
+            }
+            false
+        }
+        ConfigAction::CopyCitation => {
+            let message = cite::cite(&pane.file).and_then(|x| {
+                copy_text(&x)?;
+                Ok(format!("Copied the citation of {}", pane.file))
+            });
+            command_line.set_message(message.unwrap_or_else(|x| x));
+            false
+        }
+        ConfigAction::CopyDeepLink => {
+            let link = DeepLink {
+                file: canonical(&pane.file),
+                page: viewer.page_first() + 1,
+                zoom: Some(viewer.get_scale() * 100.0),
+            };
+
+            execute!(io::stdout(), CopyToClipboard(link.to_uri()))
+                .expect("Could not copy to clipboard");
             false
         }
         ConfigAction::ZoomIn => {
-            viewer.scale(config.viewer.scale_amount);
+            viewer.scale_at(config.viewer.scale_amount, mouse);
             false
         }
         ConfigAction::ZoomOut => {
-            viewer.scale(-config.viewer.scale_amount);
+            viewer.scale_at(-config.viewer.scale_amount, mouse);
             false
         }
-    }
+    };
+
+    sync_panes(panes, 0);
+    quit
 }
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::config::ConfigEvictionPolicy;
+
+struct Entry {
+    size: usize,
+    /* Ticks of the clock at which the page has been received and last displayed */
+    inserted: u64,
+    used: u64,
+}
+
+/* Keeps track of the memory taken by the rendered pages and picks the page to drop
+ * once the memory limit has been reached. Pages that are on the screen are pinned and
+ * never picked */
+pub struct MemoryManager {
+    policy: ConfigEvictionPolicy,
+    entries: HashMap<usize, Entry>,
+    pinned: HashSet<usize>,
+    used: usize,
+    clock: u64,
+}
+
+impl MemoryManager {
+    pub fn new(policy: ConfigEvictionPolicy) -> Self {
+        Self {
+            policy,
+            entries: HashMap::new(),
+            pinned: HashSet::new(),
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /* Bytes taken by all of the pages in memory */
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn insert(&mut self, page: usize, size: usize) {
+        self.remove(page);
+
+        let now = self.tick();
+        self.used += size;
+        self.entries.insert(
+            page,
+            Entry {
+                size,
+                inserted: now,
+                used: now,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, page: usize) {
+        if let Some(entry) = self.entries.remove(&page) {
+            self.used -= entry.size;
+        }
+    }
+
+    /* Called whenever a page is displayed */
+    pub fn touch(&mut self, page: usize) {
+        let now = self.tick();
+        if let Some(entry) = self.entries.get_mut(&page) {
+            entry.used = now;
+        }
+    }
+
+    /* Replaces the pages that are on the screen */
+    pub fn pin(&mut self, pages: impl IntoIterator<Item = usize>) {
+        self.pinned = pages.into_iter().collect();
+    }
+
+    /* The page that is dropped next with the page in the middle of the view being
+     * `view`, or `None` if only pinned pages are left */
+    pub fn victim(&self, view: usize) -> Option<usize> {
+        let candidates = self
+            .entries
+            .iter()
+            .filter(|(page, _)| !self.pinned.contains(page));
+
+        let victim = match self.policy {
+            ConfigEvictionPolicy::Fifo => candidates.min_by_key(|(_, x)| x.inserted),
+            ConfigEvictionPolicy::Lru => candidates.min_by_key(|(_, x)| x.used),
+            /* The farthest page goes first, the least recently displayed one of
+             * equally far pages on both sides */
+            ConfigEvictionPolicy::Distance => candidates
+                .max_by_key(|(page, x)| (page.abs_diff(view), u64::MAX - x.used)),
+        };
+
+        victim.map(|(page, _)| *page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_victim() {
+        let mut memory = MemoryManager::new(ConfigEvictionPolicy::Distance);
+        for page in [4, 0, 9, 5, 1] {
+            memory.insert(page, 10);
+        }
+        memory.insert(9, 5);
+        assert!(memory.used() == 45);
+
+        memory.pin([4, 5]);
+        assert!(memory.victim(4) == Some(9));
+        memory.touch(0);
+        assert!(memory.victim(0) == Some(9));
+        memory.remove(9);
+        assert!(memory.victim(9) == Some(0));
+        assert!(memory.used() == 40);
+
+        memory.policy = ConfigEvictionPolicy::Lru;
+        assert!(memory.victim(4) == Some(1));
+        memory.policy = ConfigEvictionPolicy::Fifo;
+        assert!(memory.victim(4) == Some(0));
+
+        memory.pin([0, 1, 4, 5]);
+        assert!(memory.victim(4).is_none());
+    }
+}
@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use mupdf::pdf::PdfDocument;
+use serde::{Deserialize, Serialize};
+
+use crate::state::state_dir;
+
+/* The notes of the pages of a document as they are written to its file */
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NotesFile {
+    /* The canonical path of the document, for the ones reading the files */
+    #[serde(default)]
+    file: String,
+    #[serde(default)]
+    note: Vec<Note>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Note {
+    /* The page counted from one */
+    page: usize,
+    text: String,
+}
+
+/* FNV-1a of the bytes. The hash of the standard library may change between releases
+ * of Rust, which would lose the notes */
+fn hash(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xCBF29CE484222325, |hash, x| {
+        (hash ^ x as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+fn path_hash(path: &str) -> u64 {
+    hash(path.bytes())
+}
+
+/* The first half of the `/ID` of a PDF, which stays the same when the document is
+ * written again */
+fn pdf_id(file: &str) -> Option<Vec<u8>> {
+    let pdf = PdfDocument::open(file).ok()?;
+    let id = pdf.trailer().ok()?.get_dict("ID").ok()??;
+    let first = id.get_array(0).ok()??;
+    let bytes = first.as_bytes().ok()?;
+    (!bytes.is_empty()).then(|| bytes.to_vec())
+}
+
+/* The name of the notes file of a document, by its `/ID` so that the notes follow
+ * it when it is moved, renamed or written again, or by its path for documents
+ * without one */
+fn notes_name(file: &str) -> String {
+    match pdf_id(file) {
+        Some(id) => format!("id-{:016X}.toml", hash(id)),
+        None => format!("{:016X}.toml", path_hash(file)),
+    }
+}
+
+/* Free-form notes of the pages of a document. They are stored outside of it in
+ * `<state>/meowpdf/notes/`, so read-only documents get notes too */
+#[derive(Debug, Default)]
+pub struct Notes {
+    file: String,
+    path: Option<PathBuf>,
+    /* The file of the notes by the path as older versions have written it, read when
+     * the document has none of its own and removed once its notes are written */
+    fallback: Option<PathBuf>,
+    /* The notes by their zero-indexed page */
+    pages: BTreeMap<usize, String>,
+}
+
+impl Notes {
+    /* Documents without notes or with an unreadable notes file have none */
+    pub fn open(file: &str) -> Self {
+        let notes = state_dir().ok().map(|x| x.join("notes"));
+        let path = notes.as_ref().map(|x| x.join(notes_name(file)));
+        let fallback = notes
+            .map(|x| x.join(format!("{:016X}.toml", path_hash(file))))
+            .filter(|x| Some(x) != path.as_ref());
+        let stored: NotesFile = [&path, &fallback]
+            .into_iter()
+            .flatten()
+            .find_map(|x| std::fs::read_to_string(x).ok())
+            .and_then(|x| toml::from_str(&x).ok())
+            .unwrap_or_default();
+
+        Self {
+            file: file.to_string(),
+            path,
+            fallback,
+            pages: stored
+                .note
+                .into_iter()
+                .filter(|x| x.page > 0)
+                .map(|x| (x.page - 1, x.text))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, page: usize) -> Option<&str> {
+        self.pages.get(&page).map(String::as_str)
+    }
+
+    pub fn has(&self, page: usize) -> bool {
+        self.pages.contains_key(&page)
+    }
+
+    /* Replaces the note of the zero-indexed page and writes the notes. An empty text
+     * removes the note */
+    pub fn set(&mut self, page: usize, text: &str) -> Result<(), String> {
+        match text.trim() {
+            "" => self.pages.remove(&page),
+            text => self.pages.insert(page, text.to_string()),
+        };
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or("No state directory has been found")?;
+        if self.pages.is_empty() {
+            let _ = std::fs::remove_file(path);
+            self.remove_fallback();
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|x| format!("Could not create {}: {}", parent.display(), x))?;
+        }
+
+        let stored = NotesFile {
+            file: self.file.clone(),
+            note: self
+                .pages
+                .iter()
+                .map(|(page, text)| Note {
+                    page: page + 1,
+                    text: text.clone(),
+                })
+                .collect(),
+        };
+        let serialized = toml::to_string(&stored)
+            .map_err(|x| format!("Could not serialize the notes: {}", x))?;
+        std::fs::write(path, serialized)
+            .map_err(|x| format!("Could not write {}: {}", path.display(), x))?;
+
+        /* Only once the notes are safe in their new file */
+        self.remove_fallback();
+        Ok(())
+    }
+
+    fn remove_fallback(&self) {
+        if let Some(fallback) = self.fallback.as_ref() {
+            let _ = std::fs::remove_file(fallback);
+        }
+    }
+
+    /* The lines of the overlay listing the notes, each with the first line of its
+     * text */
+    pub fn summary(&self) -> Vec<String> {
+        if self.pages.is_empty() {
+            return vec!["No page has a note, add one with `:note`".to_string()];
+        }
+
+        let mut lines = vec![format!(
+            "{} notes, jump to them with `:<page>`",
+            self.pages.len()
+        )];
+        for (page, text) in self.pages.iter() {
+            let first = text.lines().next().unwrap_or_default();
+            let more = if text.lines().count() > 1 { " .." } else { "" };
+            lines.push(format!("{:>5}  {}{}", page + 1, first, more));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes() {
+        assert!(path_hash("") == 0xCBF29CE484222325);
+        assert!(path_hash("a") == 0xAF63DC4C8601EC8C);
+        assert!(path_hash("/a.pdf") != path_hash("/b.pdf"));
+        assert!(
+            notes_name("/nonexistent/a.pdf")
+                == format!("{:016X}.toml", path_hash("/nonexistent/a.pdf"))
+        );
+
+        let mut notes = Notes::default();
+        notes
+            .pages
+            .insert(2, "Proof of the lemma\nsee also page 9".to_string());
+        notes.pages.insert(11, "Typo".to_string());
+        assert!(notes.has(2) && !notes.has(3));
+        assert!(notes.get(11) == Some("Typo"));
+        assert!(
+            notes.summary()
+                == [
+                    "2 notes, jump to them with `:<page>`",
+                    "    3  Proof of the lemma ..",
+                    "   12  Typo",
+                ]
+        );
+
+        let stored: NotesFile =
+            toml::from_str("file = \"/a.pdf\"\n[[note]]\npage = 3\ntext = \"x\"\n")
+                .unwrap();
+        assert!(
+            stored.note
+                == [Note {
+                    page: 3,
+                    text: "x".to_string()
+                }]
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::BTreeSet;
+
+use mupdf::pdf::PdfDocument;
+
+/* A page of the organized document, with the zero-indexed page of the document it
+ * comes from and the rotation in degrees that is added to the one of that page */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrganizedPage {
+    pub source: usize,
+    pub rotation: i32,
+}
+
+/* The pages of a document deleted, moved and rotated before they are saved to a new
+ * PDF. The document itself stays as it is, pages are marked and moved by the numbers
+ * they have in it */
+#[derive(Debug, Default)]
+pub struct Organizer {
+    total: usize,
+    pages: Vec<OrganizedPage>,
+    marked: BTreeSet<usize>,
+}
+
+/* Describes pages counted from one with runs of following pages joined, such as
+ * `1-3, 5, 4 (90°)` */
+pub fn describe_pages(pages: &[OrganizedPage]) -> String {
+    let mut runs: Vec<(OrganizedPage, usize)> = Vec::new();
+    for page in pages {
+        match runs.last_mut() {
+            Some((first, length))
+                if page.rotation == 0
+                    && first.rotation == 0
+                    && first.source + *length == page.source =>
+            {
+                *length += 1
+            }
+            _ => runs.push((*page, 1)),
+        }
+    }
+
+    let runs: Vec<String> = runs
+        .iter()
+        .map(|(first, length)| match (length, first.rotation) {
+            (1, 0) => format!("{}", first.source + 1),
+            (1, rotation) => format!("{} ({}°)", first.source + 1, rotation),
+            (length, _) => format!("{}-{}", first.source + 1, first.source + length),
+        })
+        .collect();
+    runs.join(", ")
+}
+
+impl Organizer {
+    /* Starts over when the number of pages of the document has changed */
+    pub fn sync(&mut self, total: usize) {
+        if self.total == total {
+            return;
+        }
+        *self = Self {
+            total,
+            pages: (0..total)
+                .map(|source| OrganizedPage {
+                    source,
+                    rotation: 0,
+                })
+                .collect(),
+            marked: BTreeSet::new(),
+        };
+    }
+
+    pub fn reset(&mut self) {
+        let total = self.total;
+        self.total = 0;
+        self.sync(total);
+    }
+
+    /* Marks the page or unmarks it if it is marked already. Returns whether it is
+     * marked */
+    pub fn toggle_mark(&mut self, page: usize) -> bool {
+        if self.marked.remove(&page) {
+            return false;
+        }
+        self.marked.insert(page);
+        true
+    }
+
+    pub fn marked(&self) -> String {
+        let marked: Vec<OrganizedPage> = self
+            .marked
+            .iter()
+            .map(|x| OrganizedPage {
+                source: *x,
+                rotation: 0,
+            })
+            .collect();
+        describe_pages(&marked)
+    }
+
+    pub fn pages(&self) -> &[OrganizedPage] {
+        &self.pages
+    }
+
+    /* Edits apply to the marked pages or to the current page when none are marked.
+     * The marks are cleared by the edits so that the next one starts with new ones */
+    fn targets(&self, current: usize) -> BTreeSet<usize> {
+        match self.marked.is_empty() {
+            true => BTreeSet::from([current]),
+            false => self.marked.clone(),
+        }
+    }
+
+    /* Returns the number of deleted pages */
+    pub fn delete(&mut self, current: usize) -> Result<usize, String> {
+        let targets = self.targets(current);
+        if self.pages.iter().all(|x| targets.contains(&x.source)) {
+            return Err("Every page of the document can not be deleted".to_string());
+        }
+
+        let before = self.pages.len();
+        self.pages.retain(|x| !targets.contains(&x.source));
+        self.marked.clear();
+        Ok(before - self.pages.len())
+    }
+
+    /* Returns the number of rotated pages */
+    pub fn rotate(&mut self, current: usize, degrees: i32) -> usize {
+        let targets = self.targets(current);
+        let mut rotated = 0;
+        for page in self
+            .pages
+            .iter_mut()
+            .filter(|x| targets.contains(&x.source))
+        {
+            page.rotation = (page.rotation + degrees).rem_euclid(360);
+            rotated += 1;
+        }
+        self.marked.clear();
+        rotated
+    }
+
+    /* Moves the pages behind the given page, in front of the first page for `None`.
+     * Returns the number of moved pages */
+    pub fn move_after(
+        &mut self,
+        current: usize,
+        after: Option<usize>,
+    ) -> Result<usize, String> {
+        let targets = self.targets(current);
+        if after.is_some_and(|x| targets.contains(&x)) {
+            return Err("Pages can not be moved behind themselves".to_string());
+        }
+
+        let (moved, mut rest): (Vec<OrganizedPage>, Vec<OrganizedPage>) =
+            self.pages.iter().partition(|x| targets.contains(&x.source));
+        let position = match after {
+            None => 0,
+            Some(after) => match rest.iter().position(|x| x.source == after) {
+                Some(x) => x + 1,
+                None => return Err(format!("Page {} has been deleted", after + 1)),
+            },
+        };
+
+        let count = moved.len();
+        rest.splice(position..position, moved);
+        self.pages = rest;
+        self.marked.clear();
+        Ok(count)
+    }
+
+    /* Writes the organized pages of the PDF to `out`. Returns the number of pages */
+    pub fn save(&self, file: &str, out: &str) -> Result<usize, String> {
+        let same = std::fs::canonicalize(out)
+            .is_ok_and(|x| std::fs::canonicalize(file).is_ok_and(|y| x == y));
+        if same {
+            return Err(
+                "The document can not be overwritten by its organized pages".to_string()
+            );
+        }
+
+        let source = PdfDocument::open(file)
+            .map_err(|x| format!("Only PDF documents can be organized: {}", x))?;
+        let mut organized = PdfDocument::new();
+
+        for (i, page) in self.pages.iter().enumerate() {
+            organized
+                .graft_page(i as i32, &source, page.source as i32)
+                .map_err(|x| format!("Could not copy page {}: {}", page.source + 1, x))?;
+            if page.rotation == 0 {
+                continue;
+            }
+
+            /* Grafted pages carry the rotation they inherit from the page tree */
+            let rotate = |organized: &PdfDocument| -> Result<(), mupdf::Error> {
+                let mut object = organized.find_page(i as i32)?;
+                let rotation = match object.get_dict("Rotate")? {
+                    Some(x) => x.as_int()?,
+                    None => 0,
+                };
+                let rotation = (rotation + page.rotation).rem_euclid(360);
+                object.dict_put("Rotate", organized.new_int(rotation)?)
+            };
+            rotate(&organized).map_err(|x| {
+                format!("Could not rotate page {}: {}", page.source + 1, x)
+            })?;
+        }
+
+        organized
+            .save(out)
+            .map_err(|x| format!("Could not save {}: {}", out, x))?;
+        Ok(self.pages.len())
+    }
+}
+
+/* Inserts the pages of another PDF into a PDF at the zero-indexed position, at the
+ * end for `None`, and writes it back. It is written next to it first so that the
+ * reloads never see it half written. Returns the number of inserted pages */
+pub fn append_document(
+    file: &str,
+    other: &str,
+    position: Option<usize>,
+) -> Result<usize, String> {
+    let mut pdf = PdfDocument::open(file)
+        .map_err(|x| format!("Only pages of PDF documents can be appended: {}", x))?;
+    let source = PdfDocument::open(other)
+        .map_err(|x| format!("Could not open {} as a PDF: {}", other, x))?;
+    let pages = pdf
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?;
+    let count = source
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?;
+
+    let position = position.map_or(pages, |x| x as i32);
+    if position > pages {
+        return Err(format!("The document has {} pages", pages));
+    }
+    for i in 0..count {
+        pdf.graft_page(position + i, &source, i)
+            .map_err(|x| format!("Could not copy page {} of {}: {}", i + 1, other, x))?;
+    }
+
+    let written = format!("{}.meowpdf-append", file);
+    pdf.save(&written)
+        .map_err(|x| format!("Could not save {}: {}", written, x))
+        .and_then(|_| {
+            std::fs::rename(&written, file)
+                .map_err(|x| format!("Could not replace {}: {}", file, x))
+        })
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&written);
+        })?;
+    Ok(count as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_organizer() {
+        let mut organizer = Organizer::default();
+        organizer.sync(8);
+        assert!(describe_pages(organizer.pages()) == "1-8");
+
+        organizer.toggle_mark(1);
+        organizer.toggle_mark(5);
+        organizer.toggle_mark(6);
+        assert!(!organizer.toggle_mark(6));
+        assert!(organizer.marked() == "2, 6");
+        assert!(organizer.delete(0) == Ok(2));
+        assert!(describe_pages(organizer.pages()) == "1, 3-5, 7-8");
+
+        assert!(organizer.rotate(3, 270) == 1);
+        assert!(organizer.rotate(3, 180) == 1);
+        assert!(describe_pages(organizer.pages()) == "1, 3, 4 (90°), 5, 7-8");
+
+        organizer.toggle_mark(0);
+        organizer.toggle_mark(2);
+        assert!(organizer.move_after(0, Some(6)) == Ok(2));
+        assert!(describe_pages(organizer.pages()) == "4 (90°), 5, 7, 1, 3, 8");
+        assert!(organizer.move_after(7, None) == Ok(1));
+        assert!(organizer.move_after(4, Some(4)).is_err());
+        assert!(organizer.move_after(4, Some(1)).is_err());
+        assert!(describe_pages(organizer.pages()) == "8, 4 (90°), 5, 7, 1, 3");
+
+        organizer.reset();
+        assert!(describe_pages(organizer.pages()) == "1-8");
+        for page in 0..7 {
+            organizer.toggle_mark(page);
+        }
+        organizer.toggle_mark(7);
+        assert!(organizer.delete(0).is_err());
+    }
+}
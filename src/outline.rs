@@ -0,0 +1,436 @@
+use mupdf::{pdf::PdfDocument, Outline};
+
+use crate::document::open_document;
+
+/* Flattens the outline in document order into the titles and zero-indexed pages of
+ * its entries */
+fn flatten(outlines: &[Outline], entries: &mut Vec<(String, Option<usize>)>) {
+    for outline in outlines {
+        entries.push((outline.title.clone(), outline.page.map(|x| x as usize)));
+        flatten(&outline.down, entries);
+    }
+}
+
+/* Finds the page of the first entry titled like the given title, ignoring case and
+ * surrounding whitespace. Entries starting with the title, such as `Chapter 3: Results`
+ * for `Chapter 3`, are only considered when no entry matches exactly */
+pub fn match_section(entries: &[(String, Option<usize>)], title: &str) -> Option<usize> {
+    let title = title.trim().to_lowercase();
+    let titles: Vec<String> = entries.iter().map(|x| x.0.trim().to_lowercase()).collect();
+
+    let exact = titles.iter().position(|x| *x == title);
+    let prefix = || titles.iter().position(|x| x.starts_with(&title));
+    entries[exact.or_else(prefix)?].1
+}
+
+/* The titles and zero-indexed pages of the outline entries in document order */
+pub fn outline_sections(file: &str) -> Result<Vec<(String, Option<usize>)>, String> {
+    let document = open_document(file)?;
+    let outlines = document
+        .outlines()
+        .map_err(|x| format!("Could not read the outline: {}", x))?;
+
+    let mut entries = Vec::new();
+    flatten(&outlines, &mut entries);
+    Ok(entries)
+}
+
+/* The title of the section that the zero-indexed page belongs to, which is the last
+ * entry starting on the page or before it */
+pub fn section_at(entries: &[(String, Option<usize>)], page: usize) -> Option<&str> {
+    entries
+        .iter()
+        .filter_map(|(title, x)| x.filter(|x| *x <= page).map(|x| (title, x)))
+        .max_by_key(|x| x.1)
+        .map(|x| x.0.trim())
+}
+
+/* The zero-indexed page of the outline entry titled like the given title */
+pub fn resolve_section(file: &str, title: &str) -> Result<usize, String> {
+    let entries = outline_sections(file)?;
+    match_section(&entries, title).ok_or(format!("No section `{}` has been found", title))
+}
+
+/* The zero-indexed page a named destination of the document points to */
+pub fn resolve_destination(file: &str, name: &str) -> Result<usize, String> {
+    let document = open_document(file)?;
+    let location = document
+        .resolve_link(&format!("#nameddest={}", name))
+        .map_err(|x| format!("Could not resolve destination `{}`: {}", name, x))?;
+
+    location
+        .map(|x| x.page as usize)
+        .ok_or(format!("No destination `{}` has been found", name))
+}
+
+/* The point of a page and the zoom in percent an internal link points to */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LinkTarget {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub zoom: Option<f32>,
+}
+
+/* Parses the view of the internal link URIs of mupdf, such as `#page=3&zoom=150,72,400`
+ * or `#page=3&view=FitH,400`, with the coordinates in page units from the top left
+ * corner of the page. Unspecified values are left out */
+pub fn parse_link_target(uri: &str) -> LinkTarget {
+    let mut target = LinkTarget::default();
+    let fragment = uri.split_once('#').map_or("", |x| x.1);
+
+    for (key, value) in fragment.split('&').filter_map(|x| x.split_once('=')) {
+        let words: Vec<&str> = value.split(',').collect();
+        let value = |i: usize| {
+            words
+                .get(i)
+                .and_then(|x| x.trim().parse::<f32>().ok())
+                .filter(|x| x.is_finite())
+        };
+
+        match (key, words[0]) {
+            ("zoom", _) => {
+                target.zoom = value(0).filter(|x| *x > 0.0);
+                target.x = value(1);
+                target.y = value(2);
+            }
+            ("view", "FitH" | "FitBH") => target.y = value(1),
+            ("view", "FitV" | "FitBV") => target.x = value(1),
+            ("view", "FitR") => {
+                target.x = value(1);
+                target.y = value(2);
+            }
+            _ => (),
+        }
+    }
+
+    target
+}
+
+/* An entry of an outline that is edited, pointing at the top of the view at `y` in
+ * page units on the zero-indexed page */
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineEntry {
+    pub title: String,
+    pub depth: usize,
+    pub page: usize,
+    pub y: f32,
+}
+
+/* The entries that point into the document, the others can not be written back */
+fn flatten_entries(outlines: &[Outline], depth: usize, entries: &mut Vec<OutlineEntry>) {
+    for outline in outlines {
+        if let Some(page) = outline.page {
+            entries.push(OutlineEntry {
+                title: outline.title.clone(),
+                depth,
+                page: page as usize,
+                y: outline
+                    .uri
+                    .as_deref()
+                    .and_then(|x| parse_link_target(x).y)
+                    .unwrap_or(0.0),
+            });
+        }
+        flatten_entries(&outline.down, depth + 1, entries);
+    }
+}
+
+/* Where an entry for the position is inserted so that the entries stay in the order
+ * of the document. It is put at the level of the entry before it */
+pub fn insert_position(entries: &[OutlineEntry], page: usize, y: f32) -> (usize, usize) {
+    let index = entries
+        .iter()
+        .position(|x| (x.page, x.y) > (page, y))
+        .unwrap_or(entries.len());
+    let depth = match index {
+        0 => 0,
+        x => entries[x - 1].depth,
+    };
+    (index, depth)
+}
+
+/* Removes an entry, its children move up by one level */
+pub fn remove_entry(entries: &mut Vec<OutlineEntry>, index: usize) -> OutlineEntry {
+    let removed = entries.remove(index);
+    for entry in entries[index..]
+        .iter_mut()
+        .take_while(|x| x.depth > removed.depth)
+    {
+        entry.depth -= 1;
+    }
+    removed
+}
+
+/* The parent, the previous and the next sibling, the first and the last child and the
+ * number of descendants of the entries of a flattened outline with the given levels */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OutlineLinks {
+    pub parent: Option<usize>,
+    pub prev: Option<usize>,
+    pub next: Option<usize>,
+    pub first: Option<usize>,
+    pub last: Option<usize>,
+    pub descendants: usize,
+}
+
+pub fn outline_links(depths: &[usize]) -> Vec<OutlineLinks> {
+    let mut links = vec![OutlineLinks::default(); depths.len()];
+    /* The entries of the levels above the current entry */
+    let mut ancestors: Vec<usize> = Vec::new();
+
+    for (i, depth) in depths.iter().enumerate() {
+        /* Levels skipped by an entry are attached to the last entry above them */
+        while ancestors.len() > *depth {
+            ancestors.pop();
+        }
+        let parent = ancestors.last().copied();
+
+        let prev = (0..i)
+            .rev()
+            .take_while(|x| Some(*x) != parent)
+            .find(|x| links[*x].parent == parent);
+        links[i].parent = parent;
+        links[i].prev = prev;
+        if let Some(prev) = prev {
+            links[prev].next = Some(i);
+        }
+        if let Some(parent) = parent {
+            links[parent].first.get_or_insert(i);
+            links[parent].last = Some(i);
+        }
+        for ancestor in &ancestors {
+            links[*ancestor].descendants += 1;
+        }
+
+        ancestors.push(i);
+    }
+
+    links
+}
+
+/* The outline entries of a document that are edited before they are written back */
+pub struct OutlineEditor {
+    file: String,
+    entries: Vec<OutlineEntry>,
+}
+
+impl OutlineEditor {
+    pub fn open(file: &str) -> Result<Self, String> {
+        let document = open_document(file)?;
+        let outlines = document
+            .outlines()
+            .map_err(|x| format!("Could not read the outline: {}", x))?;
+
+        let mut entries = Vec::new();
+        flatten_entries(&outlines, 0, &mut entries);
+        Ok(Self {
+            file: file.to_owned(),
+            entries,
+        })
+    }
+
+    /* The numbered entries indented by their level */
+    pub fn lines(&self) -> Vec<String> {
+        if self.entries.is_empty() {
+            return vec!["The outline is empty".to_string()];
+        }
+
+        let mut lines = vec!["Outline".to_string()];
+        for (i, entry) in self.entries.iter().enumerate() {
+            lines.push(format!(
+                "{:>3} {}{} ({})",
+                i + 1,
+                "  ".repeat(entry.depth),
+                entry.title,
+                entry.page + 1
+            ));
+        }
+        lines
+    }
+
+    /* Adds an entry for the point of the page that is the given fraction of its
+     * height from the top. Returns the number of the entry counted from one */
+    pub fn add(
+        &mut self,
+        title: &str,
+        page: usize,
+        fraction: f32,
+    ) -> Result<usize, String> {
+        let bounds = open_document(&self.file)?
+            .load_page(page as i32)
+            .and_then(|x| x.bounds())
+            .map_err(|x| format!("Could not load page {}: {}", page + 1, x))?;
+        let y = fraction.clamp(0.0, 1.0) * (bounds.y1 - bounds.y0);
+
+        let (index, depth) = insert_position(&self.entries, page, y);
+        self.entries.insert(
+            index,
+            OutlineEntry {
+                title: title.to_string(),
+                depth,
+                page,
+                y,
+            },
+        );
+        Ok(index + 1)
+    }
+
+    fn index(&self, number: usize) -> Result<usize, String> {
+        match number {
+            x if (1..=self.entries.len()).contains(&x) => Ok(x - 1),
+            x => Err(format!("No outline entry {}", x)),
+        }
+    }
+
+    pub fn rename(&mut self, number: usize, title: &str) -> Result<(), String> {
+        let index = self.index(number)?;
+        self.entries[index].title = title.to_string();
+        Ok(())
+    }
+
+    /* Returns the title of the removed entry */
+    pub fn remove(&mut self, number: usize) -> Result<String, String> {
+        let index = self.index(number)?;
+        Ok(remove_entry(&mut self.entries, index).title)
+    }
+
+    /* Replaces the outline of the PDF with the entries. It is written next to it
+     * first so that the reloads never see it half written */
+    pub fn save(&self) -> Result<usize, String> {
+        let mut pdf = PdfDocument::open(&self.file)
+            .map_err(|x| format!("Only outlines of PDF documents can be saved: {}", x))?;
+        self.write(&mut pdf)
+            .map_err(|x| format!("Could not write the outline: {}", x))?;
+
+        let written = format!("{}.meowpdf-outline", self.file);
+        pdf.save(&written)
+            .map_err(|x| format!("Could not save {}: {}", written, x))?;
+        std::fs::rename(&written, &self.file)
+            .map_err(|x| format!("Could not replace {}: {}", self.file, x))?;
+        Ok(self.entries.len())
+    }
+
+    fn write(&self, pdf: &mut PdfDocument) -> Result<(), mupdf::Error> {
+        let mut catalog = pdf.catalog()?;
+        if self.entries.is_empty() {
+            return catalog.dict_delete("Outlines");
+        }
+
+        /* The items refer to each other, so every one is added before they are linked */
+        let root = pdf.add_object(&pdf.new_dict()?)?.as_indirect()?;
+        let mut items = Vec::new();
+        for _ in &self.entries {
+            items.push(pdf.add_object(&pdf.new_dict()?)?.as_indirect()?);
+        }
+
+        let depths: Vec<usize> = self.entries.iter().map(|x| x.depth).collect();
+        let links = outline_links(&depths);
+        for (i, entry) in self.entries.iter().enumerate() {
+            let mut item = pdf.new_indirect(items[i], 0)?;
+            item.dict_put("Title", pdf.new_string(&entry.title)?)?;
+            let parent = links[i].parent.map_or(root, |x| items[x]);
+            item.dict_put("Parent", pdf.new_indirect(parent, 0)?)?;
+
+            let siblings = [
+                ("Prev", links[i].prev),
+                ("Next", links[i].next),
+                ("First", links[i].first),
+                ("Last", links[i].last),
+            ];
+            for (key, sibling) in siblings {
+                if let Some(x) = sibling {
+                    item.dict_put(key, pdf.new_indirect(items[x], 0)?)?;
+                }
+            }
+            if links[i].descendants > 0 {
+                item.dict_put("Count", pdf.new_int(links[i].descendants as i32)?)?;
+            }
+
+            /* Destinations are given from the bottom of the page */
+            let bounds = pdf.load_page(entry.page as i32)?.bounds()?;
+            let mut destination = pdf.new_array()?;
+            destination.array_push(pdf.find_page(entry.page as i32)?)?;
+            destination.array_push(pdf.new_name("FitH")?)?;
+            destination.array_push(pdf.new_real(bounds.y1 - bounds.y0 - entry.y)?)?;
+            item.dict_put("Dest", destination)?;
+        }
+
+        let top: Vec<usize> = (0..links.len())
+            .filter(|x| links[*x].parent.is_none())
+            .collect();
+        let mut outlines = pdf.new_indirect(root, 0)?;
+        outlines.dict_put("Type", pdf.new_name("Outlines")?)?;
+        outlines.dict_put("First", pdf.new_indirect(items[top[0]], 0)?)?;
+        outlines.dict_put("Last", pdf.new_indirect(items[top[top.len() - 1]], 0)?)?;
+        outlines.dict_put("Count", pdf.new_int(self.entries.len() as i32)?)?;
+        catalog.dict_put("Outlines", pdf.new_indirect(root, 0)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_section() {
+        let entries = vec![
+            ("Introduction".to_string(), Some(0)),
+            ("Chapter 3: Results".to_string(), Some(12)),
+            ("chapter 3".to_string(), Some(10)),
+            ("Appendix".to_string(), None),
+        ];
+
+        assert!(match_section(&entries, " Chapter 3 ") == Some(10));
+        assert!(match_section(&entries, "chapter 3: res") == Some(12));
+        assert!(match_section(&entries, "INTRODUCTION") == Some(0));
+        assert!(match_section(&entries, "Appendix").is_none());
+        assert!(match_section(&entries, "Bibliography").is_none());
+
+        assert!(section_at(&entries, 0) == Some("Introduction"));
+        assert!(section_at(&entries, 11) == Some("chapter 3"));
+        assert!(section_at(&entries, 40) == Some("Chapter 3: Results"));
+        assert!(section_at(&entries[2..], 0).is_none());
+    }
+
+    #[test]
+    fn test_parse_link_target() {
+        let target = parse_link_target("#page=3&zoom=150,72.5,400");
+        assert!(target.zoom == Some(150.0) && target.x == Some(72.5));
+        assert!(target.y == Some(400.0));
+
+        let target = parse_link_target("#page=3&zoom=0,nan,120");
+        assert!(target.zoom.is_none() && target.x.is_none() && target.y == Some(120.0));
+        assert!(parse_link_target("#page=2&view=FitH,80").y == Some(80.0));
+        assert!(parse_link_target("#page=2&view=Fit") == LinkTarget::default());
+        assert!(parse_link_target("#nameddest=intro") == LinkTarget::default());
+    }
+
+    #[test]
+    fn test_outline_entries() {
+        let entry = |title: &str, depth, page| OutlineEntry {
+            title: title.to_string(),
+            depth,
+            page,
+            y: 0.0,
+        };
+        let mut entries = vec![
+            entry("1", 0, 0),
+            entry("1.1", 1, 2),
+            entry("1.1.1", 2, 3),
+            entry("2", 0, 5),
+        ];
+        assert!(insert_position(&entries, 4, 0.0) == (3, 2));
+        assert!(insert_position(&entries, 0, -1.0) == (0, 0));
+        assert!(insert_position(&entries, 9, 0.0) == (4, 0));
+
+        let links = outline_links(&[0, 1, 2, 1, 0]);
+        assert!(links[0].first == Some(1) && links[0].last == Some(3));
+        assert!(links[0].descendants == 3 && links[0].next == Some(4));
+        assert!(links[3].prev == Some(1) && links[3].parent == Some(0));
+        assert!(links[2].parent == Some(1) && links[2].prev.is_none());
+        assert!(links[4].prev == Some(0) && links[4].parent.is_none());
+
+        assert!(remove_entry(&mut entries, 1).title == "1.1");
+        assert!(entries.iter().map(|x| x.depth).collect::<Vec<_>>() == [0, 1, 0]);
+    }
+}
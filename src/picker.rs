@@ -0,0 +1,208 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{read, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{
+        disable_raw_mode, enable_raw_mode, size, Clear, ClearType, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
+};
+
+/* Extensions of the documents that mupdf reads */
+const EXTENSIONS: [&str; 8] = ["pdf", "xps", "oxps", "epub", "fb2", "mobi", "cbz", "svg"];
+/* How deep the directories below the working directory are scanned and how many
+ * documents are listed at most, so that starting in the home directory stays fast */
+const MAX_DEPTH: usize = 3;
+const MAX_FILES: usize = 10000;
+
+/* Scores how well the query matches the path, ignoring case, or `None` if the
+ * characters of the query do not appear in it in order. Characters that follow each
+ * other and ones at the start of a word of the path score higher, shorter paths win
+ * ties */
+fn fuzzy_score(query: &str, path: &str) -> Option<i64> {
+    let lower = |x: char| x.to_lowercase().next().unwrap_or(x);
+    let chars: Vec<char> = path.chars().map(lower).collect();
+
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+    for x in query.chars().map(lower).filter(|x| !x.is_whitespace()) {
+        let found = position + chars[position..].iter().position(|y| *y == x)?;
+        let word_start = found == 0 || !chars[found - 1].is_alphanumeric();
+
+        score += 1;
+        if previous.is_some_and(|x| x + 1 == found) {
+            score += 5;
+        }
+        if word_start {
+            score += 3;
+        }
+        previous = Some(found);
+        position = found + 1;
+    }
+
+    Some(score * 1000 - chars.len() as i64)
+}
+
+/* The paths matching the query, best first */
+fn filter_paths<'a>(paths: &'a [String], query: &str) -> Vec<&'a str> {
+    let mut matches: Vec<(i64, &str)> = paths
+        .iter()
+        .filter_map(|x| fuzzy_score(query, x).map(|score| (score, x.as_str())))
+        .collect();
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    matches.into_iter().map(|x| x.1).collect()
+}
+
+/* Collects the documents below the directory, leaving out hidden files and
+ * directories */
+fn scan(directory: &Path, prefix: &str, depth: usize, paths: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+
+    let mut entries: Vec<_> = entries.filter_map(|x| x.ok()).collect();
+    entries.sort_by_key(|x| x.file_name());
+    for entry in entries {
+        if paths.len() >= MAX_FILES {
+            return;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let path = format!("{}{}", prefix, name);
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        if kind.is_dir() {
+            if depth < MAX_DEPTH {
+                scan(&entry.path(), &format!("{}/", path), depth + 1, paths);
+            }
+            continue;
+        }
+
+        let supported = Path::new(&name)
+            .extension()
+            .map(|x| x.to_string_lossy().to_lowercase())
+            .is_some_and(|x| EXTENSIONS.contains(&x.as_str()));
+        if supported {
+            paths.push(path);
+        }
+    }
+}
+
+fn draw(query: &str, matches: &[&str], selected: usize, total: usize) -> io::Result<()> {
+    let (columns, rows) = size()?;
+    let mut stdout = io::stdout();
+    queue!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+    queue!(
+        stdout,
+        Print(format!("{}/{} > {}", matches.len(), total, query))
+    )?;
+
+    /* The list scrolls so that the selected path stays visible */
+    let visible = rows.saturating_sub(1) as usize;
+    let first = (selected + 1).saturating_sub(visible);
+    for (i, path) in matches.iter().enumerate().skip(first).take(visible) {
+        let line: String = path.chars().take(columns as usize).collect();
+        queue!(stdout, MoveTo(0, (i - first + 1) as u16))?;
+        if i == selected {
+            queue!(
+                stdout,
+                SetAttribute(Attribute::Reverse),
+                Print(line),
+                SetAttribute(Attribute::Reset)
+            )?;
+        } else {
+            queue!(stdout, Print(line))?;
+        }
+    }
+    stdout.flush()
+}
+
+fn run(paths: &[String]) -> io::Result<Option<String>> {
+    let mut query = String::new();
+    let mut selected = 0;
+
+    loop {
+        let matches = filter_paths(paths, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+        draw(&query, &matches, selected, paths.len())?;
+
+        let Event::Key(key) = read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter => return Ok(matches.get(selected).map(|x| x.to_string())),
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if control => return Ok(None),
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p') if control => selected = selected.saturating_sub(1),
+            KeyCode::Down => selected += 1,
+            KeyCode::Char('n') if control => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char(x) if !control => {
+                query.push(x);
+                selected = 0;
+            }
+            _ => (),
+        }
+    }
+}
+
+/* Lets the user pick one of the documents below the working directory by typing a
+ * part of its path. Returns `None` when the picker is left without a document */
+pub fn pick() -> Result<Option<String>, String> {
+    let mut paths = Vec::new();
+    scan(Path::new("."), "", 0, &mut paths);
+    if paths.is_empty() {
+        return Err("No documents have been found in the working directory".to_string());
+    }
+
+    enable_raw_mode().map_err(|x| format!("Could not uncook the terminal: {}", x))?;
+    let _ = execute!(io::stdout(), EnterAlternateScreen, Hide);
+    let picked = run(&paths);
+    let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+    disable_raw_mode().map_err(|x| format!("Could not cook the terminal: {}", x))?;
+
+    picked.map_err(|x| format!("Could not read the input: {}", x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("ppr", "papers/report.pdf").is_some());
+        assert!(fuzzy_score("xyz", "papers/report.pdf").is_none());
+        assert!(fuzzy_score("", "a.pdf") == Some(-5));
+
+        /* Following characters and word starts score higher */
+        assert!(fuzzy_score("rep", "report.pdf") > fuzzy_score("rep", "roadmap.pdf"));
+        assert!(fuzzy_score("ro", "a/roadmap.pdf") > fuzzy_score("ro", "euro.pdf"));
+
+        let paths = [
+            "notes/draft.pdf".to_string(),
+            "report.pdf".to_string(),
+            "books/rust.epub".to_string(),
+        ];
+        assert!(filter_paths(&paths, "rep") == ["report.pdf", "books/rust.epub"]);
+        assert!(filter_paths(&paths, "R")[0] == "report.pdf");
+        assert!(filter_paths(&paths, "").len() == 3);
+    }
+}
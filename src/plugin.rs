@@ -0,0 +1,280 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+
+use crate::command::{parse_command, split_words};
+
+/* Messages that plugins write to their stdout, one JSON object per line, such as
+ * `{"type": "message", "text": "Saved"}` */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum PluginMessage {
+    /* Adds a command to the command line, which is sent back as a `command` event */
+    RegisterCommand { name: String },
+    /* Shows the text in the bar */
+    Message { text: String },
+}
+
+/* Events that are written to the stdin of every plugin, one JSON object per line.
+ * Pages are counted from one */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum PluginEvent<'a> {
+    PageChanged {
+        file: &'a str,
+        page: usize,
+        pages: usize,
+    },
+    SelectionMade {
+        file: &'a str,
+        text: &'a str,
+    },
+    Command {
+        name: &'a str,
+        args: &'a [String],
+        file: &'a str,
+        page: usize,
+    },
+}
+
+struct Plugin {
+    name: String,
+    input: Sender<String>,
+    commands: Vec<String>,
+}
+
+/* The executables of the plugin directory, which are started with the viewer and
+ * talk to it over their stdin and stdout */
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+    /* Kept so that the messages never disconnect when there are no plugins */
+    sender: Sender<(usize, Result<PluginMessage, String>)>,
+    messages: Receiver<(usize, Result<PluginMessage, String>)>,
+    /* The document and the page of the last `page-changed` event */
+    page: Option<(String, usize)>,
+}
+
+impl Default for Plugins {
+    fn default() -> Self {
+        let (sender, messages) = unbounded();
+        Self {
+            plugins: Vec::new(),
+            sender,
+            messages,
+            page: None,
+        }
+    }
+}
+
+impl Plugins {
+    /* Starts every executable of the directory, an empty directory starts none.
+     * Plugins that can not be started are left out and reported in the error */
+    pub fn load(&mut self, directory: &str) -> Result<(), String> {
+        if directory.is_empty() {
+            return Ok(());
+        }
+
+        let entries = std::fs::read_dir(directory)
+            .map_err(|x| format!("Could not read the plugin directory: {}", x))?;
+        let mut paths: Vec<_> = entries
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| {
+                x.metadata()
+                    .is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+            })
+            .collect();
+        paths.sort();
+
+        let errors: Vec<String> =
+            paths.iter().filter_map(|x| self.spawn(x).err()).collect();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join(", ")),
+        }
+    }
+
+    fn spawn(&mut self, path: &Path) -> Result<(), String> {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|x| format!("Could not start plugin {}: {}", name, x))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        /* Events are written by another thread so that a plugin that does not read
+         * them never blocks the viewer. Its stdin is closed once the viewer quits */
+        let (input, lines) = unbounded::<String>();
+        std::thread::spawn(move || {
+            for line in lines.iter() {
+                if writeln!(stdin, "{}", line)
+                    .and_then(|_| stdin.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let index = self.plugins.len();
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let message = serde_json::from_str(&line)
+                    .map_err(|x| format!("Invalid message `{}`: {}", line, x));
+                if sender.send((index, message)).is_err() {
+                    break;
+                }
+            }
+            /* Reaped here so that no zombie is left behind */
+            let _ = child.wait();
+        });
+
+        self.plugins.push(Plugin {
+            name,
+            input,
+            commands: Vec::new(),
+        });
+        Ok(())
+    }
+
+    pub fn messages(&self) -> &Receiver<(usize, Result<PluginMessage, String>)> {
+        &self.messages
+    }
+
+    /* Handles a message of a plugin. Returns the text to show in the bar */
+    pub fn receive(&mut self) -> Option<String> {
+        let (index, message) = self.messages.try_recv().ok()?;
+        let plugin = &mut self.plugins[index];
+
+        match message {
+            Ok(PluginMessage::RegisterCommand { name }) => {
+                /* Built-in commands can not be replaced */
+                let unknown = matches!(
+                    parse_command(&name),
+                    Err(x) if x.starts_with("Unknown command")
+                );
+                if !unknown || name.contains(char::is_whitespace) {
+                    return Some(format!(
+                        "{}: The command `{}` can not be registered",
+                        plugin.name, name
+                    ));
+                }
+                plugin.commands.push(name);
+                None
+            }
+            Ok(PluginMessage::Message { text }) => Some(text),
+            Err(x) => Some(format!("{}: {}", plugin.name, x)),
+        }
+    }
+
+    /* Writes the event to every plugin. Plugins that have exited are skipped */
+    pub fn send(&self, event: &PluginEvent) {
+        let Ok(line) = serde_json::to_string(event) else {
+            return;
+        };
+        for plugin in self.plugins.iter() {
+            let _ = plugin.input.send(line.clone());
+        }
+    }
+
+    /* Sends a `page-changed` event when the document or the page are not the ones
+     * of the last event */
+    pub fn page_changed(&mut self, file: &str, page: usize, pages: usize) {
+        if self
+            .page
+            .as_ref()
+            .is_some_and(|(x, y)| x == file && *y == page)
+        {
+            return;
+        }
+        self.page = Some((file.to_string(), page));
+        self.send(&PluginEvent::PageChanged { file, page, pages });
+    }
+
+    /* Sends the command line to the plugin that has registered its command. Returns
+     * `None` when no plugin has */
+    pub fn run_command(
+        &self,
+        line: &str,
+        file: &str,
+        page: usize,
+    ) -> Option<Result<(), String>> {
+        let words = split_words(line).ok()?;
+        let (name, args) = words.split_first()?;
+        let plugin = self
+            .plugins
+            .iter()
+            .find(|x| x.commands.iter().any(|x| x == name))?;
+
+        let event = PluginEvent::Command {
+            name,
+            args,
+            file,
+            page,
+        };
+        let line = serde_json::to_string(&event)
+            .map_err(|x| format!("Could not serialize the command: {}", x));
+        Some(line.and_then(|x| {
+            plugin
+                .input
+                .send(x)
+                .map_err(|_| format!("Plugin {} has exited", plugin.name))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plugin_protocol() {
+        let message: PluginMessage =
+            serde_json::from_str(r#"{"type": "register-command", "name": "wc"}"#)
+                .unwrap();
+        assert!(
+            message
+                == PluginMessage::RegisterCommand {
+                    name: "wc".to_string()
+                }
+        );
+        assert!(serde_json::from_str::<PluginMessage>(r#"{"type": "draw"}"#).is_err());
+
+        let event = PluginEvent::PageChanged {
+            file: "/a.pdf",
+            page: 3,
+            pages: 12,
+        };
+        assert!(
+            serde_json::to_string(&event).unwrap()
+                == r#"{"type":"page-changed","file":"/a.pdf","page":3,"pages":12}"#
+        );
+        let args = ["all".to_string()];
+        let event = PluginEvent::Command {
+            name: "wc",
+            args: &args,
+            file: "/a.pdf",
+            page: 1,
+        };
+        assert!(
+            serde_json::to_string(&event).unwrap()
+                == r#"{"type":"command","name":"wc","args":["all"],"file":"/a.pdf","page":1}"#
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::{Arc, RwLock};
+
+use mupdf::{Colorspace, Pixmap};
+use qrcode::{Color, QrCode};
+
+use crate::{image::Image, TERMINAL_SIZE};
+
+/* Light modules around the code that scanners need to find it */
+const QUIET_ZONE: usize = 4;
+/* Share of the smaller side of the terminal that the code fills at most */
+const QR_SIZE: f32 = 0.6;
+
+/* The modules of the QR code of the text with its quiet zone, returned as the number
+ * of modules per side and whether each module is dark, row by row */
+fn qr_modules(text: &str) -> Result<(usize, Vec<bool>), String> {
+    let code = QrCode::new(text.as_bytes())
+        .map_err(|x| format!("Could not encode the link: {}", x))?;
+    let width = code.width();
+    let colors = code.to_colors();
+
+    let side = width + 2 * QUIET_ZONE;
+    let mut modules = vec![false; side * side];
+    for (i, color) in colors.iter().enumerate() {
+        let (row, column) = (i / width + QUIET_ZONE, i % width + QUIET_ZONE);
+        modules[row * side + column] = *color == Color::Dark;
+    }
+    Ok((side, modules))
+}
+
+/* The QR code of the text as an image for the popup. Every module is drawn with the
+ * same whole number of pixels so that the code stays sharp */
+pub fn qr_image(text: &str) -> Result<Arc<RwLock<Image>>, String> {
+    let (side, modules) = qr_modules(text)?;
+    let size = {
+        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        u16::min(terminal_size.width, terminal_size.height) as f32 * QR_SIZE
+    };
+    let scale = usize::max(1, size as usize / side);
+    let pixels = side * scale;
+
+    let mut pixmap = Pixmap::new_with_w_h(
+        &Colorspace::device_rgb(),
+        pixels as i32,
+        pixels as i32,
+        false,
+    )
+    .map_err(|x| format!("Could not allocate the code pixmap: {}", x))?;
+    for (i, pixel) in pixmap.samples_mut().chunks_mut(3).enumerate() {
+        let (row, column) = (i / pixels / scale, i % pixels / scale);
+        let value = if modules[row * side + column] { 0 } else { 255 };
+        pixel.fill(value);
+    }
+
+    /* The popup is displayed at its own size which makes its precision irrelevant */
+    let image = Image::new(&pixmap, 1.0)?;
+    Ok(Arc::new(RwLock::new(image)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qr_modules() {
+        /* Short links fit into version 2 with its 25 modules per side */
+        let (side, modules) = qr_modules("https://example.com/a").unwrap();
+        assert!(side == 25 + 2 * QUIET_ZONE);
+        assert!(modules.len() == side * side);
+        assert!(modules[..QUIET_ZONE * side].iter().all(|x| !x));
+
+        /* The finder pattern in the top left corner starts with a dark row */
+        let row = QUIET_ZONE * side;
+        assert!(modules[row + QUIET_ZONE..row + QUIET_ZONE + 7]
+            .iter()
+            .all(|x| *x));
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    fs::File,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use crossterm::event::Event;
+use serde::{Deserialize, Serialize};
+
+/* An input event and the milliseconds that have passed since the one before it */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub delay: u64,
+    pub event: Event,
+}
+
+impl RecordedEvent {
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay)
+    }
+}
+
+/* A recording is stored as a TOML file of `[[event]]` tables in the order of input */
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Recording {
+    #[serde(default)]
+    event: Vec<RecordedEvent>,
+}
+
+/* Loads the key and mouse events recorded with `--record` */
+pub fn load(file: &str) -> Result<Vec<RecordedEvent>, String> {
+    let content = std::fs::read_to_string(file)
+        .map_err(|x| format!("Could not read recording `{}`: {}", file, x))?;
+    let recording: Recording = toml::from_str(&content)
+        .map_err(|x| format!("Could not parse recording `{}`: {}", file, x))?;
+
+    Ok(recording.event)
+}
+
+/* Writes every event to the file as soon as it arrives, so that a recording of a
+ * session that crashed still reproduces the crash */
+pub struct Recorder {
+    file: File,
+    last: Instant,
+}
+
+impl Recorder {
+    pub fn create(file: &str) -> Result<Self, String> {
+        let file = File::create(file)
+            .map_err(|x| format!("Could not create recording `{}`: {}", file, x))?;
+
+        Ok(Self {
+            file,
+            last: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, event: &Event) -> Result<(), String> {
+        let delay = self.last.elapsed().as_millis() as u64;
+        self.last = Instant::now();
+
+        /* Concatenated `[[event]]` tables form a valid recording */
+        let recording = Recording {
+            event: vec![RecordedEvent {
+                delay,
+                event: event.clone(),
+            }],
+        };
+        let serialized = toml::to_string(&recording)
+            .map_err(|x| format!("Could not serialize event: {}", x))?;
+
+        self.file
+            .write_all(format!("{}\n", serialized).as_bytes())
+            .map_err(|x| format!("Could not write recording: {}", x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{
+        KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    };
+
+    #[test]
+    fn test_recording_roundtrip() {
+        let events = vec![
+            RecordedEvent {
+                delay: 0,
+                event: Event::Key(KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE)),
+            },
+            RecordedEvent {
+                delay: 250,
+                event: Event::Mouse(MouseEvent {
+                    kind: MouseEventKind::Down(MouseButton::Left),
+                    column: 640,
+                    row: 480,
+                    modifiers: KeyModifiers::CONTROL | KeyModifiers::SHIFT,
+                }),
+            },
+        ];
+
+        let serialized: String = events
+            .iter()
+            .map(|x| {
+                let recording = Recording {
+                    event: vec![x.clone()],
+                };
+                format!("{}\n", toml::to_string(&recording).unwrap())
+            })
+            .collect();
+        let parsed: Recording = toml::from_str(&serialized).unwrap();
+
+        assert!(parsed.event == events);
+        assert!(parsed.event[1].delay() == Duration::from_millis(250));
+        assert!(toml::from_str::<Recording>("").unwrap().event.is_empty());
+    }
+}
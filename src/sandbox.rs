@@ -0,0 +1,144 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    process::{Command, Stdio},
+};
+
+use crossbeam_channel::{unbounded, RecvTimeoutError};
+use mupdf::TextPageOptions;
+
+use crate::{
+    document::open_document,
+    threads::worker::{restrict, SANDBOX_PAGE_TIMEOUT},
+    transform::PageTransform,
+};
+
+/* Low enough to be quick, the content streams are decoded in full at any precision */
+const PREFLIGHT_PRECISION: f64 = 0.5;
+
+/* The lines that the pre-flight process reports its progress with */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Report {
+    /* The zero-indexed page has been rendered */
+    Page(usize),
+    /* Every one of the pages has been rendered */
+    Done(usize),
+}
+
+fn parse_report(line: &str) -> Option<Report> {
+    match line.trim().split_once(' ')? {
+        ("page", x) => x.parse().ok().map(Report::Page),
+        ("done", x) => x.parse().ok().map(Report::Done),
+        _ => None,
+    }
+}
+
+/* Renders every page of the document, extracts its links and its text and reports
+ * each one on stdout. Run by the hidden `--preflight` mode in the restricted process */
+pub fn preflight_pages(file: &str) -> Result<(), String> {
+    let document = open_document(file)?;
+    let pages = document
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?;
+
+    let mut stdout = io::stdout();
+    for i in 0..pages {
+        let page = document
+            .load_page(i)
+            .map_err(|x| format!("Could not load page {}: {}", i + 1, x))?;
+        let bounds = page
+            .bounds()
+            .map_err(|x| format!("Could not get bounds for page {}: {}", i + 1, x))?;
+        let transform = PageTransform::new((bounds.x0, bounds.y0, bounds.x1, bounds.y1));
+        transform.render(&page, PREFLIGHT_PRECISION, false)?;
+        page.links()
+            .map_err(|x| format!("Could not extract links of page {}: {}", i + 1, x))?;
+        page.to_text_page(TextPageOptions::empty())
+            .map_err(|x| format!("Could not extract text of page {}: {}", i + 1, x))?;
+
+        writeln!(stdout, "page {}", i)
+            .and_then(|_| stdout.flush())
+            .map_err(|x| format!("Could not report page {}: {}", i + 1, x))?;
+    }
+    println!("done {}", pages);
+
+    Ok(())
+}
+
+/* Goes through the whole document once in a separate process before `--sandbox`
+ * opens it with `sandbox_preflight`. The displayed pages are rendered by a restricted
+ * process anyway, the check further covers the links and the text that the viewer
+ * extracts itself. The process runs in safe mode with limited memory, an empty
+ * environment and no input, so that a document crashing or hanging mupdf in any of
+ * them is refused instead of taking down the viewer */
+pub fn preflight(file: &str) -> Result<(), String> {
+    let program = std::env::current_exe()
+        .map_err(|x| format!("Could not find the viewer for the check: {}", x))?;
+
+    let mut command = Command::new(program);
+    restrict(&mut command);
+    let mut child = command
+        .arg("--safe-mode")
+        .arg("--preflight")
+        .arg(file)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|x| format!("Could not start the pre-flight check: {}", x))?;
+
+    /* Read by another thread so that a hanging page can be timed out */
+    let (sender, reports) = unbounded::<Report>();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if let Some(report) = parse_report(&line) {
+                let _ = sender.send(report);
+            }
+        }
+    });
+
+    let mut page = 0;
+    loop {
+        match reports.recv_timeout(SANDBOX_PAGE_TIMEOUT) {
+            Ok(Report::Page(x)) => page = x + 1,
+            Ok(Report::Done(_)) => {
+                let _ = child.wait();
+                return Ok(());
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "Refusing to open {}: page {} takes too long to render",
+                    file,
+                    page + 1
+                ));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                let status = child
+                    .wait()
+                    .map_err(|x| format!("Could not wait for the check: {}", x))?;
+                return Err(format!(
+                    "Refusing to open {}: checking page {} has failed with {}",
+                    file,
+                    page + 1,
+                    status
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_report() {
+        assert!(parse_report("page 3\n") == Some(Report::Page(3)));
+        assert!(parse_report("done 12") == Some(Report::Done(12)));
+        assert!(parse_report("page three").is_none());
+        assert!(parse_report("warning: something").is_none());
+        assert!(parse_report("").is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use std::path::Path;
+
+use mlua::{Function, IntoLuaMulti, Lua, Table};
+
+use crate::{command::parse_action, config::ConfigAction};
+
+/* Defines the `meowpdf` table of the scripts. What the scripts ask for is queued and
+ * done by the viewer once they return */
+const PRELUDE: &str = r#"
+meowpdf = { viewer = {}, handlers = {}, actions = {}, segments = {}, requests = {} }
+
+function meowpdf.on(event, callback)
+    meowpdf.handlers[event] = meowpdf.handlers[event] or {}
+    table.insert(meowpdf.handlers[event], callback)
+end
+
+function meowpdf.define(name, callback)
+    meowpdf.actions[name] = callback
+end
+
+function meowpdf.segment(callback)
+    table.insert(meowpdf.segments, callback)
+end
+
+function meowpdf.action(name)
+    table.insert(meowpdf.requests, { "action", name })
+end
+
+function meowpdf.jump(page)
+    table.insert(meowpdf.requests, { "jump", page })
+end
+
+function meowpdf.message(text)
+    table.insert(meowpdf.requests, { "message", text })
+end
+"#;
+
+/* What the scripts have asked the viewer to do */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptRequest {
+    Action(ConfigAction),
+    /* Jumps to the page counted from one */
+    Jump(usize),
+    Message(String),
+}
+
+/* The state of the first pane, which the scripts read from `meowpdf.viewer` */
+pub struct ScriptState<'a> {
+    pub file: &'a str,
+    /* The current page counted from one */
+    pub page: usize,
+    pub pages: usize,
+    /* Zoom of the viewer in percent */
+    pub zoom: f32,
+    pub offset: (f32, f32),
+}
+
+/* The Lua scripts of the user, which define actions, status segments and callbacks
+ * of the events of the viewer */
+pub struct Scripts {
+    lua: Lua,
+    /* The document and the page of the last `page-changed` event */
+    page: Option<(String, usize)>,
+}
+
+impl Scripts {
+    pub fn new() -> Result<Self, String> {
+        let lua = Lua::new();
+        lua.load(PRELUDE)
+            .set_name("prelude")
+            .exec()
+            .map_err(|x| format!("Could not set up the scripts: {}", x))?;
+        Ok(Self { lua, page: None })
+    }
+
+    /* Runs every `.lua` file of the directory in alphabetical order. A directory that
+     * does not exist holds no scripts */
+    pub fn load(&self, directory: &Path) -> Result<(), String> {
+        let Ok(entries) = std::fs::read_dir(directory) else {
+            return Ok(());
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| x.extension().is_some_and(|x| x == "lua"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let name = path.display().to_string();
+            let source = std::fs::read_to_string(&path)
+                .map_err(|x| format!("Could not read {}: {}", name, x))?;
+            self.lua
+                .load(source)
+                .set_name(name.as_str())
+                .exec()
+                .map_err(|x| format!("Could not run {}: {}", name, x))?;
+        }
+        Ok(())
+    }
+
+    fn table(&self, name: &str) -> Result<Table, String> {
+        let meowpdf: Table = self
+            .lua
+            .globals()
+            .get("meowpdf")
+            .map_err(|x| format!("`meowpdf` has been replaced: {}", x))?;
+        meowpdf
+            .get(name)
+            .map_err(|x| format!("`meowpdf.{}` has been replaced: {}", name, x))
+    }
+
+    pub fn update(&self, state: &ScriptState) -> Result<(), String> {
+        let update = || -> mlua::Result<()> {
+            let viewer = self.lua.create_table()?;
+            viewer.set("file", state.file)?;
+            viewer.set("page", state.page)?;
+            viewer.set("pages", state.pages)?;
+            viewer.set("zoom", state.zoom)?;
+            viewer.set(
+                "offset",
+                self.lua
+                    .create_sequence_from([state.offset.0, state.offset.1])?,
+            )?;
+            self.lua
+                .globals()
+                .get::<Table>("meowpdf")?
+                .set("viewer", viewer)
+        };
+        update().map_err(|x| format!("Could not update the scripts: {}", x))
+    }
+
+    /* Calls every callback of the event with the arguments */
+    pub fn emit(
+        &self,
+        event: &str,
+        args: impl IntoLuaMulti + Clone,
+    ) -> Result<(), String> {
+        let Some(callbacks) = self
+            .table("handlers")?
+            .get::<Option<Table>>(event)
+            .map_err(|x| format!("Invalid callbacks of `{}`: {}", event, x))?
+        else {
+            return Ok(());
+        };
+
+        for callback in callbacks.sequence_values::<Function>() {
+            callback
+                .and_then(|x| x.call::<()>(args.clone()))
+                .map_err(|x| format!("Callback of `{}` failed: {}", event, x))?;
+        }
+        Ok(())
+    }
+
+    /* Calls the callbacks of `page-changed` when the document or the page are not the
+     * ones of the last event */
+    pub fn page_changed(
+        &mut self,
+        file: &str,
+        page: usize,
+        pages: usize,
+    ) -> Result<(), String> {
+        if self
+            .page
+            .as_ref()
+            .is_some_and(|(x, y)| x == file && *y == page)
+        {
+            return Ok(());
+        }
+        self.page = Some((file.to_string(), page));
+        self.emit("page-changed", (page, pages))
+    }
+
+    /* Calls the function defined with `meowpdf.define` for the `RunScript` action */
+    pub fn run_action(&self, name: &str) -> Result<(), String> {
+        let action = self
+            .table("actions")?
+            .get::<Option<Function>>(name)
+            .map_err(|x| format!("Invalid script action `{}`: {}", name, x))?
+            .ok_or_else(|| format!("No script defines the action `{}`", name))?;
+        action
+            .call::<()>(())
+            .map_err(|x| format!("Script action `{}` failed: {}", name, x))
+    }
+
+    /* The texts of the status segments, separated by spaces. Segments that fail are
+     * left out */
+    pub fn segments(&self) -> String {
+        let Ok(segments) = self.table("segments") else {
+            return String::new();
+        };
+        let texts: Vec<String> = segments
+            .sequence_values::<Function>()
+            .filter_map(|x| x.and_then(|x| x.call::<String>(())).ok())
+            .filter(|x| !x.is_empty())
+            .collect();
+        texts.join(" ")
+    }
+
+    /* Takes what the scripts have asked for since the last call */
+    pub fn requests(&self) -> Result<Vec<ScriptRequest>, String> {
+        let queued = self.table("requests")?;
+        let take = || -> mlua::Result<()> {
+            self.lua
+                .globals()
+                .get::<Table>("meowpdf")?
+                .set("requests", self.lua.create_table()?)
+        };
+        take().map_err(|x| format!("Could not take the requests: {}", x))?;
+
+        let mut requests = Vec::new();
+        for entry in queued.sequence_values::<Table>() {
+            let entry = entry.map_err(|x| format!("Invalid request: {}", x))?;
+            let kind: String = entry.get(1).unwrap_or_default();
+            let invalid =
+                |x: mlua::Error| format!("Invalid argument of `meowpdf.{}`: {}", kind, x);
+
+            requests.push(match kind.as_str() {
+                "action" => {
+                    let name: String = entry.get(2).map_err(invalid)?;
+                    ScriptRequest::Action(parse_action(&name)?)
+                }
+                "jump" => match entry.get::<usize>(2).map_err(invalid)? {
+                    0 => return Err("Pages are counted from one".to_string()),
+                    page => ScriptRequest::Jump(page),
+                },
+                _ => ScriptRequest::Message(entry.get(2).map_err(invalid)?),
+            });
+        }
+        Ok(requests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts() {
+        let scripts = Scripts::new().unwrap();
+        scripts
+            .lua
+            .load(
+                r#"
+                meowpdf.define("skip", function()
+                    meowpdf.jump(meowpdf.viewer.page + 2)
+                end)
+                meowpdf.on("page-changed", function(page, pages)
+                    if page == pages then meowpdf.action("Quit") end
+                end)
+                meowpdf.segment(function() return meowpdf.viewer.zoom .. "%" end)
+                meowpdf.segment(function() error("broken") end)
+                "#,
+            )
+            .exec()
+            .unwrap();
+
+        let state = ScriptState {
+            file: "/a.pdf",
+            page: 3,
+            pages: 5,
+            zoom: 150.0,
+            offset: (0.0, 10.0),
+        };
+        scripts.update(&state).unwrap();
+        scripts.run_action("skip").unwrap();
+        assert!(scripts.run_action("fly").is_err());
+        assert!(scripts.requests() == Ok(vec![ScriptRequest::Jump(5)]));
+        assert!(scripts.requests() == Ok(vec![]));
+
+        let mut scripts = scripts;
+        scripts.page_changed("/a.pdf", 5, 5).unwrap();
+        scripts.page_changed("/a.pdf", 5, 5).unwrap();
+        assert!(
+            scripts.requests() == Ok(vec![ScriptRequest::Action(ConfigAction::Quit)])
+        );
+        assert!(scripts.segments() == "150.0%");
+    }
+}
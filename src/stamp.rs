@@ -0,0 +1,286 @@
+use std::io::Write;
+
+use mupdf::{
+    pdf::{PdfDocument, PdfObject},
+    Buffer, Image,
+};
+
+use crate::{
+    config::{ConfigStampPosition, ConfigViewerStamp},
+    CONFIG,
+};
+
+/* Names of the resources added to the pages, which do not collide with the ones that
+ * are used by the documents */
+const FONT_NAME: &str = "MeowStampFont";
+const IMAGE_NAME: &str = "MeowStampImage";
+const STATE_NAME: &str = "MeowStampState";
+
+/* Widths of the printable ASCII characters of Helvetica in thousandths of the font
+ * size, the other characters are given the width of a digit */
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667,
+    667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722,
+    667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500,
+    556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, 556, 556, 333, 500, 278,
+    556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/* Replaces `%page` and `%pages` in the text of a stamp */
+pub fn stamp_text(template: &str, page: usize, pages: usize) -> String {
+    template
+        .replace("%pages", &pages.to_string())
+        .replace("%page", &page.to_string())
+}
+
+/* A PDF string literal of the text in the WinAnsi encoding of the standard fonts, in
+ * which the characters that it can not encode are replaced by `?` */
+pub fn pdf_string(text: &str) -> Vec<u8> {
+    let mut encoded = vec![b'('];
+    for x in text.chars() {
+        let byte = match x as u32 {
+            x @ (0x20..=0x7E | 0xA0..=0xFF) => x as u8,
+            _ => b'?',
+        };
+        if matches!(byte, b'(' | b')' | b'\\') {
+            encoded.push(b'\\');
+        }
+        encoded.push(byte);
+    }
+    encoded.push(b')');
+    encoded
+}
+
+/* The width of the text in Helvetica at the font size */
+pub fn text_width(text: &str, font_size: f32) -> f32 {
+    let width: u32 = text
+        .chars()
+        .map(|x| match x as u32 {
+            x @ 0x20..=0x7E => HELVETICA_WIDTHS[x as usize - 0x20] as u32,
+            _ => 556,
+        })
+        .sum();
+    width as f32 * font_size / 1000.0
+}
+
+/* The lower left corner of a stamp of the given size on a page with the media box
+ * `bounds`, in PDF coordinates with the origin at the bottom */
+pub fn stamp_origin(
+    bounds: (f32, f32, f32, f32),
+    size: (f32, f32),
+    position: ConfigStampPosition,
+) -> (f32, f32) {
+    let margin = 0.05 * (bounds.3 - bounds.1);
+    let x = (bounds.0 + bounds.2 - size.0) / 2.0;
+    let y = match position {
+        ConfigStampPosition::Center => (bounds.1 + bounds.3 - size.1) / 2.0,
+        ConfigStampPosition::Top => bounds.3 - margin - size.1,
+        ConfigStampPosition::Bottom => bounds.1 + margin,
+    };
+    (x, y)
+}
+
+/* Looks up a key of a page that may be inherited from the nodes of the page tree */
+pub fn inherited(page: &PdfObject, key: &str) -> Result<Option<PdfObject>, mupdf::Error> {
+    if let Some(value) = page.get_dict(key)? {
+        return Ok(Some(value));
+    }
+
+    /* Malformed page trees may contain cycles */
+    let mut parent = page.get_dict("Parent")?;
+    for _ in 0..32 {
+        let Some(node) = parent else {
+            break;
+        };
+        if let Some(value) = node.get_dict(key)? {
+            return Ok(Some(value));
+        }
+        parent = node.get_dict("Parent")?;
+    }
+    Ok(None)
+}
+
+/* The dictionary under the key, which is added if it does not exist */
+fn sub_dict(
+    pdf: &PdfDocument,
+    dict: &mut PdfObject,
+    key: &str,
+) -> Result<PdfObject, mupdf::Error> {
+    if let Some(value) = dict.get_dict(key)? {
+        return Ok(value);
+    }
+    dict.dict_put(key, pdf.new_dict()?)?;
+    Ok(dict.get_dict(key)?.unwrap())
+}
+
+fn add_stream(pdf: &mut PdfDocument, content: &[u8]) -> Result<PdfObject, mupdf::Error> {
+    let mut buffer = Buffer::new();
+    buffer.write_all(content)?;
+
+    let mut stream = pdf.add_object(&pdf.new_dict()?)?;
+    stream.write_stream_buffer(&buffer)?;
+    Ok(stream)
+}
+
+/* Adds the stamp above the contents of the page. The contents are enclosed in `q` and
+ * `Q` so that the stamp is drawn without the graphics state they leave behind */
+fn stamp_page(
+    pdf: &mut PdfDocument,
+    page: usize,
+    pages: usize,
+    stamp: &ConfigViewerStamp,
+    image: Option<(i32, (f32, f32))>,
+) -> Result<(), mupdf::Error> {
+    let mut object = pdf.find_page(page as i32)?;
+
+    let mut bounds = [0.0f32, 0.0, 612.0, 792.0];
+    if let Some(media_box) = inherited(&object, "MediaBox")? {
+        for (i, x) in bounds.iter_mut().enumerate() {
+            if let Some(value) = media_box.get_array(i as i32)? {
+                *x = value.as_float()?;
+            }
+        }
+    }
+    let bounds = (bounds[0], bounds[1], bounds[2], bounds[3]);
+
+    /* Resources inherited from the page tree get the stamp for all of their pages,
+     * which does not change the pages since the names are not used by them */
+    let mut resources = match inherited(&object, "Resources")? {
+        Some(x) => x,
+        None => sub_dict(pdf, &mut object, "Resources")?,
+    };
+    let mut state = pdf.new_dict()?;
+    state.dict_put("ca", pdf.new_real(stamp.opacity)?)?;
+    state.dict_put("CA", pdf.new_real(stamp.opacity)?)?;
+    sub_dict(pdf, &mut resources, "ExtGState")?.dict_put(STATE_NAME, state)?;
+
+    let mut content = format!("q /{} gs ", STATE_NAME).into_bytes();
+    match image {
+        Some((number, size)) => {
+            let (x, y) = stamp_origin(bounds, size, stamp.position);
+            sub_dict(pdf, &mut resources, "XObject")?
+                .dict_put(IMAGE_NAME, pdf.new_indirect(number, 0)?)?;
+            content.extend(
+                format!(
+                    "{} 0 0 {} {} {} cm /{} Do ",
+                    size.0, size.1, x, y, IMAGE_NAME
+                )
+                .bytes(),
+            );
+        }
+        None => {
+            let mut font = pdf.new_dict()?;
+            font.dict_put("Type", pdf.new_name("Font")?)?;
+            font.dict_put("Subtype", pdf.new_name("Type1")?)?;
+            font.dict_put("BaseFont", pdf.new_name("Helvetica")?)?;
+            font.dict_put("Encoding", pdf.new_name("WinAnsiEncoding")?)?;
+            sub_dict(pdf, &mut resources, "Font")?.dict_put(FONT_NAME, font)?;
+
+            let text = stamp_text(&stamp.text, page + 1, pages);
+            let size = (text_width(&text, stamp.font_size), stamp.font_size);
+            let (x, y) = stamp_origin(bounds, size, stamp.position);
+            let color = &stamp.color;
+            content.extend(
+                format!(
+                    "{} {} {} rg BT /{} {} Tf {} {} Td ",
+                    color[0], color[1], color[2], FONT_NAME, stamp.font_size, x, y
+                )
+                .bytes(),
+            );
+            content.extend(pdf_string(&text));
+            content.extend(b" Tj ET ");
+        }
+    }
+    content.extend(b"Q");
+
+    let mut contents = pdf.new_array()?;
+    contents.array_push(add_stream(pdf, b"q")?)?;
+    match object.get_dict("Contents")? {
+        Some(old) if old.is_array()? => {
+            for i in 0..old.len()? {
+                if let Some(x) = old.get_array(i as i32)? {
+                    contents.array_push(x)?;
+                }
+            }
+        }
+        Some(old) => contents.array_push(old)?,
+        None => (),
+    }
+    contents.array_push(add_stream(pdf, b"Q")?)?;
+    contents.array_push(add_stream(pdf, &content)?)?;
+    object.dict_put("Contents", contents)
+}
+
+/* Stamps the configured text or image onto the zero-indexed pages `first` to `last`
+ * of a PDF and saves the result as a copy. Returns the number of stamped pages */
+pub fn stamp_document(
+    file: &str,
+    out: &str,
+    (first, last): (usize, usize),
+) -> Result<usize, String> {
+    let same = std::fs::canonicalize(out)
+        .is_ok_and(|x| std::fs::canonicalize(file).is_ok_and(|y| x == y));
+    if same {
+        return Err("The document can not be overwritten by its stamped copy".to_string());
+    }
+
+    let stamp = &CONFIG.get().unwrap().viewer.stamp;
+    let mut pdf = PdfDocument::open(file)
+        .map_err(|x| format!("Only PDF documents can be stamped: {}", x))?;
+    let pages = pdf
+        .page_count()
+        .map_err(|x| format!("Could not extract the number of pages: {}", x))?
+        as usize;
+    if first > last || last >= pages {
+        return Err(format!("The document has {} pages", pages));
+    }
+
+    /* The image is stored once and referred to by every page. It is drawn at 72 DPI,
+     * scaled down to fit into half of the width of a letter page */
+    let image = match stamp.image.as_str() {
+        "" => None,
+        path => {
+            let image = Image::from_file(path)
+                .map_err(|x| format!("Could not read {}: {}", path, x))?;
+            let size = (image.width() as f32, image.height() as f32);
+            let fit = f32::min(1.0, 306.0 / size.0);
+            let number = pdf
+                .add_image(&image)
+                .and_then(|x| x.as_indirect())
+                .map_err(|x| format!("Could not add {}: {}", path, x))?;
+            Some((number, (size.0 * fit, size.1 * fit)))
+        }
+    };
+
+    for page in first..=last {
+        stamp_page(&mut pdf, page, pages, stamp, image)
+            .map_err(|x| format!("Could not stamp page {}: {}", page + 1, x))?;
+    }
+    pdf.save(out)
+        .map_err(|x| format!("Could not save {}: {}", out, x))?;
+
+    Ok(last - first + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stamp_text() {
+        assert!(stamp_text("Page %page of %pages", 3, 12) == "Page 3 of 12");
+        assert!(pdf_string("a(b)\\ é€") == b"(a\\(b\\)\\\\ \xE9?)".to_vec());
+        assert!(
+            text_width("DRAFT", 10.0) == (722 + 722 + 667 + 611 + 611) as f32 / 100.0
+        );
+
+        let bounds = (0.0, 0.0, 600.0, 800.0);
+        let size = (200.0, 40.0);
+        assert!(
+            stamp_origin(bounds, size, ConfigStampPosition::Center) == (200.0, 380.0)
+        );
+        assert!(stamp_origin(bounds, size, ConfigStampPosition::Top) == (200.0, 720.0));
+        assert!(stamp_origin(bounds, size, ConfigStampPosition::Bottom) == (200.0, 40.0));
+    }
+}
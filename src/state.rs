@@ -0,0 +1,119 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::drivers::graphics::TransferMedium;
+
+/* What the viewer remembers between runs, stored in `<state>/meowpdf/state.toml` */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /* Fastest transfer medium measured for each terminal identity */
+    #[serde(default)]
+    pub transfer: HashMap<String, TransferMedium>,
+    /* Reading statistics of every document by its canonical path */
+    #[serde(default)]
+    pub reading: HashMap<String, ReadingStats>,
+    /* Position of every document by its canonical path when it was last closed */
+    #[serde(default)]
+    pub bookmarks: HashMap<String, Bookmark>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    /* Zero-indexed first visible page */
+    pub page: usize,
+    pub scale: f32,
+}
+
+/* Progress through a document over all sessions */
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadingStats {
+    /* Zero-indexed pages that have been in the middle of the view */
+    #[serde(default)]
+    pub pages: BTreeSet<usize>,
+    /* Time spent reading the document in seconds */
+    #[serde(default)]
+    pub seconds: u64,
+    /* Seconds since the UNIX epoch */
+    #[serde(default)]
+    pub last_read: u64,
+}
+
+/* `<state>/meowpdf`, in which the viewer keeps what it remembers between runs */
+pub fn state_dir() -> Result<PathBuf, String> {
+    let mut path = dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .ok_or("No state directory has been found")?;
+    path.push("meowpdf");
+    Ok(path)
+}
+
+fn state_path() -> Result<PathBuf, String> {
+    Ok(state_dir()?.join("state.toml"))
+}
+
+impl State {
+    /* A missing or unreadable state file is the same as a first run */
+    pub fn load() -> Self {
+        state_path()
+            .ok()
+            .and_then(|x| std::fs::read_to_string(x).ok())
+            .and_then(|x| toml::from_str(&x).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = state_path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|x| format!("Could not create {}: {}", parent.display(), x))?;
+        }
+
+        let serialized = toml::to_string(self)
+            .map_err(|x| format!("Could not serialize the state: {}", x))?;
+        std::fs::write(&path, serialized)
+            .map_err(|x| format!("Could not write {}: {}", path.display(), x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_roundtrip() {
+        let mut state = State::default();
+        state.transfer.insert(
+            "xterm-kitty 0.35.2".to_string(),
+            TransferMedium::SharedMemory,
+        );
+        state.reading.insert(
+            "/home/user/paper.pdf".to_string(),
+            ReadingStats {
+                pages: BTreeSet::from([0, 1, 5]),
+                seconds: 3600,
+                last_read: 1791936000,
+            },
+        );
+        state.bookmarks.insert(
+            "/home/user/paper.pdf".to_string(),
+            Bookmark {
+                page: 12,
+                scale: 1.5,
+            },
+        );
+
+        let serialized = toml::to_string(&state).unwrap();
+        let parsed: State = toml::from_str(&serialized).unwrap();
+        assert!(
+            parsed.transfer.get("xterm-kitty 0.35.2")
+                == Some(&TransferMedium::SharedMemory)
+        );
+        assert!(parsed.reading == state.reading);
+        assert!(parsed.bookmarks == state.bookmarks);
+        assert!(toml::from_str::<State>("").unwrap().transfer.is_empty());
+    }
+}
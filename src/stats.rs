@@ -0,0 +1,104 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    format::{date_from_unix, format_config_date},
+    state::{ReadingStats, State},
+};
+
+/* Pauses between two redraws count as reading time for at most this long, so that a
+ * viewer left open is not counted */
+const IDLE_LIMIT: Duration = Duration::from_secs(120);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+/* The reading statistics of a document during a session of the viewer. They are
+ * added to the ones of the previous sessions in the state file when saved */
+pub struct ReadingSession {
+    /* Canonical path of the document */
+    file: String,
+    /* The statistics as they were when the session started */
+    previous: ReadingStats,
+    stats: ReadingStats,
+    active: Duration,
+    last: Instant,
+}
+
+impl ReadingSession {
+    pub fn start(file: &str) -> Self {
+        let previous = State::load().reading.remove(file).unwrap_or_default();
+
+        Self {
+            file: file.to_string(),
+            stats: previous.clone(),
+            previous,
+            active: Duration::ZERO,
+            last: Instant::now(),
+        }
+    }
+
+    /* Called on every redraw with the page in the middle of the view */
+    pub fn record(&mut self, page: usize) {
+        self.active += Duration::min(self.last.elapsed(), IDLE_LIMIT);
+        self.last = Instant::now();
+
+        self.stats.pages.insert(page);
+        self.stats.seconds = self.previous.seconds + self.active.as_secs();
+        self.stats.last_read = now();
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        /* Other instances may have written to the state file in the meantime */
+        let mut state = State::load();
+        state.reading.insert(self.file.clone(), self.stats.clone());
+        state.save()
+    }
+
+    /* The lines of the `stats` overlay for a document with `pages` pages */
+    pub fn summary(&self, pages: usize) -> Vec<String> {
+        let name = Path::new(&self.file)
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or(self.file.clone());
+        let read = self.stats.pages.iter().filter(|x| **x < pages).count();
+        let percent = if pages == 0 { 0 } else { 100 * read / pages };
+        let last_read = match self.previous.last_read {
+            0 => "Never".to_string(),
+            x => format_config_date(&date_from_unix(x)),
+        };
+
+        vec![
+            format!("Reading statistics of {}", name),
+            format!("Pages read     {} of {} ({}%)", read, pages, percent),
+            format!("Reading time   {}", format_duration(self.stats.seconds)),
+            format!("This session   {}", format_duration(self.active.as_secs())),
+            format!("Last read      {}", last_read),
+        ]
+    }
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+    match hours {
+        0 => format!("{} min", minutes),
+        _ => format!("{} h {} min", hours, minutes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert!(format_duration(59) == "0 min");
+        assert!(format_duration(5 * 3600 + 7 * 60 + 30) == "5 h 7 min");
+    }
+}
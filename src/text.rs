@@ -0,0 +1,979 @@
+use mupdf::{Link, Page, Rect, TextBlockType, TextPageOptions};
+
+use crate::language::{fold_char, Language};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptionKind {
+    Figure,
+    Table,
+}
+
+/* A figure or table caption found in the structured text of a page. `y` is the top
+ * of the caption block given in page units */
+#[derive(Clone, Copy, Debug)]
+pub struct Caption {
+    pub kind: CaptionKind,
+    pub page: usize,
+    pub y: f32,
+}
+
+/* Checks if a line of text starts a caption such as `Figure 3:`, `Fig. 2.` or
+ * `TABLE IV`. Plain references like `Figure 3 shows ..` are not captions */
+pub fn caption_kind(line: &str) -> Option<CaptionKind> {
+    const PREFIXES: [(&str, CaptionKind); 4] = [
+        ("figure", CaptionKind::Figure),
+        ("fig.", CaptionKind::Figure),
+        ("table", CaptionKind::Table),
+        ("tab.", CaptionKind::Table),
+    ];
+
+    let lower = line.trim_start().to_lowercase();
+    for (prefix, kind) in PREFIXES {
+        let rest = match lower.strip_prefix(prefix) {
+            Some(x) => x.trim_start(),
+            None => continue,
+        };
+
+        let label_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (label, remainder) = rest.split_at(label_end);
+        let label_trimmed = label.trim_end_matches([':', '.']);
+        let terminated = label_trimmed.len() != label.len();
+
+        let numeric = label_trimmed.chars().any(|c| c.is_ascii_digit())
+            && label_trimmed
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+        let roman = !label_trimmed.is_empty()
+            && label_trimmed.chars().all(|c| "ivxlc".contains(c));
+        if !numeric && !roman {
+            continue;
+        }
+
+        let remainder = remainder.trim_start();
+        if terminated
+            || remainder.is_empty()
+            || remainder.starts_with([':', '.', '|', '—', '–'])
+        {
+            return Some(kind);
+        }
+    }
+
+    None
+}
+
+pub fn page_captions(page: &Page, index: usize) -> Result<Vec<Caption>, String> {
+    let text_page = page
+        .to_text_page(TextPageOptions::empty())
+        .map_err(|x| format!("Could not extract text from page {}: {}", index, x))?;
+
+    let mut captions = Vec::new();
+    for block in text_page.blocks() {
+        if !matches!(block.r#type(), TextBlockType::Text) {
+            continue;
+        }
+
+        /* Captions always start on the first line of their block */
+        let line = match block.lines().next() {
+            Some(x) => x,
+            None => continue,
+        };
+        let text: String = line.chars().filter_map(|x| x.char()).collect();
+
+        if let Some(kind) = caption_kind(&text) {
+            captions.push(Caption {
+                kind,
+                page: index,
+                y: block.bounds().y0,
+            });
+        }
+    }
+
+    Ok(captions)
+}
+
+/* Guesses if a text block is typeset math by the share of math symbols in it */
+pub fn is_math_text(text: &str) -> bool {
+    const MATH_RANGES: [(u32, u32); 5] = [
+        (0x0370, 0x03FF),   /* Greek */
+        (0x2070, 0x209F),   /* Super- and subscripts */
+        (0x2190, 0x22FF),   /* Arrows and mathematical operators */
+        (0x27C0, 0x27EF),   /* Miscellaneous mathematical symbols */
+        (0x1D400, 0x1D7FF), /* Mathematical alphanumerics */
+    ];
+
+    let mut total = 0usize;
+    let mut math = 0usize;
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        total += 1;
+        let code = c as u32;
+        if "=+<>^_|/*".contains(c)
+            || MATH_RANGES.iter().any(|(a, b)| (*a..=*b).contains(&code))
+        {
+            math += 1;
+        }
+    }
+
+    total > 0 && math as f32 / total as f32 >= 0.15
+}
+
+/* Finds the bounds of the image or math block under the given point in page units */
+pub fn block_at(page: &Page, point: (f32, f32)) -> Result<Option<Rect>, String> {
+    let text_page = page
+        .to_text_page(TextPageOptions::PRESERVE_IMAGES)
+        .map_err(|x| format!("Could not extract text from page: {}", x))?;
+
+    for block in text_page.blocks() {
+        let bounds = block.bounds();
+        if point.0 < bounds.x0
+            || point.0 > bounds.x1
+            || point.1 < bounds.y0
+            || point.1 > bounds.y1
+        {
+            continue;
+        }
+
+        if matches!(block.r#type(), TextBlockType::Image) {
+            return Ok(Some(bounds));
+        }
+
+        let mut text = String::new();
+        for line in block.lines() {
+            text.extend(line.chars().filter_map(|x| x.char()));
+        }
+        if is_math_text(&text) {
+            return Ok(Some(bounds));
+        }
+    }
+
+    Ok(None)
+}
+
+/* An identifier printed in the text given by the range of its characters in the
+ * scanned line and the URI it resolves to */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identifier {
+    pub start: usize,
+    pub end: usize,
+    pub uri: String,
+}
+
+fn strip_prefix_ignore_case<'a>(chars: &'a [char], prefix: &str) -> Option<&'a [char]> {
+    let length = prefix.chars().count();
+    if chars.len() < length {
+        return None;
+    }
+
+    let matches = chars
+        .iter()
+        .zip(prefix.chars())
+        .all(|(a, b)| a.to_ascii_lowercase() == b);
+    if matches {
+        Some(&chars[length..])
+    } else {
+        None
+    }
+}
+
+/* Matches `10.<registrant>/<suffix>` and returns its length */
+fn doi_length(chars: &[char]) -> Option<usize> {
+    let rest = strip_prefix_ignore_case(chars, "10.")?;
+    let registrant = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+    if !(4..=9).contains(&registrant) || rest.get(registrant) != Some(&'/') {
+        return None;
+    }
+
+    let suffix = &rest[registrant + 1..];
+    let mut length = suffix.iter().take_while(|c| !c.is_whitespace()).count();
+    /* Punctuation that ends a sentence or a reference is not part of the DOI */
+    while length > 0 && ".,;:)]}\"'".contains(suffix[length - 1]) {
+        length -= 1;
+    }
+
+    (length > 0).then_some(3 + registrant + 1 + length)
+}
+
+/* Matches `<yymm>.<number>` with an optional version and returns its length */
+fn arxiv_length(chars: &[char]) -> Option<usize> {
+    let year = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    if year != 4 || chars.get(4) != Some(&'.') {
+        return None;
+    }
+
+    let number = chars[5..].iter().take_while(|c| c.is_ascii_digit()).count();
+    if !(4..=5).contains(&number) {
+        return None;
+    }
+
+    let mut length = 5 + number;
+    if chars.get(length) == Some(&'v') {
+        let version = chars[length + 1..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if version > 0 {
+            length += 1 + version;
+        }
+    }
+
+    Some(length)
+}
+
+fn isbn_valid(digits: &[u32]) -> bool {
+    match digits.len() {
+        10 => {
+            let sum: u32 = digits
+                .iter()
+                .enumerate()
+                .map(|(i, d)| (10 - i as u32) * d)
+                .sum();
+            sum % 11 == 0
+        }
+        13 => {
+            let sum: u32 = digits
+                .iter()
+                .enumerate()
+                .map(|(i, d)| if i % 2 == 0 { *d } else { 3 * d })
+                .sum();
+            sum % 10 == 0
+        }
+        _ => false,
+    }
+}
+
+/* Matches the digits of an ISBN separated by hyphens or spaces and returns the
+ * length of the match and the digits */
+fn isbn_digits(chars: &[char]) -> Option<(usize, String)> {
+    let mut digits = Vec::new();
+    /* The length of the match up to each digit */
+    let mut lengths = Vec::new();
+
+    for (i, c) in chars.iter().enumerate() {
+        match c {
+            '0'..='9' if digits.len() < 13 => digits.push(c.to_digit(10).unwrap()),
+            'X' | 'x' if digits.len() == 9 => digits.push(10),
+            '-' | ' ' if !digits.is_empty() => continue,
+            _ => break,
+        }
+        lengths.push(i + 1);
+    }
+
+    /* Prefer the longer form, the first 10 digits of an ISBN-13 may happen to be a
+     * valid ISBN-10 */
+    for count in [13, 10] {
+        if digits.len() < count || !isbn_valid(&digits[..count]) {
+            continue;
+        }
+
+        let text = digits[..count]
+            .iter()
+            .map(|d| {
+                if *d == 10 {
+                    'X'
+                } else {
+                    char::from_digit(*d, 10).unwrap()
+                }
+            })
+            .collect();
+        return Some((lengths[count - 1], text));
+    }
+
+    None
+}
+
+/* Finds DOIs, arXiv identifiers and ISBNs in a line of text. arXiv identifiers and
+ * ISBNs are only recognized after their `arXiv:` and `ISBN` prefixes, since their
+ * bare forms can not be told apart from ordinary numbers */
+pub fn find_identifiers(line: &str) -> Vec<Identifier> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut identifiers = Vec::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        /* Identifiers start at word boundaries */
+        if i > 0 && chars[i - 1].is_alphanumeric() {
+            i += 1;
+            continue;
+        }
+
+        let rest = &chars[i..];
+        let found = if let Some(length) = doi_length(rest) {
+            let doi: String = rest[..length].iter().collect();
+            Some((length, format!("https://doi.org/{}", doi)))
+        } else if let Some(id) = strip_prefix_ignore_case(rest, "arxiv:") {
+            let prefix = rest.len() - id.len();
+            arxiv_length(id).map(|length| {
+                let id: String = id[..length].iter().collect();
+                (prefix + length, format!("https://arxiv.org/abs/{}", id))
+            })
+        } else if let Some(number) = strip_prefix_ignore_case(rest, "isbn") {
+            /* Skips `-10`, `-13`, `:` and the spaces following the prefix */
+            let mut skip = 0;
+            for suffix in ["-13", "-10"] {
+                if strip_prefix_ignore_case(number, suffix).is_some() {
+                    skip = suffix.len();
+                }
+            }
+            skip += number[skip..]
+                .iter()
+                .take_while(|c| **c == ':' || **c == ' ')
+                .count();
+
+            let prefix = rest.len() - number.len() + skip;
+            isbn_digits(&number[skip..]).map(|(length, digits)| {
+                (
+                    prefix + length,
+                    format!("https://openlibrary.org/isbn/{}", digits),
+                )
+            })
+        } else {
+            None
+        };
+
+        match found {
+            Some((length, uri)) => {
+                identifiers.push(Identifier {
+                    start: i,
+                    end: i + length,
+                    uri,
+                });
+                i += length;
+            }
+            None => i += 1,
+        }
+    }
+
+    identifiers
+}
+
+/* Finds the identifiers printed on a page and turns them into links covering the
+ * characters they are printed with */
+pub fn page_identifiers(page: &Page, index: usize) -> Result<Vec<Link>, String> {
+    let text_page = page
+        .to_text_page(TextPageOptions::empty())
+        .map_err(|x| format!("Could not extract text from page {}: {}", index, x))?;
+
+    let mut links = Vec::new();
+    for block in text_page.blocks() {
+        if !matches!(block.r#type(), TextBlockType::Text) {
+            continue;
+        }
+
+        for line in block.lines() {
+            let chars: Vec<_> = line.chars().filter(|x| x.char().is_some()).collect();
+            let text: String = chars.iter().filter_map(|x| x.char()).collect();
+
+            for identifier in find_identifiers(&text) {
+                let mut bounds = Rect {
+                    x0: f32::INFINITY,
+                    y0: f32::INFINITY,
+                    x1: -f32::INFINITY,
+                    y1: -f32::INFINITY,
+                };
+                for c in &chars[identifier.start..identifier.end] {
+                    let quad = c.quad();
+                    for point in [quad.ul, quad.ur, quad.ll, quad.lr] {
+                        bounds.x0 = f32::min(bounds.x0, point.x);
+                        bounds.y0 = f32::min(bounds.y0, point.y);
+                        bounds.x1 = f32::max(bounds.x1, point.x);
+                        bounds.y1 = f32::max(bounds.y1, point.y);
+                    }
+                }
+
+                links.push(Link {
+                    bounds,
+                    page: 0,
+                    uri: identifier.uri,
+                });
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+/* The direction the characters of a line are read in */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextDirection {
+    LeftToRight,
+    RightToLeft,
+    /* Top to bottom, as in vertical CJK text */
+    Vertical,
+}
+
+/* A character of a page given by its bounds as (x0, y0, x1, y1) in page units, the
+ * index of the line it is printed in and the direction of that line */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageChar {
+    pub char: char,
+    pub bounds: (f32, f32, f32, f32),
+    pub line: usize,
+    pub direction: TextDirection,
+}
+
+/* Letters of Hebrew, Arabic, Syriac, Thaana and NKo and their presentation forms.
+ * Arabic-Indic digits are written from left to right like the other digits */
+fn is_rtl(x: char) -> bool {
+    let ranges = matches!(
+        x as u32,
+        0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF | 0x10800..=0x10FFF
+            | 0x1E800..=0x1EFFF
+    );
+    ranges && !x.is_numeric()
+}
+
+fn is_ltr(x: char) -> bool {
+    x.is_alphanumeric() && !is_rtl(x)
+}
+
+fn center(bounds: (f32, f32, f32, f32)) -> (f32, f32) {
+    ((bounds.0 + bounds.2) / 2.0, (bounds.1 + bounds.3) / 2.0)
+}
+
+/* Lines whose characters follow each other downwards are vertical, the others are
+ * read from right to left when most of their letters are of a right-to-left script */
+pub fn line_direction(chars: &[PageChar]) -> TextDirection {
+    let centers: Vec<(f32, f32)> = chars.iter().map(|x| center(x.bounds)).collect();
+    let span = |axis: fn(&(f32, f32)) -> f32| {
+        let values = centers.iter().map(axis);
+        values.clone().fold(-f32::INFINITY, f32::max)
+            - values.fold(f32::INFINITY, f32::min)
+    };
+    if chars.len() > 1 && span(|x| x.1) > span(|x| x.0) {
+        return TextDirection::Vertical;
+    }
+
+    let rtl = chars.iter().filter(|x| is_rtl(x.char)).count();
+    let ltr = chars
+        .iter()
+        .filter(|x| x.char.is_alphabetic() && is_ltr(x.char))
+        .count();
+    match rtl > ltr {
+        true => TextDirection::RightToLeft,
+        false => TextDirection::LeftToRight,
+    }
+}
+
+/* Puts the characters of a line into reading order, whichever order they are drawn
+ * in. Words and numbers of left-to-right scripts within right-to-left lines keep
+ * their order, the spaces and punctuation around them belong to the line */
+pub fn order_line(chars: &mut [PageChar], direction: TextDirection) {
+    match direction {
+        TextDirection::LeftToRight => return,
+        TextDirection::Vertical => {
+            chars.sort_by(|a, b| center(a.bounds).1.total_cmp(&center(b.bounds).1));
+            return;
+        }
+        TextDirection::RightToLeft => {
+            chars.sort_by(|a, b| center(b.bounds).0.total_cmp(&center(a.bounds).0))
+        }
+    }
+
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_ltr(chars[i].char) {
+            i += 1;
+            continue;
+        }
+        let mut end = i;
+        let mut j = i;
+        while j < chars.len() && !is_rtl(chars[j].char) {
+            if is_ltr(chars[j].char) {
+                end = j;
+            }
+            j += 1;
+        }
+        chars[i..=end].reverse();
+        i = j;
+    }
+}
+
+fn distance_to(bounds: (f32, f32, f32, f32), point: (f32, f32)) -> f32 {
+    let dx = f32::max(0.0, f32::max(bounds.0 - point.0, point.0 - bounds.2));
+    let dy = f32::max(0.0, f32::max(bounds.1 - point.1, point.1 - bounds.3));
+    dx * dx + dy * dy
+}
+
+/* Selects the characters in reading order from the one nearest to `start` to the one
+ * nearest to `end`. Returns the selected text, with the lines separated by newlines,
+ * and the bounds of the selected part of every line */
+pub fn select_chars(
+    chars: &[PageChar],
+    start: (f32, f32),
+    end: (f32, f32),
+) -> (String, Vec<(f32, f32, f32, f32)>) {
+    let nearest = |point| {
+        chars
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                distance_to(a.bounds, point).total_cmp(&distance_to(b.bounds, point))
+            })
+            .map(|(i, _)| i)
+    };
+    let (Some(a), Some(b)) = (nearest(start), nearest(end)) else {
+        return (String::new(), Vec::new());
+    };
+
+    let mut text = String::new();
+    let mut rects: Vec<(f32, f32, f32, f32)> = Vec::new();
+    let mut line = None;
+    for c in &chars[a.min(b)..=a.max(b)] {
+        if line == Some(c.line) {
+            let rect = rects.last_mut().unwrap();
+            *rect = (
+                f32::min(rect.0, c.bounds.0),
+                f32::min(rect.1, c.bounds.1),
+                f32::max(rect.2, c.bounds.2),
+                f32::max(rect.3, c.bounds.3),
+            );
+        } else {
+            if line.is_some() {
+                text.push('\n');
+            }
+            line = Some(c.line);
+            rects.push(c.bounds);
+        }
+        text.push(c.char);
+    }
+
+    (text, rects)
+}
+
+fn union(a: (f32, f32, f32, f32), b: (f32, f32, f32, f32)) -> (f32, f32, f32, f32) {
+    (
+        f32::min(a.0, b.0),
+        f32::min(a.1, b.1),
+        f32::max(a.2, b.2),
+        f32::max(a.3, b.3),
+    )
+}
+
+/* Finds the query, ignoring case, where it is split by a hyphen at the end of a line
+ * such as `render-` and `ing`. Matches within one line are left out. Returns the
+ * bounds of the part of every match before and after the line break */
+pub fn hyphenated_matches(
+    chars: &[PageChar],
+    query: &str,
+) -> Vec<((f32, f32, f32, f32), (f32, f32, f32, f32))> {
+    let lower = |x: char| x.to_lowercase().next().unwrap_or(x);
+    let query: Vec<char> = query.chars().map(lower).collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    /* The characters of the text with the hyphens of split words left out, lines that
+     * are not joined are separated by a space without a character */
+    let mut text: Vec<(char, Option<usize>)> = Vec::new();
+    let mut joins = Vec::new();
+    for (i, c) in chars.iter().enumerate() {
+        let next = chars.get(i + 1);
+        let line_end = next.is_none_or(|x| x.line != c.line);
+        let split = matches!(c.char, '-' | '\u{00AD}')
+            && line_end
+            && i > 0
+            && chars[i - 1].line == c.line
+            && chars[i - 1].char.is_alphabetic()
+            && next.is_some_and(|x| x.char.is_alphabetic());
+
+        if split {
+            joins.push(text.len());
+            continue;
+        }
+        text.push((lower(c.char), Some(i)));
+        if line_end {
+            text.push((' ', None));
+        }
+    }
+
+    let mut matches = Vec::new();
+    for start in 0..text.len().saturating_sub(query.len() - 1) {
+        let end = start + query.len();
+        let found = text[start..end]
+            .iter()
+            .map(|x| x.0)
+            .eq(query.iter().copied());
+        if !found || !joins.iter().any(|x| start < *x && *x < end) {
+            continue;
+        }
+
+        let matched: Vec<&PageChar> = text[start..end]
+            .iter()
+            .filter_map(|x| x.1.map(|i| &chars[i]))
+            .collect();
+        let first_line = matched[0].line;
+        let (before, after): (Vec<&PageChar>, Vec<&PageChar>) =
+            matched.iter().partition(|x| x.line == first_line);
+        let bounds = |part: &[&PageChar]| {
+            part.iter()
+                .map(|x| x.bounds)
+                .reduce(union)
+                .unwrap_or_default()
+        };
+        matches.push((bounds(&before), bounds(&after)));
+    }
+
+    matches
+}
+
+/* Finds the query, ignoring case, within the lines that are not read from left to
+ * right. The search of mupdf goes by the order the characters are drawn in, which
+ * is not the reading order of those lines. Returns the bounds of every match */
+pub fn directional_matches(chars: &[PageChar], query: &str) -> Vec<(f32, f32, f32, f32)> {
+    let lower = |x: char| x.to_lowercase().next().unwrap_or(x);
+    let query: Vec<char> = query.chars().map(lower).collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let mut matches = Vec::new();
+    for line in chars.chunk_by(|a, b| a.line == b.line) {
+        if line[0].direction == TextDirection::LeftToRight || line.len() < query.len() {
+            continue;
+        }
+        for part in line.windows(query.len()) {
+            if part.iter().map(|x| lower(x.char)).eq(query.iter().copied()) {
+                matches.extend(part.iter().map(|x| x.bounds).reduce(union));
+            }
+        }
+    }
+
+    matches
+}
+
+/* Finds the query within the lines read from left to right by the characters folded
+ * with `fold_char`, so that plain quotation marks match the ones of the language.
+ * Returns the bounds of every match */
+pub fn folded_matches(
+    chars: &[PageChar],
+    query: &str,
+    language: Option<Language>,
+) -> Vec<(f32, f32, f32, f32)> {
+    let query: Vec<char> = query
+        .chars()
+        .filter_map(|x| fold_char(x, language))
+        .collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let mut matches = Vec::new();
+    for line in chars.chunk_by(|a, b| a.line == b.line) {
+        if line[0].direction != TextDirection::LeftToRight {
+            continue;
+        }
+        let folded: Vec<(char, (f32, f32, f32, f32))> = line
+            .iter()
+            .filter_map(|x| Some((fold_char(x.char, language)?, x.bounds)))
+            .collect();
+        for part in folded.windows(query.len()) {
+            if part.iter().map(|x| x.0).eq(query.iter().copied()) {
+                matches.extend(part.iter().map(|x| x.1).reduce(union));
+            }
+        }
+    }
+
+    matches
+}
+
+/* The bounds of the lines that are not read from left to right, on which the matches
+ * of mupdf are replaced by the ones of `directional_matches` */
+pub fn directional_lines(chars: &[PageChar]) -> Vec<(f32, f32, f32, f32)> {
+    chars
+        .chunk_by(|a, b| a.line == b.line)
+        .filter(|x| x[0].direction != TextDirection::LeftToRight)
+        .filter_map(|x| x.iter().map(|x| x.bounds).reduce(union))
+        .collect()
+}
+
+/* The index of the first character within the bounds, by which the matches of a page
+ * are put into reading order. Bounds without characters come last */
+pub fn reading_position(chars: &[PageChar], bounds: (f32, f32, f32, f32)) -> usize {
+    chars
+        .iter()
+        .position(|x| {
+            let (x, y) = center(x.bounds);
+            bounds.0 <= x && x <= bounds.2 && bounds.1 <= y && y <= bounds.3
+        })
+        .unwrap_or(usize::MAX)
+}
+
+/* The characters of the text blocks of a page in reading order */
+pub fn page_chars(page: &Page) -> Result<Vec<PageChar>, String> {
+    let text_page = page
+        .to_text_page(TextPageOptions::empty())
+        .map_err(|x| format!("Could not extract text from page: {}", x))?;
+
+    let mut chars = Vec::new();
+    let mut index = 0;
+    for block in text_page.blocks() {
+        if !matches!(block.r#type(), TextBlockType::Text) {
+            continue;
+        }
+
+        for line in block.lines() {
+            let mut line_chars = Vec::new();
+            for c in line.chars() {
+                let Some(char) = c.char() else {
+                    continue;
+                };
+                let quad = c.quad();
+                let xs = [quad.ul.x, quad.ur.x, quad.ll.x, quad.lr.x];
+                let ys = [quad.ul.y, quad.ur.y, quad.ll.y, quad.lr.y];
+                line_chars.push(PageChar {
+                    char,
+                    bounds: (
+                        xs.into_iter().fold(f32::INFINITY, f32::min),
+                        ys.into_iter().fold(f32::INFINITY, f32::min),
+                        xs.into_iter().fold(-f32::INFINITY, f32::max),
+                        ys.into_iter().fold(-f32::INFINITY, f32::max),
+                    ),
+                    line: index,
+                    direction: TextDirection::LeftToRight,
+                });
+            }
+
+            let direction = line_direction(&line_chars);
+            order_line(&mut line_chars, direction);
+            chars.extend(line_chars.into_iter().map(|x| PageChar { direction, ..x }));
+            index += 1;
+        }
+    }
+
+    Ok(chars)
+}
+
+/* Joins the lines of a text block into a paragraph. Words hyphenated at the end of
+ * a line are joined back together, other hyphens are kept without a space */
+pub fn join_lines(lines: &[String]) -> String {
+    let mut paragraph = String::new();
+    for line in lines.iter().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let before_hyphen = paragraph.strip_suffix('-').and_then(|x| x.chars().last());
+        match before_hyphen {
+            Some(x) if x.is_alphabetic() && line.starts_with(char::is_lowercase) => {
+                paragraph.pop();
+            }
+            Some(x) if x.is_alphanumeric() => (),
+            _ if !paragraph.is_empty() => paragraph.push(' '),
+            _ => (),
+        }
+        paragraph.push_str(line);
+    }
+    paragraph
+}
+
+/* The text blocks of a page as paragraphs in reading order */
+pub fn page_paragraphs(page: &Page) -> Result<Vec<String>, String> {
+    let text_page = page
+        .to_text_page(TextPageOptions::empty())
+        .map_err(|x| format!("Could not extract text from page: {}", x))?;
+
+    let mut paragraphs = Vec::new();
+    for block in text_page.blocks() {
+        if !matches!(block.r#type(), TextBlockType::Text) {
+            continue;
+        }
+
+        let lines: Vec<String> = block
+            .lines()
+            .map(|x| x.chars().filter_map(|x| x.char()).collect())
+            .collect();
+        let paragraph = join_lines(&lines);
+        if !paragraph.is_empty() {
+            paragraphs.push(paragraph);
+        }
+    }
+
+    Ok(paragraphs)
+}
+
+/* Wraps the paragraphs to the width with an empty line between each of them */
+pub fn wrap_paragraphs(paragraphs: &[String], width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in paragraphs {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(
+            textwrap::wrap(paragraph, usize::max(width, 1))
+                .iter()
+                .map(|x| x.to_string()),
+        );
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caption_kind() {
+        assert!(caption_kind("Figure 3: Results") == Some(CaptionKind::Figure));
+        assert!(caption_kind("Fig. 2. Overview") == Some(CaptionKind::Figure));
+        assert!(caption_kind("  Figure 3.2 | Setup") == Some(CaptionKind::Figure));
+        assert!(caption_kind("TABLE IV") == Some(CaptionKind::Table));
+        assert!(caption_kind("Table A.1: Symbols") == Some(CaptionKind::Table));
+        assert!(caption_kind("Figure 3 shows the results").is_none());
+        assert!(caption_kind("Tables are listed below.").is_none());
+        assert!(caption_kind("Figures").is_none());
+    }
+
+    #[test]
+    fn test_is_math_text() {
+        assert!(is_math_text("∑ α_i = β + γ"));
+        assert!(is_math_text("f(x) = x^2 + 1"));
+        assert!(!is_math_text("The results are shown in the table below."));
+        assert!(!is_math_text(""));
+    }
+
+    #[test]
+    fn test_find_identifiers() {
+        let found = find_identifiers("See doi:10.1145/3290605.3300233. for details");
+        assert!(found.len() == 1);
+        assert!(found[0].uri == "https://doi.org/10.1145/3290605.3300233");
+        assert!(found[0].start == 8 && found[0].end == 31);
+
+        let found = find_identifiers("Preprint arXiv:2106.09685v2, 2021");
+        assert!(found.len() == 1);
+        assert!(found[0].uri == "https://arxiv.org/abs/2106.09685v2");
+
+        let found = find_identifiers("ISBN 978-0-262-03384-8 and ISBN-10: 0-306-40615-2");
+        assert!(found.len() == 2);
+        assert!(found[0].uri == "https://openlibrary.org/isbn/9780262033848");
+        assert!(found[1].uri == "https://openlibrary.org/isbn/0306406152");
+
+        /* Invalid checksums, bare numbers and partial words are not identifiers */
+        assert!(find_identifiers("ISBN 978-0-262-03384-9").is_empty());
+        assert!(find_identifiers("2106.09685 and 110.1145/3290605").is_empty());
+    }
+
+    #[test]
+    fn test_select_chars() {
+        let chars: Vec<PageChar> = "ab\ncd"
+            .split('\n')
+            .enumerate()
+            .flat_map(|(line, text)| {
+                text.chars().enumerate().map(move |(i, char)| PageChar {
+                    char,
+                    bounds: (
+                        10.0 * i as f32,
+                        20.0 * line as f32,
+                        10.0 * (i + 1) as f32,
+                        20.0 * line as f32 + 10.0,
+                    ),
+                    line,
+                    direction: TextDirection::LeftToRight,
+                })
+            })
+            .collect();
+
+        /* Selecting backwards gives the same text as selecting forwards */
+        let (text, rects) = select_chars(&chars, (15.0, 5.0), (5.0, 25.0));
+        assert!(text == "b\nc");
+        assert!(rects == vec![(10.0, 0.0, 20.0, 10.0), (0.0, 20.0, 10.0, 30.0)]);
+        assert!(select_chars(&chars, (5.0, 25.0), (15.0, 5.0)).0 == "b\nc");
+
+        /* Points beside the text select the nearest characters */
+        assert!(select_chars(&chars, (-50.0, 0.0), (100.0, 40.0)).0 == "ab\ncd");
+        assert!(select_chars(&[], (0.0, 0.0), (1.0, 1.0)).0.is_empty());
+    }
+
+    #[test]
+    fn test_text_direction() {
+        let placed = |text: &str, vertical: bool| -> Vec<PageChar> {
+            text.chars()
+                .enumerate()
+                .map(|(i, char)| {
+                    let offset = 10.0 * i as f32;
+                    PageChar {
+                        char,
+                        bounds: match vertical {
+                            true => (0.0, offset, 10.0, offset + 10.0),
+                            false => (offset, 0.0, offset + 10.0, 10.0),
+                        },
+                        line: 0,
+                        direction: TextDirection::LeftToRight,
+                    }
+                })
+                .collect()
+        };
+        let ordered = |chars: &mut Vec<PageChar>| -> String {
+            let direction = line_direction(chars);
+            order_line(chars, direction);
+            chars.iter_mut().for_each(|x| x.direction = direction);
+            chars.iter().map(|x| x.char).collect()
+        };
+
+        /* Drawn from left to right as it is displayed */
+        let mut chars = placed("!abc 12 םולש", false);
+        assert!(line_direction(&chars) == TextDirection::RightToLeft);
+        assert!(ordered(&mut chars) == "שלום abc 12!");
+        assert!(directional_matches(&chars, "שלום") == [(80.0, 0.0, 120.0, 10.0)]);
+        assert!(directional_matches(&chars, "ABC") == [(10.0, 0.0, 40.0, 10.0)]);
+        assert!(directional_lines(&chars) == [(0.0, 0.0, 120.0, 10.0)]);
+        assert!(reading_position(&chars, (10.0, 0.0, 40.0, 10.0)) == 5);
+
+        let mut chars = placed("語本日", true);
+        chars.reverse();
+        assert!(ordered(&mut chars) == "語本日");
+        assert!(chars[0].direction == TextDirection::Vertical);
+
+        /* Left-to-right lines are found by the search of mupdf */
+        let mut chars = placed("abc", false);
+        assert!(ordered(&mut chars) == "abc");
+        assert!(directional_matches(&chars, "abc").is_empty());
+    }
+
+    #[test]
+    fn test_hyphenated_matches() {
+        let chars: Vec<PageChar> = "The render-\ning is re-\nRendered"
+            .split('\n')
+            .enumerate()
+            .flat_map(|(line, text)| {
+                text.chars().enumerate().map(move |(i, char)| PageChar {
+                    char,
+                    bounds: (
+                        10.0 * i as f32,
+                        20.0 * line as f32,
+                        10.0 * (i + 1) as f32,
+                        20.0 * line as f32 + 10.0,
+                    ),
+                    line,
+                    direction: TextDirection::LeftToRight,
+                })
+            })
+            .collect();
+
+        let matches = hyphenated_matches(&chars, "RENDERING");
+        assert!(matches == [((40.0, 0.0, 100.0, 10.0), (0.0, 20.0, 30.0, 30.0))]);
+        /* Matches within one line are found by the search of mupdf */
+        assert!(hyphenated_matches(&chars, "render").is_empty());
+        assert!(hyphenated_matches(&chars, "rendered").is_empty());
+        assert!(hyphenated_matches(&chars, "").is_empty());
+
+        let chars: Vec<PageChar> = "„Zitat“ und"
+            .chars()
+            .enumerate()
+            .map(|(i, char)| PageChar {
+                char,
+                bounds: (10.0 * i as f32, 0.0, 10.0 * (i + 1) as f32, 10.0),
+                line: 0,
+                direction: TextDirection::LeftToRight,
+            })
+            .collect();
+        let german = Some(Language::German);
+        assert!(folded_matches(&chars, "\"zitat\"", german) == [(0.0, 0.0, 70.0, 10.0)]);
+        assert!(folded_matches(&chars, "\"zitat\"", None).is_empty());
+    }
+
+    #[test]
+    fn test_reflow() {
+        let lines = ["An exam-", " ple of  ", "", "Jean-", "Luc and 1-", "2"];
+        let lines: Vec<String> = lines.iter().map(|x| x.to_string()).collect();
+        assert!(join_lines(&lines) == "An example of Jean-Luc and 1-2");
+
+        let paragraphs = ["one two three".to_string(), "four".to_string()];
+        assert!(wrap_paragraphs(&paragraphs, 8) == ["one two", "three", "", "four"]);
+        assert!(wrap_paragraphs(&[], 8).is_empty());
+    }
+}
@@ -1,71 +1,181 @@
-use std::{sync::atomic::Ordering, thread};
+use std::{
+    sync::{atomic::Ordering, OnceLock},
+    thread,
+    time::Duration,
+};
 
-use crossbeam_channel::{unbounded, Receiver};
-use crossterm::event::{read, Event, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossterm::event::{
+    poll, read, Event, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind,
+};
 
-use crate::{drivers::graphics::GraphicsResponse, globals::RUNNING};
+use crate::{
+    drivers::graphics::GraphicsResponse,
+    globals::{INPUT_PAUSED, RUNNING},
+    replay::{RecordedEvent, Recorder},
+    threads::fnotify::FileEvent,
+};
 
 pub enum InputEvent {
     Key(KeyEvent),
     MouseScroll(MouseEventKind, KeyModifiers),
+    /* The replayed input is over and there is no terminal to read more from */
+    End,
 }
 
-pub struct EventThreadData(
-    pub Receiver<InputEvent>,
-    pub Receiver<MouseEvent>,
-    pub Receiver<GraphicsResponse>,
-    pub Receiver<(u16, u16)>,
-);
+/* Everything that the main loop waits for apart from the results of the renderers,
+ * the requests and its timers, in the order it has happened. The events of a pane are
+ * tagged with its id */
+pub enum AppEvent {
+    Input(InputEvent),
+    Mouse(MouseEvent),
+    /* The new size of the terminal in cells */
+    Resize(u16, u16),
+    /* The document of the pane has been written to or removed */
+    Reload(usize, FileEvent),
+    /* The pane has to be drawn again, e.g. once a zoom has settled */
+    Rerender(usize),
+}
+
+/* The sources of the events send through clones of the sender */
+static EVENTS: OnceLock<(Sender<AppEvent>, Receiver<AppEvent>)> = OnceLock::new();
+
+pub fn sender() -> Sender<AppEvent> {
+    EVENTS.get_or_init(unbounded).0.clone()
+}
+
+/* The graphics responses are read apart from the other events, by the graphics
+ * drivers waiting for the answers of the terminal through `RECEIVER_GR` */
+pub struct EventThreadData {
+    pub events: Receiver<AppEvent>,
+    pub graphics: Receiver<GraphicsResponse>,
+}
+
+#[derive(Clone)]
+struct EventSenders {
+    events: Sender<AppEvent>,
+    gr: Sender<GraphicsResponse>,
+}
+
+fn forward(event: Event, senders: &EventSenders) {
+    match event {
+        Event::Key(event) => {
+            senders
+                .events
+                .try_send(AppEvent::Input(InputEvent::Key(event)))
+                .expect("Could not send key event");
+        }
+        Event::ApplicationProgramCommand(command) => {
+            senders
+                .gr
+                .try_send(GraphicsResponse::new(command.as_bytes()))
+                .expect("Could not send graphics response");
+        }
+        Event::Mouse(event) => match event {
+            MouseEvent {
+                kind:
+                    kind @ (MouseEventKind::ScrollUp
+                    | MouseEventKind::ScrollDown
+                    | MouseEventKind::ScrollLeft
+                    | MouseEventKind::ScrollRight),
+                modifiers,
+                ..
+            } => {
+                senders
+                    .events
+                    .try_send(AppEvent::Input(InputEvent::MouseScroll(kind, modifiers)))
+                    .expect("Could not send mouse scroll event");
+                senders
+                    .events
+                    .try_send(AppEvent::Mouse(event))
+                    .expect("Could not send mouse");
+            }
+            x => {
+                senders
+                    .events
+                    .try_send(AppEvent::Mouse(x))
+                    .expect("Could not send mouse event");
+            }
+        },
+        Event::Resize(width, height) => {
+            senders
+                .events
+                .try_send(AppEvent::Resize(width, height))
+                .expect("Could not send new window dimensions");
+        }
+        _ => (),
+    }
+}
 
-pub fn spawn() -> EventThreadData {
-    let (sender_input, receive_input) = unbounded::<InputEvent>();
-    let (sender_mouse, receive_mouse) = unbounded::<MouseEvent>();
+/* How often the paused input is checked for whether it has been resumed */
+const PAUSE_POLL: Duration = Duration::from_millis(50);
+
+/* Reads the events of the terminal unless there is none. The key and mouse events are
+ * written to the recorder if there is one, and the replayed events are fed in with
+ * their original timing next to the ones of the terminal */
+pub fn spawn(
+    mut recorder: Option<Recorder>,
+    replay: Vec<RecordedEvent>,
+    terminal: bool,
+) -> EventThreadData {
     let (sender_gr, receive_gr) = unbounded::<GraphicsResponse>();
-    let (sender_ws, receive_ws) = unbounded::<(u16, u16)>();
+    let senders = EventSenders {
+        events: sender(),
+        gr: sender_gr,
+    };
+    let data = EventThreadData {
+        events: EVENTS.get().unwrap().1.clone(),
+        graphics: receive_gr,
+    };
 
-    thread::spawn(move || {
-        while RUNNING.load(Ordering::Acquire) {
-            match read().expect("Could not read event") {
-                Event::Key(event) => {
-                    sender_input
-                        .try_send(InputEvent::Key(event))
-                        .expect("Could not send key event");
+    if !replay.is_empty() {
+        let senders = senders.clone();
+        thread::spawn(move || {
+            for recorded in replay {
+                thread::sleep(recorded.delay());
+                if !RUNNING.load(Ordering::Acquire) {
+                    break;
                 }
-                Event::ApplicationProgramCommand(command) => {
-                    sender_gr
-                        .try_send(GraphicsResponse::new(command.as_bytes()))
-                        .expect("Could not send graphics response");
+
+                /* Only the input is replayed, the terminal answers for the images of
+                 * this session by itself */
+                if matches!(recorded.event, Event::Key(_) | Event::Mouse(_)) {
+                    forward(recorded.event, &senders);
                 }
-                Event::Mouse(event) => match event {
-                    MouseEvent {
-                        kind:
-                            kind @ (MouseEventKind::ScrollUp
-                            | MouseEventKind::ScrollDown
-                            | MouseEventKind::ScrollLeft
-                            | MouseEventKind::ScrollRight),
-                        modifiers,
-                        ..
-                    } => {
-                        sender_input
-                            .try_send(InputEvent::MouseScroll(kind, modifiers))
-                            .expect("Could not send mouse scroll event");
-                        sender_mouse.try_send(event).expect("Could not send mouse");
-                    }
-                    x => {
-                        sender_mouse
-                            .try_send(x)
-                            .expect("Could not send mouse event");
-                    }
-                },
-                Event::Resize(width, height) => {
-                    sender_ws
-                        .try_send((width, height))
-                        .expect("Could not send new window dimensions");
+            }
+
+            if !terminal {
+                let _ = senders.events.try_send(AppEvent::Input(InputEvent::End));
+            }
+        });
+    }
+
+    if !terminal {
+        return data;
+    }
+
+    thread::spawn(move || {
+        while RUNNING.load(Ordering::Acquire) {
+            /* The input is left to the other program while it is paused */
+            if INPUT_PAUSED.load(Ordering::Acquire) {
+                thread::sleep(PAUSE_POLL);
+                continue;
+            }
+            if !poll(PAUSE_POLL).expect("Could not poll event")
+                || INPUT_PAUSED.load(Ordering::Acquire)
+            {
+                continue;
+            }
+            let event = read().expect("Could not read event");
+
+            if let Some(recorder) = recorder.as_mut() {
+                if matches!(event, Event::Key(_) | Event::Mouse(_)) {
+                    recorder.record(&event).expect("Could not record event");
                 }
-                _ => (),
             }
+            forward(event, &senders);
         }
     });
 
-    EventThreadData(receive_input, receive_mouse, receive_gr, receive_ws)
+    data
 }
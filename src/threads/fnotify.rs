@@ -1,38 +1,106 @@
-use std::{path::Path, sync::OnceLock};
+use std::{path::Path, sync::Mutex, thread, time::Duration};
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use notify::{
-    event::{DataChange, ModifyKind},
-    RecommendedWatcher, RecursiveMode, Watcher,
-};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError};
+use notify::{event::ModifyKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 
-static SENDER_FILE_RELOAD: OnceLock<Sender<()>> = OnceLock::new();
-static WATCHER_FILE: OnceLock<RecommendedWatcher> = OnceLock::new();
+use crate::threads::event::{self, AppEvent};
 
-pub fn spawn(file: &str) -> Result<Receiver<()>, String> {
-    let (sender_file_reload, receiver_file_reload) = unbounded::<()>();
+/* Editors and LaTeX toolchains write a file in several steps, the last event is
+ * forwarded once no other one has followed for this long */
+const DEBOUNCE: Duration = Duration::from_millis(250);
 
-    SENDER_FILE_RELOAD.get_or_init(|| sender_file_reload.clone());
+/* Watchers stop watching when dropped, so keep one alive for every watched file */
+static WATCHERS_FILE: Mutex<Vec<(String, RecommendedWatcher)>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileEvent {
+    /* The file has been written to, or created again after it had been removed */
+    Changed,
+    /* The file has been removed or moved away, e.g. by cleaning a build */
+    Removed,
+}
+
+/* Forwards the last event of every burst as a reload of the pane. Stops when the
+ * watcher is gone */
+fn debounce(events: Receiver<FileEvent>, pane: usize) {
+    let sender = event::sender();
+    while let Ok(mut event) = events.recv() {
+        loop {
+            match events.recv_timeout(DEBOUNCE) {
+                Ok(x) => event = x,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        if sender.send(AppEvent::Reload(pane, event)).is_err() {
+            return;
+        }
+    }
+}
+
+/* Watches the file of the pane with the id, whose reloads arrive among the events */
+pub fn spawn(file: &str, pane: usize) -> Result<(), String> {
+    let (sender_events, receiver_events) = unbounded::<FileEvent>();
+
+    /* The directory is watched instead of the file itself, since watches of a removed
+     * file do not notice it being created again */
+    let watched = std::fs::canonicalize(file)
+        .map_err(|x| format!("Could not resolve {}: {}", file, x))?;
+    let directory = watched
+        .parent()
+        .ok_or(format!("{} has no parent directory", file))?
+        .to_path_buf();
 
     let mut watcher_file =
         notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
-            let event = res.expect("Could not watch file changes for the given file");
-
-            if let notify::EventKind::Modify(ModifyKind::Data(DataChange::Any)) =
-                event.kind
-            {
-                (*SENDER_FILE_RELOAD.get().unwrap())
-                    .send(())
-                    .expect("Could not send a file change signal");
+            /* A failed event does not stop the watcher, later ones may succeed */
+            let event = match res {
+                Ok(x) => x,
+                Err(_) => return,
+            };
+            if !event.paths.iter().any(|x| *x == watched) {
+                return;
             }
+
+            let file_event = match event.kind {
+                EventKind::Remove(_) => FileEvent::Removed,
+                /* Renames are reported for both the old and the new name */
+                EventKind::Create(_)
+                | EventKind::Modify(ModifyKind::Data(_))
+                | EventKind::Modify(ModifyKind::Name(_)) => {
+                    if watched.exists() {
+                        FileEvent::Changed
+                    } else {
+                        FileEvent::Removed
+                    }
+                }
+                _ => return,
+            };
+
+            /* Sending only fails once the debounce thread is gone */
+            let _ = sender_events.send(file_event);
         })
         .map_err(|x| format!("Could not initialize a file watcher: {}", x))?;
 
     watcher_file
-        .watch(Path::new(file), RecursiveMode::NonRecursive)
-        .expect("Could not start watching file changes for the given file");
+        .watch(Path::new(&directory), RecursiveMode::NonRecursive)
+        .map_err(|x| format!("Could not watch {}: {}", directory.display(), x))?;
 
-    WATCHER_FILE.get_or_init(|| watcher_file);
+    thread::spawn(move || debounce(receiver_events, pane));
+
+    WATCHERS_FILE
+        .lock()
+        .unwrap()
+        .push((file.to_owned(), watcher_file));
+
+    Ok(())
+}
 
-    Ok(receiver_file_reload)
+/* Stops one of the watchers of the given file */
+pub fn unwatch(file: &str) {
+    let mut watchers = WATCHERS_FILE.lock().unwrap();
+    if let Some(i) = watchers.iter().position(|(x, _)| x == file) {
+        watchers.remove(i);
+    }
 }
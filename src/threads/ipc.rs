@@ -0,0 +1,283 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, RwLock},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::Sender;
+
+use crate::{
+    deeplink::{split_fragment, DeepLink, DEEPLINK_SCHEME},
+    globals::RUNNING,
+    outline::resolve_destination,
+};
+
+/* Name of the socket that instances started with `--serve` listen on */
+pub const SERVE_SOCKET: &str = "meowpdf.sock";
+/* Lines starting with it ask for the state of the instance instead of opening a
+ * document, such as `QUERY page` */
+const QUERY_PREFIX: &str = "QUERY ";
+/* Status lines run the query on every redraw, a stuck instance must not hang them */
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/* What the first pane of the instance displays, as answered to queries */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub file: String,
+    /* The page in the middle of the view counted from one */
+    pub page: usize,
+    pub pages: usize,
+}
+
+static STATUS: RwLock<Option<Status>> = RwLock::new(None);
+
+/* Updated by the main loop whenever it draws */
+pub fn set_status(status: Status) {
+    *STATUS.write().unwrap() = Some(status);
+}
+
+/* Answers a query. `page` is the name of the document with the page, the number of
+ * pages and how far the page is into the document, e.g. `paper.pdf 3/12 25%` */
+fn answer(query: &str, status: Option<&Status>) -> Result<String, String> {
+    let status = status.ok_or("No document is displayed")?;
+    match query.trim() {
+        "page" => {
+            let name = Path::new(&status.file)
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_else(|| status.file.clone());
+            let percent = status.page * 100 / usize::max(status.pages, 1);
+            Ok(format!(
+                "{} {}/{} {}%",
+                name, status.page, status.pages, percent
+            ))
+        }
+        x => Err(format!("Unknown query `{}`", x)),
+    }
+}
+
+/* A request to display a document. Requests without a position only bring the
+ * document to the front if it is already displayed */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub file: String,
+    /* Zero-indexed page and scale to jump to */
+    pub position: Option<(usize, Option<f32>)>,
+}
+
+impl Request {
+    /* Requests are sent as one line per request. A line is either a `meowpdf://`
+     * deep link or the path to a document, optionally with a fragment such as
+     * `#page=12` or `#nameddest=intro` */
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        if line.starts_with(DEEPLINK_SCHEME) {
+            let link = DeepLink::parse(line)?;
+            return Ok(Self {
+                file: link.file,
+                position: Some((link.page - 1, link.zoom.map(|x| x / 100.0))),
+            });
+        }
+
+        if line.is_empty() {
+            return Err("Empty request".to_string());
+        }
+
+        let (file, parameters) = split_fragment(line)?;
+        let Some(parameters) = parameters else {
+            return Ok(Self {
+                file,
+                position: None,
+            });
+        };
+
+        let page = match &parameters.nameddest {
+            Some(name) => Some(resolve_destination(&file, name)?),
+            None => parameters.page.map(|x| x - 1),
+        };
+        let scale = parameters.zoom.map(|x| x / 100.0);
+        let position = match (page, scale) {
+            (None, None) => None,
+            (page, scale) => Some((page.unwrap_or(0), scale)),
+        };
+        Ok(Self { file, position })
+    }
+
+    pub fn to_line(&self) -> String {
+        match self.position {
+            Some((page, scale)) => DeepLink {
+                file: self.file.clone(),
+                page: page + 1,
+                zoom: scale.map(|x| x * 100.0),
+            }
+            .to_uri(),
+            None => self.file.clone(),
+        }
+    }
+}
+
+/* The directory in which the sockets of the running instances are placed */
+pub fn socket_dir() -> PathBuf {
+    dirs::runtime_dir().unwrap_or(std::env::temp_dir())
+}
+
+/* The socket of the instance displaying the given canonical document path. Binding it
+ * doubles as a lock so that only one instance can own a document at a time */
+pub fn file_socket(file: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    file.hash(&mut hasher);
+    socket_dir().join(format!("meowpdf-{:016X}.sock", hasher.finish()))
+}
+
+/* Removes the socket file when the listening instance exits */
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/* Listens for requests on the given socket and forwards them to `sender_request` */
+pub fn spawn(path: &Path, sender_request: Sender<Request>) -> Result<Server, String> {
+    let listener = match UnixListener::bind(path) {
+        Ok(x) => x,
+        Err(_) => {
+            /* A socket left behind by a crashed instance refuses connections */
+            if UnixStream::connect(path).is_ok() {
+                return Err(format!(
+                    "Another instance is already listening on {}",
+                    path.display()
+                ));
+            }
+            let _ = std::fs::remove_file(path);
+            UnixListener::bind(path)
+                .map_err(|x| format!("Could not bind to {}: {}", path.display(), x))?
+        }
+    };
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if !RUNNING.load(Ordering::Acquire) {
+                break;
+            }
+
+            let mut stream = match stream {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let mut line = String::new();
+            if BufReader::new(&stream).read_line(&mut line).is_err() {
+                continue;
+            }
+
+            if let Some(query) = line.trim().strip_prefix(QUERY_PREFIX) {
+                let reply = match answer(query, STATUS.read().unwrap().as_ref()) {
+                    Ok(x) => format!("OK {}", x),
+                    Err(x) => format!("ERR {}", x),
+                };
+                let _ = writeln!(stream, "{}", reply);
+                continue;
+            }
+
+            let reply = match Request::parse(&line) {
+                Ok(request) => {
+                    if sender_request.send(request).is_err() {
+                        break;
+                    }
+                    "OK".to_string()
+                }
+                Err(x) => format!("ERR {}", x),
+            };
+            let _ = writeln!(stream, "{}", reply);
+        }
+    });
+
+    Ok(Server {
+        path: path.to_owned(),
+    })
+}
+
+/* Sends a request to the instance listening on the given socket */
+pub fn send(path: &Path, request: &Request) -> Result<(), String> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|x| format!("Could not connect to {}: {}", path.display(), x))?;
+    writeln!(stream, "{}", request.to_line())
+        .map_err(|x| format!("Could not send request: {}", x))?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut reply)
+        .map_err(|x| format!("Could not receive reply: {}", x))?;
+
+    match reply.trim().strip_prefix("ERR ") {
+        Some(x) => Err(x.to_owned()),
+        None => Ok(()),
+    }
+}
+
+/* Asks the instance listening on the given socket for its state */
+pub fn query(path: &Path, query: &str) -> Result<String, String> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|x| format!("Could not connect to {}: {}", path.display(), x))?;
+    stream
+        .set_read_timeout(Some(QUERY_TIMEOUT))
+        .map_err(|x| format!("Could not set the timeout: {}", x))?;
+    writeln!(stream, "{}{}", QUERY_PREFIX, query)
+        .map_err(|x| format!("Could not send query: {}", x))?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut reply)
+        .map_err(|x| format!("Could not receive reply: {}", x))?;
+
+    let reply = reply.trim();
+    match (reply.strip_prefix("OK "), reply.strip_prefix("ERR ")) {
+        (Some(x), _) => Ok(x.to_owned()),
+        (_, Some(x)) => Err(x.to_owned()),
+        _ => Err(format!("Unexpected reply `{}`", reply)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request() {
+        let request = Request::parse("/home/user/paper.pdf\n").unwrap();
+        assert!(request.file == "/home/user/paper.pdf" && request.position.is_none());
+        assert!(request.to_line() == "/home/user/paper.pdf");
+
+        let line = "meowpdf:///home/user/paper.pdf?page=3&zoom=150";
+        let request = Request::parse(line).unwrap();
+        assert!(request.position == Some((2, Some(1.5))));
+        assert!(request.to_line() == line);
+
+        let request = Request::parse("paper.pdf#page=12").unwrap();
+        assert!(request.file == "paper.pdf" && request.position == Some((11, None)));
+        assert!(
+            Request::parse("paper.pdf#zoom=50").unwrap().position == Some((0, Some(0.5)))
+        );
+
+        assert!(Request::parse("  \n").is_err());
+    }
+
+    #[test]
+    fn test_answer() {
+        let status = Status {
+            file: "/home/user/paper.pdf".to_string(),
+            page: 3,
+            pages: 12,
+        };
+        assert!(answer("page\n", Some(&status)) == Ok("paper.pdf 3/12 25%".to_string()));
+        assert!(answer("zoom", Some(&status)).is_err());
+        assert!(answer("page", None).is_err());
+    }
+}
@@ -1,3 +1,6 @@
 pub mod event;
 pub mod fnotify;
+pub mod ipc;
+pub(crate) mod priority;
 pub mod renderer;
+pub mod worker;
@@ -0,0 +1,23 @@
+/* Niceness added to the threads doing heavy background work */
+#[cfg(target_os = "linux")]
+const BACKGROUND_NICENESS: i32 = 10;
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn nice(inc: std::ffi::c_int) -> std::ffi::c_int;
+}
+
+/* Lowers the scheduling priority of the calling thread so that rasterization never
+ * starves the input loop. Threads have their own niceness on Linux, on other systems
+ * this would renice the whole process and is left out */
+pub fn lower_current_thread() {
+    if !crate::CONFIG.get().unwrap().viewer.background_priority {
+        return;
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe {
+        /* Failing to renice is harmless, the thread simply keeps its priority */
+        nice(BACKGROUND_NICENESS);
+    }
+}
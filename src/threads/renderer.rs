@@ -1,25 +1,112 @@
 use core::f32;
 use std::{
+    cell::RefCell,
+    path::Path,
     sync::{atomic::Ordering, Arc, RwLock},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use mupdf::{Colorspace, Document, Link, Matrix, Page};
+use mupdf::{
+    Colorspace, Device, Document, IRect, ImageFormat, Link, Matrix, Page, Pixmap,
+};
 
 use crate::{
+    annotate::{save_annotations, AnnotationKind, PendingAnnotation},
+    cache::{copy_pixmap, render_key, DiskCache, RenderCache},
     config::Config,
+    document::open_document,
     drivers::priority_channel::{unbounded_priority, PriorityReceiver, PrioritySender},
-    globals::{CONFIG, RUNNING},
+    filters::{
+        apply_filters, apply_paper, diff_tiles, tint_rect, tint_tiles, Daltonize,
+        InverseMode, Samples,
+    },
+    globals::{CONFIG, RUNNING, TERMINAL_SIZE},
     image::Image,
+    language::{document_language, needs_folding, normalize_text, Language},
+    stamp,
+    text::{
+        block_at, directional_lines, directional_matches, folded_matches,
+        hyphenated_matches, page_captions, page_chars, page_identifiers, page_paragraphs,
+        reading_position, select_chars, Caption, PageChar,
+    },
+    threads::{
+        priority::lower_current_thread,
+        worker::{self, RenderWorker},
+    },
+    transform::PageTransform,
 };
 
-#[derive(Copy, Clone, PartialEq)]
+/* Loads of a document that is being written which are retried before giving up,
+ * waiting twice as long before every retry */
+const RELOAD_RETRIES: u32 = 6;
+const RELOAD_BACKOFF: Duration = Duration::from_millis(250);
+
+#[derive(Clone, PartialEq)]
 pub enum RendererAction {
     Load,
-    Display(usize),
-    ToggleInverse,
+    /* Page and the precision to rasterize it at */
+    Display(usize, f64),
+    /* Page to rasterize quickly at `preview_precision` before it is displayed */
+    Preview(usize),
+    SetInverse(InverseMode),
     ToggleAlpha,
+    ToggleDiff,
+    TogglePaper,
+    Captions,
+    /* Page and point in page units of the block to zoom into */
+    ZoomBlock(usize, f32, f32),
+    /* Stores every page in the disk cache at the given precision */
+    Prerender(f64),
+    SetDaltonize(Option<Daltonize>),
+    /* Page, region in page units and the precision to rasterize it at */
+    InvertRegion(usize, (f32, f32, f32, f32), f64),
+    /* Finds the query on every page, an empty query clears the matches */
+    Search(String),
+    /* Page and the points in page units the text is selected between, `None` clears
+     * the selection */
+    Select(Option<(usize, (f32, f32), (f32, f32))>),
+    /* Answers with the text of the selection once the selects before it are done */
+    FinishSelection,
+    /* Saves a copy of the document stamped on the zero-indexed pages `first` to
+     * `last` to the path */
+    Stamp((usize, usize), String),
+    /* Annotates the selected text, which is written with `SaveAnnotations` */
+    Annotate(AnnotationKind),
+    /* Drops the last annotation that has not been written */
+    UndoAnnotation,
+    /* Writes the annotations into the document */
+    SaveAnnotations,
+    /* Writes the page as a PNG file into the working directory */
+    ExportPage(usize),
+    /* Extracts the paragraphs of a page for the text mode */
+    ExtractText(usize),
+}
+
+impl RendererAction {
+    /* Confirmed actions change the state of the renderer and are echoed back by the
+     * renderer thread once they have been accepted */
+    pub fn confirmed(&self) -> bool {
+        matches!(
+            self,
+            RendererAction::Load
+                | RendererAction::SetInverse(_)
+                | RendererAction::ToggleAlpha
+                | RendererAction::ToggleDiff
+                | RendererAction::TogglePaper
+                | RendererAction::SetDaltonize(_)
+        )
+    }
+}
+
+/* A match of a search given by its page and its bounds in page units */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchHit {
+    pub page: usize,
+    pub bounds: (f32, f32, f32, f32),
+    /* The part on the next line of a match split by a hyphen at the end of a line */
+    pub continuation: Option<(f32, f32, f32, f32)>,
 }
 
 #[derive(Clone)]
@@ -29,11 +116,72 @@ pub enum RendererResult {
         cumulative_heights: Vec<f32>,
         widths: Vec<f32>,
         links: Vec<Vec<Link>>,
+        transforms: Vec<PageTransform>,
     },
     Image {
         page: usize,
         data: Option<Arc<RwLock<Image>>>,
     },
+    /* A low precision render to display until the one of `Image` arrives */
+    ImagePreview {
+        page: usize,
+        data: Arc<RwLock<Image>>,
+    },
+    Captions(Vec<Caption>),
+    Popup(Option<Arc<RwLock<Image>>>),
+    Region {
+        page: usize,
+        crop: (f32, f32, f32, f32),
+        data: Arc<RwLock<Image>>,
+    },
+    Prerender {
+        done: usize,
+        total: usize,
+    },
+    Search {
+        query: String,
+        hits: Vec<SearchHit>,
+    },
+    /* Links to the identifiers printed on a page */
+    Identifiers {
+        page: usize,
+        links: Vec<Link>,
+    },
+    /* The page the selection has changed on and the selected text */
+    Selection {
+        page: usize,
+        text: String,
+    },
+    /* The text of the selection as it is when the mouse has been released */
+    SelectionFinished(String),
+    /* The page whose annotations have changed and the outcome to show */
+    Annotations {
+        page: usize,
+        message: String,
+    },
+    /* The outcome of an action to show in the command line */
+    Message(String),
+    /* The document could not be loaded, e.g. while it is being rewritten, and is
+     * loaded again in a moment. The previous pages stay displayed meanwhile */
+    Rebuilding,
+    /* The retries of the load have run out with the error */
+    ReloadFailed(String),
+    /* A page that could not be rendered, which the viewer may request again */
+    RenderFailed {
+        page: usize,
+        message: String,
+    },
+    /* An inverted region that could not be rendered */
+    RegionFailed {
+        page: usize,
+        crop: (f32, f32, f32, f32),
+        message: String,
+    },
+    /* The paragraphs of a page in reading order */
+    Text {
+        page: usize,
+        paragraphs: Vec<String>,
+    },
 }
 
 struct RendererInnerState<'a> {
@@ -42,33 +190,80 @@ struct RendererInnerState<'a> {
     pub file: String,
     pub document: Document,
 
+    /* The document that pages are diffed against in comparison mode */
+    pub compare_file: Option<String>,
+    pub compare: Option<Document>,
+
     pub cache: Vec<Page>,
+    pub transforms: Vec<PageTransform>,
+    /* Matches of the current search which are highlighted on the pages */
+    pub search: Vec<SearchHit>,
+    /* Page of the selected text and the bounds of its selected lines */
+    pub selection: Option<(usize, Vec<(f32, f32, f32, f32)>)>,
+    /* The text of the selection, empty if there is none */
+    pub selection_text: String,
+    /* Characters of the page that is being selected on, kept while dragging */
+    pub selection_chars: Option<(usize, Vec<PageChar>)>,
+    /* Annotations made since the document has last been written */
+    pub annotations: Vec<PendingAnnotation>,
+    /* The language of the document, which drives the search and the copied text */
+    pub language: Option<Language>,
+    /* Identifiers found in the text of each page, extracted when first displayed */
+    pub identifiers: Vec<Option<Vec<Link>>>,
+    /* Pages rendered ahead of time by `:prerender` */
+    pub disk_cache: Option<DiskCache>,
+    /* Displayed pages of this load with the toggles they have been rendered with */
+    pub rendered: RefCell<RenderCache>,
+    /* The next page to prerender and the precision to prerender at */
+    pub prerendering: Option<(usize, f64)>,
+    /* When the failed load is tried again and how many retries have been made */
+    pub reload_retry: Option<(Instant, u32)>,
 
     pub alpha: bool,
-    pub inverse: bool,
+    pub inverse: InverseMode,
+    pub diff: bool,
+    /* Whether `page_background` and `page_tint` are applied */
+    pub paper: bool,
+    pub daltonize: Option<Daltonize>,
+
+    /* The process that renders the pages with `isolate_renderer` and `--sandbox` */
+    pub worker: Option<RefCell<RenderWorker>>,
 
     pub cs: Colorspace,
-    pub ctm: Matrix,
 }
 
 impl<'a> RendererInnerState<'a> {
-    pub fn new(file: String) -> Result<Self, String> {
-        let document = Document::open(&file)
-            .map_err(|x| format!("Could not open the given PDF file: {}", x))?;
+    pub fn new(file: String, compare_file: Option<String>) -> Result<Self, String> {
+        let document = open_document(&file)?;
 
         let config = CONFIG.get().unwrap();
+        let worker = worker::isolated().then(|| RefCell::new(RenderWorker::new(&file)));
         let inner_state = Self {
             config,
             file,
             document,
+            compare_file,
+            compare: None,
             cache: Vec::new(),
+            transforms: Vec::new(),
+            search: Vec::new(),
+            selection: None,
+            selection_text: String::new(),
+            selection_chars: None,
+            annotations: Vec::new(),
+            language: None,
+            identifiers: Vec::new(),
+            disk_cache: None,
+            rendered: RefCell::new(RenderCache::new(config.viewer.render_cache)),
+            prerendering: None,
+            reload_retry: None,
             alpha: false,
-            inverse: false,
+            inverse: InverseMode::Off,
+            diff: false,
+            paper: true,
+            daltonize: None,
+            worker,
             cs: Colorspace::device_rgb(),
-            ctm: Matrix::new_scale(
-                config.viewer.render_precision as f32,
-                config.viewer.render_precision as f32,
-            ),
         };
 
         Ok(inner_state)
@@ -80,21 +275,18 @@ impl<'a> RendererInnerState<'a> {
         let mut widths = Vec::new();
         let mut links = Vec::new();
 
-        self.document = Document::open(&self.file)
-            .map_err(|x| format!("Could not open the given PDF file: {}", x))?;
-        if !self.document.is_pdf() {
-            Err("The given PDF file is not a PDF!".to_string())?;
-        }
-        self.cache.clear();
+        /* Nothing is replaced until every page has been loaded, so that a failed load
+         * keeps the previous document usable */
+        let document = open_document(&self.file)?;
+        let mut cache = Vec::new();
+        let mut transforms = Vec::new();
 
-        let page_count = self
-            .document
+        let page_count = document
             .page_count()
             .map_err(|x| format!("Could not extract the number of pages: {}", x))?;
 
         for i in 0..page_count {
-            let page = self
-                .document
+            let page = document
                 .load_page(i)
                 .map_err(|x| format!("Could not load page {}: {}", i, x))?;
 
@@ -102,8 +294,9 @@ impl<'a> RendererInnerState<'a> {
                 .bounds()
                 .map_err(|x| format!("Could not get bounds for page {}: {}", i, x))?;
 
-            let width = bounds.width();
-            let height = bounds.height();
+            let transform =
+                PageTransform::new((bounds.x0, bounds.y0, bounds.x1, bounds.y1));
+            let (width, height) = transform.size();
 
             max_page_width = f32::max(max_page_width, width);
             cumulative_heights.push(
@@ -112,8 +305,39 @@ impl<'a> RendererInnerState<'a> {
                     + self.config.viewer.margin_bottom,
             );
             widths.push(width);
-            links.push(page.links().expect("Could not extract links").collect());
-            self.cache.push(page);
+            links.push(
+                page.links()
+                    .map_err(|x| format!("Could not extract links of page {}: {}", i, x))?
+                    .collect(),
+            );
+            cache.push(page);
+            transforms.push(transform);
+        }
+
+        self.language = match self.config.viewer.language.as_str() {
+            "" => document_language(&self.file, &document),
+            tag => Language::from_tag(tag),
+        };
+        self.document = document;
+        self.cache = cache;
+        if let Some(worker) = &self.worker {
+            worker.borrow_mut().restart();
+        }
+        self.transforms = transforms;
+        self.identifiers = vec![None; page_count as usize];
+        self.search.clear();
+        self.selection = None;
+        self.selection_text.clear();
+        self.selection_chars = None;
+        self.annotations.retain(|x| x.page < page_count as usize);
+        self.disk_cache = DiskCache::open(&self.file).ok();
+        self.rendered.borrow_mut().clear();
+        self.prerendering = None;
+
+        /* The compared document may be in the middle of being rewritten, in that case
+         * every page is shown as different until the next successful load */
+        if let Some(compare_file) = &self.compare_file {
+            self.compare = open_document(compare_file).ok();
         }
 
         Ok(RendererResult::PageMetadata {
@@ -121,10 +345,629 @@ impl<'a> RendererInnerState<'a> {
             cumulative_heights,
             widths,
             links,
+            transforms: self.transforms.clone(),
+        })
+    }
+
+    /* Loads the document and retries with backoff when it fails, since a document that
+     * is being written, e.g. by pdflatex, is truncated until it has been written in
+     * full. Returns `None` when the load is not finished yet and the retry has been
+     * reported before */
+    pub fn reload(&mut self) -> Option<RendererResult> {
+        let error = match self.load() {
+            Ok(x) => {
+                self.reload_retry = None;
+                return Some(x);
+            }
+            Err(x) => x,
+        };
+
+        let retries = self.reload_retry.map_or(0, |x| x.1);
+        if retries >= RELOAD_RETRIES {
+            self.reload_retry = None;
+            return Some(RendererResult::ReloadFailed(format!(
+                "Could not load {}: {}",
+                self.file, error
+            )));
+        }
+        self.reload_retry = Some((
+            Instant::now() + RELOAD_BACKOFF * 2u32.pow(retries),
+            retries + 1,
+        ));
+        (retries == 0).then_some(RendererResult::Rebuilding)
+    }
+
+    pub fn captions(&self) -> RendererResult {
+        let mut captions = Vec::new();
+        for (i, page) in self.cache.iter().enumerate() {
+            /* A page without extractable text should not disable the jumps for the
+             * rest of the document */
+            if let Ok(page_captions) = page_captions(page, i) {
+                captions.extend(page_captions);
+            }
+        }
+
+        RendererResult::Captions(captions)
+    }
+
+    /* Renders the displayed part of a page, in the renderer process when there is one.
+     * The process opens the document itself, so only pages of the main document are
+     * rendered by it */
+    fn render(
+        &self,
+        page: usize,
+        transform: &PageTransform,
+        precision: f64,
+    ) -> Result<Pixmap, String> {
+        match &self.worker {
+            Some(worker) => worker
+                .borrow_mut()
+                .render(page, transform, precision, self.alpha),
+            None => transform.render(&self.cache[page], precision, self.alpha),
+        }
+    }
+
+    /* Rasterizes a page at the given precision with the current render settings */
+    pub fn rasterize(&self, page: usize, precision: f64) -> Result<Pixmap, String> {
+        let transform = &self.transforms[page];
+
+        /* The matches, the selection and the underlines are drawn on top afterwards,
+         * they change too often to be part of the checksum */
+        let key = render_key(&(
+            page,
+            precision.to_bits(),
+            self.alpha,
+            transform.key(),
+            format!("{:?}", self.inverse),
+            self.diff,
+            self.paper,
+            format!("{:?}", self.daltonize),
+        ));
+        let rendered = self.rendered.borrow().get(key).map(copy_pixmap);
+
+        let mut pixmap = match rendered {
+            Some(x) => x?,
+            None => {
+                let cached = self
+                    .disk_cache
+                    .as_ref()
+                    .and_then(|x| x.load(page, precision, self.alpha, transform));
+
+                let mut pixmap = match cached {
+                    Some(x) => x,
+                    None => self.render(page, transform, precision).map_err(|x| {
+                        format!("Could not rasterize page {}: {}", page + 1, x)
+                    })?,
+                };
+
+                let differences = if self.diff {
+                    self.differences(page, &pixmap, precision)
+                } else {
+                    None
+                };
+
+                self.post_process(&mut pixmap);
+
+                if let Some(tiles) = differences {
+                    let (width, n) = (pixmap.width() as usize, pixmap.n() as usize);
+                    tint_tiles(pixmap.samples_mut(), width, n, &tiles);
+                }
+
+                if let Ok(copy) = copy_pixmap(&pixmap) {
+                    self.rendered.borrow_mut().insert(key, copy);
+                }
+                pixmap
+            }
+        };
+
+        if let Some(Some(links)) = self.identifiers.get(page) {
+            self.underline(&mut pixmap, transform, precision, links);
+        }
+
+        self.highlight(&mut pixmap, page, transform, precision);
+
+        Ok(pixmap)
+    }
+
+    /* Writes the page with the filters but without the matches and the selection as
+     * `<document>-page<page>.png`. Returns the name of the file */
+    pub fn export_page(&self, page: usize) -> Result<String, String> {
+        if page >= self.cache.len() {
+            return Err(format!("Page {} has not been loaded", page + 1));
+        }
+        let precision = self.config.viewer.export_dpi as f64 / 72.0;
+        let mut pixmap = self.render(page, &self.transforms[page], precision)?;
+        self.post_process(&mut pixmap);
+
+        let stem = Path::new(&self.file)
+            .file_stem()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "document".to_string());
+        let filename = format!("{}-page{}.png", stem, page + 1);
+        pixmap
+            .save_as(&filename, ImageFormat::PNG)
+            .map_err(|x| format!("Could not write {}: {}", filename, x))?;
+        Ok(filename)
+    }
+
+    /* Extracts the identifiers of a page the first time it is displayed. Returns the
+     * links to them which are yet unknown to the viewer */
+    pub fn extract_identifiers(&mut self, page: usize) -> Option<Vec<Link>> {
+        if !self.config.viewer.detect_identifiers || self.identifiers[page].is_some() {
+            return None;
+        }
+
+        let links = page_identifiers(&self.cache[page], page).unwrap_or_default();
+        self.identifiers[page] = Some(links.clone());
+        Some(links)
+    }
+
+    pub fn search(&mut self, query: &str) -> RendererResult {
+        const HITS_PER_PAGE: u32 = 512;
+
+        self.search.clear();
+        let folding = needs_folding(query, self.language);
+        if !query.is_empty() {
+            for (i, page) in self.cache.iter().enumerate() {
+                /* A page that can not be searched should not end the search. Queries
+                 * with quotation marks are matched by folded characters instead */
+                let quads = match folding {
+                    true => Vec::new(),
+                    false => match page.search(query, HITS_PER_PAGE) {
+                        Ok(x) => x,
+                        Err(_) => continue,
+                    },
+                };
+
+                /* Right-to-left and vertical lines are searched in reading order
+                 * here, their matches replace the ones of mupdf */
+                let chars = page_chars(page).unwrap_or_default();
+                let lines = directional_lines(&chars);
+                let contains = |bounds: &(f32, f32, f32, f32), (x, y): (f32, f32)| {
+                    bounds.0 <= x && x <= bounds.2 && bounds.1 <= y && y <= bounds.3
+                };
+
+                let mut hits = Vec::new();
+                for quad in quads {
+                    let xs = [quad.ul.x, quad.ur.x, quad.ll.x, quad.lr.x];
+                    let ys = [quad.ul.y, quad.ur.y, quad.ll.y, quad.lr.y];
+                    let bounds = (
+                        xs.into_iter().fold(f32::INFINITY, f32::min),
+                        ys.into_iter().fold(f32::INFINITY, f32::min),
+                        xs.into_iter().fold(-f32::INFINITY, f32::max),
+                        ys.into_iter().fold(-f32::INFINITY, f32::max),
+                    );
+                    let center =
+                        ((bounds.0 + bounds.2) / 2.0, (bounds.1 + bounds.3) / 2.0);
+                    if lines.iter().any(|x| contains(x, center)) {
+                        continue;
+                    }
+                    hits.push(SearchHit {
+                        page: i,
+                        bounds,
+                        continuation: None,
+                    });
+                }
+                for bounds in directional_matches(&chars, query) {
+                    hits.push(SearchHit {
+                        page: i,
+                        bounds,
+                        continuation: None,
+                    });
+                }
+                if folding {
+                    for bounds in folded_matches(&chars, query, self.language) {
+                        hits.push(SearchHit {
+                            page: i,
+                            bounds,
+                            continuation: None,
+                        });
+                    }
+                }
+
+                if self.config.viewer.search_hyphenation {
+                    for (bounds, continuation) in hyphenated_matches(&chars, query) {
+                        hits.push(SearchHit {
+                            page: i,
+                            bounds,
+                            continuation: Some(continuation),
+                        });
+                    }
+                }
+
+                hits.sort_by_key(|x| reading_position(&chars, x.bounds));
+                self.search.extend(hits);
+            }
+        }
+
+        RendererResult::Search {
+            query: query.to_string(),
+            hits: self.search.clone(),
+        }
+    }
+
+    /* Selects the text between two points of a page. Returns the page whose selection
+     * has changed, which is the previously selected one when clearing */
+    pub fn select(
+        &mut self,
+        selection: Option<(usize, (f32, f32), (f32, f32))>,
+    ) -> Option<RendererResult> {
+        let Some((page, start, end)) = selection else {
+            self.selection_text.clear();
+            let (page, _) = self.selection.take()?;
+            return Some(RendererResult::Selection {
+                page,
+                text: String::new(),
+            });
+        };
+
+        if self
+            .selection_chars
+            .as_ref()
+            .is_none_or(|(x, _)| *x != page)
+        {
+            let chars = page_chars(self.cache.get(page)?).ok()?;
+            self.selection_chars = Some((page, chars));
+        }
+
+        let (_, chars) = self.selection_chars.as_ref()?;
+        let (text, rects) = select_chars(chars, start, end);
+        let text = normalize_text(&text, self.language, self.config.viewer.plain_quotes);
+        self.selection = Some((page, rects));
+        self.selection_text = text.clone();
+        Some(RendererResult::Selection { page, text })
+    }
+
+    /* Annotates the selected text. The selection is cleared so that the annotation
+     * is seen in its own color */
+    pub fn annotate(&mut self, kind: AnnotationKind) -> RendererResult {
+        let Some((page, rects)) = self.selection.take() else {
+            return RendererResult::Message(
+                "Select the text to annotate first".to_string(),
+            );
+        };
+        self.selection_text.clear();
+
+        self.annotations
+            .push(PendingAnnotation { page, kind, rects });
+        RendererResult::Annotations {
+            page,
+            message: format!(
+                "Added a {} on page {}, {} annotations to write with `:w`",
+                kind.subtype().to_lowercase(),
+                page + 1,
+                self.annotations.len()
+            ),
+        }
+    }
+
+    pub fn undo_annotation(&mut self) -> RendererResult {
+        let Some(annotation) = self.annotations.pop() else {
+            return RendererResult::Message("No annotation to undo".to_string());
+        };
+
+        RendererResult::Annotations {
+            page: annotation.page,
+            message: format!(
+                "Removed the {} on page {}",
+                annotation.kind.subtype().to_lowercase(),
+                annotation.page + 1
+            ),
+        }
+    }
+
+    /* The annotations are drawn by the document once it has been reloaded */
+    pub fn save_annotations(&mut self) -> RendererResult {
+        if self.annotations.is_empty() {
+            return RendererResult::Message("No annotations to write".to_string());
+        }
+
+        let message = match save_annotations(&self.file, &self.annotations) {
+            Ok(x) => {
+                self.annotations.clear();
+                format!("Wrote {} annotations into {}", x, self.file)
+            }
+            Err(x) => x,
+        };
+        RendererResult::Message(message)
+    }
+
+    /* Tints the matches of the current search, the selected text and the annotations
+     * that have not been written on a page */
+    fn highlight(
+        &self,
+        pixmap: &mut Pixmap,
+        page: usize,
+        transform: &PageTransform,
+        precision: f64,
+    ) {
+        const HIGHLIGHT_COLOR: [u8; 3] = [255, 214, 0];
+        const HIGHLIGHT_ALPHA: f32 = 0.4;
+        const SELECTION_COLOR: [u8; 3] = [51, 144, 255];
+        const SELECTION_ALPHA: f32 = 0.35;
+
+        let [a, b, c, d, e, f] = transform.coefficients(precision as f32);
+        let (width, height, n) = (
+            pixmap.width() as usize,
+            pixmap.height() as usize,
+            pixmap.n() as usize,
+        );
+
+        let hits = self
+            .search
+            .iter()
+            .filter(|x| x.page == page)
+            .flat_map(|x| std::iter::once(x.bounds).chain(x.continuation))
+            .map(|x| (x, HIGHLIGHT_COLOR, HIGHLIGHT_ALPHA));
+        let selected = self
+            .selection
+            .iter()
+            .filter(|(x, _)| *x == page)
+            .flat_map(|(_, rects)| rects.iter())
+            .map(|x| (*x, SELECTION_COLOR, SELECTION_ALPHA));
+
+        /* Underlines are tinted on the bottom sixth of the line */
+        let annotated = self
+            .annotations
+            .iter()
+            .filter(|x| x.page == page)
+            .flat_map(|x| x.rects.iter().map(move |rect| (x.kind, *rect)))
+            .map(|(kind, (x0, y0, x1, y1))| match kind {
+                AnnotationKind::Highlight => ((x0, y0, x1, y1), kind.color(), 0.4),
+                AnnotationKind::Underline => {
+                    ((x0, y1 - (y1 - y0) / 6.0, x1, y1), kind.color(), 1.0)
+                }
+            });
+
+        for ((x0, y0, x1, y1), color, alpha) in annotated.chain(hits).chain(selected) {
+            let start = (a * x0 + c * y0 + e, b * x0 + d * y0 + f);
+            let end = (a * x1 + c * y1 + e, b * x1 + d * y1 + f);
+            let rect = (
+                f32::min(start.0, end.0),
+                f32::min(start.1, end.1),
+                f32::max(start.0, end.0),
+                f32::max(start.1, end.1),
+            );
+
+            tint_rect(pixmap.samples_mut(), (width, height, n), rect, color, alpha);
+        }
+    }
+
+    /* Underlines the identifiers in the same color as links are commonly printed */
+    fn underline(
+        &self,
+        pixmap: &mut Pixmap,
+        transform: &PageTransform,
+        precision: f64,
+        links: &[Link],
+    ) {
+        const UNDERLINE_COLOR: [u8; 3] = [0, 0, 238];
+
+        let [a, b, c, d, e, f] = transform.coefficients(precision as f32);
+        let (width, height, n) = (
+            pixmap.width() as usize,
+            pixmap.height() as usize,
+            pixmap.n() as usize,
+        );
+        let thickness = f32::max(1.0, precision as f32);
+
+        for link in links {
+            /* The bottom edge of the identifier in pixmap pixels */
+            let (x0, y0) = (link.bounds.x0, link.bounds.y1);
+            let (x1, y1) = (link.bounds.x1, link.bounds.y1);
+            let start = (a * x0 + c * y0 + e, b * x0 + d * y0 + f);
+            let end = (a * x1 + c * y1 + e, b * x1 + d * y1 + f);
+
+            /* Pages are rotated in quarter turns, so the edge is either horizontal
+             * or vertical */
+            let rect = if start.1 == end.1 {
+                (
+                    f32::min(start.0, end.0),
+                    start.1 - thickness / 2.0,
+                    f32::max(start.0, end.0),
+                    start.1 + thickness / 2.0,
+                )
+            } else {
+                (
+                    start.0 - thickness / 2.0,
+                    f32::min(start.1, end.1),
+                    start.0 + thickness / 2.0,
+                    f32::max(start.1, end.1),
+                )
+            };
+            tint_rect(
+                pixmap.samples_mut(),
+                (width, height, n),
+                rect,
+                UNDERLINE_COLOR,
+                1.0,
+            );
+        }
+    }
+
+    /* Stores the next page in the disk cache. Pages are stored before the color
+     * modes are applied, so that the cached pages stay valid when toggling them */
+    pub fn prerender_next(&mut self) -> Option<RendererResult> {
+        let (page, precision) = self.prerendering?;
+        let total = self.cache.len();
+
+        let disk_cache = match &self.disk_cache {
+            Some(x) if page < total => x,
+            _ => {
+                self.prerendering = None;
+                return None;
+            }
+        };
+        if page == 0 {
+            disk_cache.prune();
+        }
+
+        /* A page that can not be rendered is skipped, it is rendered when displayed */
+        let transform = &self.transforms[page];
+        if !disk_cache.contains(page, precision, self.alpha, transform) {
+            if let Ok(pixmap) = self.render(page, transform, precision) {
+                let _ = disk_cache.store(page, precision, self.alpha, transform, &pixmap);
+            }
+        }
+
+        self.prerendering = if page + 1 < total {
+            Some((page + 1, precision))
+        } else {
+            None
+        };
+
+        Some(RendererResult::Prerender {
+            done: page + 1,
+            total,
+        })
+    }
+
+    /* Diffs a rasterized page against the same page of the compared document */
+    fn differences(
+        &self,
+        page: usize,
+        pixmap: &Pixmap,
+        precision: f64,
+    ) -> Option<Vec<bool>> {
+        self.compare_file.as_ref()?;
+
+        /* The compared page is rendered through the same transform so that the
+         * pixels of both renders line up */
+        let other = self
+            .compare
+            .as_ref()
+            .and_then(|x| x.load_page(page as i32).ok())
+            .and_then(|x| self.transforms[page].render(&x, precision, self.alpha).ok());
+
+        Some(diff_tiles(
+            Samples::from_pixmap(pixmap),
+            other.as_ref().map(Samples::from_pixmap),
+        ))
+    }
+
+    /* Applies the color modes and the configured filters to a freshly rasterized
+     * pixmap */
+    pub fn post_process(&self, pixmap: &mut Pixmap) {
+        let n = pixmap.n() as usize;
+        self.inverse.apply(pixmap.samples_mut(), n);
+
+        if !self.config.viewer.filters.is_empty() {
+            apply_filters(pixmap.samples_mut(), n, &self.config.viewer.filters);
+        }
+
+        let viewer = &self.config.viewer;
+        if self.paper
+            && !(viewer.page_background.is_empty() && viewer.page_tint.is_empty())
+        {
+            apply_paper(
+                pixmap.samples_mut(),
+                n,
+                &viewer.page_background,
+                &viewer.page_tint,
+            );
+        }
+
+        if let Some(daltonize) = self.daltonize {
+            daltonize.apply(pixmap.samples_mut(), n);
+        }
+    }
+
+    /* Renders the image or math block under the given point so that it fills at most
+     * 80% of the terminal, 1 pixel of the pixmap corresponding to 1 terminal pixel */
+    pub fn zoom_block(
+        &self,
+        page: usize,
+        point: (f32, f32),
+    ) -> Result<RendererResult, String> {
+        const POPUP_SIZE: f32 = 0.8;
+        const POPUP_MAX_SCALE: f32 = 8.0;
+
+        let page = match self.cache.get(page) {
+            Some(x) => x,
+            None => return Ok(RendererResult::Popup(None)),
+        };
+
+        let bounds = match block_at(page, point)? {
+            Some(x) => x,
+            None => return Ok(RendererResult::Popup(None)),
+        };
+
+        let (terminal_width, terminal_height) = {
+            let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+            (terminal_size.width as f32, terminal_size.height as f32)
+        };
+
+        let scale = f32::min(
+            terminal_width * POPUP_SIZE / bounds.width(),
+            terminal_height * POPUP_SIZE / bounds.height(),
+        );
+        let scale = f32::min(scale, POPUP_MAX_SCALE);
+
+        let bbox = IRect::new(
+            (bounds.x0 * scale).floor() as i32,
+            (bounds.y0 * scale).floor() as i32,
+            (bounds.x1 * scale).ceil() as i32,
+            (bounds.y1 * scale).ceil() as i32,
+        );
+
+        let mut pixmap = Pixmap::new_with_rect(&self.cs, bbox, self.alpha)
+            .map_err(|x| format!("Could not allocate popup pixmap: {}", x))?;
+        let _ = pixmap.clear_with(if self.alpha { 0 } else { 255 });
+        {
+            let device = Device::from_pixmap(&pixmap)
+                .map_err(|x| format!("Could not create popup device: {}", x))?;
+            page.run(&device, &Matrix::new_scale(scale, scale))
+                .map_err(|x| format!("Could not render popup: {}", x))?;
+        }
+        self.post_process(&mut pixmap);
+
+        /* The popup is displayed at its own size which makes its precision irrelevant */
+        let image = Image::new(&pixmap, 1.0)?;
+        Ok(RendererResult::Popup(Some(Arc::new(RwLock::new(image)))))
+    }
+
+    /* Renders a region of a page with its colors inverted relative to the page */
+    pub fn invert_region(
+        &self,
+        page: usize,
+        crop: (f32, f32, f32, f32),
+        precision: f64,
+    ) -> Result<RendererResult, String> {
+        let transform = match (self.cache.get(page), self.transforms.get(page)) {
+            (Some(_), Some(x)) => x,
+            _ => return Err(format!("Page {} does not exist", page)),
+        };
+
+        /* The region keeps the rotation of its page */
+        let transform = PageTransform {
+            rotation: transform.rotation,
+            crop,
+        };
+        let mut pixmap = self.render(page, &transform, precision)?;
+        self.post_process(&mut pixmap);
+        invert(&mut pixmap);
+
+        let image = Image::new(&pixmap, precision)?;
+        Ok(RendererResult::Region {
+            page,
+            crop,
+            data: Arc::new(RwLock::new(image)),
         })
     }
 }
 
+fn invert(pixmap: &mut Pixmap) {
+    let n = pixmap.n() as usize;
+    for pixel in pixmap.samples_mut().chunks_mut(n) {
+        pixel[0] = 255 - pixel[0];
+        pixel[1] = 255 - pixel[1];
+        pixel[2] = 255 - pixel[2];
+    }
+}
+
+/** The thread that renders the pages of a document. The requests go through the
+ * `Renderer` and the rendered pages come back through the receiver of `Renderer::new`,
+ * usually to a `Viewer` */
 pub struct Renderer {
     thread_render: Option<JoinHandle<Result<(), String>>>,
 
@@ -164,8 +1007,9 @@ impl Renderer {
         )
     }
 
-    pub fn run(&mut self, file_input: &str) -> Result<(), String> {
+    pub fn run(&mut self, file_input: &str, compare: Option<&str>) -> Result<(), String> {
         let file_string = file_input.to_owned();
+        let compare_string = compare.map(|x| x.to_owned());
         let priority_server_receiver = self.priority_server_receiver.clone();
         let general_server_sender = self.general_server_sender.clone();
         let result_server_sender = self.result_server_sender.clone();
@@ -175,31 +1019,67 @@ impl Renderer {
             let general_server_sender = general_server_sender;
             let result_server_sender = result_server_sender;
 
-            let mut state = RendererInnerState::new(file_string)?;
+            lower_current_thread();
+
+            let mut state = RendererInnerState::new(file_string, compare_string)?;
 
             let mut sel = priority_server_receiver.construct_biased_select();
 
             while RUNNING.load(Ordering::Acquire) {
+                /* Prerendering continues one page at a time while no other actions
+                 * are waiting so that displaying pages is never blocked by it. A
+                 * failed load is retried when no action has arrived until its time */
+                let ready = match (state.prerendering, state.reload_retry) {
+                    (Some(_), _) => sel.try_ready().ok(),
+                    (None, Some((at, _))) => sel.ready_deadline(at).ok(),
+                    (None, None) => Some(sel.ready()),
+                };
+                let ready = match ready {
+                    Some(x) => x,
+                    None => {
+                        if state.reload_retry.is_some_and(|x| Instant::now() >= x.0) {
+                            if let Some(result) = state.reload() {
+                                if matches!(result, RendererResult::PageMetadata { .. }) {
+                                    priority_server_receiver.clear_priority(1);
+                                }
+                                result_server_sender
+                                    .try_send_priority(result, 0)
+                                    .map_err(|x| {
+                                        format!("Could not send results to client: {}", x)
+                                    })?;
+                            }
+                        } else if let Some(result) = state.prerender_next() {
+                            result_server_sender.try_send_priority(result, 0).map_err(
+                                |x| format!("Could not send results to client: {}", x),
+                            )?;
+                        }
+                        continue;
+                    }
+                };
+
                 let action = priority_server_receiver
-                    .recv_priority(sel.ready())
+                    .recv_priority(ready)
                     .map_err(|x| format!("Could not receive from client: {}", x))?;
 
-                match action {
-                    RendererAction::Display(_) => (),
-                    _ => {
-                        general_server_sender.try_send(action).map_err(|x| {
-                            format!("Could not send action to client: {}", x)
-                        })?;
-                    }
+                if action.confirmed() {
+                    general_server_sender
+                        .try_send(action.clone())
+                        .map_err(|x| format!("Could not send action to client: {}", x))?;
                 }
 
                 match action {
                     RendererAction::Load => {
                         priority_server_receiver.clear_priority(0);
-                        let result = state.load()?;
+                        /* Another change starts the retries over */
+                        state.reload_retry = None;
+                        let Some(result) = state.reload() else {
+                            continue;
+                        };
 
                         // Clear the scheduled pages for rendering
-                        priority_server_receiver.clear_priority(1);
+                        if matches!(result, RendererResult::PageMetadata { .. }) {
+                            priority_server_receiver.clear_priority(1);
+                        }
 
                         result_server_sender.try_send_priority(result, 0).map_err(
                             |x| format!("Could not send results to client: {}", x),
@@ -211,13 +1091,178 @@ impl Renderer {
                         // Clear the scheduled pages for rendering
                         priority_server_receiver.clear_priority(1);
                     }
-                    RendererAction::ToggleInverse => {
-                        state.inverse = !state.inverse;
+                    RendererAction::SetInverse(inverse) => {
+                        state.inverse = inverse;
 
                         // Clear the scheduled pages for rendering
                         priority_server_receiver.clear_priority(1);
                     }
-                    RendererAction::Display(page) => {
+                    RendererAction::ToggleDiff => {
+                        state.diff = !state.diff;
+
+                        // Clear the scheduled pages for rendering
+                        priority_server_receiver.clear_priority(1);
+                    }
+                    RendererAction::TogglePaper => {
+                        state.paper = !state.paper;
+
+                        // Clear the scheduled pages for rendering
+                        priority_server_receiver.clear_priority(1);
+                    }
+                    RendererAction::SetDaltonize(daltonize) => {
+                        state.daltonize = daltonize;
+
+                        // Clear the scheduled pages for rendering
+                        priority_server_receiver.clear_priority(1);
+                    }
+                    RendererAction::Captions => {
+                        let result = state.captions();
+
+                        result_server_sender.try_send_priority(result, 0).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::ZoomBlock(page, x, y) => {
+                        let result = match state.zoom_block(page, (x, y)) {
+                            Ok(x) => x,
+                            Err(_) => continue,
+                        };
+
+                        result_server_sender.try_send_priority(result, 1).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::InvertRegion(page, crop, precision) => {
+                        let result = state
+                            .invert_region(page, crop, precision)
+                            .unwrap_or_else(|message| RendererResult::RegionFailed {
+                                page,
+                                crop,
+                                message,
+                            });
+
+                        result_server_sender.try_send_priority(result, 1).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::Search(query) => {
+                        let result = state.search(&query);
+
+                        // Clear the scheduled pages for rendering
+                        priority_server_receiver.clear_priority(1);
+
+                        result_server_sender.try_send_priority(result, 0).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::Select(selection) => {
+                        let Some(result) = state.select(selection) else {
+                            continue;
+                        };
+
+                        result_server_sender.try_send_priority(result, 0).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::FinishSelection => {
+                        let result = RendererResult::SelectionFinished(
+                            state.selection_text.clone(),
+                        );
+                        result_server_sender.try_send_priority(result, 0).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::Prerender(precision) => {
+                        state.prerendering = Some((0, precision));
+                    }
+                    RendererAction::ExtractText(page) => {
+                        let Some(data) = state.cache.get(page) else {
+                            continue;
+                        };
+                        /* Pages without extractable text are shown as empty */
+                        let paragraphs = page_paragraphs(data).unwrap_or_default();
+
+                        result_server_sender
+                            .try_send_priority(
+                                RendererResult::Text { page, paragraphs },
+                                0,
+                            )
+                            .map_err(|x| {
+                                format!("Could not send results to client: {}", x)
+                            })?;
+                    }
+                    RendererAction::Stamp(pages, out) => {
+                        let message =
+                            match stamp::stamp_document(&state.file, &out, pages) {
+                                Ok(x) => format!("Stamped {} pages into {}", x, out),
+                                Err(x) => x,
+                            };
+
+                        result_server_sender
+                            .try_send_priority(RendererResult::Message(message), 0)
+                            .map_err(|x| {
+                                format!("Could not send results to client: {}", x)
+                            })?;
+                    }
+                    RendererAction::Annotate(kind) => {
+                        let result = state.annotate(kind);
+
+                        result_server_sender.try_send_priority(result, 0).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::UndoAnnotation => {
+                        let result = state.undo_annotation();
+
+                        result_server_sender.try_send_priority(result, 0).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::SaveAnnotations => {
+                        let result = state.save_annotations();
+
+                        result_server_sender.try_send_priority(result, 0).map_err(
+                            |x| format!("Could not send results to client: {}", x),
+                        )?;
+                    }
+                    RendererAction::ExportPage(page) => {
+                        let message = match state.export_page(page) {
+                            Ok(x) => format!("Exported page {} to {}", page + 1, x),
+                            Err(x) => x,
+                        };
+
+                        result_server_sender
+                            .try_send_priority(RendererResult::Message(message), 0)
+                            .map_err(|x| {
+                                format!("Could not send results to client: {}", x)
+                            })?;
+                    }
+                    RendererAction::Preview(page) => {
+                        if state.cache.get(page).is_none() {
+                            continue;
+                        }
+
+                        let precision = CONFIG.get().unwrap().viewer.preview_precision;
+                        let Ok(data) = state.rasterize(page, precision) else {
+                            continue;
+                        };
+                        let Ok(image) = Image::new(&data, precision) else {
+                            continue;
+                        };
+
+                        result_server_sender
+                            .try_send_priority(
+                                RendererResult::ImagePreview {
+                                    page,
+                                    data: Arc::new(RwLock::new(image)),
+                                },
+                                1,
+                            )
+                            .map_err(|x| {
+                                format!("Could not send results to client: {}", x)
+                            })?;
+                    }
+                    RendererAction::Display(page, precision) => {
                         if state.cache.get(page).is_none() {
                             // Sending `None` as data signals that it should be
                             // removed from the registry
@@ -232,34 +1277,36 @@ impl Renderer {
                             continue;
                         }
 
-                        /* Load the image */
-                        let data = state.cache[page].to_pixmap(
-                            &state.ctm,
-                            &state.cs,
-                            state.alpha,
-                            false,
-                        );
-
-                        if data.is_err() {
-                            continue;
+                        if let Some(links) = state.extract_identifiers(page) {
+                            result_server_sender
+                                .try_send_priority(
+                                    RendererResult::Identifiers { page, links },
+                                    1,
+                                )
+                                .map_err(|x| {
+                                    format!("Could not send results to client: {}", x)
+                                })?;
                         }
 
-                        let mut data_unwrapped = data.unwrap();
-                        let n = data_unwrapped.n() as usize;
-                        if state.inverse {
-                            for pixel in data_unwrapped.samples_mut().chunks_mut(n) {
-                                pixel[0] = 255 - pixel[0];
-                                pixel[1] = 255 - pixel[1];
-                                pixel[2] = 255 - pixel[2];
+                        /* Load the image. The viewer is told about pages that could
+                         * not be rendered so that it can request them again */
+                        let image = state
+                            .rasterize(page, precision)
+                            .and_then(|x| Image::new(&x, precision));
+                        let image = match image {
+                            Ok(x) => x,
+                            Err(message) => {
+                                result_server_sender
+                                    .try_send_priority(
+                                        RendererResult::RenderFailed { page, message },
+                                        1,
+                                    )
+                                    .map_err(|x| {
+                                        format!("Could not send results to client: {}", x)
+                                    })?;
+                                continue;
                             }
-                        }
-
-                        let res = Image::new(&data_unwrapped);
-                        if res.is_err() {
-                            continue;
-                        }
-
-                        let image = res.unwrap();
+                        };
                         result_server_sender
                             .try_send_priority(
                                 RendererResult::Image {
@@ -289,34 +1336,28 @@ impl Renderer {
     }
 
     pub fn send_action(&self, action: RendererAction) -> Result<(), String> {
-        match action {
-            RendererAction::Load
-            | RendererAction::ToggleAlpha
-            | RendererAction::ToggleInverse => {
-                Err("Cannot wait for Load, Alpha and Inverse".to_string())?
-            }
-            RendererAction::Display(_) => self
-                .priority_client_sender
-                .try_send_priority(action, 0)
-                .map_err(|x| format!("Could not send action to renderer: {}", x))?,
+        if action.confirmed() {
+            Err("Cannot send confirmed actions without waiting".to_string())?
         }
 
+        self.priority_client_sender
+            .try_send_priority(action, 0)
+            .map_err(|x| format!("Could not send action to renderer: {}", x))?;
+
         Ok(())
     }
 
     pub fn send_and_confirm_action(&self, action: RendererAction) -> Result<(), String> {
         /* Sends an action to the renderer thread and waits until the thread confirms
          * that the action has been accepted and scheduled */
-        match action {
-            RendererAction::Load
-            | RendererAction::ToggleAlpha
-            | RendererAction::ToggleInverse => self
-                .priority_client_sender
-                .try_send_priority(action, 0)
-                .map_err(|x| format!("Could not send action to renderer: {}", x))?,
-            RendererAction::Display(_) => Err("Cannot wait for display".to_string())?,
+        if !action.confirmed() {
+            Err("Cannot wait for unconfirmed actions".to_string())?
         }
 
+        self.priority_client_sender
+            .try_send_priority(action.clone(), 0)
+            .map_err(|x| format!("Could not send action to renderer: {}", x))?;
+
         let result = self
             .general_client_receiver
             .recv()
@@ -0,0 +1,355 @@
+use std::{
+    ffi::c_int,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::process::CommandExt,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+use mupdf::{Colorspace, Pixmap};
+
+use crate::{
+    document::open_document,
+    globals::{CONFIG, CONFIG_FILE, SAFE_MODE, SANDBOX},
+    transform::PageTransform,
+};
+
+/* The renderer process with the pipes its requests and answers go through. The
+ * process is shared with the watchdog of `--sandbox` */
+type Process = (Arc<Mutex<Child>>, ChildStdin, BufReader<ChildStdout>);
+
+/* Bytes of address space of the restricted processes of `--sandbox` */
+const SANDBOX_MEMORY: u64 = 2 * 1024 * 1024 * 1024;
+/* A page that takes longer to render in a restricted process is taken as an attempt
+ * to hang the viewer */
+pub const SANDBOX_PAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(target_os = "linux")]
+const RLIMIT_AS: c_int = 9;
+#[cfg(target_os = "macos")]
+const RLIMIT_AS: c_int = 5;
+
+#[cfg(target_os = "linux")]
+type Rlim = std::ffi::c_ulong;
+#[cfg(target_os = "macos")]
+type Rlim = u64;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[repr(C)]
+struct Rlimit {
+    current: Rlim,
+    maximum: Rlim,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+extern "C" {
+    fn setrlimit(resource: c_int, limit: *const Rlimit) -> c_int;
+}
+
+/* Whether the pages are rendered by a `RenderWorker`, which `--sandbox` always does */
+pub fn isolated() -> bool {
+    CONFIG.get().unwrap().viewer.isolate_renderer || SANDBOX.load(Ordering::Acquire)
+}
+
+/* Starts the command as a restricted process of `--sandbox`, with an empty
+ * environment and limited memory. The process is not started when the limit can not
+ * be applied. Platforms without the limit get only the empty environment, the time a
+ * page takes is limited by the viewer waiting for it */
+pub fn restrict(command: &mut Command) {
+    command.env_clear();
+
+    /* Runs in the child between fork and exec, where only async-signal-safe calls
+     * such as setrlimit may be made */
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    unsafe {
+        command.pre_exec(|| {
+            let limit = Rlimit {
+                current: SANDBOX_MEMORY as Rlim,
+                maximum: SANDBOX_MEMORY as Rlim,
+            };
+            match setrlimit(RLIMIT_AS, &limit) {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            }
+        });
+    }
+}
+
+/* Kills the process when the page has not been answered within `timeout`. The
+ * returned sender is dropped once it has been answered */
+fn watchdog(child: &Arc<Mutex<Child>>, timeout: Duration) -> Sender<()> {
+    let child = child.clone();
+    let (answered, waiting) = bounded::<()>(0);
+    std::thread::spawn(move || {
+        /* Killed and reaped under the same lock, a process that has been reaped in
+         * the meantime is not signaled again */
+        if let Err(RecvTimeoutError::Timeout) = waiting.recv_timeout(timeout) {
+            let _ = child.lock().unwrap().kill();
+        }
+    });
+    answered
+}
+
+/* A page to render as sent to the process, such as
+ * `render 3 1.5 0 90 0 0 612 792` for the zero-indexed page, the precision, the
+ * alpha, the rotation and the crop of the transform */
+fn format_request(
+    page: usize,
+    transform: &PageTransform,
+    precision: f64,
+    alpha: bool,
+) -> String {
+    let (x0, y0, x1, y1) = transform.crop;
+    format!(
+        "render {} {} {} {} {} {} {} {}",
+        page, precision, alpha as u8, transform.rotation, x0, y0, x1, y1
+    )
+}
+
+fn parse_request(line: &str) -> Option<(usize, PageTransform, f64, bool)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let ["render", page, precision, alpha, rotation, x0, y0, x1, y1] = words[..] else {
+        return None;
+    };
+
+    let crop = (
+        x0.parse().ok()?,
+        y0.parse().ok()?,
+        x1.parse().ok()?,
+        y1.parse().ok()?,
+    );
+    let transform = PageTransform {
+        rotation: rotation.parse().ok()?,
+        crop,
+    };
+    Some((
+        page.parse().ok()?,
+        transform,
+        precision.parse().ok()?,
+        alpha == "1",
+    ))
+}
+
+/* The line before the samples of a rendered page, `ok <width> <height> <n>`, or the
+ * error of a page that could not be rendered, `err <message>` */
+fn parse_header(line: &str) -> Result<(usize, usize, usize), String> {
+    let line = line.trim_end();
+    if let Some(message) = line.strip_prefix("err ") {
+        return Err(message.to_string());
+    }
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+        ["ok", width, height, n] => match (width.parse(), height.parse(), n.parse()) {
+            (Ok(width), Ok(height), Ok(n)) => Ok((width, height, n)),
+            _ => Err(format!("Invalid answer of the renderer process `{}`", line)),
+        },
+        _ => Err(format!("Invalid answer of the renderer process `{}`", line)),
+    }
+}
+
+/* Answers the requests on stdin with the rendered pages on stdout. Run by the hidden
+ * `--render-worker` mode in the process of `RenderWorker` */
+pub fn serve(file: &str) -> Result<(), String> {
+    let document = open_document(file)?;
+    let mut stdout = io::stdout().lock();
+
+    for line in io::stdin().lock().lines() {
+        let line = line.map_err(|x| format!("Could not read the request: {}", x))?;
+        let rendered = match parse_request(&line) {
+            Some((page, transform, precision, alpha)) => document
+                .load_page(page as i32)
+                .map_err(|x| format!("Could not load page {}: {}", page + 1, x))
+                .and_then(|x| transform.render(&x, precision, alpha)),
+            None => Err(format!("Invalid request `{}`", line)),
+        };
+
+        let written = match rendered {
+            Ok(pixmap) => {
+                let (width, height, n) = (pixmap.width(), pixmap.height(), pixmap.n());
+                writeln!(stdout, "ok {} {} {}", width, height, n)
+                    .and_then(|_| stdout.write_all(pixmap.samples()))
+            }
+            /* The error has to stay on its line */
+            Err(x) => writeln!(stdout, "err {}", x.replace('\n', " ")),
+        };
+        written
+            .and_then(|_| stdout.flush())
+            .map_err(|x| format!("Could not answer the request: {}", x))?;
+    }
+
+    Ok(())
+}
+
+/* Renders the pages of a document in a child process, so that mupdf crashing on a
+ * malformed page takes down the child instead of the viewer. The process is started
+ * when the first page is rendered and again after it has crashed */
+pub struct RenderWorker {
+    file: String,
+    process: Option<Process>,
+}
+
+impl RenderWorker {
+    pub fn new(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            process: None,
+        }
+    }
+
+    /* Runs the viewer itself with the config it has been started with, since
+     * reflowable documents are laid out by it */
+    fn spawn(&self) -> Result<Process, String> {
+        let program = std::env::current_exe()
+            .map_err(|x| format!("Could not find the renderer process: {}", x))?;
+
+        let mut command = Command::new(program);
+        if SAFE_MODE.load(Ordering::Acquire) {
+            command.arg("--safe-mode");
+        }
+        if let Some(config) = CONFIG_FILE.get() {
+            command.arg("--config").arg(config);
+        }
+        if SANDBOX.load(Ordering::Acquire) {
+            restrict(&mut command);
+        }
+        let mut child = command
+            .arg("--render-worker")
+            .arg(&self.file)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|x| format!("Could not start the renderer process: {}", x))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok((Arc::new(Mutex::new(child)), stdin, stdout))
+    }
+
+    /* Ends the process, the next page starts a new one with the document as it is
+     * then */
+    pub fn restart(&mut self) {
+        if let Some((child, _, _)) = self.process.take() {
+            let mut child = child.lock().unwrap();
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    pub fn render(
+        &mut self,
+        page: usize,
+        transform: &PageTransform,
+        precision: f64,
+        alpha: bool,
+    ) -> Result<Pixmap, String> {
+        if self.process.is_none() {
+            self.process = Some(self.spawn()?);
+        }
+        let request = format_request(page, transform, precision, alpha);
+        let process = self.process.as_mut().unwrap();
+        let answered = SANDBOX
+            .load(Ordering::Acquire)
+            .then(|| watchdog(&process.0, SANDBOX_PAGE_TIMEOUT));
+        let answer = exchange(process, &request);
+        drop(answered);
+
+        /* The process is gone when it can not be written to or read from, and ended
+         * when its answer is invalid */
+        let (width, height, n, samples) = match answer {
+            Ok(x) => x?,
+            Err(x) => {
+                let (child, _, _) = self.process.take().unwrap();
+                let mut child = child.lock().unwrap();
+                let _ = child.kill();
+                let status = child
+                    .wait()
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|x| x.to_string());
+                return Err(match x.kind() {
+                    io::ErrorKind::InvalidData => format!("{} on page {}", x, page + 1),
+                    _ => format!(
+                        "The renderer has crashed on page {} with {}",
+                        page + 1,
+                        status
+                    ),
+                });
+            }
+        };
+
+        let mut pixmap = Pixmap::new_with_w_h(
+            &Colorspace::device_rgb(),
+            width as i32,
+            height as i32,
+            n == 4,
+        )
+        .map_err(|x| format!("Could not allocate page pixmap: {}", x))?;
+        if pixmap.samples().len() != samples.len() {
+            return Err(format!("Page {} has arrived with the wrong size", page + 1));
+        }
+        pixmap.samples_mut().copy_from_slice(&samples);
+        Ok(pixmap)
+    }
+}
+
+/* Sends the request to the process and reads its answer, the width, the height, the
+ * number of components and the samples of the page. Pages that could not be rendered
+ * are the inner error, an invalid answer is `InvalidData` */
+#[allow(clippy::type_complexity)]
+fn exchange(
+    (_, stdin, stdout): &mut Process,
+    request: &str,
+) -> io::Result<Result<(usize, usize, usize, Vec<u8>), String>> {
+    writeln!(stdin, "{}", request)?;
+    stdin.flush()?;
+
+    let mut header = String::new();
+    if stdout.read_line(&mut header)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (width, height, n) = match parse_header(&header) {
+        Ok(x) => x,
+        Err(x) if header.starts_with("err ") => return Ok(Err(x)),
+        /* The samples that may follow an invalid answer would be read as the answers
+         * to the next pages */
+        Err(x) => return Err(io::Error::new(io::ErrorKind::InvalidData, x)),
+    };
+
+    let mut samples = vec![0u8; width * height * n];
+    stdout.read_exact(&mut samples)?;
+    Ok(Ok((width, height, n, samples)))
+}
+
+impl Drop for RenderWorker {
+    fn drop(&mut self) {
+        self.restart();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol() {
+        let transform = PageTransform {
+            rotation: 90,
+            crop: (0.0, 10.5, 612.0, 792.0),
+        };
+        let request = format_request(3, &transform, 1.5, true);
+        assert!(request == "render 3 1.5 1 90 0 10.5 612 792");
+        assert!(parse_request(&request) == Some((3, transform, 1.5, true)));
+        assert!(parse_request("render 3 1.5").is_none());
+        assert!(parse_request("render x 1.5 0 0 0 0 1 1").is_none());
+
+        assert!(parse_header("ok 100 200 3\n") == Ok((100, 200, 3)));
+        assert!(
+            parse_header("err Could not load page 2\n")
+                == Err("Could not load page 2".to_string())
+        );
+        assert!(parse_header("ok 100").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use mupdf::{Colorspace, Device, IRect, Matrix, Page, Pixmap};
+
+/* Maps the points of a page given in page units to the displayed page given in
+ * unscaled display units and back. The renderer rasterizes pages through `matrix`
+ * and the viewer hit-tests through `to_page`, so that both always agree on where
+ * the content of a page is displayed */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageTransform {
+    /* Clockwise rotation in degrees, one of 0, 90, 180 and 270 */
+    pub rotation: u32,
+    /* The displayed part of the page as (x0, y0, x1, y1) in page units */
+    pub crop: (f32, f32, f32, f32),
+}
+
+impl PageTransform {
+    /* Displays the given bounds of a page without rotating them */
+    pub fn new(bounds: (f32, f32, f32, f32)) -> Self {
+        Self {
+            rotation: 0,
+            crop: bounds,
+        }
+    }
+
+    fn crop_size(&self) -> (f32, f32) {
+        (self.crop.2 - self.crop.0, self.crop.3 - self.crop.1)
+    }
+
+    /* Size of the displayed page in unscaled display units */
+    pub fn size(&self) -> (f32, f32) {
+        let (w, h) = self.crop_size();
+        match self.rotation {
+            90 | 270 => (h, w),
+            _ => (w, h),
+        }
+    }
+
+    /* The affine coefficients (a, b, c, d, e, f) mapping page units to display units
+     * at the given scale, where x' = a*x + c*y + e and y' = b*x + d*y + f */
+    pub fn coefficients(&self, scale: f32) -> [f32; 6] {
+        let (w, h) = self.crop_size();
+        let (x0, y0) = (self.crop.0, self.crop.1);
+
+        /* Rotation of the cropped page with its origin at the top left corner */
+        let [a, b, c, d, e, f] = match self.rotation {
+            90 => [0.0, 1.0, -1.0, 0.0, h + y0, -x0],
+            180 => [-1.0, 0.0, 0.0, -1.0, w + x0, h + y0],
+            270 => [0.0, -1.0, 1.0, 0.0, -y0, w + x0],
+            _ => [1.0, 0.0, 0.0, 1.0, -x0, -y0],
+        };
+
+        [
+            a * scale,
+            b * scale,
+            c * scale,
+            d * scale,
+            e * scale,
+            f * scale,
+        ]
+    }
+
+    pub fn matrix(&self, scale: f32) -> Matrix {
+        let [a, b, c, d, e, f] = self.coefficients(scale);
+        Matrix::new(a, b, c, d, e, f)
+    }
+
+    pub fn to_display(&self, point: (f32, f32)) -> (f32, f32) {
+        let [a, b, c, d, e, f] = self.coefficients(1.0);
+        (a * point.0 + c * point.1 + e, b * point.0 + d * point.1 + f)
+    }
+
+    /* Inverse of `to_display` */
+    pub fn to_page(&self, point: (f32, f32)) -> (f32, f32) {
+        let [a, b, c, d, e, f] = self.coefficients(1.0);
+        let (x, y) = (point.0 - e, point.1 - f);
+
+        /* The rotations are orthonormal so the inverse is the transpose */
+        (a * x + b * y, c * x + d * y)
+    }
+
+    /* Rasterizes the displayed part of a page at the given precision */
+    pub fn render(
+        &self,
+        page: &Page,
+        precision: f64,
+        alpha: bool,
+    ) -> Result<Pixmap, String> {
+        let (width, height) = self.size();
+        let bbox = IRect::new(
+            0,
+            0,
+            (width * precision as f32).ceil() as i32,
+            (height * precision as f32).ceil() as i32,
+        );
+
+        let mut pixmap = Pixmap::new_with_rect(&Colorspace::device_rgb(), bbox, alpha)
+            .map_err(|x| format!("Could not allocate page pixmap: {}", x))?;
+        let _ = pixmap.clear_with(if alpha { 0 } else { 255 });
+        {
+            let device = Device::from_pixmap(&pixmap)
+                .map_err(|x| format!("Could not create page device: {}", x))?;
+            page.run(&device, &self.matrix(precision as f32))
+                .map_err(|x| format!("Could not render page: {}", x))?;
+        }
+
+        Ok(pixmap)
+    }
+
+    /* Identifies the transform in the names of cached renders */
+    pub fn key(&self) -> String {
+        format!(
+            "{}_{}_{}_{}_{}",
+            self.rotation, self.crop.0, self.crop.1, self.crop.2, self.crop.3
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_transform() {
+        let mut transform = PageTransform::new((10.0, 20.0, 110.0, 220.0));
+
+        for rotation in [0, 90, 180, 270] {
+            transform.rotation = rotation;
+
+            let point = (30.0, 50.0);
+            let displayed = transform.to_display(point);
+            let (width, height) = transform.size();
+            assert!(displayed.0 >= 0.0 && displayed.0 <= width);
+            assert!(displayed.1 >= 0.0 && displayed.1 <= height);
+            assert!(transform.to_page(displayed) == point);
+        }
+
+        /* The top left corner of the crop ends up in the top right when rotated */
+        transform.rotation = 90;
+        assert!(transform.to_display((10.0, 20.0)) == (200.0, 0.0));
+    }
+}
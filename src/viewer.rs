@@ -1,20 +1,35 @@
 use core::f32;
 use std::{
-    collections::{HashMap, VecDeque},
-    io,
+    collections::HashMap,
+    io::{self, Write},
     sync::{Arc, RwLock},
+    thread,
+    time::{Duration, Instant},
 };
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossbeam_channel::Sender;
 use crossterm::{
-    cursor::{MoveToRow, RestorePosition, SavePosition},
+    cursor::{MoveTo, MoveToRow, RestorePosition, SavePosition},
     event::MouseEvent,
-    execute,
+    execute, queue,
     style::{Print, ResetColor, SetBackgroundColor, SetForegroundColor},
 };
 use mupdf::Link;
 
-use crate::{threads::renderer::*, Image, CONFIG, TERMINAL_SIZE};
+use crate::{
+    config::ConfigPageLayout,
+    glide::{Glide, Momentum},
+    image::{Z_INDEX_POPUP, Z_INDEX_REGION},
+    memory::MemoryManager,
+    outline::parse_link_target,
+    text::{wrap_paragraphs, Caption, CaptionKind},
+    threads::{
+        event::{self, AppEvent},
+        renderer::*,
+    },
+    transform::PageTransform,
+    Image, CONFIG, TERMINAL_SIZE,
+};
 
 #[derive(Clone, Copy, Debug)]
 pub struct DisplayRect {
@@ -36,6 +51,19 @@ pub trait Rect {
     fn height(&self) -> i32;
 }
 
+/* The match that a search jumps to while none is selected, the first one from the
+ * current offset on going forward and the last one before it going back. The matches
+ * wrap around at the ends of the document */
+fn first_match(offsets: &[f32], offset: f32, forward: bool) -> usize {
+    match forward {
+        true => offsets.iter().position(|x| *x >= offset).unwrap_or(0),
+        false => offsets
+            .iter()
+            .rposition(|x| *x < offset)
+            .unwrap_or(offsets.len().saturating_sub(1)),
+    }
+}
+
 fn rect_point_intersect<R: Rect, P: Point>(rect: &R, point: &P) -> bool {
     let rect_x = rect.x();
     let rect_y = rect.y();
@@ -107,48 +135,260 @@ impl Rect for mupdf::rect::Rect {
     }
 }
 
+/* Shows in the bottom right corner that the renderer has fallen behind or is
+ * prerendering so that slow renders of heavy documents do not look like a hang */
+pub fn status_hint(
+    queued: usize,
+    prerendered: Option<(usize, usize)>,
+    removed: bool,
+    rebuilding: bool,
+) {
+    let config = CONFIG.get().unwrap();
+
+    let mut status = Vec::new();
+    if removed {
+        status.push("file removed, waiting for it to reappear…".to_string());
+    } else if rebuilding {
+        status.push("document is being rebuilt…".to_string());
+    }
+    if let Some((done, total)) = prerendered {
+        status.push(format!("prerendering… ({}/{} pages)", done, total));
+    }
+    if queued >= config.viewer.queue_hint_threshold {
+        status.push(format!("rendering… ({} pages queued)", queued));
+    }
+    if status.is_empty() {
+        return;
+    }
+
+    let text = status.join(" ");
+    let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+    let column = terminal_size
+        .columns
+        .saturating_sub(text.chars().count() as u16);
+
+    execute!(
+        io::stdout(),
+        SavePosition,
+        MoveTo(column, terminal_size.rows),
+        SetBackgroundColor(config.viewer.uri_hint.background),
+        SetForegroundColor(config.viewer.uri_hint.foreground),
+        Print(text),
+        ResetColor,
+        RestorePosition
+    )
+    .unwrap();
+}
+
+/* Drawn in place of the pages while the terminal is resized, until its size settles */
+pub fn resize_placeholder((columns, rows): (u16, u16)) {
+    let config = CONFIG.get().unwrap();
+    let text = "resizing…";
+    let column = columns.saturating_sub(text.chars().count() as u16) / 2;
+
+    execute!(
+        io::stdout(),
+        SavePosition,
+        MoveTo(column, rows / 2),
+        SetBackgroundColor(config.viewer.uri_hint.background),
+        SetForegroundColor(config.viewer.uri_hint.foreground),
+        Print(text),
+        ResetColor,
+        RestorePosition
+    )
+    .unwrap();
+}
+
+/* A rectangle of a page given in page units that is displayed inverted */
+struct InvertedRegion {
+    page: usize,
+    crop: (f32, f32, f32, f32),
+    image: Option<Arc<RwLock<Image>>>,
+    invalidated: bool,
+    scheduled: bool,
+    /* Times the region has failed to render */
+    failures: u32,
+}
+
+/* The previews on the screen with the generation of their page that they show. The
+ * generations count the changes of the full renders of every page, so that only the
+ * previews of the pages that have changed are refreshed */
+#[derive(Default)]
+struct Previews {
+    shown: HashMap<usize, u64>,
+    generations: HashMap<usize, u64>,
+}
+
+impl Previews {
+    fn generation(&self, page: usize) -> u64 {
+        self.generations.get(&page).copied().unwrap_or(0)
+    }
+
+    fn contains(&self, page: usize) -> bool {
+        self.shown.contains_key(&page)
+    }
+
+    fn show(&mut self, page: usize) {
+        self.shown.insert(page, self.generation(page));
+    }
+
+    /* Returns whether the page has shown a preview */
+    fn remove(&mut self, page: usize) -> bool {
+        self.shown.remove(&page).is_some()
+    }
+
+    fn change(&mut self, page: usize) {
+        *self.generations.entry(page).or_default() += 1;
+    }
+
+    /* The pages whose previews show an older generation */
+    fn stale(&self) -> Vec<usize> {
+        self.shown
+            .iter()
+            .filter(|(page, shown)| **shown < self.generation(**page))
+            .map(|(page, _)| *page)
+            .collect()
+    }
+}
+
+/* A position in the document that marks and the jump list return to */
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Position {
+    offset: (f32, f32),
+    scale: f32,
+}
+
+/* Positions kept in the jump list before the oldest ones are dropped */
+const JUMP_LIST_LIMIT: usize = 100;
+/* Transfers of a page whose placement keeps failing before an error is shown on it
+ * instead, waiting twice as long before every retry */
+const TRANSFER_RETRIES: u32 = 5;
+const TRANSFER_BACKOFF: Duration = Duration::from_millis(50);
+/* Renders of a page that has failed, e.g. by crashing the renderer process, which are
+ * requested again before an error is shown on it instead */
+const RENDER_RETRIES: u32 = 2;
+
+/** The pages of one document as they are laid out on the screen, together with the
+ * state of the pane showing them: the scroll position, the search matches and the
+ * selections */
 pub struct Viewer {
     scale: f32,
-    page_first: usize,  /* The first page in the view */
-    page_view: usize,   /* The page in the middle */
-    offset: (f32, f32), /* Offset is given in page size units ≈ pixels */
+    page_first: usize, /* The first page in the view */
+    page_view: usize,  /* The page in the middle */
+    /* Offset along the pages is given in page size units ≈ pixels, the one across
+     * them in terminal pixels */
+    offset: (f32, f32),
+    pane: (usize, usize), /* Index of this viewer's pane and the number of panes */
+    /* The precision of the zoom band that the scale lies in */
+    precision: f64,
+    /* Precision of a changed zoom and since when it has not changed */
+    precision_pending: Option<(f64, Instant)>,
+    /* Page and scale to start at once the document has been loaded */
+    start: Option<(usize, Option<f32>)>,
 
     max_width: f32,
     cumulative_heights: Vec<f32>,
     widths: Vec<f32>,
+    /* End of every page along the layout including the margin after it, and the
+     * largest page size across the layout */
+    cumulative_extents: Vec<f32>,
+    max_across: f32,
     links: Vec<Vec<Link>>,
+    transforms: Vec<PageTransform>,
+    /* Extracted lazily on the first caption jump, `None` until then */
+    captions: Option<Vec<Caption>>,
+    /* Direction of a caption jump waiting for the captions to be extracted */
+    caption_pending: Option<bool>,
+    /* Set when scrolling forward while the end of the document is already shown */
+    past_end: bool,
+    /* Running animation of `scroll_smooth` */
+    glide: Glide,
+    /* Running kinetic scroll of `fling` */
+    momentum: Momentum,
+    /* High precision render of a single block displayed above the pages */
+    popup: Option<Arc<RwLock<Image>>>,
+    /* Progress of a running prerender given as done and total pages */
+    prerendered: Option<(usize, usize)>,
+    /* Matches of the current search and the one that has been jumped to last */
+    search: Vec<SearchHit>,
+    search_index: Option<usize>,
+    /* Regions displayed inverted above their pages */
+    regions: Vec<InvertedRegion>,
+    /* The first corner of a region that is being selected */
+    region_anchor: Option<(usize, (f32, f32))>,
+    /* Page and point in page units where the text selection has started */
+    selection_anchor: Option<(usize, (f32, f32))>,
+    /* The selected text as last extracted by the renderer */
+    selected_text: String,
+    /* Positions recorded with `m{a-z}` and the ones jumped away from, the latest last */
+    marks: HashMap<char, Position>,
+    jumps: Vec<Position>,
+    /* Set while the extracted text is shown in place of the rendered pages */
+    text_mode: bool,
+    /* Paragraphs of the pages extracted for the text mode, `None` while requested */
+    texts: HashMap<usize, Option<Vec<String>>>,
 
     pub images: HashMap<usize, Arc<RwLock<Image>>>,
     invalidated: HashMap<usize, ()>,
+    /* Pages of which only the preview has arrived */
+    previews: Previews,
     scheduled4render: HashMap<usize, ()>,
-    memory_used: usize,
-    last_rendered: VecDeque<usize>,
-
-    sender_rerender: Sender<()>,
+    /* Failed placements of the pages with the time from which they are sent again */
+    transfer_failures: HashMap<usize, (u32, Instant)>,
+    /* Failed renders of the pages */
+    render_failures: HashMap<usize, u32>,
+    memory: MemoryManager,
+
+    /* The id of the pane, which the rerender signals are tagged with */
+    id: usize,
+    sender_rerender: Sender<AppEvent>,
 }
 
 impl Viewer {
-    pub fn new() -> (Self, Receiver<()>) {
-        let (sender_rerender, receiver_rerender) = unbounded::<()>();
-        (
-            Self {
-                scale: 1.0,
-                page_first: 0,
-                page_view: 0,
-                offset: (0.0f32, 0.0f32),
-                max_width: -f32::INFINITY,
-                cumulative_heights: Vec::new(),
-                widths: Vec::new(),
-                links: Vec::new(),
-                images: HashMap::new(),
-                invalidated: HashMap::new(),
-                scheduled4render: HashMap::new(),
-                memory_used: 0,
-                last_rendered: VecDeque::new(),
-                sender_rerender,
-            },
-            receiver_rerender,
-        )
+    pub fn new(id: usize) -> Self {
+        Self {
+            scale: 1.0,
+            precision: CONFIG.get().unwrap().viewer.precision_at(1.0),
+            precision_pending: None,
+            page_first: 0,
+            page_view: 0,
+            offset: (0.0f32, 0.0f32),
+            pane: (0, 1),
+            start: None,
+            max_width: -f32::INFINITY,
+            cumulative_heights: Vec::new(),
+            widths: Vec::new(),
+            cumulative_extents: Vec::new(),
+            max_across: -f32::INFINITY,
+            links: Vec::new(),
+            transforms: Vec::new(),
+            captions: None,
+            caption_pending: None,
+            past_end: false,
+            glide: Glide::default(),
+            momentum: Momentum::default(),
+            popup: None,
+            prerendered: None,
+            search: Vec::new(),
+            search_index: None,
+            regions: Vec::new(),
+            region_anchor: None,
+            selection_anchor: None,
+            selected_text: String::new(),
+            marks: HashMap::new(),
+            jumps: Vec::new(),
+            text_mode: false,
+            texts: HashMap::new(),
+            images: HashMap::new(),
+            invalidated: HashMap::new(),
+            previews: Previews::default(),
+            scheduled4render: HashMap::new(),
+            transfer_failures: HashMap::new(),
+            render_failures: HashMap::new(),
+            memory: MemoryManager::new(CONFIG.get().unwrap().viewer.eviction_policy),
+            id,
+            sender_rerender: event::sender(),
+        }
     }
 
     pub fn is_uninit(&self) -> bool {
@@ -161,11 +401,72 @@ impl Viewer {
         cumulative_heights: &[f32],
         widths: &[f32],
         links: &[Vec<Link>],
+        transforms: &[PageTransform],
     ) {
+        /* A reload keeps the page and how far it has been scrolled through, since the
+         * offsets move when the sizes of the pages change */
+        let progress = (!self.is_uninit()).then(|| self.page_progress());
+        /* The pages of the new document get new renders */
+        self.render_failures.clear();
+
         self.max_width = max_width;
         self.cumulative_heights = cumulative_heights.to_owned();
         self.widths = widths.to_owned();
         self.links = links.to_owned();
+
+        match self.layout() {
+            ConfigPageLayout::Vertical => {
+                self.cumulative_extents = self.cumulative_heights.clone();
+                self.max_across = max_width;
+            }
+            ConfigPageLayout::Horizontal => {
+                let margin = CONFIG.get().unwrap().viewer.margin_bottom;
+                self.cumulative_extents = self
+                    .widths
+                    .iter()
+                    .scan(0.0f32, |x, width| {
+                        *x += width + margin;
+                        Some(*x)
+                    })
+                    .collect();
+                self.max_across = (0..self.pages())
+                    .map(|x| self.page_height(x).unwrap_or(0.0) - margin)
+                    .fold(-f32::INFINITY, f32::max);
+            }
+        }
+        self.transforms = transforms.to_owned();
+
+        if let Some((page, fraction)) = progress.filter(|_| self.pages() > 0) {
+            /* Documents that have lost pages keep the position on their last page */
+            let page = usize::min(page, self.pages() - 1);
+            let start = self.page_start(page);
+            self.set_offset_along(
+                start + fraction * (self.cumulative_extents[page] - start),
+            );
+            self.bound_viewer();
+        }
+
+        self.captions = None;
+        self.caption_pending = None;
+        self.prerendered = None;
+        self.search.clear();
+        self.search_index = None;
+        self.texts.clear();
+    }
+
+    /* Adds the links to the identifiers found in the text of a page */
+    pub fn update_identifiers(&mut self, page: usize, links: Vec<Link>) {
+        if let Some(x) = self.links.get_mut(page) {
+            x.extend(links);
+        }
+    }
+
+    pub fn update_captions(&mut self, captions: Vec<Caption>) {
+        self.captions = Some(captions);
+
+        if let Some(forward) = self.caption_pending.take() {
+            self.jump_caption(forward, None);
+        }
     }
 
     pub fn invalidate_registry(&mut self) {
@@ -173,48 +474,269 @@ impl Viewer {
         self.scheduled4render.clear();
         for k in self.images.keys() {
             self.invalidated.insert(*k, ());
+            self.previews.change(*k);
+        }
+        for region in self.regions.iter_mut() {
+            region.invalidated = true;
+            region.scheduled = false;
         }
     }
 
     pub fn scroll(&mut self, amount: (f32, f32)) {
+        self.past_end = self.layout().split(amount).0 > 0.0f32 && self.at_end();
         self.offset.0 += amount.0;
         self.offset.1 += amount.1;
         self.bound_viewer();
     }
 
+    /* Scrolls by the amount spread over `smooth_scroll.duration`, which the main loop
+     * plays back with `glide`. Scrolls at once when smooth scrolling is disabled */
+    pub fn scroll_smooth(&mut self, amount: (f32, f32)) {
+        let smooth_scroll = &CONFIG.get().unwrap().viewer.smooth_scroll;
+        if !smooth_scroll.enabled || smooth_scroll.duration <= 0.0f32 {
+            self.scroll(amount);
+            return;
+        }
+        /* Scrolling past the end is told when the scroll starts, like without the
+         * animation, not once it reaches the end */
+        self.past_end = self.layout().split(amount).0 > 0.0f32 && self.at_end();
+        self.glide.push(amount, Instant::now());
+    }
+
+    /* Adds a wheel event to the kinetic scroll, which the main loop plays back with
+     * `glide`. Falls back to `scroll_smooth` when kinetic scrolling is disabled */
+    pub fn fling(&mut self, amount: (f32, f32)) {
+        let kinetic_scroll = &CONFIG.get().unwrap().viewer.kinetic_scroll;
+        if !kinetic_scroll.enabled {
+            self.scroll_smooth(amount);
+            return;
+        }
+        self.past_end = self.layout().split(amount).0 > 0.0f32 && self.at_end();
+        self.momentum.push(
+            amount,
+            Instant::now(),
+            kinetic_scroll.friction,
+            kinetic_scroll.max_velocity,
+        );
+    }
+
+    pub fn is_gliding(&self) -> bool {
+        self.glide.is_running() || self.momentum.is_running()
+    }
+
+    /* Advances the running smooth and kinetic scrolls to where they are by now */
+    pub fn glide(&mut self) {
+        let config = &CONFIG.get().unwrap().viewer;
+        let now = Instant::now();
+        let amount = self.glide.step(
+            now,
+            Duration::from_secs_f32(config.smooth_scroll.duration),
+            config.smooth_scroll.easing,
+        );
+        let coast = self.momentum.step(now, config.kinetic_scroll.friction);
+        self.scroll((amount.0 + coast.0, amount.1 + coast.1));
+        self.past_end = false;
+    }
+
     pub fn pages(&self) -> usize {
         self.cumulative_heights.len()
     }
 
+    /* Whether the bottom of the last page is shown */
+    pub fn at_end(&self) -> bool {
+        !self.cumulative_extents.is_empty() && self.offset_along() >= self.max_offset()
+    }
+
+    /* Returns `true` once after the viewer has been scrolled past the end */
+    pub fn take_past_end(&mut self) -> bool {
+        std::mem::take(&mut self.past_end)
+    }
+
     pub fn jump(&mut self, page: usize) -> Result<(), String> {
-        let res = if page >= self.cumulative_heights.len() {
+        let res = if page >= self.cumulative_extents.len() {
             Err("Given page number is larger than the number of pages".to_owned())
         } else {
             Ok(())
         };
 
-        self.page_first = usize::min(page, self.cumulative_heights.len() - 1);
+        /* Jumps land where they point instead of sliding on */
+        self.glide = Glide::default();
+        self.momentum = Momentum::default();
+        self.page_first = usize::min(page, self.cumulative_extents.len() - 1);
 
         if page == 0 {
-            self.offset.1 = 0.0f32;
+            self.set_offset_along(0.0f32);
         } else {
-            self.offset.1 = self.cumulative_heights[self.page_first - 1] + 1.0;
+            self.set_offset_along(self.page_start(self.page_first) + 1.0);
         }
         self.bound_viewer();
 
         res
     }
 
+    /* Jumps to the target of an internal link with the point it points to at the top
+     * of the view and at the zoom it gives, or to the start of its page */
+    pub fn jump_link(&mut self, link: &Link) -> Result<(), String> {
+        let page = link.page as usize;
+        self.jump(page)?;
+
+        let target = parse_link_target(&link.uri);
+        if let Some(zoom) = target.zoom {
+            self.scale = zoom / 100.0;
+            self.center_viewer();
+        }
+        if let (Some(along), _) = self.layout().split((target.x, target.y)) {
+            self.set_offset_along(self.page_start(page) + along);
+        }
+        self.bound_viewer();
+
+        Ok(())
+    }
+
+    /* Jumps to the next or previous figure/table caption relative to the current
+     * offset. Figure captions are placed in the middle of the terminal so that the
+     * figure above them stays visible, table captions are placed near the top */
+    pub fn jump_caption(&mut self, forward: bool, renderer: Option<&Renderer>) {
+        let captions = match &self.captions {
+            Some(x) => x,
+            None => {
+                if let Some(renderer) = renderer {
+                    if renderer.send_action(RendererAction::Captions).is_ok() {
+                        self.caption_pending = Some(forward);
+                    }
+                }
+                return;
+            }
+        };
+
+        /* Captions of pages placed next to each other jump to the start of the page */
+        let layout = self.layout();
+        let view_height = self.viewport().height as f32 / self.scale;
+        let offset = self.offset_along();
+        let targets = captions.iter().map(|caption| {
+            let anchor = match caption.kind {
+                CaptionKind::Figure => view_height * 0.5,
+                CaptionKind::Table => view_height * 0.1,
+            };
+
+            self.page_start(caption.page) + layout.split((0.0f32, caption.y - anchor)).0
+        });
+
+        let target = if forward {
+            targets
+                .filter(|x| *x > offset + 1.0)
+                .min_by(|a, b| a.total_cmp(b))
+        } else {
+            targets
+                .filter(|x| *x < offset - 1.0)
+                .max_by(|a, b| a.total_cmp(b))
+        };
+
+        if let Some(target) = target {
+            self.set_offset_along(target);
+            self.bound_viewer();
+        }
+    }
+
+    pub fn set_search(&mut self, hits: Vec<SearchHit>) {
+        self.search = hits;
+        self.search_index = None;
+    }
+
+    /* Jumps to the next or previous match of the search, wrapping around at the ends
+     * of the document. The first jump goes to the first match below the current
+     * offset. Returns the index of the match and the number of matches */
+    pub fn jump_search(&mut self, forward: bool) -> Option<(usize, usize)> {
+        let total = self.search.len();
+        if total == 0 {
+            return None;
+        }
+
+        let layout = self.layout();
+        let offsets: Vec<f32> = self
+            .search
+            .iter()
+            .map(|hit| {
+                let transform = &self.transforms[hit.page];
+                let a = transform.to_display((hit.bounds.0, hit.bounds.1));
+                let b = transform.to_display((hit.bounds.2, hit.bounds.3));
+                self.page_start(hit.page) + layout.split((a.0.min(b.0), a.1.min(b.1))).0
+            })
+            .collect();
+
+        let offset = self.offset_along();
+        let index = match self.search_index {
+            Some(i) if forward => (i + 1) % total,
+            Some(i) => (i + total - 1) % total,
+            None => first_match(&offsets, offset, forward),
+        };
+        self.search_index = Some(index);
+
+        /* Keep some of the text before the match visible */
+        let viewport = self.viewport();
+        let view = layout.split((viewport.width, viewport.height)).0 as f32 / self.scale;
+        self.set_offset_along(offsets[index] - view * 0.3);
+        self.bound_viewer();
+
+        Some((index, total))
+    }
+
     pub fn scale(&mut self, scale: f32) {
         self.scale += scale;
         self.bound_viewer();
     }
 
-    #[allow(dead_code)]
+    /* Zooms so that the point under the mouse stays where it is. Zooms like `scale`
+     * when the mouse is not over a page */
+    pub fn scale_at(&mut self, scale: f32, mouse: MouseEvent) {
+        if self.mouse2page(mouse).is_none() {
+            self.scale(scale);
+            return;
+        }
+
+        let layout = self.layout();
+        let viewport = self.viewport();
+        let (mouse_along, mouse_across) = layout.split((
+            (mouse.column as i32 - viewport.x) as f32,
+            (mouse.row as i32 - viewport.y) as f32,
+        ));
+        /* The offset along the layout is in page units, the one across in pixels */
+        let (along, across) = layout.split(self.offset);
+        let point = (
+            along + mouse_along / self.scale,
+            (mouse_across - across) / self.scale,
+        );
+
+        self.scale = f32::max(self.scale + scale, CONFIG.get().unwrap().viewer.scale_min);
+        self.offset = layout.join(
+            point.0 - mouse_along / self.scale,
+            mouse_across - point.1 * self.scale,
+        );
+        self.bound_viewer();
+    }
+
+    /* Follows a change of the size of the cells in pixels, when the font size changes
+     * or the window is moved to a monitor of another DPI, so that the pages keep their
+     * size in cells. They are rendered again at the precision of the new scale instead
+     * of stretching the old renders */
+    pub fn rescale_cells(&mut self, factor: f32) {
+        let layout = self.layout();
+        let (along, across) = layout.split(self.offset);
+        self.scale *= factor;
+        /* Only the offset across the layout is kept in terminal pixels */
+        self.offset = layout.join(along, across * factor);
+        self.bound_viewer();
+        self.invalidate_registry();
+    }
+
     pub fn get_scale(&self) -> f32 {
         self.scale
     }
 
+    pub fn precision(&self) -> f64 {
+        self.precision
+    }
+
     pub fn page_first(&self) -> usize {
         self.page_first
     }
@@ -229,11 +751,40 @@ impl Viewer {
     }
 
     /* ============================= Calculation methods ============================= */
+    fn layout(&self) -> ConfigPageLayout {
+        CONFIG.get().unwrap().viewer.page_layout
+    }
+
+    fn offset_along(&self) -> f32 {
+        self.layout().split(self.offset).0
+    }
+
+    fn set_offset_along(&mut self, offset: f32) {
+        let layout = self.layout();
+        self.offset = layout.join(offset, layout.split(self.offset).1);
+    }
+
+    /* Offset along the layout at which the given page starts */
+    fn page_start(&self, page: usize) -> f32 {
+        if page == 0 {
+            0.0f32
+        } else {
+            self.cumulative_extents[page - 1]
+        }
+    }
+
+    /* Size of the viewport along and across the layout in terminal pixels */
+    fn viewport_extents(&self) -> (f32, f32) {
+        let viewport = self.viewport();
+        self.layout()
+            .split((viewport.width as f32, viewport.height as f32))
+    }
+
     fn offset2page(&self, offset: f32) -> usize {
         /* Update page index by performing binary search */
-        let res = self.cumulative_heights.binary_search_by(|x: &f32| {
+        let res = self.cumulative_extents.binary_search_by(|x: &f32| {
             x.partial_cmp(&offset)
-                .expect("NaN value found in cumulative height vector")
+                .expect("NaN value found in cumulative extent vector")
         });
 
         match res {
@@ -242,58 +793,179 @@ impl Viewer {
         }
     }
 
+    fn max_offset(&self) -> f32 {
+        f32::max(
+            -10.0f32,
+            self.cumulative_extents.last().unwrap_or(&0.0f32)
+                - self.viewport_extents().0 / self.scale,
+        )
+    }
+
     pub fn bound_viewer(&mut self) {
         let config = CONFIG.get().unwrap();
 
-        let terminal_size_lock = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        let layout = self.layout();
+        let (viewport_along, viewport_across) = self.viewport_extents();
 
         self.scale = f32::max(self.scale, config.viewer.scale_min);
-        self.offset.0 = f32::max(
-            self.offset.0,
-            f32::min(
-                0.0f32,
-                terminal_size_lock.width as f32 - self.max_width * self.scale,
-            ),
+        let (mut along, mut across) = layout.split(self.offset);
+        across = f32::max(
+            across,
+            f32::min(0.0f32, viewport_across - self.max_across * self.scale),
         );
-        self.offset.0 = f32::min(
-            self.offset.0,
-            f32::max(
-                0.0f32,
-                terminal_size_lock.width as f32 - self.max_width * self.scale,
-            ),
+        across = f32::min(
+            across,
+            f32::max(0.0f32, viewport_across - self.max_across * self.scale),
         );
-        let max_yoffset = f32::max(
-            -10.0f32,
-            self.cumulative_heights.last().unwrap_or(&0.0f32)
-                - terminal_size_lock.height as f32 / self.scale,
-        );
-        self.offset.1 = f32::max(self.offset.1, -10.0f32);
-        self.offset.1 = f32::min(self.offset.1, max_yoffset);
+        along = f32::max(along, -10.0f32);
+        along = f32::min(along, self.max_offset());
+        self.offset = layout.join(along, across);
 
-        self.page_first = self.offset2page(self.offset.1);
-        self.page_view = self.offset2page(
-            self.offset.1 + terminal_size_lock.height as f32 * 0.5 / self.scale,
-        );
+        self.page_first = self.offset2page(along);
+        self.page_view = self.offset2page(along + viewport_along * 0.5 / self.scale);
         let mut min_page = 0;
-        if !self.cumulative_heights.is_empty() {
-            min_page = self.cumulative_heights.len() - 1;
+        if !self.cumulative_extents.is_empty() {
+            min_page = self.cumulative_extents.len() - 1;
         }
         self.page_view = usize::min(self.page_view, min_page);
     }
 
+    /* Fits the largest page into the viewport across the layout */
     pub fn scale_page2terminal(&mut self) {
-        let terminal_size_lock = TERMINAL_SIZE.get().unwrap().read().unwrap();
-
-        let factor = terminal_size_lock.width as f32 / (self.max_width * self.scale);
+        let factor = self.viewport_extents().1 / (self.max_across * self.scale);
         self.scale *= factor;
         self.bound_viewer();
     }
 
     pub fn center_viewer(&mut self) {
-        let terminal_size_lock = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        let layout = self.layout();
+        let across = self.viewport_extents().1 * 0.5 - self.max_across * self.scale * 0.5;
+        self.offset = layout.join(layout.split(self.offset).0, across);
+    }
+
+    /* Sets the page and optionally the scale that are applied after the first load */
+    fn position(&self) -> Position {
+        Position {
+            offset: self.offset,
+            scale: self.scale,
+        }
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.offset = position.offset;
+        self.scale = position.scale;
+        self.bound_viewer();
+    }
+
+    /* Records the current position in the jump list before jumping away from it */
+    pub fn push_jump(&mut self) {
+        let position = self.position();
+        if self.jumps.last() != Some(&position) {
+            self.jumps.push(position);
+        }
+        if self.jumps.len() > JUMP_LIST_LIMIT {
+            self.jumps.remove(0);
+        }
+    }
 
-        self.offset.0 =
-            terminal_size_lock.width as f32 * 0.5 - self.max_width * self.scale * 0.5;
+    /* Goes back to the latest position of the jump list, dropping it from the list.
+     * Returns `false` if the list is empty */
+    pub fn jump_back(&mut self) -> bool {
+        match self.jumps.pop() {
+            Some(position) => {
+                self.set_position(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /* Swaps the current position with the latest one of the jump list, so that jumping
+     * again returns, like `''` in vim */
+    pub fn jump_previous(&mut self) -> bool {
+        let current = self.position();
+        if !self.jump_back() {
+            return false;
+        }
+        self.jumps.push(current);
+        true
+    }
+
+    pub fn set_mark(&mut self, name: char) {
+        self.marks.insert(name, self.position());
+    }
+
+    pub fn jump_mark(&mut self, name: char) -> Result<(), String> {
+        let position = *self
+            .marks
+            .get(&name)
+            .ok_or(format!("Mark `{}` is not set", name))?;
+        self.push_jump();
+        self.set_position(position);
+        Ok(())
+    }
+
+    pub fn set_start(&mut self, page: usize, scale: Option<f32>) {
+        self.start = Some((page, scale));
+    }
+
+    pub fn apply_start(&mut self) {
+        if let Some((page, scale)) = self.start.take() {
+            if let Some(scale) = scale {
+                self.scale = scale;
+                self.center_viewer();
+            }
+            let _ = self.jump(page);
+        }
+    }
+
+    /* Places this viewer in the `index`th of `count` side by side panes */
+    pub fn set_pane(&mut self, index: usize, count: usize) {
+        self.pane = (index, usize::max(count, 1));
+        self.bound_viewer();
+    }
+
+    /* The part of the terminal given in pixels that this viewer is drawn on. The
+     * edges are kept inside of whole cells since images are clipped against them */
+    pub fn viewport(&self) -> DisplayRect {
+        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        let pxpercol = terminal_size.width as f32 / terminal_size.columns as f32;
+        let columns = (terminal_size.columns as usize / self.pane.1) as f32;
+
+        let x0 = (self.pane.0 as f32 * columns * pxpercol).ceil() as i32;
+        let x1 = ((self.pane.0 + 1) as f32 * columns * pxpercol).floor() as i32;
+
+        DisplayRect {
+            x: x0,
+            y: 0,
+            width: x1 - x0,
+            height: terminal_size.height as i32,
+        }
+    }
+
+    /* Copies the zoom, horizontal offset and relative position from another viewer
+     * so that side by side documents scroll together */
+    pub fn sync_with(&mut self, other: &Viewer) {
+        if self.cumulative_extents.is_empty() || other.cumulative_extents.is_empty() {
+            return;
+        }
+
+        let other_page = other.page_first;
+        let other_start = other.page_start(other_page);
+        let fraction = (other.offset_along() - other_start)
+            / (other.cumulative_extents[other_page] - other_start);
+
+        let page = usize::min(other_page, self.cumulative_extents.len() - 1);
+        let start = self.page_start(page);
+
+        let layout = self.layout();
+        self.scale = other.scale;
+        self.text_mode = other.text_mode;
+        self.offset = layout.join(
+            start + fraction * (self.cumulative_extents[page] - start),
+            layout.split(other.offset).1,
+        );
+        self.bound_viewer();
     }
 
     pub fn page_height(&self, page: usize) -> Result<f32, String> {
@@ -321,12 +993,15 @@ impl Viewer {
     fn calculate_display_bounds(&self) -> Vec<(usize, DisplayRect)> {
         /* Calculated bounds to display the pages in the temrinal */
         let mut bounds = Vec::new();
-        /* Current terminal height */
-        let terminal_height = TERMINAL_SIZE.get().unwrap().read().unwrap().height as f32;
+        /* The part of the terminal this viewer is drawn on */
+        let viewport = self.viewport();
+        /* The pages are placed in a row along the layout, starting at the offset */
+        let layout = self.layout();
+        let viewport_along = self.viewport_extents().0;
         /* Bottom margin */
         let margin_bottom = CONFIG.get().unwrap().viewer.margin_bottom;
         /* Number of pages */
-        let pages_num = self.cumulative_heights.len();
+        let pages_num = self.cumulative_extents.len();
         /* The index of the first rendered page */
         let mut page_index = self.page_first();
 
@@ -334,16 +1009,11 @@ impl Viewer {
             return bounds;
         }
 
-        let mut page_offset = self.offset().1;
-        page_offset -= if page_index == 0 {
-            0.0f32
-        } else {
-            self.cumulative_heights[page_index - 1]
-        };
-        let mut displayed_offset = -page_offset * self.scale;
+        let (along, across) = layout.split(self.offset());
+        let mut displayed_offset = -(along - self.page_start(page_index)) * self.scale;
 
-        /* Cumulative displayed page height */
-        while displayed_offset < terminal_height && page_index < pages_num {
+        /* Cumulative displayed page extent */
+        while displayed_offset < viewport_along && page_index < pages_num {
             let height = ((self
                 .page_height(page_index)
                 .expect("Could not retrieve page height"))
@@ -351,18 +1021,20 @@ impl Viewer {
                 * self.scale;
 
             let width = self.page_width(page_index).unwrap() * self.scale;
+            let (x, y) = layout.join(displayed_offset, across);
 
             bounds.push((
                 page_index,
                 DisplayRect {
-                    x: self.offset().0 as i32,
-                    y: displayed_offset as i32,
+                    x: viewport.x + x as i32,
+                    y: viewport.y + y as i32,
                     width: width as i32,
                     height: height as i32,
                 },
             ));
 
-            displayed_offset += height + margin_bottom * self.scale;
+            displayed_offset +=
+                layout.split((width, height)).0 + margin_bottom * self.scale;
             page_index += 1;
         }
 
@@ -370,7 +1042,9 @@ impl Viewer {
     }
 
     /* ================================ Miscellaneous ================================ */
-    pub fn intersect_link(&self, mouse: MouseEvent) -> Option<Link> {
+    /* Translates a mouse position in terminal pixels to the page under it and the
+     * point on that page given in page units */
+    pub fn mouse2page(&self, mouse: MouseEvent) -> Option<(usize, (f32, f32))> {
         let mut intersected_page = None;
         let mut intersected_rect = None;
         for bound in self.calculate_display_bounds() {
@@ -385,12 +1059,26 @@ impl Viewer {
         intersected_page?;
 
         let page_point = (
-            ((mouse.column as i32 - intersected_rect.unwrap().x) as f32 / self.scale)
-                as i32,
-            ((mouse.row as i32 - intersected_rect.unwrap().y) as f32 / self.scale) as i32,
+            (mouse.column as i32 - intersected_rect.unwrap().x) as f32 / self.scale,
+            (mouse.row as i32 - intersected_rect.unwrap().y) as f32 / self.scale,
         );
+
+        Some((intersected_page.unwrap(), page_point))
+    }
+
+    /* Translates a mouse position to the page under it and the point in the content
+     * of that page, undoing the rotation and cropping of the page */
+    pub fn mouse2content(&self, mouse: MouseEvent) -> Option<(usize, (f32, f32))> {
+        let (page, point) = self.mouse2page(mouse)?;
+        Some((page, self.transforms.get(page)?.to_page(point)))
+    }
+
+    pub fn intersect_link(&self, mouse: MouseEvent) -> Option<Link> {
+        let (page, page_point) = self.mouse2content(mouse)?;
+        let page_point = (page_point.0 as i32, page_point.1 as i32);
+
         let mut intersected_link = None;
-        let links = &self.links[intersected_page.unwrap()];
+        let links = &self.links[page];
         for link in links.iter() {
             if rect_point_intersect(&link.bounds, &page_point) {
                 intersected_link = Some(link);
@@ -403,6 +1091,52 @@ impl Viewer {
         Some(intersected_link.unwrap().clone())
     }
 
+    /* The terminal cell at the top left corner of the link on the page, `None` while
+     * that corner is not on the screen */
+    pub fn link_cell(&self, page: usize, link: &Link) -> Option<(u16, u16)> {
+        let (_, rect) = self
+            .calculate_display_bounds()
+            .into_iter()
+            .find(|x| x.0 == page)?;
+        let transform = self.transforms.get(page)?;
+        let a = transform.to_display((link.bounds.x0, link.bounds.y0));
+        let b = transform.to_display((link.bounds.x1, link.bounds.y1));
+        let x = rect.x as f32 + a.0.min(b.0) * self.scale;
+        let y = rect.y as f32 + a.1.min(b.1) * self.scale;
+
+        let viewport = self.viewport();
+        if x < viewport.x as f32
+            || y < viewport.y as f32
+            || x >= (viewport.x + viewport.width) as f32
+            || y >= (viewport.y + viewport.height) as f32
+        {
+            return None;
+        }
+
+        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        let pxpercol = terminal_size.width as f32 / terminal_size.columns as f32;
+        let pxperrow = terminal_size.height as f32 / terminal_size.rows as f32;
+        let cell = ((x / pxpercol) as u16, (y / pxperrow) as u16);
+        (cell.1 < terminal_size.rows).then_some(cell)
+    }
+
+    /* The links of the displayed pages that are on the screen with their pages */
+    pub fn visible_links(&self) -> Vec<(usize, Link)> {
+        if self.text_mode {
+            return Vec::new();
+        }
+
+        let mut visible = Vec::new();
+        for (page, _) in self.calculate_display_bounds() {
+            for link in self.links.get(page).into_iter().flatten() {
+                if self.link_cell(page, link).is_some() {
+                    visible.push((page, link.clone()));
+                }
+            }
+        }
+        visible
+    }
+
     pub fn uri_hint(&self, link: &Link) {
         let config = CONFIG.get().unwrap();
         if !config.viewer.uri_hint.enabled {
@@ -439,11 +1173,135 @@ impl Viewer {
         .unwrap();
     }
 
+    /* Prints the marker into the top right corner of the displayed pages for which
+     * `marked` holds, such as the ones that have notes */
+    pub fn mark_pages(&self, marked: impl Fn(usize) -> bool, marker: &str) {
+        let bounds = self.calculate_display_bounds();
+        let viewport = self.viewport();
+
+        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        let pxpercol = terminal_size.width as f32 / terminal_size.columns as f32;
+        let pxperrow = terminal_size.height as f32 / terminal_size.rows as f32;
+
+        let mut handle = io::stdout().lock();
+        for (_, rect) in bounds.into_iter().filter(|x| marked(x.0)) {
+            let right = i32::min(rect.x + rect.width, viewport.x + viewport.width);
+            let column = (right as f32 / pxpercol) as i32 - 2;
+            let row = (i32::max(rect.y, 0) as f32 / pxperrow) as i32;
+            if column < 0 || row >= terminal_size.rows as i32 || right <= rect.x {
+                continue;
+            }
+
+            let _ = queue!(
+                handle,
+                SavePosition,
+                MoveTo(column as u16, row as u16),
+                Print(marker),
+                RestorePosition
+            );
+        }
+        let _ = handle.flush();
+    }
+
+    /* Shows an error on top of the pages that could not be placed after all retries */
+    pub fn mark_failed_pages(&self) {
+        let config = CONFIG.get().unwrap();
+        let bounds = self.calculate_display_bounds();
+        let viewport = self.viewport();
+
+        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        let pxpercol = terminal_size.width as f32 / terminal_size.columns as f32;
+        let pxperrow = terminal_size.height as f32 / terminal_size.rows as f32;
+
+        let failed = |page: &usize| {
+            self.transfer_failures
+                .get(page)
+                .is_some_and(|x| x.0 >= TRANSFER_RETRIES)
+                || self.render_failed_for_good(*page)
+        };
+        let mut handle = io::stdout().lock();
+        for (page, rect) in bounds.into_iter().filter(|x| failed(&x.0)) {
+            let column = (i32::max(rect.x, viewport.x) as f32 / pxpercol).ceil() as i32;
+            let row = (i32::max(rect.y, 0) as f32 / pxperrow) as i32;
+            if row >= terminal_size.rows as i32 {
+                continue;
+            }
+
+            let _ = queue!(
+                handle,
+                SavePosition,
+                MoveTo(column as u16, row as u16),
+                SetBackgroundColor(config.viewer.uri_hint.background),
+                SetForegroundColor(config.viewer.uri_hint.foreground),
+                Print(format!(" Page {} could not be displayed ", page + 1)),
+                ResetColor,
+                RestorePosition
+            );
+        }
+        let _ = handle.flush();
+    }
+
+    pub fn set_prerendered(&mut self, done: usize, total: usize) {
+        self.prerendered = if done < total {
+            Some((done, total))
+        } else {
+            None
+        };
+    }
+
+    pub fn prerendered(&self) -> Option<(usize, usize)> {
+        self.prerendered
+    }
+
+    /* Bytes taken by the rendered pages in memory */
+    pub fn memory_used(&self) -> usize {
+        self.memory.used()
+    }
+
+    /* Shows a low precision render of a page until the full one arrives */
+    pub fn handle_preview(&mut self, page: usize, image: Arc<RwLock<Image>>) {
+        /* A refreshed preview replaces the one still on the screen, which may no
+         * longer be scheduled after the pages have been invalidated. Other pages may
+         * have been dropped or already be rendered in full */
+        let refreshed = self.previews.contains(page);
+        if !refreshed
+            && (!self.scheduled4render.contains_key(&page)
+                || self.images.contains_key(&page))
+        {
+            return;
+        }
+
+        self.memory.insert(page, image.read().unwrap().size());
+        self.images.insert(page, image);
+        self.previews.show(page);
+    }
+
+    /* Whether any of the displayed previews shows an older generation of its page */
+    pub fn stale_previews(&self) -> bool {
+        !self.previews.stale().is_empty()
+    }
+
+    /* Requests the stale previews again, called only while the viewer is idle so that
+     * they do not take render time from the pages on the screen */
+    pub fn refresh_previews(&mut self, renderer: &Renderer) {
+        for page in self.previews.stale() {
+            if renderer.send_action(RendererAction::Preview(page)).is_ok() {
+                self.previews.show(page);
+            }
+        }
+    }
+
+    /* Number of pages requested from the renderer that have not arrived yet */
+    pub fn queued(&self) -> usize {
+        self.scheduled4render.len()
+    }
+
     pub fn handle_image(&mut self, page: usize, image: Option<Arc<RwLock<Image>>>) {
         macro_rules! remove_image {
             ($page:expr) => {
-                self.memory_used -= self.images[&$page].read().unwrap().size();
+                self.memory.remove($page);
                 self.images.remove(&$page);
+                self.previews.remove($page);
                 self.invalidated.remove(&$page);
             };
         }
@@ -460,18 +1318,28 @@ impl Viewer {
         }
 
         let image_unwrapped = image.unwrap();
-        self.memory_used += image_unwrapped.read().unwrap().size();
-        self.last_rendered.push_back(page);
+        /* The preview stays displayed until the full render has been transferred, so
+         * that the page is not missing for a frame */
+        if self.previews.remove(page) {
+            let _ = image_unwrapped.read().unwrap().transfer();
+        }
+        self.memory
+            .insert(page, image_unwrapped.read().unwrap().size());
 
         self.images.insert(page, image_unwrapped);
         self.scheduled4render.remove(&page);
-
-        while self.memory_used >= config.viewer.memory_limit {
-            let page2remove = self.last_rendered.pop_front().unwrap();
-            if !self.images.contains_key(&page2remove) {
-                continue;
+        /* A new render gets new retries */
+        self.transfer_failures.remove(&page);
+        self.render_failures.remove(&page);
+
+        /* The limit may be exceeded while the pages on the screen take more */
+        while self.memory.used() >= config.viewer.memory_limit {
+            match self.memory.victim(self.page_view) {
+                Some(page2remove) => {
+                    remove_image!(page2remove);
+                }
+                None => break,
             }
-            remove_image!(page2remove);
         }
     }
 
@@ -484,8 +1352,20 @@ impl Viewer {
     ) -> Option<usize> {
         if (!self.images.contains_key(&page) || self.invalidated.contains_key(&page))
             && !self.scheduled4render.contains_key(&page)
+            && !self.render_failed_for_good(page)
         {
-            let res = renderer.send_action(RendererAction::Display(page));
+            /* Pages that are shown for the first time get a quick preview. The
+             * previews of all pages are rendered before the full renders */
+            let preview = CONFIG.get().unwrap().viewer.preview_precision;
+            if !preload
+                && !self.images.contains_key(&page)
+                && preview > 0.0f64
+                && preview < self.precision
+            {
+                let _ = renderer.send_action(RendererAction::Preview(page));
+            }
+
+            let res = renderer.send_action(RendererAction::Display(page, self.precision));
             if res.is_ok() {
                 self.scheduled4render.insert(page, ());
             }
@@ -496,10 +1376,12 @@ impl Viewer {
         if self.images.contains_key(&page) {
             let image = self.images[&page].read().unwrap();
             if preload {
-                image.check().unwrap();
-                return Some(page);
+                if image.check().unwrap() {
+                    return Some(page);
+                }
             } else {
-                let has_displayed = image.display(rect).unwrap();
+                let has_displayed = image.display(rect, self.viewport()).unwrap();
+                self.memory.touch(page);
 
                 if has_displayed {
                     return Some(page);
@@ -516,6 +1398,38 @@ impl Viewer {
         let config = CONFIG.get().unwrap();
         let preloaded = config.viewer.pages_preloaded;
 
+        /* Rerender the pages once the scale has entered another zoom band. The old
+         * renders are displayed until the new ones arrive */
+        let precision = config.viewer.precision_at(self.scale);
+        let debounce = Duration::from_millis(config.viewer.zoom_debounce);
+        match self.precision_pending {
+            _ if precision == self.precision => self.precision_pending = None,
+            /* Nothing has been rendered yet that could be displayed meanwhile */
+            _ if self.images.is_empty() => {
+                self.precision = precision;
+                self.precision_pending = None;
+                self.invalidate_registry();
+            }
+            Some((x, since)) if x == precision => {
+                if since.elapsed() >= debounce {
+                    self.precision = precision;
+                    self.precision_pending = None;
+                    self.invalidate_registry();
+                }
+            }
+            /* Zooming through several steps in a row only renders the pages at the
+             * last one, which is displayed once it has stayed for `zoom_debounce` */
+            _ => {
+                self.precision_pending = Some((precision, Instant::now()));
+
+                let (sender, id) = (self.sender_rerender.clone(), self.id);
+                thread::spawn(move || {
+                    thread::sleep(debounce);
+                    let _ = sender.send(AppEvent::Rerender(id));
+                });
+            }
+        }
+
         /* Track what images have been actually displayed on the screen to
          * later check if there occured errors during the display */
         let mut displayed = Vec::new();
@@ -528,7 +1442,7 @@ impl Viewer {
 
         /* The index of the first rendered page */
         let mut page_index = self.page_first();
-        if self.cumulative_heights.len() <= page_index {
+        if self.cumulative_extents.len() <= page_index {
             return Ok(displayed);
         }
 
@@ -540,7 +1454,9 @@ impl Viewer {
             }
         }
 
-        for (page, rect) in self.calculate_display_bounds() {
+        let bounds = self.calculate_display_bounds();
+        self.memory.pin(bounds.iter().map(|x| x.0));
+        for (page, rect) in bounds {
             let r = self.load_or_display(page, rect, false, renderer);
             if let Some(page) = r {
                 displayed.push(page);
@@ -549,7 +1465,7 @@ impl Viewer {
         }
 
         /* Preload N pages after the last displayed page */
-        for _ in 0..usize::min(preloaded, self.cumulative_heights.len() - page_index) {
+        for _ in 0..usize::min(preloaded, self.cumulative_extents.len() - page_index) {
             let r = self.load_or_display(page_index, none_rect, true, renderer);
             if let Some(page) = r {
                 displayed.push(page);
@@ -560,9 +1476,416 @@ impl Viewer {
         Ok(displayed)
     }
 
+    /* The first page in the view and how far it has been scrolled through along the
+     * layout, between 0 and 1 */
+    pub fn page_progress(&self) -> (usize, f32) {
+        let page = self.page_first();
+        if self.cumulative_extents.len() <= page {
+            return (page, 0.0);
+        }
+
+        let extent = self.cumulative_extents[page] - self.page_start(page);
+        let progress = (self.offset_along() - self.page_start(page)) / extent;
+        (page, progress.clamp(0.0, 1.0))
+    }
+
+    /* Toggles the text mode. Returns whether it is on */
+    pub fn toggle_text_mode(&mut self) -> bool {
+        self.text_mode = !self.text_mode;
+        self.text_mode
+    }
+
+    pub fn text_mode(&self) -> bool {
+        self.text_mode
+    }
+
+    pub fn handle_text(&mut self, page: usize, paragraphs: Vec<String>) {
+        if let Some(x) = self.texts.get_mut(&page) {
+            *x = Some(paragraphs);
+        }
+    }
+
+    /* Prints the text of the first page in the view wrapped to the columns of the
+     * pane. The lines follow the position in the page so that the page is read by
+     * scrolling through it as usual */
+    pub fn display_text(&mut self, renderer: &Renderer) -> Result<(), String> {
+        let page = self.page_first();
+        if self.cumulative_extents.len() <= page {
+            return Ok(());
+        }
+
+        let paragraphs = match self.texts.get(&page) {
+            Some(Some(x)) => x,
+            Some(None) => return Ok(()),
+            None => {
+                self.texts.insert(page, None);
+                return renderer.send_action(RendererAction::ExtractText(page));
+            }
+        };
+
+        let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+        let columns = terminal_size.columns as usize / self.pane.1;
+        let column = self.pane.0 * columns;
+        /* The first line is left free for the bar and the last one for the command
+         * line */
+        let rows = (terminal_size.rows as usize).saturating_sub(1);
+
+        let mut lines = vec![format!("Page {}/{}", page + 1, self.pages())];
+        let text = wrap_paragraphs(paragraphs, columns.saturating_sub(2));
+        let first = (self.page_progress().1 * text.len() as f32) as usize;
+        lines.push(String::new());
+        lines.extend(text.into_iter().skip(first));
+        if paragraphs.is_empty() {
+            lines.push("No text has been found on this page".to_string());
+        }
+
+        let mut handle = io::stdout().lock();
+        for (i, line) in lines.iter().take(rows).enumerate() {
+            queue!(handle, MoveTo(column as u16 + 1, i as u16 + 1), Print(line))
+                .map_err(|x| format!("Could not print the text: {}", x))?;
+        }
+        handle
+            .flush()
+            .map_err(|x| format!("Could not print the text: {}", x))
+    }
+
+    /* Requests a high precision render of the image or math block under the mouse */
+    pub fn open_popup(&self, mouse: MouseEvent, renderer: &Renderer) {
+        if let Some((page, point)) = self.mouse2content(mouse) {
+            let _ =
+                renderer.send_action(RendererAction::ZoomBlock(page, point.0, point.1));
+        }
+    }
+
+    pub fn set_popup(&mut self, image: Option<Arc<RwLock<Image>>>) {
+        if image.is_some() {
+            self.popup = image;
+        }
+    }
+
+    pub fn close_popup(&mut self) {
+        self.popup = None;
+    }
+
+    /* Displays the popup centered in the terminal. Returns `true` if a placement has
+     * been issued and a graphics response should be expected */
+    pub fn display_popup(&self) -> Result<bool, String> {
+        let image = match &self.popup {
+            Some(x) => x.read().unwrap(),
+            None => return Ok(false),
+        };
+
+        let (width, height) = image.dimensions();
+        let terminal = {
+            let terminal_size = TERMINAL_SIZE.get().unwrap().read().unwrap();
+            DisplayRect {
+                x: 0,
+                y: 0,
+                width: terminal_size.width as i32,
+                height: terminal_size.height as i32,
+            }
+        };
+        let rect = DisplayRect {
+            x: (terminal.width - width) / 2,
+            y: (terminal.height - height) / 2,
+            width,
+            height,
+        };
+
+        image.display_layer(rect, terminal, Z_INDEX_POPUP)
+    }
+
+    pub fn transfer_popup(&self) {
+        if let Some(image) = &self.popup {
+            let _ = image.read().unwrap().transfer();
+            let _ = self.sender_rerender.send(AppEvent::Rerender(self.id));
+        }
+    }
+
+    /* Marks a corner of a region under the mouse. Returns `true` once the opposite
+     * corner has been marked on the same page and the region has been added */
+    pub fn mark_region(&mut self, mouse: MouseEvent) -> Result<bool, String> {
+        let (page, point) = self.mouse2content(mouse).ok_or("No page under the mouse")?;
+
+        let anchor = match self.region_anchor.take() {
+            Some((anchor_page, anchor)) if anchor_page == page => anchor,
+            _ => {
+                self.region_anchor = Some((page, point));
+                return Ok(false);
+            }
+        };
+
+        let crop = (
+            f32::min(anchor.0, point.0),
+            f32::min(anchor.1, point.1),
+            f32::max(anchor.0, point.0),
+            f32::max(anchor.1, point.1),
+        );
+        if crop.2 - crop.0 < 1.0 || crop.3 - crop.1 < 1.0 {
+            return Err("The selected region is empty".to_string());
+        }
+
+        self.regions.push(InvertedRegion {
+            page,
+            crop,
+            image: None,
+            invalidated: false,
+            scheduled: false,
+            failures: 0,
+        });
+        Ok(true)
+    }
+
+    /* Starts selecting text at the mouse and clears the previous selection. Returns
+     * `false` when there is no page under the mouse */
+    pub fn start_selection(&mut self, mouse: MouseEvent, renderer: &Renderer) -> bool {
+        if self.selection_anchor.is_some() || !self.selected_text.is_empty() {
+            let _ = renderer.send_action(RendererAction::Select(None));
+        }
+        self.selected_text.clear();
+
+        self.selection_anchor = self.mouse2content(mouse);
+        self.selection_anchor.is_some()
+    }
+
+    /* Extends the selection to the mouse while it stays on the page of the anchor */
+    pub fn drag_selection(&self, mouse: MouseEvent, renderer: &Renderer) {
+        let (Some((page, anchor)), Some((mouse_page, point))) =
+            (self.selection_anchor, self.mouse2content(mouse))
+        else {
+            return;
+        };
+
+        if page == mouse_page {
+            let _ =
+                renderer.send_action(RendererAction::Select(Some((page, anchor, point))));
+        }
+    }
+
+    /* Ends the selection. The text is copied once the renderer has answered with it,
+     * since the selects of the last drags may still be on their way. Returns `false`
+     * when no selection has been started */
+    pub fn finish_selection(&mut self, renderer: &Renderer) -> bool {
+        if self.selection_anchor.take().is_none() {
+            return false;
+        }
+        renderer
+            .send_action(RendererAction::FinishSelection)
+            .is_ok()
+    }
+
+    /* The text of the last selection, empty if there is none */
+    pub fn selected_text(&self) -> &str {
+        &self.selected_text
+    }
+
+    pub fn handle_selection(&mut self, page: usize, text: String) {
+        self.selected_text = text;
+
+        /* Only the page of the selection is rendered again */
+        self.invalidate_page(page);
+    }
+
+    /* Renders the page again if it is displayed */
+    pub fn invalidate_page(&mut self, page: usize) {
+        if self.images.contains_key(&page) {
+            self.previews.change(page);
+            self.invalidated.insert(page, ());
+            self.scheduled4render.remove(&page);
+        }
+    }
+
+    pub fn clear_regions(&mut self) {
+        self.regions.clear();
+        self.region_anchor = None;
+    }
+
+    pub fn handle_region(
+        &mut self,
+        page: usize,
+        crop: (f32, f32, f32, f32),
+        image: Arc<RwLock<Image>>,
+    ) {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|x| x.page == page && x.crop == crop);
+        if let Some(region) = region {
+            region.image = Some(image);
+            region.invalidated = false;
+            region.scheduled = false;
+            region.failures = 0;
+        }
+    }
+
+    /* The region is requested again when it is displayed, until it has failed
+     * `RENDER_RETRIES` times more and is removed */
+    pub fn region_failed(&mut self, page: usize, crop: (f32, f32, f32, f32)) {
+        let Some(i) = self
+            .regions
+            .iter()
+            .position(|x| x.page == page && x.crop == crop)
+        else {
+            return;
+        };
+
+        let region = &mut self.regions[i];
+        region.scheduled = false;
+        region.failures += 1;
+        if region.failures > RENDER_RETRIES {
+            self.regions.remove(i);
+        }
+        let _ = self.sender_rerender.send(AppEvent::Rerender(self.id));
+    }
+
+    /* Displays the inverted regions above the visible pages and requests the regions
+     * that have not been rendered yet. Returns the regions that have been placed */
+    pub fn display_regions(&mut self, renderer: &Renderer) -> Result<Vec<usize>, String> {
+        let mut displayed = Vec::new();
+        let viewport = self.viewport();
+
+        for (page, rect) in self.calculate_display_bounds() {
+            let transform = match self.transforms.get(page) {
+                Some(x) => *x,
+                None => continue,
+            };
+
+            for (i, region) in self.regions.iter_mut().enumerate() {
+                if region.page != page {
+                    continue;
+                }
+
+                if (region.image.is_none() || region.invalidated) && !region.scheduled {
+                    let action =
+                        RendererAction::InvertRegion(page, region.crop, self.precision);
+                    region.scheduled = renderer.send_action(action).is_ok();
+                }
+
+                let image = match &region.image {
+                    Some(x) => x.read().unwrap(),
+                    None => continue,
+                };
+
+                /* The corners of the region swap places on rotated pages */
+                let a = transform.to_display((region.crop.0, region.crop.1));
+                let b = transform.to_display((region.crop.2, region.crop.3));
+                let region_rect = DisplayRect {
+                    x: rect.x + (f32::min(a.0, b.0) * self.scale) as i32,
+                    y: rect.y + (f32::min(a.1, b.1) * self.scale) as i32,
+                    width: ((a.0 - b.0).abs() * self.scale) as i32,
+                    height: ((a.1 - b.1).abs() * self.scale) as i32,
+                };
+
+                if image.display_layer(region_rect, viewport, Z_INDEX_REGION)? {
+                    displayed.push(i);
+                }
+            }
+        }
+
+        Ok(displayed)
+    }
+
+    pub fn transfer_region(&self, region: usize) {
+        if let Some(image) = &self.regions[region].image {
+            let _ = image.read().unwrap().transfer();
+            let _ = self.sender_rerender.send(AppEvent::Rerender(self.id));
+        }
+    }
+
+    /* Sends the page to the terminal again after its placement has failed. Retries
+     * back off exponentially and stop after `TRANSFER_RETRIES`, so that a terminal
+     * that keeps refusing the page is not flooded with it */
     pub fn schedule_transfer(&mut self, page: usize) {
+        let now = Instant::now();
+        let (failures, retry_at) = self.transfer_failures.entry(page).or_insert((0, now));
+        if *failures >= TRANSFER_RETRIES || now < *retry_at {
+            return;
+        }
+        let backoff = TRANSFER_BACKOFF * 2u32.pow(*failures);
+        *failures += 1;
+        *retry_at = now + backoff;
+
         let image = self.images[&page].clone();
         let _ = image.read().unwrap().transfer();
-        let _ = self.sender_rerender.send(());
+        let _ = self.sender_rerender.send(AppEvent::Rerender(self.id));
+
+        /* Displays the page again once the next retry is allowed */
+        let (sender, id) = (self.sender_rerender.clone(), self.id);
+        thread::spawn(move || {
+            thread::sleep(backoff);
+            let _ = sender.send(AppEvent::Rerender(id));
+        });
+    }
+
+    pub fn transfer_placed(&mut self, page: usize) {
+        self.transfer_failures.remove(&page);
+    }
+
+    /* The page is requested again when it is displayed, until it has failed
+     * `RENDER_RETRIES` times more */
+    pub fn render_failed(&mut self, page: usize) {
+        self.scheduled4render.remove(&page);
+        *self.render_failures.entry(page).or_insert(0) += 1;
+        let _ = self.sender_rerender.send(AppEvent::Rerender(self.id));
+    }
+
+    fn render_failed_for_good(&self, page: usize) -> bool {
+        self.render_failures
+            .get(&page)
+            .is_some_and(|x| *x > RENDER_RETRIES)
+    }
+
+    /* Renders the failed page, or every failed page, again with new retries */
+    pub fn retry_pages(&mut self, page: Option<usize>) {
+        match page {
+            Some(x) => {
+                self.render_failures.remove(&x);
+                self.transfer_failures.remove(&x);
+                self.invalidate_page(x);
+            }
+            None => {
+                self.render_failures.clear();
+                self.transfer_failures.clear();
+            }
+        }
+        let _ = self.sender_rerender.send(AppEvent::Rerender(self.id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_match() {
+        let offsets = [10.0, 200.0, 350.0, 900.0];
+        assert!(first_match(&offsets, 300.0, true) == 2);
+        assert!(first_match(&offsets, 300.0, false) == 1);
+        assert!(first_match(&offsets, 350.0, true) == 2);
+        assert!(first_match(&offsets, 350.0, false) == 1);
+
+        /* Wraps around at the ends of the document */
+        assert!(first_match(&offsets, 1000.0, true) == 0);
+        assert!(first_match(&offsets, 5.0, false) == 3);
+    }
+
+    #[test]
+    fn test_previews() {
+        let mut previews = Previews::default();
+        previews.show(1);
+        previews.show(4);
+        previews.show(7);
+        assert!(previews.stale().is_empty());
+
+        /* Only the page that has changed is refreshed */
+        previews.change(4);
+        previews.change(12);
+        assert!(previews.stale() == [4]);
+        previews.show(4);
+        assert!(previews.stale().is_empty());
+
+        assert!(previews.remove(7) && !previews.remove(7));
+        previews.change(7);
+        assert!(previews.stale().is_empty());
     }
 }